@@ -0,0 +1,95 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use clap::arg_enum;
+use rand::{rngs::StdRng, Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+#[cfg(test)]
+#[path = "unit_tests/backoff_tests.rs"]
+mod backoff_tests;
+
+// How to randomize the delays between two retries.
+arg_enum! {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
+    pub enum Jitter {
+        None,
+        Full,
+        Decorrelated,
+    }
+}
+
+/// Sequence of delays between retries, growing exponentially from `initial` up to `max`.
+/// * With `Jitter::Full`, each delay is drawn uniformly between zero and the
+///   exponential interval.
+/// * With `Jitter::Decorrelated`, each delay is drawn uniformly between `initial` and
+///   three times the previous delay (capped by `max`).
+pub struct Backoff {
+    initial: Duration,
+    max: Duration,
+    jitter: Jitter,
+    attempt: u32,
+    previous: Duration,
+    rng: StdRng,
+}
+
+impl Backoff {
+    /// Use the given seed, if any, to make the sequence of delays reproducible.
+    pub fn new(initial: Duration, max: Duration, jitter: Jitter, seed: Option<u64>) -> Self {
+        let rng = match seed {
+            Some(seed) => StdRng::seed_from_u64(seed),
+            None => StdRng::from_entropy(),
+        };
+        Self {
+            initial,
+            max: std::cmp::max(initial, max),
+            jitter,
+            attempt: 0,
+            previous: initial,
+            rng,
+        }
+    }
+
+    /// The (non-randomized) exponential interval for the given attempt.
+    pub fn interval(&self, attempt: u32) -> Duration {
+        let factor = 1u32.checked_shl(attempt).unwrap_or(u32::MAX);
+        self.initial
+            .checked_mul(factor)
+            .map_or(self.max, |interval| std::cmp::min(interval, self.max))
+    }
+
+    fn uniform(&mut self, low: Duration, high: Duration) -> Duration {
+        if low >= high {
+            return high;
+        }
+        let micros = self
+            .rng
+            .gen_range(low.as_micros() as u64, high.as_micros() as u64 + 1);
+        Duration::from_micros(micros)
+    }
+
+    /// Delay to wait before the next retry.
+    pub fn next_delay(&mut self) -> Duration {
+        let interval = self.interval(self.attempt);
+        self.attempt = self.attempt.saturating_add(1);
+        let delay = match self.jitter {
+            Jitter::None => interval,
+            Jitter::Full => self.uniform(Duration::from_micros(0), interval),
+            Jitter::Decorrelated => {
+                let high = std::cmp::min(self.max, self.previous * 3);
+                self.uniform(self.initial, high)
+            }
+        };
+        self.previous = delay;
+        delay
+    }
+}
+
+impl Iterator for Backoff {
+    type Item = Duration;
+
+    fn next(&mut self) -> Option<Duration> {
+        Some(self.next_delay())
+    }
+}
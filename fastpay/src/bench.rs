@@ -102,10 +102,7 @@ impl ClientServerBenchmark {
         for _ in 0..self.committee_size {
             keys.push(get_key_pair());
         }
-        let committee = Committee {
-            voting_rights: keys.iter().map(|(k, _)| (*k, 1)).collect(),
-            total_votes: self.committee_size,
-        };
+        let committee = Committee::new(keys.iter().map(|(k, _)| (*k, 1)).collect());
 
         // Pick an authority and create one state per shard.
         let (public_auth0, secret_auth0) = keys.pop().unwrap();
@@ -129,11 +126,7 @@ impl ClientServerBenchmark {
             assert!(states[i].in_shard(&keypair.0));
             let client = AccountOffchainState {
                 balance: Balance::from(Amount::from(100)),
-                next_sequence_number: SequenceNumber::from(0),
-                pending_confirmation: None,
-                confirmed_log: Vec::new(),
-                synchronization_log: Vec::new(),
-                received_log: Vec::new(),
+                ..AccountOffchainState::new()
             };
             states[i].accounts.insert(keypair.0, client);
             account_keys.push(keypair);
@@ -150,6 +143,8 @@ impl ClientServerBenchmark {
                 amount: Amount::from(50),
                 sequence_number: SequenceNumber::from(0),
                 user_data: UserData::default(),
+                account_state: None,
+                domain: committee.domain,
             };
             next_recipient = *pubx;
             let order = TransferOrder::new(transfer.clone(), secx);
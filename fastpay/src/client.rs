@@ -5,7 +5,12 @@
 
 use fastpay::{config::*, network, transport};
 use fastpay_core::{
-    authority::*, base_types::*, client::*, committee::Committee, messages::*, serialize::*,
+    authority::*,
+    base_types::*,
+    client::*,
+    committee::{Committee, SigningDomain},
+    messages::*,
+    serialize::*,
 };
 
 use bytes::Bytes;
@@ -71,12 +76,13 @@ fn make_client_state(
     buffer_size: usize,
     send_timeout: std::time::Duration,
     recv_timeout: std::time::Duration,
+    accept_unsigned_responses: bool,
 ) -> ClientState<network::Client> {
     let account = accounts.get(&address).expect("Unknown account");
-    let committee = Committee::new(committee_config.voting_rights());
+    let committee = committee_config.make_committee();
     let authority_clients =
         make_authority_clients(committee_config, buffer_size, send_timeout, recv_timeout);
-    ClientState::new(
+    let state = ClientState::new(
         address,
         account.key.copy(),
         committee,
@@ -85,12 +91,18 @@ fn make_client_state(
         account.sent_certificates.clone(),
         account.received_certificates.clone(),
         account.balance,
-    )
+    );
+    if accept_unsigned_responses {
+        state.with_unsigned_responses()
+    } else {
+        state
+    }
 }
 
 /// Make one transfer order per account, up to `max_orders` transfers.
 fn make_benchmark_transfer_orders(
     accounts_config: &mut AccountsConfig,
+    domain: SigningDomain,
     max_orders: usize,
 ) -> (Vec<TransferOrder>, Vec<(FastPayAddress, Bytes)>) {
     let mut orders = Vec::new();
@@ -104,6 +116,8 @@ fn make_benchmark_transfer_orders(
             amount: Amount::from(1),
             sequence_number: account.next_sequence_number,
             user_data: UserData::default(),
+            account_state: None,
+            domain,
         };
         debug!("Preparing transfer order: {:?}", transfer);
         account.next_sequence_number = account.next_sequence_number.increment().unwrap();
@@ -127,12 +141,13 @@ fn make_benchmark_certificates_from_orders_and_server_configs(
     let mut keys = Vec::new();
     for file in server_config {
         let server_config = AuthorityServerConfig::read(file).expect("Fail to read server config");
-        keys.push((server_config.authority.address, server_config.key));
+        let key = server_config
+            .key
+            .unlock(None)
+            .expect("Server keys used for benchmarks must not be encrypted");
+        keys.push((server_config.authority.address, key));
     }
-    let committee = Committee {
-        voting_rights: keys.iter().map(|(k, _)| (*k, 1)).collect(),
-        total_votes: keys.len(),
-    };
+    let committee = Committee::new(keys.iter().map(|(k, _)| (*k, 1)).collect());
     assert!(
         keys.len() >= committee.quorum_threshold(),
         "Not enough server configs were provided with --server-configs"
@@ -159,7 +174,7 @@ fn make_benchmark_certificates_from_votes(
     committee_config: &CommitteeConfig,
     votes: Vec<SignedTransferOrder>,
 ) -> Vec<(FastPayAddress, Bytes)> {
-    let committee = Committee::new(committee_config.voting_rights());
+    let committee = committee_config.make_committee();
     let mut aggregators = HashMap::new();
     let mut certificates = Vec::new();
     let mut done_senders = HashSet::new();
@@ -301,7 +316,11 @@ struct ClientOpt {
     #[structopt(long, default_value = transport::DEFAULT_MAX_DATAGRAM_SIZE)]
     buffer_size: usize,
 
-    /// Subcommands. Acceptable values are transfer, query_balance, benchmark, and create_accounts.
+    /// Trust answers to account queries that authorities did not sign (see the server option --unsigned-read-responses)
+    #[structopt(long)]
+    accept_unsigned_responses: bool,
+
+    /// Subcommands. Acceptable values are transfer, query_balance, benchmark, create_accounts, and ping.
     #[structopt(subcommand)]
     cmd: ClientCommands,
 }
@@ -319,8 +338,8 @@ enum ClientCommands {
         #[structopt(long)]
         to: String,
 
-        /// Amount to transfer
-        amount: u64,
+        /// Amount to transfer, in tokens (e.g. 1.5)
+        amount: Amount,
     },
 
     /// Obtain the spendable balance
@@ -349,13 +368,17 @@ enum ClientCommands {
     /// Create new user accounts and print the public keys
     #[structopt(name = "create_accounts")]
     CreateAccounts {
-        /// known initial balance of the account
+        /// known initial balance of the account, in base units
         #[structopt(long, default_value = "0")]
-        initial_funding: Balance,
+        initial_funding: i128,
 
         /// Number of additional accounts to create
         num: u32,
     },
+
+    /// Measure the round-trip time to each shard of each authority
+    #[structopt(name = "ping")]
+    Ping,
 }
 
 fn main() {
@@ -367,6 +390,7 @@ fn main() {
     let accounts_config_path = &options.accounts;
     let committee_config_path = &options.committee;
     let buffer_size = options.buffer_size;
+    let accept_unsigned_responses = options.accept_unsigned_responses;
 
     let mut accounts_config =
         AccountsConfig::read_or_create(accounts_config_path).expect("Unable to read user accounts");
@@ -377,7 +401,6 @@ fn main() {
         ClientCommands::Transfer { from, to, amount } => {
             let sender = decode_address(&from).expect("Failed to decode sender's address");
             let recipient = decode_address(&to).expect("Failed to decode recipient's address");
-            let amount = Amount::from(amount);
 
             let mut rt = Runtime::new().unwrap();
             rt.block_on(async move {
@@ -388,6 +411,7 @@ fn main() {
                     buffer_size,
                     send_timeout,
                     recv_timeout,
+                    accept_unsigned_responses,
                 );
                 info!("Starting transfer");
                 let time_start = Instant::now();
@@ -407,6 +431,7 @@ fn main() {
                     buffer_size,
                     send_timeout,
                     recv_timeout,
+                    accept_unsigned_responses,
                 );
                 recipient_client_state
                     .receive_from_fastpay(cert)
@@ -432,13 +457,14 @@ fn main() {
                     buffer_size,
                     send_timeout,
                     recv_timeout,
+                    accept_unsigned_responses,
                 );
                 info!("Starting balance query");
                 let time_start = Instant::now();
                 let amount = client_state.get_spendable_amount().await.unwrap();
                 let time_total = time_start.elapsed().as_micros();
                 info!("Balance confirmed after {} us", time_total);
-                println!("{:?}", amount);
+                println!("{}", amount);
                 accounts_config.update_from_state(&client_state);
                 accounts_config
                     .write(accounts_config_path)
@@ -457,8 +483,11 @@ fn main() {
             let mut rt = Runtime::new().unwrap();
             rt.block_on(async move {
                 warn!("Starting benchmark phase 1 (transfer orders)");
-                let (orders, serialize_orders) =
-                    make_benchmark_transfer_orders(&mut accounts_config, max_orders);
+                let (orders, serialize_orders) = make_benchmark_transfer_orders(
+                    &mut accounts_config,
+                    committee_config.make_committee().domain,
+                    max_orders,
+                );
                 let responses = mass_broadcast_orders(
                     "transfer",
                     &committee_config,
@@ -530,7 +559,7 @@ fn main() {
         } => {
             let num_accounts: u32 = num;
             for _ in 0..num_accounts {
-                let account = UserAccount::new(initial_funding);
+                let account = UserAccount::new(Balance::from(initial_funding));
                 println!("{}:{}", encode_address(&account.address), initial_funding);
                 accounts_config.insert(account);
             }
@@ -538,5 +567,45 @@ fn main() {
                 .write(accounts_config_path)
                 .expect("Unable to write user accounts");
         }
+
+        ClientCommands::Ping => {
+            let mut rt = Runtime::new().unwrap();
+            rt.block_on(async move {
+                let mut authority_clients = make_authority_clients(
+                    &committee_config,
+                    buffer_size,
+                    send_timeout,
+                    recv_timeout,
+                );
+                for config in &committee_config.authorities {
+                    let client = authority_clients
+                        .get_mut(&config.address)
+                        .expect("Authority clients are indexed by address");
+                    for shard in 0..config.num_shards {
+                        match client.ping(shard).await {
+                            Ok(measurement) => println!(
+                                "{} shard {}: rtt {} us (estimated one-way delays: {} us / {} us){}",
+                                encode_address(&config.address),
+                                shard,
+                                measurement.rtt.as_micros(),
+                                measurement.request_delay_us,
+                                measurement.response_delay_us,
+                                if measurement.read_only {
+                                    ", read-only"
+                                } else {
+                                    ""
+                                },
+                            ),
+                            Err(error) => println!(
+                                "{} shard {}: {}",
+                                encode_address(&config.address),
+                                shard,
+                                error
+                            ),
+                        }
+                    }
+                }
+            });
+        }
     }
 }
@@ -3,17 +3,30 @@
 
 use crate::transport::NetworkProtocol;
 use fastpay_core::{
+    authority::AuthorityState,
     base_types::*,
     client::ClientState,
-    messages::{Address, CertifiedTransferOrder},
+    committee::{Committee, Feature, RecipientPolicy, SigningDomain, DEFAULT_MIN_COMMITTEE_SIZE},
+    error::FastPayError,
+    messages::{Address, CertifiedTransferOrder, CommitteeInfoResponse},
+    serialize::{DeserializationMode, UnknownMessagePolicy},
 };
 
-use serde::{Deserialize, Serialize};
+use rand::{rngs::OsRng, CryptoRng, RngCore};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
 use std::{
-    collections::BTreeMap,
+    collections::{BTreeMap, BTreeSet},
     fs::{self, File, OpenOptions},
     io::{BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::Arc,
+    thread,
 };
+use structopt::StructOpt;
+
+#[cfg(test)]
+#[path = "unit_tests/config_tests.rs"]
+mod config_tests;
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct AuthorityConfig {
@@ -26,6 +39,13 @@ pub struct AuthorityConfig {
     pub host: String,
     pub base_port: u32,
     pub num_shards: u32,
+    /// Human-friendly name of the authority, for logs and reports. The authority is
+    /// identified by its address only.
+    #[serde(default)]
+    pub label: Option<String>,
+    /// How to reach the operator of the authority (free text).
+    #[serde(default)]
+    pub operator_info: Option<String>,
 }
 
 impl AuthorityConfig {
@@ -33,22 +53,253 @@ impl AuthorityConfig {
         let data = serde_json::to_string(self).unwrap();
         println!("{}", data);
     }
+
+    /// Check that running all the shards of this authority in one process is reasonable.
+    pub fn check_num_shards(&self, max_shards: u32) -> Result<(), failure::Error> {
+        if self.num_shards == 0 || self.num_shards > max_shards {
+            failure::bail!(
+                "authority {} has {} shards (expected between 1 and {})",
+                encode_address(&self.address),
+                self.num_shards,
+                max_shards
+            );
+        }
+        self.ports()?;
+        Ok(())
+    }
+
+    /// The ports of the shards of this authority, if they are all valid ports.
+    pub fn ports(&self) -> Result<std::ops::Range<u32>, failure::Error> {
+        match self.base_port.checked_add(self.num_shards) {
+            Some(end) if end <= u32::from(u16::MAX) + 1 => Ok(self.base_port..end),
+            _ => failure::bail!(
+                "the {} ports of authority {} from port {} are out of range",
+                self.num_shards,
+                encode_address(&self.address),
+                self.base_port
+            ),
+        }
+    }
+}
+
+/// Half-open range of the shards of an authority run by a process, written `start..end`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct ShardRange {
+    pub start: ShardId,
+    pub end: ShardId,
+}
+
+impl ShardRange {
+    pub fn single(shard: ShardId) -> Self {
+        Self {
+            start: shard,
+            end: shard + 1,
+        }
+    }
+
+    pub fn all(num_shards: u32) -> Self {
+        Self {
+            start: 0,
+            end: num_shards,
+        }
+    }
+
+    /// Check that the range is not empty and within the shards of the authority.
+    pub fn check(&self, num_shards: u32) -> Result<(), failure::Error> {
+        if self.start >= self.end {
+            failure::bail!("shard range {} is empty", self);
+        }
+        if self.end > num_shards {
+            failure::bail!(
+                "shard range {} is out of the {} shards of the authority",
+                self,
+                num_shards
+            );
+        }
+        Ok(())
+    }
+
+    pub fn shards(&self) -> std::ops::Range<ShardId> {
+        self.start..self.end
+    }
+}
+
+impl std::fmt::Display for ShardRange {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}..{}", self.start, self.end)
+    }
+}
+
+impl std::str::FromStr for ShardRange {
+    type Err = failure::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<_> = s.split("..").collect();
+        if parts.len() != 2 {
+            failure::bail!("invalid shard range {:?}: expected <start>..<end>", s);
+        }
+        Ok(Self {
+            start: parts[0].trim().parse()?,
+            end: parts[1].trim().parse()?,
+        })
+    }
+}
+
+/// Secret key of an authority, stored either in clear or encrypted with a passphrase.
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum AuthorityKey {
+    Plain(KeyPair),
+    Encrypted(EncryptedKeyPair),
+}
+
+impl AuthorityKey {
+    /// Obtain the key pair, using the passphrase if the key is encrypted.
+    pub fn unlock(&self, passphrase: Option<&str>) -> Result<KeyPair, failure::Error> {
+        match self {
+            AuthorityKey::Plain(key) => Ok(key.copy()),
+            AuthorityKey::Encrypted(key) => match passphrase {
+                Some(passphrase) => key.decrypt(passphrase),
+                None => failure::bail!("a passphrase is required to decrypt the key"),
+            },
+        }
+    }
+}
+
+/// Version of the format of the committee and server configuration files.
+pub const CONFIG_VERSION: u32 = 2;
+
+/// The version of configurations written before the format was versioned.
+fn unversioned_config() -> u32 {
+    1
+}
+
+fn invalid_config(path: &str, message: String) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!("{}: {}", path, message),
+    )
+}
+
+/// Upgrade a configuration of version 1, written before the format was versioned. The
+/// fields are unchanged, so only the version is added.
+pub fn migrate_v1_to_v2(config: &mut serde_json::Value) -> Result<(), failure::Error> {
+    match config.as_object_mut() {
+        Some(fields) => {
+            fields.insert("version".to_string(), 2.into());
+            Ok(())
+        }
+        None => failure::bail!("a configuration must be a JSON object"),
+    }
+}
+
+/// Parse a JSON configuration, upgrading the older versions of the format.
+fn from_versioned_json<T: DeserializeOwned>(
+    path: &str,
+    mut config: serde_json::Value,
+) -> Result<T, std::io::Error> {
+    let version = match config.get("version") {
+        None => u64::from(unversioned_config()),
+        Some(version) => version
+            .as_u64()
+            .ok_or_else(|| invalid_config(path, format!("invalid config version {}", version)))?,
+    };
+    if version == 1 {
+        migrate_v1_to_v2(&mut config).map_err(|error| invalid_config(path, error.to_string()))?;
+    } else if version != u64::from(CONFIG_VERSION) {
+        return Err(invalid_config(
+            path,
+            format!(
+                "expected config version {}, found {}",
+                CONFIG_VERSION, version
+            ),
+        ));
+    }
+    serde_json::from_value(config).map_err(|error| {
+        invalid_config(
+            path,
+            format!("invalid config of version {}: {}", CONFIG_VERSION, error),
+        )
+    })
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct AuthorityServerConfig {
+    /// Version of the format, see `CONFIG_VERSION`.
+    #[serde(default = "unversioned_config")]
+    pub version: u32,
     pub authority: AuthorityConfig,
-    pub key: KeyPair,
+    pub key: AuthorityKey,
+    /// Confirmed transfers above this amount (in base units, like all the amounts of
+    /// configuration files) are logged at warning level.
+    #[serde(default)]
+    pub large_transfer_threshold: Option<Amount>,
+    /// Refuse to vote for transfers above this amount.
+    #[serde(default)]
+    pub max_transfer_amount: Option<Amount>,
+    /// Accept orders containing keys or signatures that are not canonically encoded.
+    #[serde(default)]
+    pub allow_non_canonical_encodings: bool,
+    /// Whether to reject or ignore unexpected data at the end of incoming messages.
+    #[serde(default)]
+    pub deserialization_mode: DeserializationMode,
+    /// Optional protocol features that this authority is willing to enable, including the
+    /// ones implied by settings of the committee, see `CommitteeConfig::required_features`.
+    #[serde(default)]
+    pub features: BTreeSet<Feature>,
+    /// If set, keep a bloom filter over the accounts of each shard with this false
+    /// positive rate, to answer lookups of missing accounts without probing the store.
+    #[serde(default)]
+    pub account_filter_false_positive_rate: Option<f64>,
+    /// Soft limit on the memory used by the accounts of each shard, in bytes.
+    #[serde(default)]
+    pub memory_limit: Option<usize>,
+    /// Sign cross-shard updates with the authority key and require signatures on the
+    /// updates received, when shards run on hosts that do not trust each other.
+    #[serde(default)]
+    pub sign_cross_shard_updates: bool,
+    /// If set, the only shards allowed to send cross-shard updates to each shard.
+    #[serde(default)]
+    pub cross_shard_sources: Option<BTreeSet<ShardId>>,
+    /// If set, how many verified certificates each shard remembers, so that their
+    /// signatures are not checked again when they are submitted several times.
+    #[serde(default)]
+    pub verification_cache_capacity: Option<usize>,
+    /// How long verified certificates are remembered, in milliseconds.
+    #[serde(default)]
+    pub verification_cache_max_age: Option<u64>,
+    /// Whether to answer messages of an unknown type with an error.
+    #[serde(default)]
+    pub unknown_message_policy: UnknownMessagePolicy,
+    /// Verify certificates again when they are replayed, instead of comparing them to
+    /// the certificates already confirmed.
+    #[serde(default)]
+    pub reverify_confirmed_certificates: bool,
+    /// Refuse to confirm transfers that would leave the sender with a negative balance.
+    #[serde(default)]
+    pub reject_negative_balances: bool,
+    /// Log addresses and user data in full instead of redacting them. Only enable when
+    /// the logs stay private.
+    #[serde(default)]
+    pub log_full_details: bool,
+    /// Log the transfer orders rejected because a different order of the same account and
+    /// sequence number was already signed, to help debugging clients.
+    #[serde(default)]
+    pub log_conflicting_orders: bool,
 }
 
 impl AuthorityServerConfig {
     pub fn read(path: &str) -> Result<Self, std::io::Error> {
         let data = fs::read(path)?;
-        Ok(serde_json::from_slice(data.as_slice())?)
+        from_versioned_json(path, serde_json::from_slice(data.as_slice())?)
     }
 
     pub fn write(&self, path: &str) -> Result<(), std::io::Error> {
-        let file = OpenOptions::new().create(true).write(true).open(path)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
         let mut writer = BufWriter::new(file);
         let data = serde_json::to_string_pretty(self).unwrap();
         writer.write_all(data.as_ref())?;
@@ -57,26 +308,295 @@ impl AuthorityServerConfig {
     }
 }
 
+#[derive(Serialize, Deserialize)]
 pub struct CommitteeConfig {
+    /// Version of the format, see `CONFIG_VERSION`.
+    #[serde(default = "unversioned_config")]
+    pub version: u32,
     pub authorities: Vec<AuthorityConfig>,
+    /// How authorities handle transfers to accounts that they do not know.
+    #[serde(default)]
+    pub recipient_policy: RecipientPolicy,
+    /// Optional protocol features enabled in this committee.
+    #[serde(default)]
+    pub features: BTreeSet<Feature>,
+    /// If set, how many sequence numbers old the account state referenced by a transfer
+    /// order may be.
+    #[serde(default)]
+    pub order_acceptance_window: Option<u64>,
+    /// Identifier of the deployment, part of the signed content of every transfer so that
+    /// orders cannot be replayed across deployments.
+    #[serde(default)]
+    pub chain_id: u64,
+    /// When the deployment started, in milliseconds since the Unix epoch. Also part of the
+    /// signed content of every transfer.
+    #[serde(default)]
+    pub genesis_timestamp: u64,
+    /// If set, the minimal time between two transfers from the same account, in
+    /// milliseconds.
+    #[serde(default)]
+    pub transfer_cooldown_ms: Option<u64>,
+    /// Record the confirmed transfers of each account at strictly increasing times.
+    #[serde(default)]
+    pub strictly_increasing_transfer_times: bool,
 }
 
 impl CommitteeConfig {
+    /// Read a committee given either as a JSON document or, as produced by successive
+    /// calls to `server generate`, as one JSON authority description per line.
     pub fn read(path: &str) -> Result<Self, std::io::Error> {
-        let file = File::open(path)?;
-        let reader = BufReader::new(file);
-        let stream = serde_json::Deserializer::from_reader(reader).into_iter();
+        let data = fs::read(path)?;
+        if let Ok(config) = serde_json::from_slice::<serde_json::Value>(&data) {
+            if config.get("authorities").is_some() {
+                return from_versioned_json(path, config);
+            }
+        }
+        let stream = serde_json::Deserializer::from_slice(&data).into_iter();
         Ok(Self {
+            version: CONFIG_VERSION,
             authorities: stream.filter_map(Result::ok).collect(),
+            recipient_policy: RecipientPolicy::default(),
+            features: BTreeSet::new(),
+            order_acceptance_window: None,
+            chain_id: 0,
+            genesis_timestamp: 0,
+            transfer_cooldown_ms: None,
+            strictly_increasing_transfer_times: false,
         })
     }
 
     pub fn write(&self, path: &str) -> Result<(), std::io::Error> {
-        let file = OpenOptions::new().create(true).write(true).open(path)?;
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
         let mut writer = BufWriter::new(file);
-        for config in &self.authorities {
-            serde_json::to_writer(&mut writer, config)?;
-            writer.write_all(b"\n")?;
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    pub fn make_committee(&self) -> Committee {
+        let mut committee = Committee::new(self.voting_rights());
+        committee.recipient_policy = self.recipient_policy;
+        committee.features = self.required_features();
+        committee.order_acceptance_window = self.order_acceptance_window;
+        committee.transfer_cooldown_ms = self.transfer_cooldown_ms;
+        committee.strictly_increasing_transfer_times = self.strictly_increasing_transfer_times;
+        committee.domain = SigningDomain {
+            chain_id: self.chain_id,
+            genesis_timestamp: self.genesis_timestamp,
+        };
+        committee
+    }
+
+    /// The features enabled in the committee: the ones listed, and the ones implied by the
+    /// other settings.
+    pub fn required_features(&self) -> BTreeSet<Feature> {
+        let mut features = self.features.clone();
+        let implied = [
+            (
+                self.recipient_policy == RecipientPolicy::Reject,
+                Feature::RejectMissingRecipients,
+            ),
+            (
+                self.order_acceptance_window.is_some(),
+                Feature::OrderAcceptanceWindow,
+            ),
+            (
+                self.transfer_cooldown_ms.is_some(),
+                Feature::TransferCooldown,
+            ),
+            (
+                self.strictly_increasing_transfer_times,
+                Feature::StrictlyIncreasingTransferTimes,
+            ),
+        ];
+        for (enabled, feature) in implied.iter() {
+            if *enabled {
+                features.insert(*feature);
+            }
+        }
+        features
+    }
+
+    /// Check that an authority supports all the features enabled in the committee.
+    pub fn check_features(
+        &self,
+        server_config: &AuthorityServerConfig,
+    ) -> Result<(), failure::Error> {
+        let required = self.required_features();
+        let missing: Vec<_> = required.difference(&server_config.features).collect();
+        if !missing.is_empty() {
+            failure::bail!(
+                "committee features {:?} are not enabled for authority {}",
+                missing,
+                encode_address(&server_config.authority.address)
+            );
+        }
+        Ok(())
+    }
+
+    /// Cross-check the committee descriptions served by the authorities of this committee.
+    /// All signed answers must agree, and they must come from at least a validity
+    /// threshold of authorities, so that one of them is honest.
+    pub fn check_committee_info(
+        &self,
+        responses: &[CommitteeInfoResponse],
+    ) -> Result<CommitteeConfig, failure::Error> {
+        let committee = self.make_committee();
+        let mut fingerprint = None;
+        let mut weights = BTreeMap::new();
+        for response in responses {
+            if committee.weight(&response.authority) == 0 {
+                failure::bail!(
+                    "unexpected committee description from {}",
+                    encode_address(&response.authority)
+                );
+            }
+            response.check_signature()?;
+            match fingerprint {
+                None => fingerprint = Some(response.fingerprint()),
+                Some(value) if value != response.fingerprint() => {
+                    failure::bail!(
+                        "authority {} serves a different committee description",
+                        encode_address(&response.authority)
+                    );
+                }
+                Some(_) => (),
+            }
+            weights.insert(response.authority, committee.weight(&response.authority));
+        }
+        let weight: usize = weights.values().sum();
+        if weight < committee.validity_threshold() {
+            failure::bail!(
+                "not enough authorities agree on the committee description ({} < {})",
+                weight,
+                committee.validity_threshold()
+            );
+        }
+        Ok(serde_json::from_str(&responses[0].description)?)
+    }
+
+    /// Check that a server config describes an authority of this committee, as listed,
+    /// and that its key matches the address of the authority.
+    pub fn check_server_config(
+        &self,
+        server_config: &AuthorityServerConfig,
+        key_passphrase: Option<&str>,
+    ) -> Result<(), failure::Error> {
+        let authority = &server_config.authority;
+        let address = encode_address(&authority.address);
+        let listed = match self
+            .authorities
+            .iter()
+            .find(|listed| listed.address == authority.address)
+        {
+            Some(listed) => listed,
+            None => failure::bail!("authority {} is not part of the committee", address),
+        };
+        if listed.network_protocol != authority.network_protocol
+            || listed.host != authority.host
+            || listed.base_port != authority.base_port
+            || listed.num_shards != authority.num_shards
+        {
+            failure::bail!(
+                "authority {} is listed with a different network configuration",
+                address
+            );
+        }
+        if server_config.key.unlock(key_passphrase)?.public() != authority.address {
+            failure::bail!(
+                "the key of authority {} does not match its address",
+                address
+            );
+        }
+        self.check_features(server_config)
+    }
+
+    /// Check that the committee is consistent before running one of its authorities. In
+    /// particular, an authority listed twice would only vote once, so that the quorum
+    /// thresholds would silently differ from the ones implied by the number of entries.
+    /// All the problems found are listed in the error.
+    pub fn validate(&self) -> Result<(), failure::Error> {
+        let mut problems = Vec::new();
+        if self.authorities.is_empty() {
+            problems.push("the committee has no authorities".to_string());
+        }
+        let mut entries = BTreeMap::new();
+        for authority in &self.authorities {
+            *entries.entry(authority.address).or_insert(0) += 1;
+            if authority.num_shards == 0 {
+                problems.push(format!(
+                    "authority {} has no shards",
+                    encode_address(&authority.address)
+                ));
+            }
+        }
+        for (address, count) in entries {
+            if count > 1 {
+                problems.push(format!(
+                    "authority {} is listed {} times",
+                    encode_address(&address),
+                    count
+                ));
+            }
+        }
+        if let Err(error) = self.check_port_ranges() {
+            problems.push(error.to_string());
+        }
+        if !problems.is_empty() {
+            failure::bail!("inconsistent committee: {}", problems.join("; "));
+        }
+        Ok(())
+    }
+
+    /// Check that the ports of authorities are valid and that authorities running on the
+    /// same host listen on distinct ports. Hosts are compared by name.
+    pub fn check_port_ranges(&self) -> Result<(), failure::Error> {
+        let ports = self
+            .authorities
+            .iter()
+            .map(AuthorityConfig::ports)
+            .collect::<Result<Vec<_>, _>>()?;
+        for (i, first) in self.authorities.iter().enumerate() {
+            for (j, second) in self.authorities.iter().enumerate().skip(i + 1) {
+                if first.host != second.host {
+                    continue;
+                }
+                let start = std::cmp::max(ports[i].start, ports[j].start);
+                let end = std::cmp::min(ports[i].end, ports[j].end);
+                if start < end {
+                    failure::bail!(
+                        "authorities {} and {} both use ports {}..{} of host {}",
+                        encode_address(&first.address),
+                        encode_address(&second.address),
+                        start,
+                        end,
+                        first.host
+                    );
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Check that all authorities use the same network protocol. Clients and tools
+    /// configured with one protocol cannot reach the authorities using the other.
+    pub fn check_protocols(&self) -> Result<(), failure::Error> {
+        if let Some(first) = self.authorities.first() {
+            for other in &self.authorities[1..] {
+                if other.network_protocol != first.network_protocol {
+                    failure::bail!(
+                        "authorities {} and {} use different network protocols ({} and {})",
+                        encode_address(&first.address),
+                        encode_address(&other.address),
+                        first.network_protocol,
+                        other.network_protocol
+                    );
+                }
+            }
         }
         Ok(())
     }
@@ -90,6 +610,27 @@ impl CommitteeConfig {
     }
 }
 
+/// Options bounding the size of the committees that servers run or generate.
+#[derive(StructOpt, Clone, Debug)]
+pub struct CommitteeSizeConfig {
+    /// Smallest number of authorities accepted in a committee (4 tolerates one Byzantine authority)
+    #[structopt(long, default_value = "4")]
+    pub min_committee_size: usize,
+
+    /// Accept committees of any size, e.g. a single authority for development
+    #[structopt(long)]
+    pub allow_small_committee: bool,
+}
+
+impl CommitteeSizeConfig {
+    pub fn check(&self, committee: &Committee) -> Result<(), FastPayError> {
+        if self.allow_small_committee {
+            return Ok(());
+        }
+        committee.check_size(self.min_committee_size)
+    }
+}
+
 #[derive(Serialize, Deserialize)]
 pub struct UserAccount {
     #[serde(
@@ -106,7 +647,14 @@ pub struct UserAccount {
 
 impl UserAccount {
     pub fn new(balance: Balance) -> Self {
-        let (address, key) = get_key_pair();
+        Self::new_with_rng(balance, &mut OsRng)
+    }
+
+    pub fn new_with_rng<R>(balance: Balance, rng: &mut R) -> Self
+    where
+        R: CryptoRng + RngCore,
+    {
+        let (address, key) = get_key_pair_from_rng(rng);
         Self {
             address,
             key,
@@ -202,24 +750,414 @@ impl InitialStateConfig {
         let reader = BufReader::new(file);
         let mut accounts = Vec::new();
         for line in reader.lines() {
-            let line = line?;
-            let elements = line.split(':').collect::<Vec<_>>();
-            if elements.len() != 2 {
-                failure::bail!("expecting two columns separated with ':'")
-            }
-            let address = decode_address(elements[0])?;
-            let balance = elements[1].parse()?;
-            accounts.push((address, balance));
+            accounts.push(Self::parse_line(&line?)?);
         }
         Ok(Self { accounts })
     }
 
+    /// Read the accounts of each shard of an authority, parsing the file with the given
+    /// number of threads. Duplicate accounts are rejected.
+    pub fn read_sharded(
+        path: &str,
+        num_shards: u32,
+        num_threads: usize,
+    ) -> Result<Vec<BTreeMap<FastPayAddress, Balance>>, failure::Error> {
+        let data = Arc::new(fs::read_to_string(path)?);
+        let mut chunks = Vec::new();
+        let chunk_size = data.len() / num_threads.max(1) + 1;
+        let mut start = 0;
+        while start < data.len() {
+            // Cut after the end of a line.
+            let bytes = &data.as_bytes()[(start + chunk_size).min(data.len())..];
+            let end = match bytes.iter().position(|byte| *byte == b'\n') {
+                Some(offset) => (start + chunk_size + offset + 1).min(data.len()),
+                None => data.len(),
+            };
+            chunks.push(start..end);
+            start = end;
+        }
+        let results: Vec<_> = if chunks.len() <= 1 {
+            chunks
+                .into_iter()
+                .map(|chunk| Self::parse_chunk(&data[chunk], num_shards))
+                .collect()
+        } else {
+            let threads: Vec<_> = chunks
+                .into_iter()
+                .map(|chunk| {
+                    let data = data.clone();
+                    thread::spawn(move || Self::parse_chunk(&data[chunk], num_shards))
+                })
+                .collect();
+            threads
+                .into_iter()
+                .map(|thread| thread.join().expect("Account parsing should not panic"))
+                .collect()
+        };
+        let mut shards = vec![BTreeMap::new(); num_shards as usize];
+        for result in results {
+            for (shard, accounts) in shards.iter_mut().zip(result?) {
+                Self::merge(shard, accounts)?;
+            }
+        }
+        Ok(shards)
+    }
+
+    /// Parse `address:balance`, with the balance in base units.
+    fn parse_line(line: &str) -> Result<(FastPayAddress, Balance), failure::Error> {
+        let elements = line.split(':').collect::<Vec<_>>();
+        if elements.len() != 2 {
+            failure::bail!("expecting two columns separated with ':'")
+        }
+        let address = decode_address(elements[0])?;
+        let balance = Balance::from(elements[1].parse::<i128>()?);
+        Ok((address, balance))
+    }
+
+    fn parse_chunk(
+        chunk: &str,
+        num_shards: u32,
+    ) -> Result<Vec<BTreeMap<FastPayAddress, Balance>>, failure::Error> {
+        let mut shards = vec![BTreeMap::new(); num_shards as usize];
+        for line in chunk.lines() {
+            let (address, balance) = Self::parse_line(line)?;
+            let shard = AuthorityState::get_shard(num_shards, &address);
+            Self::merge(
+                &mut shards[shard as usize],
+                std::iter::once((address, balance)),
+            )?;
+        }
+        Ok(shards)
+    }
+
+    fn merge<I>(
+        shard: &mut BTreeMap<FastPayAddress, Balance>,
+        accounts: I,
+    ) -> Result<(), failure::Error>
+    where
+        I: IntoIterator<Item = (FastPayAddress, Balance)>,
+    {
+        for (address, balance) in accounts {
+            if shard.insert(address, balance).is_some() {
+                failure::bail!("duplicate initial account {}", encode_address(&address));
+            }
+        }
+        Ok(())
+    }
+
     pub fn write(&self, path: &str) -> Result<(), std::io::Error> {
         let file = OpenOptions::new().create(true).write(true).open(path)?;
         let mut writer = BufWriter::new(file);
         for (address, balance) in &self.accounts {
-            writeln!(writer, "{}:{}", encode_address(address), balance)?;
+            writeln!(
+                writer,
+                "{}:{}",
+                encode_address(address),
+                i128::from(*balance)
+            )?;
         }
         Ok(())
     }
 }
+
+/// Files describing an additional committee whose authority runs in the same process.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TenantConfig {
+    /// Used to tag the logs of this tenant.
+    pub id: String,
+    pub server: String,
+    pub committee: String,
+    pub initial_accounts: String,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TenantsConfig {
+    pub tenants: Vec<TenantConfig>,
+}
+
+impl TenantsConfig {
+    pub fn read(path: &str) -> Result<Self, std::io::Error> {
+        let data = fs::read(path)?;
+        Ok(serde_json::from_slice(data.as_slice())?)
+    }
+
+    pub fn write(&self, path: &str) -> Result<(), std::io::Error> {
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+        let mut writer = BufWriter::new(file);
+        serde_json::to_writer_pretty(&mut writer, self)?;
+        writer.write_all(b"\n")?;
+        Ok(())
+    }
+
+    /// Check that tenant ids are unique and that the shards of the tenants and of the
+    /// given authority (if any) listen on distinct ports.
+    pub fn check_isolation(
+        &self,
+        authority: Option<&AuthorityConfig>,
+    ) -> Result<(), failure::Error> {
+        let mut ids = BTreeSet::new();
+        let mut ports = BTreeMap::new();
+        if let Some(authority) = authority {
+            for port in authority.ports()? {
+                ports.insert(port, "default".to_string());
+            }
+        }
+        for tenant in &self.tenants {
+            if !ids.insert(&tenant.id) {
+                failure::bail!("duplicate tenant id {}", tenant.id);
+            }
+            let authority = AuthorityServerConfig::read(&tenant.server)?.authority;
+            for port in authority.ports()? {
+                if let Some(other) = ports.insert(port, tenant.id.clone()) {
+                    failure::bail!("tenants {} and {} both use port {}", other, tenant.id, port);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Names of the files written by `server genesis`.
+pub const GENESIS_COMMITTEE_FILE: &str = "committee.json";
+pub const GENESIS_ACCOUNTS_FILE: &str = "accounts.json";
+pub const GENESIS_INITIAL_ACCOUNTS_FILE: &str = "initial_accounts.txt";
+pub const GENESIS_SCRIPT_FILE: &str = "run.sh";
+
+/// All the files needed to run a local committee, as produced by `server genesis`.
+pub struct GenesisConfig {
+    pub servers: Vec<AuthorityServerConfig>,
+    pub committee: CommitteeConfig,
+    /// Keys of the funded accounts, for the client.
+    pub accounts: AccountsConfig,
+    pub initial_accounts: InitialStateConfig,
+}
+
+impl GenesisConfig {
+    /// Generate fresh keys for a committee whose authorities all run on the same host,
+    /// with consecutive ports, and for some funded accounts.
+    pub fn generate(
+        network_protocol: NetworkProtocol,
+        host: &str,
+        base_port: u32,
+        num_authorities: u32,
+        num_shards: u32,
+        num_accounts: u32,
+        initial_funding: Balance,
+    ) -> Self {
+        Self::generate_with_rng(
+            network_protocol,
+            host,
+            base_port,
+            num_authorities,
+            num_shards,
+            num_accounts,
+            initial_funding,
+            &mut OsRng,
+        )
+    }
+
+    /// Same as `generate` with keys drawn from the given generator. A seeded generator
+    /// yields the same files on every run, e.g. for tests, but its keys are predictable.
+    #[allow(clippy::too_many_arguments)]
+    pub fn generate_with_rng<R>(
+        network_protocol: NetworkProtocol,
+        host: &str,
+        base_port: u32,
+        num_authorities: u32,
+        num_shards: u32,
+        num_accounts: u32,
+        initial_funding: Balance,
+        rng: &mut R,
+    ) -> Self
+    where
+        R: CryptoRng + RngCore,
+    {
+        let servers: Vec<_> = (0..num_authorities)
+            .map(|i| {
+                let (address, key) = get_key_pair_from_rng(rng);
+                AuthorityServerConfig {
+                    version: CONFIG_VERSION,
+                    authority: AuthorityConfig {
+                        network_protocol,
+                        address,
+                        host: host.to_string(),
+                        base_port: base_port + i * num_shards,
+                        num_shards,
+                        label: Some(format!("authority{}", i)),
+                        operator_info: None,
+                    },
+                    key: AuthorityKey::Plain(key),
+                    large_transfer_threshold: None,
+                    max_transfer_amount: None,
+                    allow_non_canonical_encodings: false,
+                    deserialization_mode: DeserializationMode::default(),
+                    features: BTreeSet::new(),
+                    account_filter_false_positive_rate: None,
+                    memory_limit: None,
+                    sign_cross_shard_updates: false,
+                    cross_shard_sources: None,
+                    verification_cache_capacity: None,
+                    verification_cache_max_age: None,
+                    unknown_message_policy: UnknownMessagePolicy::default(),
+                    reverify_confirmed_certificates: false,
+                    reject_negative_balances: false,
+                    log_full_details: false,
+                    log_conflicting_orders: false,
+                }
+            })
+            .collect();
+        let committee = CommitteeConfig {
+            version: CONFIG_VERSION,
+            authorities: servers
+                .iter()
+                .map(|server| server.authority.clone())
+                .collect(),
+            recipient_policy: RecipientPolicy::default(),
+            features: BTreeSet::new(),
+            order_acceptance_window: None,
+            chain_id: 0,
+            genesis_timestamp: 0,
+            transfer_cooldown_ms: None,
+            strictly_increasing_transfer_times: false,
+        };
+        let mut accounts = AccountsConfig {
+            accounts: BTreeMap::new(),
+        };
+        let mut initial_accounts = InitialStateConfig {
+            accounts: Vec::new(),
+        };
+        for _ in 0..num_accounts {
+            let account = UserAccount::new_with_rng(initial_funding, rng);
+            initial_accounts
+                .accounts
+                .push((account.address, initial_funding));
+            accounts.insert(account);
+        }
+        Self {
+            servers,
+            committee,
+            accounts,
+            initial_accounts,
+        }
+    }
+
+    /// Write all the files in the given directory, together with a script running all
+    /// the authorities. Nothing is written if any of the files already exists.
+    pub fn write(&self, dir: &Path) -> Result<(), failure::Error> {
+        fs::create_dir_all(dir)?;
+        for name in self.file_names() {
+            let path = dir.join(name);
+            if path.exists() {
+                failure::bail!("{} already exists", path.display());
+            }
+        }
+        let committee_path = dir.join(GENESIS_COMMITTEE_FILE);
+        let mut script = String::from(
+            "#!/bin/sh\n\
+             # Run all the authorities of the committee. Set SERVER to the path of the server binary.\n\
+             cd \"$(dirname \"$0\")\"\n",
+        );
+        // The committee was accepted when it was generated, so the script should run it.
+        let size_option = if self.servers.len() < DEFAULT_MIN_COMMITTEE_SIZE {
+            " --allow-small-committee"
+        } else {
+            ""
+        };
+        for (i, server) in self.servers.iter().enumerate() {
+            let name = Self::server_file_name(i);
+            server.write(path_str(&dir.join(&name))?)?;
+            script += &format!(
+                "\"${{SERVER:-server}}\" --server {} run --committee {} --initial-accounts {}{} &\n",
+                name, GENESIS_COMMITTEE_FILE, GENESIS_INITIAL_ACCOUNTS_FILE, size_option
+            );
+        }
+        script += "wait\n";
+        self.committee.write(path_str(&committee_path)?)?;
+        let accounts_path = dir.join(GENESIS_ACCOUNTS_FILE);
+        File::create(&accounts_path)?;
+        self.accounts.write(path_str(&accounts_path)?)?;
+        let initial_accounts_path = dir.join(GENESIS_INITIAL_ACCOUNTS_FILE);
+        File::create(&initial_accounts_path)?;
+        self.initial_accounts
+            .write(path_str(&initial_accounts_path)?)?;
+        fs::write(dir.join(GENESIS_SCRIPT_FILE), script)?;
+        Ok(())
+    }
+
+    /// Describe the files that `write` would create in the given directory, without
+    /// accessing the file system.
+    pub fn summary(&self, dir: &Path) -> String {
+        let mut summary = format!(
+            "Would write the configuration of {} authorities:\n",
+            self.servers.len()
+        );
+        for (i, server) in self.servers.iter().enumerate() {
+            let authority = &server.authority;
+            let name = match &authority.label {
+                Some(label) => label.clone(),
+                None => encode_address(&authority.address),
+            };
+            summary += &format!(
+                "  {}: {} {}:{}..{} ({} shards) -> {}\n",
+                name,
+                authority.network_protocol,
+                authority.host,
+                authority.base_port,
+                authority.base_port + authority.num_shards,
+                authority.num_shards,
+                dir.join(Self::server_file_name(i)).display()
+            );
+        }
+        summary += &format!(
+            "  committee -> {}\n",
+            dir.join(GENESIS_COMMITTEE_FILE).display()
+        );
+        summary += &format!(
+            "  keys of {} accounts -> {}\n",
+            self.accounts.num_accounts(),
+            dir.join(GENESIS_ACCOUNTS_FILE).display()
+        );
+        summary += &format!(
+            "  initial balances -> {}\n",
+            dir.join(GENESIS_INITIAL_ACCOUNTS_FILE).display()
+        );
+        summary += &format!(
+            "  start script -> {}\n",
+            dir.join(GENESIS_SCRIPT_FILE).display()
+        );
+        summary
+    }
+
+    fn server_file_name(index: usize) -> String {
+        format!("server{}.json", index)
+    }
+
+    /// Names of all the files written by `write`.
+    fn file_names(&self) -> Vec<String> {
+        let mut names: Vec<_> = (0..self.servers.len())
+            .map(Self::server_file_name)
+            .collect();
+        names.extend(
+            [
+                GENESIS_COMMITTEE_FILE,
+                GENESIS_ACCOUNTS_FILE,
+                GENESIS_INITIAL_ACCOUNTS_FILE,
+                GENESIS_SCRIPT_FILE,
+            ]
+            .iter()
+            .map(|name| name.to_string()),
+        );
+        names
+    }
+}
+
+fn path_str(path: &Path) -> Result<&str, failure::Error> {
+    match path.to_str() {
+        Some(path) => Ok(path),
+        None => failure::bail!("invalid path {}", path.display()),
+    }
+}
@@ -0,0 +1,110 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use fastpay_core::{base_types::*, committee::SigningDomain, messages::*};
+
+use std::time::{Duration, Instant};
+
+#[cfg(test)]
+#[path = "unit_tests/crypto_bench_tests.rs"]
+mod crypto_bench_tests;
+
+/// Speed of the signature operations of FastPay on the current host, measured on
+/// transfer orders with the same code as the authorities and clients.
+#[derive(Clone, Debug)]
+pub struct CryptoBenchmark {
+    /// Transfer orders signed per second.
+    pub signatures_per_sec: f64,
+    /// Orders verified per second, one at a time.
+    pub verifications_per_sec: f64,
+    /// Batch size, and orders verified per second in batches of that size.
+    pub batch_verifications_per_sec: Vec<(usize, f64)>,
+}
+
+fn per_sec(count: usize, elapsed: Duration) -> f64 {
+    count as f64 / elapsed.as_secs_f64().max(1e-9)
+}
+
+impl CryptoBenchmark {
+    /// Sign and verify `samples` orders (at least as many as the largest batch) from
+    /// different senders to the same recipient.
+    pub fn run(samples: usize, batch_sizes: &[usize]) -> Self {
+        let count = batch_sizes.iter().copied().fold(samples.max(1), usize::max);
+        let recipient = Address::FastPay(get_key_pair().0);
+        let amount = Amount::from(1);
+        let senders: Vec<_> = (0..count).map(|_| get_key_pair()).collect();
+
+        let time_start = Instant::now();
+        let orders: Vec<_> = senders
+            .iter()
+            .map(|(sender, key)| {
+                let transfer = Transfer {
+                    sender: *sender,
+                    recipient,
+                    amount,
+                    sequence_number: SequenceNumber::new(),
+                    user_data: UserData::default(),
+                    account_state: None,
+                    domain: SigningDomain::default(),
+                };
+                TransferOrder::new(transfer, key)
+            })
+            .collect();
+        let signatures_per_sec = per_sec(count, time_start.elapsed());
+
+        let time_start = Instant::now();
+        for order in &orders {
+            order
+                .check_signature()
+                .expect("Benchmark signatures should be valid");
+        }
+        let verifications_per_sec = per_sec(count, time_start.elapsed());
+
+        let template = TransferTemplate::new(recipient, amount);
+        let batch_verifications_per_sec = batch_sizes
+            .iter()
+            .map(|&size| {
+                let batches: Vec<_> = orders.chunks(size.max(1)).collect();
+                let time_start = Instant::now();
+                for batch in &batches {
+                    template
+                        .check_orders(batch)
+                        .expect("Benchmark signatures should be valid");
+                }
+                (size, per_sec(count, time_start.elapsed()))
+            })
+            .collect();
+
+        Self {
+            signatures_per_sec,
+            verifications_per_sec,
+            batch_verifications_per_sec,
+        }
+    }
+}
+
+impl std::fmt::Display for CryptoBenchmark {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        writeln!(
+            f,
+            "{:<20} {:>6} {:>12} {:>12}",
+            "operation", "batch", "per second", "us each"
+        )?;
+        let mut row = |operation: &str, batch: usize, rate: f64| {
+            writeln!(
+                f,
+                "{:<20} {:>6} {:>12.0} {:>12.1}",
+                operation,
+                batch,
+                rate,
+                1e6 / rate
+            )
+        };
+        row("sign", 1, self.signatures_per_sec)?;
+        row("verify", 1, self.verifications_per_sec)?;
+        for (size, rate) in &self.batch_verifications_per_sec {
+            row("verify batch", *size, *rate)?;
+        }
+        Ok(())
+    }
+}
@@ -3,6 +3,11 @@
 
 #![deny(warnings)]
 
+pub mod backoff;
 pub mod config;
+pub mod crypto_bench;
+pub mod metrics;
 pub mod network;
+pub mod receipts;
 pub mod transport;
+pub mod wal;
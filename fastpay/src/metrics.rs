@@ -0,0 +1,321 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::network::ServerStats;
+
+use log::*;
+use std::{
+    collections::HashMap,
+    fmt::Write as _,
+    io::{self, Read, Write},
+    net::{TcpListener, TcpStream, UdpSocket},
+    sync::atomic::{AtomicU64, Ordering},
+    thread,
+    time::Duration,
+};
+use structopt::StructOpt;
+
+#[cfg(test)]
+#[path = "unit_tests/metrics_tests.rs"]
+mod metrics_tests;
+
+/// Maximal size of the datagrams sent to StatsD, so that they are not fragmented.
+const MAX_STATSD_PAYLOAD: usize = 1432;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum MetricKind {
+    /// A value that only increases while the server runs.
+    Counter,
+    /// A value that may go up and down.
+    Gauge,
+}
+
+/// Current value of a metric of a server shard.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct Metric {
+    pub name: &'static str,
+    pub kind: MetricKind,
+    pub value: u64,
+}
+
+/// Upper bounds of the buckets of latency histograms, in microseconds.
+const LATENCY_BUCKETS_MICROS: [u64; 10] = [
+    50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 25_000, 100_000,
+];
+
+/// Distribution of durations, updated concurrently by a server shard.
+#[derive(Default)]
+pub struct Histogram {
+    /// Number of observations in each bucket (not cumulative). The last one is for
+    /// durations above all the bounds.
+    buckets: [AtomicU64; LATENCY_BUCKETS_MICROS.len() + 1],
+    sum_micros: AtomicU64,
+}
+
+impl Histogram {
+    pub fn observe(&self, duration: Duration) {
+        let micros = duration.as_micros() as u64;
+        let index = LATENCY_BUCKETS_MICROS
+            .iter()
+            .position(|bound| micros <= *bound)
+            .unwrap_or(LATENCY_BUCKETS_MICROS.len());
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+    }
+
+    pub fn snapshot(&self) -> HistogramSnapshot {
+        let mut cumulative = 0;
+        let mut buckets = Vec::new();
+        for (bound, bucket) in LATENCY_BUCKETS_MICROS.iter().zip(self.buckets.iter()) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            buckets.push((*bound, cumulative));
+        }
+        let count = cumulative + self.buckets[LATENCY_BUCKETS_MICROS.len()].load(Ordering::Relaxed);
+        HistogramSnapshot {
+            buckets,
+            count,
+            sum_micros: self.sum_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// Current value of a histogram of a server shard.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct HistogramSnapshot {
+    /// Upper bound of each bucket (microseconds) and number of observations up to it.
+    pub buckets: Vec<(u64, u64)>,
+    pub count: u64,
+    pub sum_micros: u64,
+}
+
+/// Options of the StatsD exporter.
+#[derive(StructOpt, Clone, Debug)]
+pub struct StatsdConfig {
+    /// Push metrics to the StatsD server at this address (e.g. 127.0.0.1:8125)
+    #[structopt(long)]
+    pub statsd_address: Option<String>,
+
+    /// Interval between two pushes of metrics to StatsD (milliseconds)
+    #[structopt(long, default_value = "10000")]
+    pub statsd_interval_ms: u64,
+
+    /// Prefix of the names of the metrics pushed to StatsD
+    #[structopt(long, default_value = "fastpay")]
+    pub statsd_prefix: String,
+}
+
+/// Pushes the metrics of server shards to StatsD over UDP.
+pub struct StatsdExporter {
+    socket: UdpSocket,
+    prefix: String,
+    /// Last value sent for each counter, since StatsD expects increments.
+    sent_counters: HashMap<String, u64>,
+}
+
+impl StatsdExporter {
+    pub fn new(address: &str, prefix: String) -> Result<Self, io::Error> {
+        let socket = UdpSocket::bind("0.0.0.0:0")?;
+        socket.connect(address)?;
+        Ok(Self {
+            socket,
+            prefix,
+            sent_counters: HashMap::new(),
+        })
+    }
+
+    /// Name of a metric of a shard, e.g. `fastpay.tenant.label.shard3.requests_served`.
+    fn metric_name(&self, stats: &ServerStats, metric: &Metric) -> String {
+        let mut name = self.prefix.clone();
+        for part in stats.tenant().iter().chain(stats.label().iter()) {
+            name.push('.');
+            name.push_str(part);
+        }
+        name + &format!(".shard{}.{}", stats.shard(), metric.name)
+    }
+
+    /// StatsD lines describing the changes since the last call.
+    pub fn lines(&mut self, stats: &[ServerStats]) -> Vec<String> {
+        let mut lines = Vec::new();
+        for stats in stats {
+            for metric in stats.metrics() {
+                let name = self.metric_name(stats, &metric);
+                match metric.kind {
+                    MetricKind::Counter => {
+                        let sent = self.sent_counters.entry(name.clone()).or_insert(0);
+                        let increment = metric.value.saturating_sub(*sent);
+                        *sent = metric.value;
+                        if increment > 0 {
+                            lines.push(format!("{}:{}|c", name, increment));
+                        }
+                    }
+                    MetricKind::Gauge => lines.push(format!("{}:{}|g", name, metric.value)),
+                }
+            }
+        }
+        lines
+    }
+
+    /// Send the current metrics, packing several lines per datagram.
+    pub fn export(&mut self, stats: &[ServerStats]) -> Result<(), io::Error> {
+        let mut payload = String::new();
+        for line in self.lines(stats) {
+            if !payload.is_empty() && payload.len() + 1 + line.len() > MAX_STATSD_PAYLOAD {
+                self.socket.send(payload.as_bytes())?;
+                payload.clear();
+            }
+            if !payload.is_empty() {
+                payload.push('\n');
+            }
+            payload += &line;
+        }
+        if !payload.is_empty() {
+            self.socket.send(payload.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Export metrics periodically from a background thread.
+    pub fn spawn(
+        mut self,
+        stats: Vec<ServerStats>,
+        interval: Duration,
+    ) -> Result<thread::JoinHandle<()>, io::Error> {
+        thread::Builder::new()
+            .name("statsd".to_string())
+            .spawn(move || loop {
+                thread::sleep(interval);
+                if let Err(err) = self.export(&stats) {
+                    warn!("Failed to push metrics to StatsD: {}", err);
+                }
+            })
+    }
+}
+
+/// Serves the metrics of server shards over HTTP, in the Prometheus text format. Each
+/// sample is labelled with the shard, and with the tenant and label of the server if set.
+pub struct PrometheusExporter {
+    listener: TcpListener,
+    prefix: String,
+}
+
+impl PrometheusExporter {
+    pub fn bind(address: &str, prefix: String) -> Result<Self, io::Error> {
+        let listener = TcpListener::bind(address)?;
+        Ok(Self { listener, prefix })
+    }
+
+    pub fn local_addr(&self) -> Result<std::net::SocketAddr, io::Error> {
+        self.listener.local_addr()
+    }
+
+    fn labels(stats: &ServerStats) -> String {
+        let mut labels = Vec::new();
+        if let Some(tenant) = stats.tenant() {
+            labels.push(format!("tenant=\"{}\"", escape_label_value(tenant)));
+        }
+        if let Some(label) = stats.label() {
+            labels.push(format!("label=\"{}\"", escape_label_value(label)));
+        }
+        labels.push(format!("shard=\"{}\"", stats.shard()));
+        labels.join(",")
+    }
+
+    /// Text describing the current metrics of the given shards. Counters take the
+    /// conventional `_total` suffix.
+    pub fn render(&self, stats: &[ServerStats]) -> String {
+        let mut text = String::new();
+        let metrics: Vec<_> = stats.iter().map(ServerStats::metrics).collect();
+        let labels: Vec<_> = stats.iter().map(Self::labels).collect();
+        if let Some(first) = metrics.first() {
+            for (index, metric) in first.iter().enumerate() {
+                let (name, kind) = match metric.kind {
+                    MetricKind::Counter => {
+                        (format!("{}_{}_total", self.prefix, metric.name), "counter")
+                    }
+                    MetricKind::Gauge => (format!("{}_{}", self.prefix, metric.name), "gauge"),
+                };
+                writeln!(text, "# TYPE {} {}", name, kind).unwrap();
+                for (metrics, labels) in metrics.iter().zip(&labels) {
+                    writeln!(text, "{}{{{}}} {}", name, labels, metrics[index].value).unwrap();
+                }
+            }
+        }
+        let name = format!("{}_verification_seconds", self.prefix);
+        writeln!(text, "# TYPE {} histogram", name).unwrap();
+        for (stats, labels) in stats.iter().zip(&labels) {
+            let histogram = stats.verification_time();
+            for (bound, count) in &histogram.buckets {
+                writeln!(
+                    text,
+                    "{}_bucket{{{},le=\"{}\"}} {}",
+                    name,
+                    labels,
+                    *bound as f64 / 1e6,
+                    count
+                )
+                .unwrap();
+            }
+            writeln!(
+                text,
+                "{}_bucket{{{},le=\"+Inf\"}} {}",
+                name, labels, histogram.count
+            )
+            .unwrap();
+            writeln!(
+                text,
+                "{}_sum{{{}}} {}",
+                name,
+                labels,
+                histogram.sum_micros as f64 / 1e6
+            )
+            .unwrap();
+            writeln!(text, "{}_count{{{}}} {}", name, labels, histogram.count).unwrap();
+        }
+        text
+    }
+
+    /// Answer any HTTP request (e.g. `GET /metrics`) with the current metrics.
+    fn serve(&self, mut stream: TcpStream, stats: &[ServerStats]) -> Result<(), io::Error> {
+        stream.set_read_timeout(Some(Duration::from_secs(5)))?;
+        // Read the request headers, whose content does not matter.
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        while !request.windows(4).any(|window| window == b"\r\n\r\n") {
+            let size = stream.read(&mut buf)?;
+            if size == 0 || request.len() > 16 * 1024 {
+                break;
+            }
+            request.extend_from_slice(&buf[..size]);
+        }
+        let body = self.render(stats);
+        write!(
+            stream,
+            "HTTP/1.1 200 OK\r\n\
+             Content-Type: text/plain; version=0.0.4\r\n\
+             Content-Length: {}\r\n\
+             Connection: close\r\n\r\n{}",
+            body.len(),
+            body
+        )
+    }
+
+    /// Serve metrics from a background thread, one scrape at a time.
+    pub fn spawn(self, stats: Vec<ServerStats>) -> Result<thread::JoinHandle<()>, io::Error> {
+        thread::Builder::new()
+            .name("prometheus".to_string())
+            .spawn(move || {
+                for stream in self.listener.incoming() {
+                    if let Err(err) = stream.and_then(|stream| self.serve(stream, &stats)) {
+                        warn!("Failed to serve metrics: {}", err);
+                    }
+                }
+            })
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
@@ -1,15 +1,138 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::transport::*;
-use fastpay_core::{authority::*, base_types::*, client::*, error::*, messages::*, serialize::*};
+use crate::{
+    backoff::*,
+    config::{CommitteeConfig, InitialStateConfig},
+    metrics::{Histogram, HistogramSnapshot, Metric, MetricKind},
+    transport::*,
+    wal::{self, RecordLog, WalRecord, WriteAheadLog},
+};
+use fastpay_core::{
+    authority::*, base_types::*, client::*, error::*, merkle::HashValue, messages::*, persistence,
+    redaction::LogDetail, serialize::*,
+};
 
 use bytes::Bytes;
-use futures::{channel::mpsc, future::FutureExt, sink::SinkExt, stream::StreamExt};
+use clap::arg_enum;
+use futures::{
+    channel::mpsc,
+    future::{join_all, FutureExt},
+    sink::SinkExt,
+    stream::StreamExt,
+};
 use log::*;
-use std::io;
+use serde::Serialize;
+use std::{
+    collections::{HashMap, VecDeque},
+    io,
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+use structopt::StructOpt;
 use tokio::time;
 
+#[cfg(test)]
+#[path = "unit_tests/network_tests.rs"]
+mod network_tests;
+
+/// A cross-shard message waiting to be sent, with its destination shard and the key of
+/// the transfer that it credits.
+type CrossShardMessage = (Vec<u8>, ShardId, (FastPayAddress, SequenceNumber));
+
+/// Log of the changes to a shard, shared with its cross-shard forwarder.
+type SharedRecordLog = Arc<Mutex<Box<dyn RecordLog>>>;
+
+/// Whether handling the message changes the state of the shard on behalf of a client.
+fn is_client_write(message: &SerializedMessage) -> bool {
+    matches!(
+        message,
+        SerializedMessage::Order(_)
+            | SerializedMessage::Cert(_)
+            | SerializedMessage::RecipientCert(_)
+            | SerializedMessage::OwnershipOrder(_)
+            | SerializedMessage::ReloadAccountsReq(_)
+    )
+}
+
+/// Local time in microseconds since the Unix epoch.
+fn current_time_micros() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_micros() as u64)
+        .unwrap_or(0)
+}
+
+/// Options of the delivery of cross-shard messages.
+#[derive(StructOpt, Clone, Debug)]
+pub struct CrossShardConfig {
+    /// How many times to send a cross-shard message again after a failure
+    #[structopt(long = "cross-shard-retries", default_value = "8")]
+    pub retries: u32,
+
+    /// Delay before the first retry of a cross-shard message (milliseconds)
+    #[structopt(long = "cross-shard-initial-backoff-ms", default_value = "10")]
+    pub initial_backoff_ms: u64,
+
+    /// Maximal delay between two retries of a cross-shard message (milliseconds)
+    #[structopt(long = "cross-shard-max-backoff-ms", default_value = "5000")]
+    pub max_backoff_ms: u64,
+
+    /// Randomization of the retry delays: None, Full, or Decorrelated
+    #[structopt(long = "cross-shard-jitter", default_value = "Full")]
+    pub jitter: Jitter,
+
+    /// Seed making the randomized retry delays reproducible
+    #[structopt(long = "cross-shard-backoff-seed")]
+    pub backoff_seed: Option<u64>,
+
+    /// Send TCP keepalive probes on connections to other shards after they are idle for this long, to detect broken connections (milliseconds; off by default)
+    #[structopt(long = "cross-shard-tcp-keepalive-ms")]
+    pub tcp_keepalive_ms: Option<u64>,
+
+    /// Open a new TCP connection for each cross-shard message instead of keeping one per shard
+    #[structopt(long = "cross-shard-no-connection-reuse")]
+    pub no_connection_reuse: bool,
+}
+
+impl Default for CrossShardConfig {
+    fn default() -> Self {
+        Self {
+            retries: 8,
+            initial_backoff_ms: 10,
+            max_backoff_ms: 5000,
+            jitter: Jitter::Full,
+            backoff_seed: None,
+            tcp_keepalive_ms: None,
+            no_connection_reuse: false,
+        }
+    }
+}
+
+impl CrossShardConfig {
+    /// Schedule of the delays between retries of a cross-shard message.
+    pub fn backoff(&self) -> Backoff {
+        Backoff::new(
+            Duration::from_millis(self.initial_backoff_ms),
+            Duration::from_millis(self.max_backoff_ms),
+            self.jitter,
+            self.backoff_seed,
+        )
+    }
+
+    /// Options of the connections to other shards.
+    pub fn pool_config(&self) -> PoolConfig {
+        PoolConfig {
+            tcp_keepalive: self.tcp_keepalive_ms.map(Duration::from_millis),
+            reuse_connections: !self.no_connection_reuse,
+        }
+    }
+}
+
 pub struct Server {
     network_protocol: NetworkProtocol,
     base_address: String,
@@ -17,9 +140,353 @@ pub struct Server {
     state: AuthorityState,
     buffer_size: usize,
     cross_shard_queue_size: usize,
-    // Stats
-    packets_processed: u64,
-    user_errors: u64,
+    /// Identifies the committee served by this server when several are hosted in the same process.
+    tenant: Option<String>,
+    /// Human-friendly name of the authority, used in logs and reports.
+    label: Option<String>,
+    deserialization_mode: DeserializationMode,
+    unknown_message_policy: UnknownMessagePolicy,
+    cross_shard_config: CrossShardConfig,
+    transport_config: TransportConfig,
+    /// Public description of the committee, served to clients that ask for it.
+    committee_description: Option<String>,
+    /// Where to write a snapshot of the shard when the server stops.
+    snapshot_path: Option<PathBuf>,
+    /// Where to record the changes to the shard, replayed when the server starts.
+    wal_path: Option<PathBuf>,
+    /// Log of the changes to the shard, if given directly instead of `wal_path`.
+    record_log: Option<Box<dyn RecordLog>>,
+    /// In development mode, the initial accounts file that `ReloadAccountsRequest`s
+    /// reset the shard to.
+    dev_initial_accounts: Option<String>,
+    stats: ServerStats,
+}
+
+#[derive(Default)]
+struct Counters {
+    packets_processed: AtomicU64,
+    user_errors: AtomicU64,
+    transfers_confirmed: AtomicU64,
+    conflicting_orders: AtomicU64,
+    pending_cross_shard_messages: AtomicU64,
+    unknown_message_types: AtomicU64,
+    storage_failures: AtomicU64,
+    cross_shard_messages_sent: AtomicU64,
+    cross_shard_messages_received: AtomicU64,
+    /// Time to handle transfer orders and certificates, mostly spent verifying signatures.
+    verification_time: Histogram,
+    /// Set while writes are rejected because the log of changes cannot be written.
+    read_only: AtomicBool,
+    highest_sequence_number: AtomicU64,
+    /// Accounts with a signed but unconfirmed transfer, with the time of the first vote.
+    pending_since: Mutex<HashMap<FastPayAddress, Instant>>,
+}
+
+/// Statistics of a server, which remain readable after the server has stopped.
+#[derive(Clone)]
+pub struct ServerStats {
+    tenant: Option<String>,
+    label: Option<String>,
+    shard: ShardId,
+    started_at: Instant,
+    counters: Arc<Counters>,
+}
+
+/// Summary of the activity of a server shard, emitted when it shuts down.
+#[derive(Clone, Debug, Serialize)]
+pub struct ShutdownReport {
+    pub tenant: Option<String>,
+    pub label: Option<String>,
+    pub shard: ShardId,
+    pub uptime_secs: u64,
+    pub requests_served: u64,
+    pub user_errors: u64,
+    /// Number of confirmation orders processed successfully (including replays).
+    pub transfers_confirmed: u64,
+    /// Transfer orders rejected because a different order of the same account and
+    /// sequence number was already signed, usually a client bug.
+    pub conflicting_orders: u64,
+    /// Cross-shard messages scheduled but not yet sent.
+    pub pending_cross_shard_messages: u64,
+    /// Messages of an unknown type received, a sign of version drift or scanning.
+    pub unknown_message_types: u64,
+    /// Failed writes to the log of changes, each of which made the shard read-only.
+    pub storage_failures: u64,
+}
+
+impl ServerStats {
+    fn new(shard: ShardId) -> Self {
+        Self {
+            tenant: None,
+            label: None,
+            shard,
+            started_at: Instant::now(),
+            counters: Arc::new(Counters::default()),
+        }
+    }
+
+    /// Highest next sequence number of an account after a confirmation in this shard.
+    pub fn highest_sequence_number(&self) -> SequenceNumber {
+        self.counters
+            .highest_sequence_number
+            .load(Ordering::Relaxed)
+            .into()
+    }
+
+    /// Number of accounts whose sequence number has not advanced for at least `window`
+    /// after this shard voted for their pending transfer.
+    pub fn stalled_accounts(&self, window: Duration) -> u64 {
+        let pending_since = self.counters.pending_since.lock().unwrap();
+        pending_since
+            .values()
+            .filter(|since| since.elapsed() >= window)
+            .count() as u64
+    }
+
+    fn record_vote(&self, info: &AccountInfoResponse) {
+        if info.pending_confirmation.is_some() {
+            let mut pending_since = self.counters.pending_since.lock().unwrap();
+            pending_since
+                .entry(info.sender)
+                .or_insert_with(Instant::now);
+        }
+    }
+
+    fn record_confirmation(&self, info: &AccountInfoResponse) {
+        self.counters
+            .pending_since
+            .lock()
+            .unwrap()
+            .remove(&info.sender);
+        self.counters
+            .highest_sequence_number
+            .fetch_max(info.next_sequence_number.into(), Ordering::Relaxed);
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    pub fn label(&self) -> Option<&str> {
+        self.label.as_deref()
+    }
+
+    pub fn shard(&self) -> ShardId {
+        self.shard
+    }
+
+    /// Whether the shard rejects writes because its storage failed.
+    pub fn read_only(&self) -> bool {
+        self.counters.read_only.load(Ordering::Relaxed)
+    }
+
+    /// Distribution of the time spent handling transfer orders and certificates.
+    pub fn verification_time(&self) -> HistogramSnapshot {
+        self.counters.verification_time.snapshot()
+    }
+
+    /// Current values of the metrics of this shard, for monitoring systems.
+    pub fn metrics(&self) -> Vec<Metric> {
+        let report = self.report();
+        let counters = &self.counters;
+        let pending_accounts = counters.pending_since.lock().unwrap().len() as u64;
+        let metric = |name, kind, value| Metric { name, kind, value };
+        vec![
+            metric("uptime_secs", MetricKind::Gauge, report.uptime_secs),
+            metric(
+                "requests_served",
+                MetricKind::Counter,
+                report.requests_served,
+            ),
+            metric("user_errors", MetricKind::Counter, report.user_errors),
+            metric(
+                "transfers_confirmed",
+                MetricKind::Counter,
+                report.transfers_confirmed,
+            ),
+            metric(
+                "conflicting_orders",
+                MetricKind::Counter,
+                report.conflicting_orders,
+            ),
+            metric(
+                "unknown_message_types",
+                MetricKind::Counter,
+                report.unknown_message_types,
+            ),
+            metric(
+                "storage_failures",
+                MetricKind::Counter,
+                report.storage_failures,
+            ),
+            metric(
+                "cross_shard_messages_sent",
+                MetricKind::Counter,
+                counters.cross_shard_messages_sent.load(Ordering::Relaxed),
+            ),
+            metric(
+                "cross_shard_messages_received",
+                MetricKind::Counter,
+                counters
+                    .cross_shard_messages_received
+                    .load(Ordering::Relaxed),
+            ),
+            metric(
+                "pending_cross_shard_messages",
+                MetricKind::Gauge,
+                report.pending_cross_shard_messages,
+            ),
+            metric("read_only", MetricKind::Gauge, self.read_only() as u64),
+            metric("pending_accounts", MetricKind::Gauge, pending_accounts),
+            metric(
+                "highest_sequence_number",
+                MetricKind::Gauge,
+                self.highest_sequence_number().into(),
+            ),
+        ]
+    }
+
+    pub fn report(&self) -> ShutdownReport {
+        let counters = &self.counters;
+        ShutdownReport {
+            tenant: self.tenant.clone(),
+            label: self.label.clone(),
+            shard: self.shard,
+            uptime_secs: self.started_at.elapsed().as_secs(),
+            requests_served: counters.packets_processed.load(Ordering::Relaxed),
+            user_errors: counters.user_errors.load(Ordering::Relaxed),
+            transfers_confirmed: counters.transfers_confirmed.load(Ordering::Relaxed),
+            conflicting_orders: counters.conflicting_orders.load(Ordering::Relaxed),
+            pending_cross_shard_messages: counters
+                .pending_cross_shard_messages
+                .load(Ordering::Relaxed),
+            unknown_message_types: counters.unknown_message_types.load(Ordering::Relaxed),
+            storage_failures: counters.storage_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+// What an authority does when other authorities run a different committee config.
+arg_enum! {
+    #[derive(Clone, Copy, Debug, Eq, PartialEq)]
+    pub enum CommitteeMismatchPolicy {
+        Ignore,
+        Warn,
+        Refuse,
+    }
+}
+
+/// How the committee config of another authority compares with ours.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum PeerCommittee {
+    Same,
+    /// The authority serves a committee description with the given fingerprint.
+    Different {
+        fingerprint: HashValue,
+    },
+    /// The authority could not be asked, e.g. because it is not running yet. Since each
+    /// authority checks the others when it starts, the last one to start compares the
+    /// configs anyway.
+    Unavailable {
+        error: String,
+    },
+}
+
+/// Ask the other authorities of a committee for the committee config that they run, and
+/// compare it with the given one. Descriptions are compared as served, so the configs
+/// must be identical, not only equivalent.
+pub async fn check_peer_committees(
+    committee_config: &CommitteeConfig,
+    name: AuthorityName,
+    buffer_size: usize,
+    timeout: Duration,
+) -> Vec<(AuthorityName, PeerCommittee)> {
+    let description =
+        serde_json::to_string(committee_config).expect("Fail to serialize committee config");
+    let expected = committee_fingerprint(&description);
+    let checks = committee_config
+        .authorities
+        .iter()
+        .filter(|authority| authority.address != name)
+        .map(|authority| async move {
+            let mut client = Client::new(
+                authority.network_protocol,
+                authority.host.clone(),
+                authority.base_port,
+                authority.num_shards,
+                buffer_size,
+                timeout,
+                timeout,
+            );
+            let status = match client.committee_info().await {
+                Ok(response) if response.authority != authority.address => {
+                    PeerCommittee::Unavailable {
+                        error: format!(
+                            "answered as authority {}",
+                            encode_address(&response.authority)
+                        ),
+                    }
+                }
+                Ok(response) if response.fingerprint() == expected => PeerCommittee::Same,
+                Ok(response) => PeerCommittee::Different {
+                    fingerprint: response.fingerprint(),
+                },
+                Err(error) => PeerCommittee::Unavailable {
+                    error: error.to_string(),
+                },
+            };
+            (authority.address, status)
+        });
+    join_all(checks).await
+}
+
+/// Wait until the given shards have sent all their cross-shard messages, or until the
+/// timeout expires. Returns whether the queues were drained.
+pub async fn drain_cross_shard_queues(stats: &[ServerStats], timeout: Duration) -> bool {
+    let deadline = Instant::now() + timeout;
+    loop {
+        let drained = stats.iter().all(|stats| {
+            stats
+                .counters
+                .pending_cross_shard_messages
+                .load(Ordering::Relaxed)
+                == 0
+        });
+        if drained {
+            return true;
+        }
+        if Instant::now() >= deadline {
+            return false;
+        }
+        time::delay_for(Duration::from_millis(10)).await;
+    }
+}
+
+impl std::fmt::Display for ShutdownReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(tenant) = &self.tenant {
+            write!(f, "[{}] ", tenant)?;
+        }
+        if let Some(label) = &self.label {
+            write!(f, "{} ", label)?;
+        }
+        write!(
+            f,
+            "shard {} shut down after {}s: {} requests served ({} user errors), \
+             {} transfers confirmed, {} conflicting orders, \
+             {} pending cross-shard messages, {} messages of unknown type, \
+             {} storage failures",
+            self.shard,
+            self.uptime_secs,
+            self.requests_served,
+            self.user_errors,
+            self.transfers_confirmed,
+            self.conflicting_orders,
+            self.pending_cross_shard_messages,
+            self.unknown_message_types,
+            self.storage_failures
+        )
+    }
 }
 
 impl Server {
@@ -31,6 +498,7 @@ impl Server {
         buffer_size: usize,
         cross_shard_queue_size: usize,
     ) -> Self {
+        let stats = ServerStats::new(state.shard_id);
         Self {
             network_protocol,
             base_address,
@@ -38,44 +506,235 @@ impl Server {
             state,
             buffer_size,
             cross_shard_queue_size,
-            packets_processed: 0,
-            user_errors: 0,
+            tenant: None,
+            label: None,
+            deserialization_mode: DeserializationMode::default(),
+            unknown_message_policy: UnknownMessagePolicy::default(),
+            cross_shard_config: CrossShardConfig::default(),
+            transport_config: TransportConfig::default(),
+            committee_description: None,
+            snapshot_path: None,
+            wal_path: None,
+            record_log: None,
+            dev_initial_accounts: None,
+            stats,
         }
     }
 
+    /// Tag the logs of this server with the given tenant id.
+    pub fn with_tenant(mut self, tenant: String) -> Self {
+        self.stats.tenant = Some(tenant.clone());
+        self.tenant = Some(tenant);
+        self
+    }
+
+    /// Name the authority in the logs and reports of this server.
+    pub fn with_label(mut self, label: String) -> Self {
+        self.stats.label = Some(label.clone());
+        self.label = Some(label);
+        self
+    }
+
+    /// Choose how to treat unexpected bytes at the end of incoming messages.
+    pub fn with_deserialization_mode(mut self, mode: DeserializationMode) -> Self {
+        self.deserialization_mode = mode;
+        self
+    }
+
+    /// Choose how to answer messages of an unknown type.
+    pub fn with_unknown_message_policy(mut self, policy: UnknownMessagePolicy) -> Self {
+        self.unknown_message_policy = policy;
+        self
+    }
+
+    pub fn with_cross_shard_config(mut self, config: CrossShardConfig) -> Self {
+        self.cross_shard_config = config;
+        self
+    }
+
+    pub fn cross_shard_config(&self) -> &CrossShardConfig {
+        &self.cross_shard_config
+    }
+
+    pub fn with_transport_config(mut self, config: TransportConfig) -> Self {
+        self.transport_config = config;
+        self
+    }
+
+    /// Answer `CommitteeInfoRequest`s with the given (public) description.
+    pub fn with_committee_description(mut self, description: String) -> Self {
+        self.committee_description = Some(description);
+        self
+    }
+
+    /// Write a snapshot of the shard to the given file when the server stops.
+    pub fn with_snapshot_on_shutdown(mut self, path: PathBuf) -> Self {
+        self.snapshot_path = Some(path);
+        self
+    }
+
+    /// Record the changes to the shard in a write-ahead log at the given path. When the
+    /// server starts, the changes already in the log are applied again, and the
+    /// cross-shard updates that may not have been sent are sent again.
+    pub fn with_write_ahead_log(mut self, path: PathBuf) -> Self {
+        self.wal_path = Some(path);
+        self
+    }
+
+    /// Record the changes to the shard in the given log, which is not replayed.
+    pub fn with_record_log(mut self, log: Box<dyn RecordLog>) -> Self {
+        self.record_log = Some(log);
+        self
+    }
+
+    /// Development mode: let clients reset the shard to the accounts of the given
+    /// initial accounts file, discarding all the changes made since.
+    pub fn with_dev_accounts_reload(mut self, initial_accounts_path: String) -> Self {
+        self.dev_initial_accounts = Some(initial_accounts_path);
+        self
+    }
+
+    /// Replace the accounts of the shard with those of a snapshot.
+    pub fn restore_snapshot(&mut self, bytes: &[u8]) -> Result<(), FastPayError> {
+        persistence::restore_snapshot(&mut self.state, bytes)
+    }
+
+    /// Keep the shard in the given file across restarts: load it if it exists, otherwise
+    /// create it with the current (initial) accounts, and write it again on shutdown.
+    pub fn with_state_file(mut self, path: PathBuf) -> Result<Self, failure::Error> {
+        if path.exists() {
+            self.state.load(&path)?;
+            info!("{}Loaded state from {}", self.log_prefix(), path.display());
+        } else {
+            self.state.persist(&path)?;
+            info!(
+                "{}No state in {}, starting from the initial accounts",
+                self.log_prefix(),
+                path.display()
+            );
+        }
+        Ok(self.with_snapshot_on_shutdown(path))
+    }
+
+    /// Name of the snapshot file of this shard, distinct for each tenant.
+    pub fn snapshot_file_name(&self) -> String {
+        match &self.tenant {
+            Some(tenant) => format!("{}-shard{}.snapshot", tenant, self.state.shard_id),
+            None => format!("shard{}.snapshot", self.state.shard_id),
+        }
+    }
+
+    /// Name of the write-ahead log of this shard, distinct for each tenant.
+    pub fn wal_file_name(&self) -> String {
+        match &self.tenant {
+            Some(tenant) => format!("{}-shard{}.wal", tenant, self.state.shard_id),
+            None => format!("shard{}.wal", self.state.shard_id),
+        }
+    }
+
+    pub fn tenant(&self) -> Option<&str> {
+        self.tenant.as_deref()
+    }
+
+    fn log_prefix(&self) -> String {
+        let mut prefix = String::new();
+        if let Some(tenant) = &self.tenant {
+            prefix += &format!("[{}] ", tenant);
+        }
+        if let Some(label) = &self.label {
+            prefix += &format!("{} ", label);
+        }
+        prefix
+    }
+
     pub fn packets_processed(&self) -> u64 {
-        self.packets_processed
+        self.stats
+            .counters
+            .packets_processed
+            .load(Ordering::Relaxed)
     }
 
     pub fn user_errors(&self) -> u64 {
-        self.user_errors
+        self.stats.counters.user_errors.load(Ordering::Relaxed)
     }
 
+    /// Handle on the statistics of this server, to be kept after spawning it.
+    pub fn stats(&self) -> ServerStats {
+        self.stats.clone()
+    }
+
+    #[allow(clippy::too_many_arguments)]
     async fn forward_cross_shard_queries(
+        log_prefix: String,
+        log_detail: LogDetail,
         network_protocol: NetworkProtocol,
         base_address: String,
         base_port: u32,
         this_shard: ShardId,
-        mut receiver: mpsc::Receiver<(Vec<u8>, ShardId)>,
+        transport_config: TransportConfig,
+        cross_shard_config: CrossShardConfig,
+        stats: ServerStats,
+        wal: Option<SharedRecordLog>,
+        mut receiver: mpsc::Receiver<CrossShardMessage>,
     ) {
         let mut pool = network_protocol
-            .make_outgoing_connection_pool()
+            .make_outgoing_connection_pool_with(transport_config, cross_shard_config.pool_config())
             .await
             .expect("Initialization should not fail");
 
         let mut queries_sent = 0u64;
-        while let Some((buf, shard)) = receiver.next().await {
+        while let Some((buf, shard, (sender, sequence_number))) = receiver.next().await {
             // Send cross-shard query.
-            let remote_address = format!("{}:{}", base_address, base_port + shard);
-            let status = pool.send_data_to(&buf, &remote_address).await;
+            let remote_address = socket_address(&base_address, base_port + shard);
+            let mut status = pool.send_data_to(&buf, &remote_address).await;
+            let mut backoff = cross_shard_config.backoff();
+            let mut retries = 0;
+            while status.is_err() && retries < cross_shard_config.retries {
+                tokio::time::delay_for(backoff.next_delay()).await;
+                retries += 1;
+                status = pool.send_data_to(&buf, &remote_address).await;
+            }
+            stats
+                .counters
+                .pending_cross_shard_messages
+                .fetch_sub(1, Ordering::Relaxed);
             if let Err(error) = status {
-                error!("Failed to send cross-shard query: {}", error);
+                error!(
+                    "{}Failed to send the cross-shard update of transfer {} from {} to shard {} after {} retries: {}",
+                    log_prefix,
+                    u64::from(sequence_number),
+                    log_detail.address(&sender),
+                    shard,
+                    retries,
+                    error
+                );
             } else {
-                debug!("Sent cross shard query: {} -> {}", this_shard, shard);
+                debug!(
+                    "{}Sent cross shard query: {} -> {}",
+                    log_prefix, this_shard, shard
+                );
+                if let Some(wal) = &wal {
+                    let record = WalRecord::CrossShardSent {
+                        sender,
+                        sequence_number,
+                    };
+                    // At worst, the update is sent again after a restart.
+                    if let Err(error) = wal.lock().unwrap().append(&record) {
+                        warn!(
+                            "{}Failed to write to the write-ahead log: {}",
+                            log_prefix, error
+                        );
+                    }
+                }
+                stats
+                    .counters
+                    .cross_shard_messages_sent
+                    .fetch_add(1, Ordering::Relaxed);
                 queries_sent += 1;
                 if queries_sent % 2000 == 0 {
                     info!(
-                        "{}:{} (shard {}) has sent {} cross-shard queries",
+                        "{}{}:{} (shard {}) has sent {} cross-shard queries",
+                        log_prefix,
                         base_address,
                         base_port + this_shard,
                         this_shard,
@@ -86,42 +745,269 @@ impl Server {
         }
     }
 
-    pub async fn spawn(self) -> Result<SpawnedServer, io::Error> {
+    /// The message sending the given update to the shard of the recipient.
+    fn cross_shard_message(&self, update: CrossShardUpdate) -> Vec<u8> {
+        if self.state.sign_cross_shard_updates {
+            serialize_signed_cross_shard(&SignedCrossShardUpdate::new(update, &self.state.secret))
+        } else {
+            serialize_cross_shard(&update)
+        }
+    }
+
+    pub async fn spawn(mut self) -> Result<SpawnedServer, io::Error> {
         info!(
-            "Listening to {} traffic on {}:{}",
+            "{}Listening to {} traffic on {}:{}",
+            self.log_prefix(),
             self.network_protocol,
             self.base_address,
             self.base_port + self.state.shard_id
         );
-        let address = format!(
-            "{}:{}",
-            self.base_address,
-            self.base_port + self.state.shard_id
-        );
+        let address = socket_address(&self.base_address, self.base_port + self.state.shard_id);
+
+        let (wal, unsent_updates) = match &self.wal_path {
+            Some(path) => {
+                let (wal, records) = WriteAheadLog::open(path)?;
+                let unsent_updates = wal::replay(&mut self.state, &records);
+                info!(
+                    "{}Replayed {} records of {}",
+                    self.log_prefix(),
+                    records.len(),
+                    path.display()
+                );
+                let wal: Box<dyn RecordLog> = Box::new(wal);
+                (Some(wal), unsent_updates)
+            }
+            None => (self.record_log.take(), Vec::new()),
+        };
+        let wal = wal.map(|wal| Arc::new(Mutex::new(wal)));
 
-        let (cross_shard_sender, cross_shard_receiver) = mpsc::channel(self.cross_shard_queue_size);
+        let (mut cross_shard_sender, cross_shard_receiver) =
+            mpsc::channel(self.cross_shard_queue_size);
         tokio::spawn(Self::forward_cross_shard_queries(
+            self.log_prefix(),
+            self.state.log_detail,
             self.network_protocol,
             self.base_address.clone(),
             self.base_port,
             self.state.shard_id,
+            self.transport_config,
+            self.cross_shard_config.clone(),
+            self.stats(),
+            wal.clone(),
             cross_shard_receiver,
         ));
+        for update in unsent_updates {
+            let key = update.transfer_certificate.key();
+            let shard = update.shard_id;
+            let message = self.cross_shard_message(update);
+            self.stats
+                .counters
+                .pending_cross_shard_messages
+                .fetch_add(1, Ordering::Relaxed);
+            cross_shard_sender
+                .send((message, shard, key))
+                .await
+                .expect("internal channel should not fail");
+        }
 
         let buffer_size = self.buffer_size;
         let protocol = self.network_protocol;
+        let transport_config = self.transport_config;
         let state = RunningServerState {
             server: self,
             cross_shard_sender,
+            wal,
+            unlogged_records: VecDeque::new(),
+            held_cross_shard_messages: Vec::new(),
+            stopped: false,
         };
         // Launch server for the appropriate protocol.
-        protocol.spawn_server(&address, state, buffer_size).await
+        protocol
+            .spawn_server(&address, state, buffer_size, transport_config)
+            .await
     }
+
+    /// Run the server on a dedicated thread with its own runtime, so that a panic while
+    /// serving this shard is contained and reported instead of affecting the other shards.
+    /// The server stops when `shutdown` completes, waiting for the messages being handled
+    /// for at most `grace_period`.
+    pub fn spawn_isolated<F>(
+        self,
+        shutdown: F,
+        grace_period: Duration,
+    ) -> io::Result<std::thread::JoinHandle<Result<(), String>>>
+    where
+        F: futures::Future<Output = ()> + Send + 'static,
+    {
+        let name = format!("{}shard {}", self.log_prefix(), self.state.shard_id);
+        run_isolated(name, move || async move {
+            self.spawn().await?.run_until(shutdown, grace_period).await
+        })
+    }
+}
+
+/// Run a task to completion on a new thread with its own runtime. Errors and panics are
+/// logged and returned as a message when the thread is joined.
+pub fn run_isolated<F, Fut>(
+    name: String,
+    task: F,
+) -> io::Result<std::thread::JoinHandle<Result<(), String>>>
+where
+    F: FnOnce() -> Fut + Send + 'static,
+    Fut: futures::Future<Output = Result<(), io::Error>>,
+{
+    std::thread::Builder::new()
+        .name(name.clone())
+        .spawn(move || {
+            let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+                let mut runtime = tokio::runtime::Runtime::new()?;
+                runtime.block_on(task())
+            }));
+            let result = match result {
+                Ok(Ok(())) => return Ok(()),
+                Ok(Err(error)) => format!("{} ended with an error: {}", name, error),
+                Err(payload) => {
+                    let message = match payload.downcast_ref::<&str>() {
+                        Some(message) => message.to_string(),
+                        None => match payload.downcast_ref::<String>() {
+                            Some(message) => message.clone(),
+                            None => "unknown panic".to_string(),
+                        },
+                    };
+                    format!("{} panicked: {}", name, message)
+                }
+            };
+            error!("{}", result);
+            Err(result)
+        })
 }
 
 struct RunningServerState {
     server: Server,
-    cross_shard_sender: mpsc::Sender<(Vec<u8>, ShardId)>,
+    cross_shard_sender: mpsc::Sender<CrossShardMessage>,
+    wal: Option<SharedRecordLog>,
+    /// In read-only mode, the changes applied since the storage failed, to be written
+    /// once it recovers.
+    unlogged_records: VecDeque<WalRecord>,
+    /// In read-only mode, the cross-shard updates of confirmations that could not be
+    /// written, to be sent once they are.
+    held_cross_shard_messages: Vec<CrossShardMessage>,
+    /// Set on shutdown, after which messages still arriving on open connections are dropped.
+    stopped: bool,
+}
+
+impl RunningServerState {
+    /// Prepare the record of a change, if there is a write-ahead log.
+    fn wal_record<F>(&self, record: F) -> Option<WalRecord>
+    where
+        F: FnOnce() -> WalRecord,
+    {
+        self.wal.as_ref().map(|_| record())
+    }
+
+    /// Record a change to the shard in the write-ahead log. This must happen before the
+    /// effects of the change leave the shard: if the log cannot be written, the shard
+    /// becomes read-only and the change is kept in memory until the storage recovers.
+    fn log_change(&mut self, record: Option<WalRecord>) -> Result<(), FastPayError> {
+        let (wal, record) = match (&self.wal, record) {
+            (Some(wal), Some(record)) => (wal, record),
+            _ => return Ok(()),
+        };
+        if self.server.stats.read_only() {
+            // Records must be written in order.
+            self.unlogged_records.push_back(record);
+            return Err(FastPayError::StorageUnavailable);
+        }
+        if let Err(error) = wal.lock().unwrap().append(&record) {
+            error!(
+                "{}Failed to write to the write-ahead log, only serving reads from now on: {}",
+                self.server.log_prefix(),
+                error
+            );
+            let counters = &self.server.stats.counters;
+            counters.storage_failures.fetch_add(1, Ordering::Relaxed);
+            counters.read_only.store(true, Ordering::Relaxed);
+            self.unlogged_records.push_back(record);
+            return Err(FastPayError::StorageUnavailable);
+        }
+        Ok(())
+    }
+
+    /// Check that the shard accepts the given message. In read-only mode, writes are only
+    /// accepted once the pending records could be written: the shard then leaves
+    /// read-only mode and sends the cross-shard updates that were held back.
+    async fn check_writable(&mut self, message: &SerializedMessage) -> Result<(), FastPayError> {
+        if !self.server.stats.read_only() || !is_client_write(message) {
+            return Ok(());
+        }
+        if let Some(wal) = &self.wal {
+            let mut wal = wal.lock().unwrap();
+            while let Some(record) = self.unlogged_records.front() {
+                if wal.append(record).is_err() {
+                    return Err(FastPayError::StorageUnavailable);
+                }
+                self.unlogged_records.pop_front();
+            }
+        }
+        self.server
+            .stats
+            .counters
+            .read_only
+            .store(false, Ordering::Relaxed);
+        info!(
+            "{}The write-ahead log can be written again, accepting writes",
+            self.server.log_prefix()
+        );
+        for message in std::mem::take(&mut self.held_cross_shard_messages) {
+            self.cross_shard_sender
+                .send(message)
+                .await
+                .expect("internal channel should not fail");
+        }
+        Ok(())
+    }
+
+    fn reload_accounts(&mut self) -> Result<ReloadAccountsResponse, FastPayError> {
+        let path = self
+            .server
+            .dev_initial_accounts
+            .as_ref()
+            .ok_or(FastPayError::DevModeRequired)?;
+        let state = &self.server.state;
+        let accounts = InitialStateConfig::read_sharded(path, state.number_of_shards, 1)
+            .map_err(|error| FastPayError::AccountsReloadFailed {
+                error: error.to_string(),
+            })?
+            .swap_remove(state.shard_id as usize);
+        warn!(
+            "{}Resetting shard {} to the {} accounts of {}",
+            self.server.log_prefix(),
+            state.shard_id,
+            accounts.len(),
+            path
+        );
+        if let Some(wal) = &self.wal {
+            wal.lock()
+                .unwrap()
+                .clear()
+                .map_err(|_| FastPayError::StorageUnavailable)?;
+        }
+        self.server.state.reset_accounts(accounts);
+        Ok(ReloadAccountsResponse {
+            shard_id: self.server.state.shard_id,
+            num_accounts: self.server.state.accounts.len(),
+        })
+    }
+
+    fn log_cross_shard_error(&self, result: Result<(), FastPayError>) {
+        if let Err(error) = result {
+            error!(
+                "{}Failed to handle cross-shard query: {}",
+                self.server.log_prefix(),
+                error
+            );
+        }
+    }
 }
 
 impl MessageHandler for RunningServerState {
@@ -130,42 +1016,118 @@ impl MessageHandler for RunningServerState {
         buffer: &'a [u8],
     ) -> futures::future::BoxFuture<'a, Option<Vec<u8>>> {
         Box::pin(async move {
-            let result = deserialize_message(buffer);
+            if self.stopped {
+                return None;
+            }
+            let result = deserialize_message_with_mode(buffer, self.server.deserialization_mode);
             let reply = match result {
-                Err(_) => Err(FastPayError::InvalidDecoding),
-                Ok(result) => {
-                    match result {
-                        SerializedMessage::Order(message) => self
-                            .server
-                            .state
-                            .handle_transfer_order(*message)
-                            .map(|info| Some(serialize_info_response(&info))),
+                Err(_) => match message_tag(buffer) {
+                    Some(tag) if tag >= NUM_MESSAGE_TYPES => {
+                        self.server
+                            .stats
+                            .counters
+                            .unknown_message_types
+                            .fetch_add(1, Ordering::Relaxed);
+                        if self.server.unknown_message_policy == UnknownMessagePolicy::Ignore {
+                            return None;
+                        }
+                        Err(FastPayError::UnknownMessageType { tag })
+                    }
+                    _ => Err(FastPayError::InvalidDecoding),
+                },
+                Ok(result) => match self.check_writable(&result).await {
+                    Err(error) => Err(error),
+                    Ok(()) => match result {
+                        SerializedMessage::Order(message) => {
+                            let key = message.transfer.key();
+                            let record = self.wal_record(|| WalRecord::Order((*message).clone()));
+                            let time_start = Instant::now();
+                            let mut result = self.server.state.handle_transfer_order(*message);
+                            self.server
+                                .stats
+                                .counters
+                                .verification_time
+                                .observe(time_start.elapsed());
+                            if result.is_ok() {
+                                if let Err(error) = self.log_change(record) {
+                                    result = Err(error);
+                                }
+                            }
+                            if let Err(FastPayError::PreviousTransferMustBeConfirmedFirst {
+                                pending_confirmation,
+                            }) = &result
+                            {
+                                if pending_confirmation.transfer.key() == key {
+                                    self.server
+                                        .stats
+                                        .counters
+                                        .conflicting_orders
+                                        .fetch_add(1, Ordering::Relaxed);
+                                }
+                            }
+                            result.map(|info| {
+                                self.server.stats.record_vote(&info);
+                                Some(serialize_info_response(&info))
+                            })
+                        }
                         SerializedMessage::Cert(message) => {
                             let confirmation_order = ConfirmationOrder {
                                 transfer_certificate: message.as_ref().clone(),
                             };
-                            match self
+                            let time_start = Instant::now();
+                            let result = self
                                 .server
                                 .state
-                                .handle_confirmation_order(confirmation_order)
-                            {
+                                .handle_confirmation_order(confirmation_order);
+                            self.server
+                                .stats
+                                .counters
+                                .verification_time
+                                .observe(time_start.elapsed());
+                            match result {
                                 Ok((info, send_shard)) => {
-                                    // Send a message to other shard
+                                    let record = self
+                                        .wal_record(|| WalRecord::Confirmation((*message).clone()));
+                                    let logged = self.log_change(record);
+                                    // Send a message to other shard, once the confirmation
+                                    // is written.
                                     if let Some(cross_shard_update) = send_shard {
                                         let shard = cross_shard_update.shard_id;
-                                        let tmp_out = serialize_cross_shard(&message);
+                                        let key = message.key();
+                                        let tmp_out =
+                                            self.server.cross_shard_message(cross_shard_update);
                                         debug!(
-                                            "Scheduling cross shard query: {} -> {}",
-                                            self.server.state.shard_id, shard
+                                            "{}Scheduling cross shard query: {} -> {}",
+                                            self.server.log_prefix(),
+                                            self.server.state.shard_id,
+                                            shard
                                         );
-                                        self.cross_shard_sender
-                                            .send((tmp_out, shard))
-                                            .await
-                                            .expect("internal channel should not fail");
+                                        self.server
+                                            .stats
+                                            .counters
+                                            .pending_cross_shard_messages
+                                            .fetch_add(1, Ordering::Relaxed);
+                                        if logged.is_ok() {
+                                            self.cross_shard_sender
+                                                .send((tmp_out, shard, key))
+                                                .await
+                                                .expect("internal channel should not fail");
+                                        } else {
+                                            self.held_cross_shard_messages
+                                                .push((tmp_out, shard, key));
+                                        }
                                     };
 
                                     // Response
-                                    Ok(Some(serialize_info_response(&info)))
+                                    logged.map(|()| {
+                                        self.server
+                                            .stats
+                                            .counters
+                                            .transfers_confirmed
+                                            .fetch_add(1, Ordering::Relaxed);
+                                        self.server.stats.record_confirmation(&info);
+                                        Some(serialize_info_response(&info))
+                                    })
                                 }
                                 Err(error) => Err(error),
                             }
@@ -176,44 +1138,192 @@ impl MessageHandler for RunningServerState {
                             .handle_account_info_request(*message)
                             .map(|info| Some(serialize_info_response(&info))),
                         SerializedMessage::CrossShard(message) => {
-                            match self
+                            self.server
+                                .stats
+                                .counters
+                                .cross_shard_messages_received
+                                .fetch_add(1, Ordering::Relaxed);
+                            let record =
+                                self.wal_record(|| WalRecord::CrossShard((*message).clone()));
+                            let result = self
                                 .server
                                 .state
-                                .handle_cross_shard_recipient_commit(*message)
-                            {
-                                Ok(_) => Ok(None), // Nothing to reply
-                                Err(error) => {
-                                    error!("Failed to handle cross-shard query: {}", error);
-                                    Ok(None) // Nothing to reply
+                                .handle_cross_shard_recipient_commit(*message);
+                            if result.is_ok() {
+                                // Kept in memory if the storage fails.
+                                let _ = self.log_change(record);
+                            }
+                            self.log_cross_shard_error(result);
+                            Ok(None) // Nothing to reply
+                        }
+                        SerializedMessage::SignedCrossShard(message) => {
+                            self.server
+                                .stats
+                                .counters
+                                .cross_shard_messages_received
+                                .fetch_add(1, Ordering::Relaxed);
+                            let record =
+                                self.wal_record(|| WalRecord::SignedCrossShard((*message).clone()));
+                            let result = self
+                                .server
+                                .state
+                                .handle_signed_cross_shard_recipient_commit(*message);
+                            if result.is_ok() {
+                                // Kept in memory if the storage fails.
+                                let _ = self.log_change(record);
+                            }
+                            self.log_cross_shard_error(result);
+                            Ok(None) // Nothing to reply
+                        }
+                        SerializedMessage::Ping(message) => {
+                            let pong = Pong {
+                                sent_at: message.sent_at,
+                                received_at: current_time_micros(),
+                                read_only: self.server.stats.read_only(),
+                            };
+                            Ok(Some(serialize_pong(&pong)))
+                        }
+                        SerializedMessage::RecipientCert(message) => {
+                            let record = self.wal_record(|| {
+                                WalRecord::RecipientConfirmation((*message).clone())
+                            });
+                            self.server
+                                .state
+                                .handle_recipient_confirmation_order(ConfirmationOrder {
+                                    transfer_certificate: *message,
+                                })
+                                .and_then(|info| {
+                                    self.log_change(record)?;
+                                    Ok(Some(serialize_info_response(&info)))
+                                })
+                        }
+                        SerializedMessage::ListAccountsReq(message) => self
+                            .server
+                            .state
+                            .handle_list_accounts_request(*message)
+                            .map(|response| Some(serialize_list_accounts_response(&response))),
+                        SerializedMessage::SequenceNumberReq(message) => self
+                            .server
+                            .state
+                            .handle_sequence_number_request(*message)
+                            .map(|statement| Some(serialize_sequence_number_statement(&statement))),
+                        SerializedMessage::CommitteeInfoReq(_) => {
+                            match &self.server.committee_description {
+                                Some(description) => {
+                                    let response = CommitteeInfoResponse::new(
+                                        description.clone(),
+                                        self.server.state.name,
+                                        &self.server.state.secret,
+                                    );
+                                    Ok(Some(serialize_committee_info_response(&response)))
                                 }
+                                None => Err(FastPayError::UnexpectedMessage),
                             }
                         }
+                        SerializedMessage::ReloadAccountsReq(_) => self
+                            .reload_accounts()
+                            .map(|response| Some(serialize_reload_accounts_response(&response))),
+                        SerializedMessage::OwnershipOrder(message) => {
+                            let record =
+                                self.wal_record(|| WalRecord::Ownership((*message).clone()));
+                            self.server
+                                .state
+                                .handle_ownership_order(*message)
+                                .and_then(|info| {
+                                    self.log_change(record)?;
+                                    Ok(Some(serialize_info_response(&info)))
+                                })
+                        }
                         _ => Err(FastPayError::UnexpectedMessage),
-                    }
-                }
+                    },
+                },
             };
 
-            self.server.packets_processed += 1;
-            if self.server.packets_processed % 5000 == 0 {
+            let packets_processed = self
+                .server
+                .stats
+                .counters
+                .packets_processed
+                .fetch_add(1, Ordering::Relaxed)
+                + 1;
+            if packets_processed % 5000 == 0 {
                 info!(
-                    "{}:{} (shard {}) has processed {} packets",
+                    "{}{}:{} (shard {}) has processed {} packets",
+                    self.server.log_prefix(),
                     self.server.base_address,
                     self.server.base_port + self.server.state.shard_id,
                     self.server.state.shard_id,
-                    self.server.packets_processed
+                    packets_processed
                 );
             }
 
             match reply {
                 Ok(x) => x,
                 Err(error) => {
-                    warn!("User query failed: {}", error);
-                    self.server.user_errors += 1;
+                    warn!(
+                        "{}User query failed: {}",
+                        self.server.log_prefix(),
+                        self.server.state.log_detail.error(&error)
+                    );
+                    self.server
+                        .stats
+                        .counters
+                        .user_errors
+                        .fetch_add(1, Ordering::Relaxed);
                     Some(serialize_error(&error))
                 }
             }
         })
     }
+
+    fn shutdown(&mut self) {
+        self.stopped = true;
+        let path = match &self.server.snapshot_path {
+            Some(path) => path,
+            None => return,
+        };
+        match self.server.state.persist(path) {
+            Ok(()) => {
+                info!(
+                    "{}Wrote snapshot of shard {} to {}",
+                    self.server.log_prefix(),
+                    self.server.state.shard_id,
+                    path.display()
+                );
+                // The snapshot now holds the changes recorded so far.
+                if let Some(wal) = &self.wal {
+                    if let Err(error) = wal.lock().unwrap().compact() {
+                        error!(
+                            "{}Failed to compact the write-ahead log: {}",
+                            self.server.log_prefix(),
+                            error
+                        );
+                    }
+                }
+            }
+            Err(error) => error!(
+                "{}Failed to write snapshot to {}: {}",
+                self.server.log_prefix(),
+                path.display(),
+                error
+            ),
+        }
+    }
+}
+
+/// Round-trip measurement obtained by pinging an authority shard.
+#[derive(Clone, Copy, Debug)]
+pub struct PingMeasurement {
+    /// Round-trip time measured with the local clock.
+    pub rtt: Duration,
+    /// Estimated delay from the client to the authority (microseconds).
+    /// Only meaningful if the clocks of both machines are synchronized.
+    pub request_delay_us: i64,
+    /// Estimated delay from the authority back to the client (microseconds).
+    /// Only meaningful if the clocks of both machines are synchronized.
+    pub response_delay_us: i64,
+    /// Whether the shard only serves reads, e.g. after a storage failure.
+    pub read_only: bool,
 }
 
 #[derive(Clone)]
@@ -225,6 +1335,7 @@ pub struct Client {
     buffer_size: usize,
     send_timeout: std::time::Duration,
     recv_timeout: std::time::Duration,
+    transport_config: TransportConfig,
 }
 
 impl Client {
@@ -245,18 +1356,24 @@ impl Client {
             buffer_size,
             send_timeout,
             recv_timeout,
+            transport_config: TransportConfig::default(),
         }
     }
 
+    pub fn with_transport_config(mut self, config: TransportConfig) -> Self {
+        self.transport_config = config;
+        self
+    }
+
     async fn send_recv_bytes_internal(
         &mut self,
         shard: ShardId,
         buf: Vec<u8>,
     ) -> Result<Vec<u8>, io::Error> {
-        let address = format!("{}:{}", self.base_address, self.base_port + shard);
+        let address = socket_address(&self.base_address, self.base_port + shard);
         let mut stream = self
             .network_protocol
-            .connect(address, self.buffer_size)
+            .connect(address, self.buffer_size, self.transport_config)
             .await?;
         // Send message
         time::timeout(self.send_timeout, stream.write_data(&buf)).await??;
@@ -270,6 +1387,9 @@ impl Client {
         buf: Vec<u8>,
     ) -> Result<AccountInfoResponse, FastPayError> {
         match self.send_recv_bytes_internal(shard, buf).await {
+            Err(error) if error.kind() == io::ErrorKind::TimedOut => {
+                Err(FastPayError::RequestTimeout)
+            }
             Err(error) => Err(FastPayError::ClientIoError {
                 error: format!("{}", error),
             }),
@@ -286,6 +1406,176 @@ impl Client {
     }
 }
 
+impl Client {
+    /// Ping the given shard and measure the round-trip time.
+    pub async fn ping(&mut self, shard: ShardId) -> Result<PingMeasurement, FastPayError> {
+        let ping = Ping {
+            sent_at: current_time_micros(),
+        };
+        let time_start = Instant::now();
+        let response = self
+            .send_recv_bytes_internal(shard, serialize_ping(&ping))
+            .await
+            .map_err(|error| FastPayError::ClientIoError {
+                error: format!("{}", error),
+            })?;
+        let rtt = time_start.elapsed();
+        let now = current_time_micros();
+        match deserialize_message(&response[..]) {
+            Ok(SerializedMessage::Pong(pong)) if pong.sent_at == ping.sent_at => {
+                Ok(PingMeasurement {
+                    rtt,
+                    request_delay_us: pong.received_at as i64 - ping.sent_at as i64,
+                    response_delay_us: now as i64 - pong.received_at as i64,
+                    read_only: pong.read_only,
+                })
+            }
+            Ok(SerializedMessage::Error(error)) => Err(*error),
+            Err(_) => Err(FastPayError::InvalidDecoding),
+            _ => Err(FastPayError::UnexpectedMessage),
+        }
+    }
+}
+
+impl Client {
+    /// List a page of the accounts known to the given shard.
+    pub async fn list_accounts(
+        &mut self,
+        shard: ShardId,
+        request: ListAccountsRequest,
+    ) -> Result<ListAccountsResponse, FastPayError> {
+        let response = self
+            .send_recv_bytes_internal(shard, serialize_list_accounts_request(&request))
+            .await
+            .map_err(|error| FastPayError::ClientIoError {
+                error: format!("{}", error),
+            })?;
+        match deserialize_message(&response[..]) {
+            Ok(SerializedMessage::ListAccountsResp(response)) => Ok(*response),
+            Ok(SerializedMessage::Error(error)) => Err(*error),
+            Err(_) => Err(FastPayError::InvalidDecoding),
+            _ => Err(FastPayError::UnexpectedMessage),
+        }
+    }
+}
+
+impl Client {
+    /// Obtain the statement of this authority about a sequence number of an account. The
+    /// signature of the statement is checked against the authority that it names.
+    pub async fn sequence_number_statement(
+        &mut self,
+        request: SequenceNumberRequest,
+    ) -> Result<SequenceNumberStatement, FastPayError> {
+        let shard = AuthorityState::get_shard(self.num_shards, &request.sender);
+        let response = self
+            .send_recv_bytes_internal(shard, serialize_sequence_number_request(&request))
+            .await
+            .map_err(|error| FastPayError::ClientIoError {
+                error: format!("{}", error),
+            })?;
+        match deserialize_message(&response[..]) {
+            Ok(SerializedMessage::SequenceNumberResp(statement)) => {
+                statement.check_signature()?;
+                Ok(*statement)
+            }
+            Ok(SerializedMessage::Error(error)) => Err(*error),
+            Err(_) => Err(FastPayError::InvalidDecoding),
+            _ => Err(FastPayError::UnexpectedMessage),
+        }
+    }
+}
+
+impl Client {
+    /// Ask the given shard what it knows about an account, whether or not the shard owns
+    /// it. Useful to debug the routing of accounts to shards.
+    pub async fn observe_account_info(
+        &mut self,
+        shard: ShardId,
+        sender: FastPayAddress,
+    ) -> Result<AccountInfoResponse, FastPayError> {
+        let request = AccountInfoRequest {
+            sender,
+            request_sequence_number: None,
+            request_received_transfers_excluding_first_nth: None,
+            observer: true,
+        };
+        self.send_recv_bytes(shard, serialize_info_request(&request))
+            .await
+    }
+}
+
+impl Client {
+    /// Reset a shard of a server running in development mode to its initial accounts.
+    pub async fn reload_accounts(
+        &mut self,
+        shard: ShardId,
+    ) -> Result<ReloadAccountsResponse, FastPayError> {
+        let response = self
+            .send_recv_bytes_internal(
+                shard,
+                serialize_reload_accounts_request(&ReloadAccountsRequest),
+            )
+            .await
+            .map_err(|error| FastPayError::ClientIoError {
+                error: format!("{}", error),
+            })?;
+        match deserialize_message(&response[..]) {
+            Ok(SerializedMessage::ReloadAccountsResp(response)) => Ok(*response),
+            Ok(SerializedMessage::Error(error)) => Err(*error),
+            Err(_) => Err(FastPayError::InvalidDecoding),
+            _ => Err(FastPayError::UnexpectedMessage),
+        }
+    }
+}
+
+impl Client {
+    /// Obtain the description of the committee from this authority. The signature of the
+    /// response is checked against the authority that it names.
+    pub async fn committee_info(&mut self) -> Result<CommitteeInfoResponse, FastPayError> {
+        let response = self
+            .send_recv_bytes_internal(0, serialize_committee_info_request(&CommitteeInfoRequest))
+            .await
+            .map_err(|error| FastPayError::ClientIoError {
+                error: format!("{}", error),
+            })?;
+        match deserialize_message(&response[..]) {
+            Ok(SerializedMessage::CommitteeInfoResp(response)) => {
+                response.check_signature()?;
+                Ok(*response)
+            }
+            Ok(SerializedMessage::Error(error)) => Err(*error),
+            Err(_) => Err(FastPayError::InvalidDecoding),
+            _ => Err(FastPayError::UnexpectedMessage),
+        }
+    }
+}
+
+impl Client {
+    /// Change the keys allowed to authorize the transfers of an account.
+    pub async fn handle_ownership_order(
+        &mut self,
+        order: OwnershipOrder,
+    ) -> Result<AccountInfoResponse, FastPayError> {
+        let shard = AuthorityState::get_shard(self.num_shards, &order.change.account);
+        self.send_recv_bytes(shard, serialize_ownership_order(&order))
+            .await
+    }
+
+    /// Credit the recipient of a confirmed transfer using only the recipient's shard.
+    pub async fn handle_recipient_confirmation_order(
+        &mut self,
+        order: ConfirmationOrder,
+    ) -> Result<AccountInfoResponse, FastPayError> {
+        let recipient = match order.transfer_certificate.value.transfer.recipient {
+            Address::FastPay(recipient) => recipient,
+            Address::Primary(_) => return Err(FastPayError::UnexpectedPrimaryRecipient),
+        };
+        let shard = AuthorityState::get_shard(self.num_shards, &recipient);
+        self.send_recv_bytes(shard, serialize_recipient_cert(&order.transfer_certificate))
+            .await
+    }
+}
+
 impl AuthorityClient for Client {
     /// Initiate a new transfer to a FastPay or Primary account.
     fn handle_transfer_order(
@@ -336,6 +1626,7 @@ pub struct MassClient {
     send_timeout: std::time::Duration,
     recv_timeout: std::time::Duration,
     max_in_flight: u64,
+    transport_config: TransportConfig,
 }
 
 impl MassClient {
@@ -356,14 +1647,20 @@ impl MassClient {
             send_timeout,
             recv_timeout,
             max_in_flight,
+            transport_config: TransportConfig::default(),
         }
     }
 
+    pub fn with_transport_config(mut self, config: TransportConfig) -> Self {
+        self.transport_config = config;
+        self
+    }
+
     async fn run_shard(&self, shard: u32, requests: Vec<Bytes>) -> Result<Vec<Bytes>, io::Error> {
-        let address = format!("{}:{}", self.base_address, self.base_port + shard);
+        let address = socket_address(&self.base_address, self.base_port + shard);
         let mut stream = self
             .network_protocol
-            .connect(address, self.buffer_size)
+            .connect(address, self.buffer_size, self.transport_config)
             .await?;
         let mut requests = requests.iter();
         let mut in_flight: u64 = 0;
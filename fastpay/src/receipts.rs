@@ -0,0 +1,88 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use fastpay_core::{
+    base_types::{encode_address_hex, FastPayAddress, SequenceNumber},
+    client::{ReceiptStore, TransferReceipt},
+};
+
+use std::{
+    ffi::OsStr,
+    fs,
+    io::ErrorKind,
+    path::{Path, PathBuf},
+};
+
+#[cfg(test)]
+#[path = "unit_tests/receipts_tests.rs"]
+mod receipts_tests;
+
+/// Transfer receipts saved as JSON files in a directory, one sub-directory per sender
+/// and one file per sequence number, e.g. `<dir>/<sender in hex>/3.json`.
+#[derive(Debug, Clone)]
+pub struct FileReceiptStore {
+    dir: PathBuf,
+}
+
+impl FileReceiptStore {
+    /// Use the given directory, creating it if needed.
+    pub fn open(dir: &Path) -> Result<Self, failure::Error> {
+        fs::create_dir_all(dir)?;
+        Ok(Self {
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    fn account_dir(&self, sender: FastPayAddress) -> PathBuf {
+        self.dir.join(encode_address_hex(&sender))
+    }
+
+    fn receipt_path(&self, sender: FastPayAddress, sequence_number: SequenceNumber) -> PathBuf {
+        self.account_dir(sender)
+            .join(format!("{}.json", u64::from(sequence_number)))
+    }
+
+    /// All the receipts of the given sender, by increasing sequence number.
+    pub fn list(&self, sender: FastPayAddress) -> Result<Vec<TransferReceipt>, failure::Error> {
+        let entries = match fs::read_dir(self.account_dir(sender)) {
+            Ok(entries) => entries,
+            Err(error) if error.kind() == ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(error) => return Err(error.into()),
+        };
+        let mut receipts = Vec::new();
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension() == Some(OsStr::new("json")) {
+                let receipt: TransferReceipt = serde_json::from_slice(&fs::read(&path)?)?;
+                receipts.push(receipt);
+            }
+        }
+        receipts.sort_by_key(|receipt| receipt.key().1);
+        Ok(receipts)
+    }
+}
+
+impl ReceiptStore for FileReceiptStore {
+    fn save(&mut self, receipt: &TransferReceipt) -> Result<(), failure::Error> {
+        let (sender, sequence_number) = receipt.key();
+        fs::create_dir_all(self.account_dir(sender))?;
+        let path = self.receipt_path(sender, sequence_number);
+        // Write to a temporary file first so that a crash never leaves a partial receipt.
+        let temp_path = path.with_extension("json.tmp");
+        fs::write(&temp_path, serde_json::to_vec_pretty(receipt)?)?;
+        fs::rename(&temp_path, &path)?;
+        Ok(())
+    }
+
+    fn get(
+        &self,
+        sender: FastPayAddress,
+        sequence_number: SequenceNumber,
+    ) -> Result<Option<TransferReceipt>, failure::Error> {
+        match fs::read(self.receipt_path(sender, sequence_number)) {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(error) if error.kind() == ErrorKind::NotFound => Ok(None),
+            Err(error) => Err(error.into()),
+        }
+    }
+}
@@ -3,59 +3,129 @@
 
 #![deny(warnings)]
 
-use fastpay::{config::*, network, transport};
-use fastpay_core::{authority::*, base_types::*, committee::Committee};
+use fastpay::{config::*, metrics, network, transport};
+use fastpay_core::{
+    authority::*,
+    base_types::*,
+    messages::committee_fingerprint,
+    persistence::{diff_snapshots, AccountDiff},
+    redaction::LogDetail,
+    serialize::{DeserializationMode, UnknownMessagePolicy},
+    verification_cache::VerificationCache,
+};
 
-use futures::future::join_all;
+use futures::{
+    channel::oneshot,
+    future::{self, join_all, Either, FutureExt},
+};
 use log::*;
+use rand::SeedableRng;
+use rand_chacha::ChaChaRng;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    path::Path,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
 use structopt::StructOpt;
 use tokio::runtime::Runtime;
 
+/// How long to wait for each other authority when comparing committee configs at startup.
+const COMMITTEE_CHECK_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Complete on the first Ctrl-C (SIGINT) or SIGTERM.
+#[cfg(unix)]
+async fn shutdown_requested() -> std::io::Result<()> {
+    let mut terminate = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    // The futures borrow `terminate`, so the outcome is extracted before returning.
+    let result = match future::select(
+        Box::pin(tokio::signal::ctrl_c()),
+        Box::pin(terminate.recv()),
+    )
+    .await
+    {
+        Either::Left((result, _)) => result,
+        Either::Right(_) => Ok(()),
+    };
+    result
+}
+
+/// Complete on the first Ctrl-C.
+#[cfg(not(unix))]
+async fn shutdown_requested() -> std::io::Result<()> {
+    tokio::signal::ctrl_c().await
+}
+
 #[allow(clippy::too_many_arguments)]
 fn make_shard_server(
     local_ip_addr: &str,
     server_config_path: &str,
     committee_config_path: &str,
-    initial_accounts_config_path: &str,
+    initial_accounts: &BTreeMap<FastPayAddress, Balance>,
     buffer_size: usize,
     cross_shard_queue_size: usize,
+    sign_read_responses: bool,
+    key_passphrase: Option<&str>,
     shard: u32,
 ) -> network::Server {
     let server_config =
         AuthorityServerConfig::read(server_config_path).expect("Fail to read server config");
     let committee_config =
         CommitteeConfig::read(committee_config_path).expect("Fail to read committee config");
-    let initial_accounts_config = InitialStateConfig::read(initial_accounts_config_path)
-        .expect("Fail to read initial account config");
 
-    let committee = Committee::new(committee_config.voting_rights());
+    committee_config
+        .check_features(&server_config)
+        .expect("Incompatible committee features");
+    committee_config
+        .validate()
+        .expect("Invalid committee config");
+    let committee = committee_config.make_committee();
+    let committee_description =
+        serde_json::to_string(&committee_config).expect("Fail to serialize committee config");
     let num_shards = server_config.authority.num_shards;
 
     let mut state = AuthorityState::new_shard(
         committee,
         server_config.authority.address,
-        server_config.key.copy(),
+        server_config
+            .key
+            .unlock(key_passphrase)
+            .expect("Fail to unlock server key"),
         shard,
         num_shards,
     );
+    state.sign_read_responses = sign_read_responses;
+    state.large_transfer_threshold = server_config.large_transfer_threshold;
+    state.max_transfer_amount = server_config.max_transfer_amount;
+    state.reject_non_canonical_encodings = !server_config.allow_non_canonical_encodings;
+    state.memory_limit = server_config.memory_limit;
+    state.sign_cross_shard_updates = server_config.sign_cross_shard_updates;
+    state.cross_shard_sources = server_config.cross_shard_sources.clone();
+    state.reuse_confirmed_certificates = !server_config.reverify_confirmed_certificates;
+    state.allow_negative_balances = !server_config.reject_negative_balances;
+    state.log_conflicting_orders = server_config.log_conflicting_orders;
+    if server_config.log_full_details {
+        state.log_detail = LogDetail::Full;
+    }
 
     // Load initial states
-    for (address, balance) in &initial_accounts_config.accounts {
-        if AuthorityState::get_shard(num_shards, address) != shard {
-            continue;
-        }
+    for (address, balance) in initial_accounts {
         let client = AccountOffchainState {
             balance: *balance,
-            next_sequence_number: SequenceNumber::from(0),
-            pending_confirmation: None,
-            confirmed_log: Vec::new(),
-            synchronization_log: Vec::new(),
-            received_log: Vec::new(),
+            ..AccountOffchainState::new()
         };
         state.accounts.insert(*address, client);
     }
+    if let Some(rate) = server_config.account_filter_false_positive_rate {
+        state.enable_account_filter(rate);
+    }
+    if let Some(capacity) = server_config.verification_cache_capacity {
+        state.verification_cache = Some(VerificationCache::new(
+            capacity,
+            server_config.verification_cache_max_age,
+        ));
+    }
 
-    network::Server::new(
+    let server = network::Server::new(
         server_config.authority.network_protocol,
         local_ip_addr.to_string(),
         server_config.authority.base_port,
@@ -63,35 +133,69 @@ fn make_shard_server(
         buffer_size,
         cross_shard_queue_size,
     )
+    .with_deserialization_mode(server_config.deserialization_mode)
+    .with_unknown_message_policy(server_config.unknown_message_policy)
+    .with_committee_description(committee_description);
+    match server_config.authority.label {
+        Some(label) => server.with_label(label),
+        None => server,
+    }
 }
 
+/// Make the servers of the given shards, which must have been checked against the
+/// number of shards of the authority.
+#[allow(clippy::too_many_arguments)]
 fn make_servers(
     local_ip_addr: &str,
     server_config_path: &str,
     committee_config_path: &str,
     initial_accounts_config_path: &str,
+    shards: ShardRange,
+    load_threads: usize,
     buffer_size: usize,
     cross_shard_queue_size: usize,
+    sign_read_responses: bool,
+    key_passphrase: Option<&str>,
 ) -> Vec<network::Server> {
-    let server_config =
-        AuthorityServerConfig::read(server_config_path).expect("Fail to read server config");
-    let num_shards = server_config.authority.num_shards;
+    let initial_accounts = read_initial_accounts(
+        server_config_path,
+        initial_accounts_config_path,
+        load_threads,
+    );
 
     let mut servers = Vec::new();
-    for shard in 0..num_shards {
+    for shard in shards.shards() {
         servers.push(make_shard_server(
             local_ip_addr,
             server_config_path,
             committee_config_path,
-            initial_accounts_config_path,
+            &initial_accounts[shard as usize],
             buffer_size,
             cross_shard_queue_size,
+            sign_read_responses,
+            key_passphrase,
             shard,
         ))
     }
     servers
 }
 
+/// Read the initial accounts of each shard of the authority.
+fn read_initial_accounts(
+    server_config_path: &str,
+    initial_accounts_config_path: &str,
+    load_threads: usize,
+) -> Vec<BTreeMap<FastPayAddress, Balance>> {
+    let server_config =
+        AuthorityServerConfig::read(server_config_path).expect("Fail to read server config");
+    InitialStateConfig::read_sharded(
+        initial_accounts_config_path,
+        server_config.authority.num_shards,
+        load_threads,
+    )
+    .expect("Fail to read initial account config")
+}
+
 #[derive(StructOpt)]
 #[structopt(
     name = "FastPay Server",
@@ -100,14 +204,19 @@ fn make_servers(
 struct ServerOpt {
     /// Path to the file containing the server configuration of this FastPay authority (including its secret key)
     #[structopt(long)]
-    server: String,
+    server: Option<String>,
+
+    /// Passphrase protecting the secret key of the server configuration, if it is encrypted
+    #[structopt(long, env = "FASTPAY_KEY_PASSPHRASE", hide_env_values = true)]
+    key_passphrase: Option<String>,
 
-    /// Subcommands. Acceptable values are run and generate.
+    /// Subcommands. Acceptable values are run, generate, verify-config, genesis, reload-accounts and diff-snapshots.
     #[structopt(subcommand)]
     cmd: ServerCommands,
 }
 
 #[derive(StructOpt)]
+#[allow(clippy::large_enum_variant)]
 enum ServerCommands {
     /// Runs a service for each shard of the FastPay authority")
     #[structopt(name = "run")]
@@ -128,9 +237,85 @@ enum ServerCommands {
         #[structopt(long)]
         initial_accounts: String,
 
+        /// Number of threads used to parse the initial user accounts
+        #[structopt(long, default_value = "4")]
+        load_threads: usize,
+
         /// Runs a specific shard (from 0 to shards-1)
         #[structopt(long)]
         shard: Option<u32>,
+
+        /// Runs the shards of a half-open range, e.g. 4..8 for shards 4 to 7
+        #[structopt(long, conflicts_with = "shard")]
+        shard_range: Option<ShardRange>,
+
+        /// Do not sign responses to read-only queries (votes on transfer orders are always signed)
+        #[structopt(long)]
+        unsigned_read_responses: bool,
+
+        /// Path to a file describing the authorities of other committees to run (all shards) in this process
+        #[structopt(long)]
+        tenants: Option<String>,
+
+        /// Maximal number of shards of an authority that may run in this process
+        #[structopt(long, default_value = "256")]
+        max_shards: u32,
+
+        /// Path to a file where to write the report of each shard when the servers stop (JSON)
+        #[structopt(long)]
+        shutdown_report: Option<String>,
+
+        /// Run each shard on its own thread and runtime, so that a panic in one shard does not stop the others
+        #[structopt(long)]
+        isolate_shards: bool,
+
+        /// Directory where each shard writes a snapshot of its accounts when the servers are stopped with Ctrl-C
+        #[structopt(long)]
+        snapshot_on_shutdown: Option<String>,
+
+        /// Directory of the snapshots to restore at startup instead of the initial accounts (shards without a snapshot keep their initial accounts)
+        #[structopt(long)]
+        restore_snapshot: Option<String>,
+
+        /// Directory where each shard keeps its accounts across restarts: loaded at startup if present, otherwise seeded from the initial accounts, and written on shutdown. Replaces --snapshot-on-shutdown and --restore-snapshot
+        #[structopt(long)]
+        state_dir: Option<String>,
+
+        /// Directory where each shard records its changes before replying, and replays them at startup (after restoring the snapshot, if any)
+        #[structopt(long)]
+        write_ahead_log_dir: Option<String>,
+
+        /// Maximal time to wait for the cross-shard messages to be sent after Ctrl-C or SIGTERM, before stopping the servers (milliseconds)
+        #[structopt(long, default_value = "5000")]
+        shutdown_drain_timeout_ms: u64,
+
+        /// Maximal time to wait for the requests being handled once the servers stop accepting new ones (seconds)
+        #[structopt(long, default_value = "5")]
+        shutdown_grace_secs: u64,
+
+        /// What to do when other authorities of the committee run a different committee config: Ignore, Warn, or Refuse to start
+        #[structopt(long, default_value = "Warn")]
+        on_committee_mismatch: network::CommitteeMismatchPolicy,
+
+        /// Development mode: accept reload-accounts commands, which reset the shards to their initial accounts. Never use in production
+        #[structopt(long)]
+        dev: bool,
+
+        #[structopt(flatten)]
+        cross_shard_config: network::CrossShardConfig,
+
+        #[structopt(flatten)]
+        transport_config: transport::TransportConfig,
+
+        #[structopt(flatten)]
+        statsd_config: metrics::StatsdConfig,
+
+        /// Serve Prometheus metrics over HTTP on this port
+        #[structopt(long)]
+        metrics_port: Option<u16>,
+
+        #[structopt(flatten)]
+        committee_size_config: CommitteeSizeConfig,
     },
 
     /// Generate a new server configuration and output its public description
@@ -151,6 +336,115 @@ enum ServerCommands {
         /// Number of shards for this authority
         #[structopt(long)]
         shards: u32,
+
+        /// Encrypt the secret key with the passphrase given by --key-passphrase
+        #[structopt(long)]
+        encrypt_key: bool,
+
+        /// Human-friendly name of the authority, for logs and reports
+        #[structopt(long)]
+        label: Option<String>,
+
+        /// How to reach the operator of the authority
+        #[structopt(long)]
+        operator_info: Option<String>,
+    },
+
+    /// Check that the server configuration is consistent with a committee and initial accounts
+    #[structopt(name = "verify-config")]
+    VerifyConfig {
+        /// Path to the file containing the public description of all authorities in this FastPay committee
+        #[structopt(long)]
+        committee: String,
+
+        /// Path to the file describing the initial user accounts
+        #[structopt(long)]
+        initial_accounts: String,
+    },
+
+    /// Generate all the configuration files of a local committee, with funded accounts (--server is not used)
+    #[structopt(name = "genesis")]
+    Genesis {
+        /// Directory where to write the configuration files
+        #[structopt(long)]
+        output_dir: String,
+
+        /// Chooses a network protocol between Udp and Tcp
+        #[structopt(long, default_value = "Udp")]
+        protocol: transport::NetworkProtocol,
+
+        /// Sets the public name of the host of all authorities
+        #[structopt(long, default_value = "127.0.0.1")]
+        host: String,
+
+        /// Sets the port of the first shard of the first authority; the other shards use the next ports
+        #[structopt(long, default_value = "9100")]
+        port: u32,
+
+        /// Number of authorities in the committee
+        #[structopt(long, default_value = "4")]
+        authorities: u32,
+
+        /// Number of shards for each authority
+        #[structopt(long, default_value = "4")]
+        shards: u32,
+
+        /// Number of funded accounts
+        #[structopt(long, default_value = "100")]
+        accounts: u32,
+
+        /// Initial balance of each account, in base units
+        #[structopt(long, default_value = "1000")]
+        initial_funding: i128,
+
+        /// Seed the generation of keys to write the same files on every run (the keys are predictable: for tests only)
+        #[structopt(long)]
+        seed: Option<u64>,
+
+        /// Identifier of the deployment, signed with every transfer so that orders cannot be replayed on other deployments
+        #[structopt(long, default_value = "0")]
+        chain_id: u64,
+
+        /// Start of the deployment in milliseconds since the Unix epoch, also signed with every transfer [default: now, or 0 with --seed]
+        #[structopt(long)]
+        genesis_timestamp: Option<u64>,
+
+        /// Print the files that would be written, and what they describe, without writing them
+        #[structopt(long)]
+        dry_run: bool,
+
+        #[structopt(flatten)]
+        committee_size_config: CommitteeSizeConfig,
+    },
+
+    /// Reset all the shards of a server running in development mode (run --dev) to their initial accounts
+    #[structopt(name = "reload-accounts")]
+    ReloadAccounts {
+        /// Maximum size of datagrams received and sent (bytes)
+        #[structopt(long, default_value = transport::DEFAULT_MAX_DATAGRAM_SIZE)]
+        buffer_size: usize,
+    },
+
+    /// Print the accounts that differ between two shard snapshots (--server is not used); exits with status 1 if there are any
+    #[structopt(name = "diff-snapshots")]
+    DiffSnapshots {
+        /// Path to the old snapshot
+        old: String,
+
+        /// Path to the new snapshot
+        new: String,
+    },
+
+    /// Measure the speed of signing and verifying transfer orders on this host, one at a time and in batches (--server is not used)
+    #[structopt(name = "bench-crypto")]
+    BenchCrypto {
+        /// Number of orders to sign and verify
+        #[structopt(long, default_value = "1000")]
+        samples: usize,
+
+        /// Sizes of the batches of orders verified together
+        #[structopt(long, default_value = "1,8,32,128", use_delimiter = true)]
+        batch_sizes: Vec<usize>,
     },
 }
 
@@ -158,7 +452,13 @@ fn main() {
     env_logger::from_env(env_logger::Env::default().default_filter_or("info")).init();
     let options = ServerOpt::from_args();
 
-    let server_config_path = &options.server;
+    let server_path = options.server;
+    let server_config_path = || {
+        server_path
+            .as_deref()
+            .expect("The path of the server config is required (--server)")
+    };
+    let key_passphrase = options.key_passphrase.as_deref();
 
     match options.cmd {
         ServerCommands::Run {
@@ -166,53 +466,332 @@ fn main() {
             cross_shard_queue_size,
             committee,
             initial_accounts,
+            load_threads,
             shard,
+            shard_range,
+            unsigned_read_responses,
+            tenants,
+            max_shards,
+            shutdown_report,
+            isolate_shards,
+            snapshot_on_shutdown,
+            restore_snapshot,
+            state_dir,
+            write_ahead_log_dir,
+            shutdown_drain_timeout_ms,
+            shutdown_grace_secs,
+            on_committee_mismatch,
+            dev,
+            cross_shard_config,
+            transport_config,
+            statsd_config,
+            metrics_port,
+            committee_size_config,
         } => {
+            let committee_config =
+                CommitteeConfig::read(&committee).expect("Fail to read committee config");
+            if let Err(err) = committee_size_config.check(&committee_config.make_committee()) {
+                error!("Invalid committee config: {}", err);
+                return;
+            }
+            if state_dir.is_some() && (snapshot_on_shutdown.is_some() || restore_snapshot.is_some())
+            {
+                error!("--state-dir cannot be combined with --snapshot-on-shutdown or --restore-snapshot");
+                return;
+            }
+
             // Run the server
-            let servers = match shard {
-                Some(shard) => {
+            let server_config = AuthorityServerConfig::read(server_config_path())
+                .expect("Fail to read server config");
+            let num_shards = server_config.authority.num_shards;
+            let shards = match (shard, shard_range) {
+                (Some(shard), _) => {
                     info!("Running shard number {}", shard);
-                    let server = make_shard_server(
-                        "0.0.0.0", // Allow local IP address to be different from the public one.
-                        server_config_path,
-                        &committee,
-                        &initial_accounts,
-                        buffer_size,
-                        cross_shard_queue_size,
-                        shard,
-                    );
-                    vec![server]
+                    ShardRange::single(shard)
+                }
+                (None, Some(range)) => {
+                    info!("Running shards {}", range);
+                    range
                 }
-                None => {
+                (None, None) => {
+                    if let Err(err) = server_config.authority.check_num_shards(max_shards) {
+                        error!("Invalid server config: {}", err);
+                        return;
+                    }
                     info!("Running all shards");
-                    make_servers(
-                        "0.0.0.0", // Allow local IP address to be different from the public one.
-                        server_config_path,
-                        &committee,
-                        &initial_accounts,
+                    ShardRange::all(num_shards)
+                }
+            };
+            if let Err(err) = shards.check(num_shards) {
+                error!("Invalid shards: {}", err);
+                return;
+            }
+            let mut servers = make_servers(
+                "0.0.0.0", // Allow local IP address to be different from the public one.
+                server_config_path(),
+                &committee,
+                &initial_accounts,
+                shards,
+                load_threads,
+                buffer_size,
+                cross_shard_queue_size,
+                !unsigned_read_responses,
+                key_passphrase,
+            );
+
+            if dev {
+                warn!("Running in development mode: the accounts may be reset at any time");
+                servers = servers
+                    .into_iter()
+                    .map(|server| server.with_dev_accounts_reload(initial_accounts.clone()))
+                    .collect();
+            }
+
+            if let Some(tenants) = tenants {
+                let tenants_config =
+                    TenantsConfig::read(&tenants).expect("Fail to read tenants config");
+                let server_config = AuthorityServerConfig::read(server_config_path())
+                    .expect("Fail to read server config");
+                for tenant in &tenants_config.tenants {
+                    let tenant_config = AuthorityServerConfig::read(&tenant.server)
+                        .expect("Fail to read tenant server config");
+                    if let Err(err) = tenant_config.authority.check_num_shards(max_shards) {
+                        error!("Invalid server config of tenant {}: {}", tenant.id, err);
+                        return;
+                    }
+                    let tenant_committee = CommitteeConfig::read(&tenant.committee)
+                        .expect("Fail to read tenant committee config");
+                    if let Err(err) =
+                        committee_size_config.check(&tenant_committee.make_committee())
+                    {
+                        error!("Invalid committee config of tenant {}: {}", tenant.id, err);
+                        return;
+                    }
+                }
+                if let Err(err) = tenants_config.check_isolation(Some(&server_config.authority)) {
+                    error!("Invalid tenants config: {}", err);
+                    return;
+                }
+                for tenant in tenants_config.tenants {
+                    info!("Running all shards of tenant {}", tenant.id);
+                    let tenant_config = AuthorityServerConfig::read(&tenant.server)
+                        .expect("Fail to read tenant server config");
+                    let tenant_servers = make_servers(
+                        "0.0.0.0",
+                        &tenant.server,
+                        &tenant.committee,
+                        &tenant.initial_accounts,
+                        ShardRange::all(tenant_config.authority.num_shards),
+                        load_threads,
                         buffer_size,
                         cross_shard_queue_size,
+                        !unsigned_read_responses,
+                        key_passphrase,
+                    );
+                    servers.extend(tenant_servers.into_iter().map(|server| {
+                        let server = server.with_tenant(tenant.id.clone());
+                        if dev {
+                            server.with_dev_accounts_reload(tenant.initial_accounts.clone())
+                        } else {
+                            server
+                        }
+                    }));
+                }
+            }
+
+            if let Some(dir) = &state_dir {
+                std::fs::create_dir_all(dir).expect("Fail to create the state directory");
+            }
+            if let Some(dir) = &write_ahead_log_dir {
+                std::fs::create_dir_all(dir).expect("Fail to create the write-ahead log directory");
+            }
+            let servers: Vec<_> = servers
+                .into_iter()
+                .map(|server| {
+                    let mut server = server
+                        .with_cross_shard_config(cross_shard_config.clone())
+                        .with_transport_config(transport_config);
+                    if let Some(dir) = &restore_snapshot {
+                        let path = Path::new(dir).join(server.snapshot_file_name());
+                        match std::fs::read(&path) {
+                            Ok(bytes) => {
+                                server
+                                    .restore_snapshot(&bytes)
+                                    .expect("Fail to restore snapshot");
+                                info!("Restored snapshot {}", path.display());
+                            }
+                            Err(err) => {
+                                warn!("No snapshot restored from {}: {}", path.display(), err)
+                            }
+                        }
+                    }
+                    let server = match (&snapshot_on_shutdown, &state_dir) {
+                        (Some(dir), _) => {
+                            let path = Path::new(dir).join(server.snapshot_file_name());
+                            server.with_snapshot_on_shutdown(path)
+                        }
+                        (_, Some(dir)) => {
+                            let path = Path::new(dir).join(server.snapshot_file_name());
+                            server
+                                .with_state_file(path)
+                                .expect("Fail to load or create the state file")
+                        }
+                        (None, None) => server,
+                    };
+                    match &write_ahead_log_dir {
+                        Some(dir) => {
+                            let path = Path::new(dir).join(server.wal_file_name());
+                            server.with_write_ahead_log(path)
+                        }
+                        None => server,
+                    }
+                })
+                .collect();
+            let stats: Vec<_> = servers.iter().map(network::Server::stats).collect();
+            if let Some(address) = &statsd_config.statsd_address {
+                let exporter =
+                    metrics::StatsdExporter::new(address, statsd_config.statsd_prefix.clone())
+                        .expect("Fail to set up the StatsD exporter");
+                exporter
+                    .spawn(
+                        stats.clone(),
+                        Duration::from_millis(statsd_config.statsd_interval_ms),
                     )
+                    .expect("Fail to start the StatsD exporter");
+                info!("Pushing metrics to StatsD at {}", address);
+            }
+            if let Some(port) = metrics_port {
+                let exporter = metrics::PrometheusExporter::bind(
+                    &format!("0.0.0.0:{}", port),
+                    "fastpay".to_string(),
+                )
+                .expect("Fail to set up the Prometheus endpoint");
+                exporter
+                    .spawn(stats.clone())
+                    .expect("Fail to start the Prometheus endpoint");
+                info!("Serving Prometheus metrics on port {}", port);
+            }
+            // On Ctrl-C or SIGTERM, let the shards send their pending cross-shard messages
+            // before stopping them.
+            let drain_timeout = Duration::from_millis(shutdown_drain_timeout_ms);
+            let grace_period = Duration::from_secs(shutdown_grace_secs);
+            let drained_stats = stats.clone();
+            let shutdown_signal = async move {
+                if let Err(err) = shutdown_requested().await {
+                    error!("Failed to listen to shutdown signals: {}", err);
+                    return future::pending().await;
+                }
+                info!("Stopping the servers");
+                if !network::drain_cross_shard_queues(&drained_stats, drain_timeout).await {
+                    warn!("Some cross-shard messages were not sent before shutdown");
                 }
             };
-
             let mut rt = Runtime::new().unwrap();
-            let mut handles = Vec::new();
-            for server in servers {
-                handles.push(async move {
-                    let spawned_server = match server.spawn().await {
-                        Ok(server) => server,
-                        Err(err) => {
-                            error!("Failed to start server: {}", err);
-                            return;
+            if on_committee_mismatch != network::CommitteeMismatchPolicy::Ignore {
+                let peers = rt.block_on(network::check_peer_committees(
+                    &committee_config,
+                    server_config.authority.address,
+                    buffer_size,
+                    COMMITTEE_CHECK_TIMEOUT,
+                ));
+                let fingerprint = committee_fingerprint(
+                    &serde_json::to_string(&committee_config)
+                        .expect("Fail to serialize committee config"),
+                );
+                let mut mismatch = false;
+                for (peer, status) in peers {
+                    match status {
+                        network::PeerCommittee::Same => (),
+                        network::PeerCommittee::Different {
+                            fingerprint: peer_fingerprint,
+                        } => {
+                            mismatch = true;
+                            error!(
+                                "Authority {} runs a different committee config \
+                                 (fingerprint {} instead of {}): quorums may not be reached",
+                                encode_address(&peer),
+                                hex::encode(peer_fingerprint),
+                                hex::encode(fingerprint)
+                            );
                         }
-                    };
-                    if let Err(err) = spawned_server.join().await {
-                        error!("Server ended with an error: {}", err);
+                        network::PeerCommittee::Unavailable { error } => info!(
+                            "Could not compare the committee config with authority {}: {}",
+                            encode_address(&peer),
+                            error
+                        ),
+                    }
+                }
+                if mismatch && on_committee_mismatch == network::CommitteeMismatchPolicy::Refuse {
+                    error!("Refusing to start with a committee config that differs from other authorities");
+                    return;
+                }
+            }
+            if isolate_shards {
+                // Failures are logged by each thread.
+                let mut stop_senders = Vec::new();
+                let threads: Vec<_> = servers
+                    .into_iter()
+                    .filter_map(|server| {
+                        let (sender, receiver) = oneshot::channel::<()>();
+                        stop_senders.push(sender);
+                        match server.spawn_isolated(receiver.map(|_| ()), grace_period) {
+                            Ok(thread) => Some(thread),
+                            Err(err) => {
+                                error!("Failed to start server thread: {}", err);
+                                None
+                            }
+                        }
+                    })
+                    .collect();
+                rt.block_on(async move {
+                    let joined = tokio::task::spawn_blocking(move || {
+                        for thread in threads {
+                            let _ = thread.join();
+                        }
+                    });
+                    if let Either::Right((_, joined)) =
+                        future::select(joined, shutdown_signal.boxed()).await
+                    {
+                        for sender in stop_senders {
+                            let _ = sender.send(());
+                        }
+                        let _ = joined.await;
                     }
                 });
+            } else {
+                let shutdown_signal = shutdown_signal.boxed().shared();
+                let mut handles = Vec::new();
+                for server in servers {
+                    let shutdown_signal = shutdown_signal.clone();
+                    handles.push(async move {
+                        let spawned_server = match server.spawn().await {
+                            Ok(server) => server,
+                            Err(err) => {
+                                error!("Failed to start server: {}", err);
+                                return;
+                            }
+                        };
+                        if let Err(err) = spawned_server
+                            .run_until(shutdown_signal, grace_period)
+                            .await
+                        {
+                            error!("Server ended with an error: {}", err);
+                        }
+                    });
+                }
+                rt.block_on(join_all(handles));
+            }
+
+            let reports: Vec<_> = stats.iter().map(network::ServerStats::report).collect();
+            for report in &reports {
+                info!("{}", report);
+            }
+            if let Some(path) = shutdown_report {
+                let file = std::fs::File::create(&path).expect("Unable to create report file");
+                serde_json::to_writer_pretty(file, &reports)
+                    .expect("Unable to write shutdown report");
+                info!("Wrote shutdown report to {}", path);
             }
-            rt.block_on(join_all(handles));
         }
 
         ServerCommands::Generate {
@@ -220,21 +799,220 @@ fn main() {
             host,
             port,
             shards,
+            encrypt_key,
+            label,
+            operator_info,
         } => {
             let (address, key) = get_key_pair();
+            let key = if encrypt_key {
+                let passphrase =
+                    key_passphrase.expect("A passphrase is required to encrypt the key");
+                AuthorityKey::Encrypted(key.encrypt(passphrase))
+            } else {
+                AuthorityKey::Plain(key)
+            };
             let authority = AuthorityConfig {
                 network_protocol: protocol,
                 address,
                 host,
                 base_port: port,
                 num_shards: shards,
+                label,
+                operator_info,
+            };
+            let server = AuthorityServerConfig {
+                version: CONFIG_VERSION,
+                authority,
+                key,
+                large_transfer_threshold: None,
+                max_transfer_amount: None,
+                allow_non_canonical_encodings: false,
+                deserialization_mode: DeserializationMode::default(),
+                features: BTreeSet::new(),
+                account_filter_false_positive_rate: None,
+                memory_limit: None,
+                sign_cross_shard_updates: false,
+                cross_shard_sources: None,
+                verification_cache_capacity: None,
+                verification_cache_max_age: None,
+                unknown_message_policy: UnknownMessagePolicy::default(),
+                reverify_confirmed_certificates: false,
+                reject_negative_balances: false,
+                log_full_details: false,
+                log_conflicting_orders: false,
             };
-            let server = AuthorityServerConfig { authority, key };
             server
-                .write(server_config_path)
+                .write(server_config_path())
                 .expect("Unable to write server config file");
             info!("Wrote server config file");
             server.authority.print();
         }
+
+        ServerCommands::VerifyConfig {
+            committee,
+            initial_accounts,
+        } => {
+            let server_config = AuthorityServerConfig::read(server_config_path())
+                .expect("Fail to read server config");
+            let committee_config =
+                CommitteeConfig::read(&committee).expect("Fail to read committee config");
+            InitialStateConfig::read(&initial_accounts)
+                .expect("Fail to read initial account config");
+            if let Err(err) = committee_config.check_protocols() {
+                warn!("Committee config mixes network protocols: {}", err);
+            }
+            let result = committee_config.check_port_ranges().and_then(|()| {
+                committee_config.check_server_config(&server_config, key_passphrase)
+            });
+            match result {
+                Ok(()) => info!("Server config is valid"),
+                Err(err) => {
+                    error!("Invalid server config: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        ServerCommands::Genesis {
+            output_dir,
+            protocol,
+            host,
+            port,
+            authorities,
+            shards,
+            accounts,
+            initial_funding,
+            seed,
+            chain_id,
+            genesis_timestamp,
+            dry_run,
+            committee_size_config,
+        } => {
+            let mut rng = match seed {
+                Some(seed) => ChaChaRng::seed_from_u64(seed),
+                None => ChaChaRng::from_entropy(),
+            };
+            let mut genesis = GenesisConfig::generate_with_rng(
+                protocol,
+                &host,
+                port,
+                authorities,
+                shards,
+                accounts,
+                Balance::from(initial_funding),
+                &mut rng,
+            );
+            genesis.committee.chain_id = chain_id;
+            genesis.committee.genesis_timestamp = genesis_timestamp.unwrap_or_else(|| {
+                if seed.is_some() {
+                    0
+                } else {
+                    SystemTime::now()
+                        .duration_since(UNIX_EPOCH)
+                        .expect("The clock should be after the Unix epoch")
+                        .as_millis() as u64
+                }
+            });
+            if let Err(err) = committee_size_config.check(&genesis.committee.make_committee()) {
+                error!("Invalid committee config: {}", err);
+                std::process::exit(1);
+            }
+            if dry_run {
+                print!("{}", genesis.summary(Path::new(&output_dir)));
+                return;
+            }
+            genesis
+                .write(Path::new(&output_dir))
+                .expect("Unable to write genesis config files");
+            info!(
+                "Wrote the configuration of {} authorities to {}",
+                authorities, output_dir
+            );
+            println!(
+                "sh {}",
+                Path::new(&output_dir).join(GENESIS_SCRIPT_FILE).display()
+            );
+        }
+
+        ServerCommands::ReloadAccounts { buffer_size } => {
+            let server_config = AuthorityServerConfig::read(server_config_path())
+                .expect("Fail to read server config");
+            let authority = server_config.authority;
+            let mut client = network::Client::new(
+                authority.network_protocol,
+                authority.host,
+                authority.base_port,
+                authority.num_shards,
+                buffer_size,
+                Duration::from_secs(4),
+                Duration::from_secs(4),
+            );
+            let mut rt = Runtime::new().unwrap();
+            for shard in 0..authority.num_shards {
+                match rt.block_on(client.reload_accounts(shard)) {
+                    Ok(response) => info!(
+                        "Reset shard {} to {} accounts",
+                        response.shard_id, response.num_accounts
+                    ),
+                    Err(err) => {
+                        error!("Failed to reset shard {}: {}", shard, err);
+                        std::process::exit(1);
+                    }
+                }
+            }
+        }
+
+        ServerCommands::BenchCrypto {
+            samples,
+            batch_sizes,
+        } => {
+            print!(
+                "{}",
+                fastpay::crypto_bench::CryptoBenchmark::run(samples, &batch_sizes)
+            );
+        }
+
+        ServerCommands::DiffSnapshots { old, new } => {
+            let old = std::fs::read(&old).expect("Unable to read old snapshot");
+            let new = std::fs::read(&new).expect("Unable to read new snapshot");
+            let diffs = match diff_snapshots(&old, &new) {
+                Ok(diffs) => diffs,
+                Err(err) => {
+                    error!("Invalid snapshot: {}", err);
+                    std::process::exit(2);
+                }
+            };
+            for (address, diff) in &diffs {
+                let address = encode_address(address);
+                match diff {
+                    AccountDiff::Added {
+                        balance,
+                        next_sequence_number,
+                    } => println!(
+                        "+ {} balance {} sequence {:?}",
+                        address, balance, next_sequence_number
+                    ),
+                    AccountDiff::Removed {
+                        balance,
+                        next_sequence_number,
+                    } => println!(
+                        "- {} balance {} sequence {:?}",
+                        address, balance, next_sequence_number
+                    ),
+                    AccountDiff::Changed {
+                        old_balance,
+                        new_balance,
+                        old_sequence_number,
+                        new_sequence_number,
+                    } => println!(
+                        "~ {} balance {} -> {} sequence {:?} -> {:?}",
+                        address, old_balance, new_balance, old_sequence_number, new_sequence_number
+                    ),
+                }
+            }
+            if !diffs.is_empty() {
+                std::process::exit(1);
+            }
+        }
     }
 }
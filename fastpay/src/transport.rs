@@ -5,7 +5,15 @@ use clap::arg_enum;
 use futures::future;
 use log::*;
 use serde::{Deserialize, Serialize};
-use std::{collections::HashMap, convert::TryInto, io, sync::Arc};
+use std::{
+    collections::{HashMap, VecDeque},
+    convert::TryInto,
+    io,
+    net::SocketAddr,
+    sync::Arc,
+    time::Duration,
+};
+use structopt::StructOpt;
 use tokio::{
     net::{TcpListener, TcpStream, UdpSocket},
     prelude::*,
@@ -18,15 +26,136 @@ mod transport_tests;
 /// Suggested buffer size
 pub const DEFAULT_MAX_DATAGRAM_SIZE: &str = "65507";
 
+/// Number of recent message sizes used to resize an adaptive UDP receive buffer.
+const ADAPTIVE_BUFFER_WINDOW: usize = 128;
+
+/// Percentile of the recent message sizes that an adaptive UDP receive buffer grows to.
+const ADAPTIVE_BUFFER_PERCENTILE: usize = 99;
+
+/// How many times to look up a host name before giving up on a connection.
+const RESOLVE_ATTEMPTS: u32 = 3;
+
+/// Delay between two lookups of the same host name.
+const RESOLVE_RETRY_DELAY: Duration = Duration::from_millis(100);
+
+/// The `host:port` address of a host name, an IPv4 address, or an IPv6 address with or
+/// without brackets. Host names are only resolved when connecting.
+pub fn socket_address(host: &str, port: u32) -> String {
+    if host.contains(':') && !host.starts_with('[') {
+        format!("[{}]:{}", host, port)
+    } else {
+        format!("{}:{}", host, port)
+    }
+}
+
+/// Resolve a `host:port` address into one or more socket addresses. Lookups are retried
+/// a few times since DNS failures are often transient.
+pub async fn resolve(address: &str) -> Result<Vec<SocketAddr>, io::Error> {
+    let mut attempt = 1;
+    loop {
+        let result = tokio::net::lookup_host(address)
+            .await
+            .and_then(|addresses| {
+                let addresses: Vec<_> = addresses.collect();
+                if addresses.is_empty() {
+                    return Err(io::Error::new(
+                        io::ErrorKind::NotFound,
+                        format!("No address found for {}", address),
+                    ));
+                }
+                Ok(addresses)
+            });
+        match result {
+            Err(error)
+                if attempt < RESOLVE_ATTEMPTS && error.kind() != io::ErrorKind::InvalidInput =>
+            {
+                warn!("Failed to resolve {}: {}", address, error);
+                tokio::time::delay_for(RESOLVE_RETRY_DELAY).await;
+                attempt += 1;
+            }
+            result => return result,
+        }
+    }
+}
+
+/// A local address to send UDP datagrams to `address` from.
+fn udp_local_address(address: &SocketAddr) -> &'static str {
+    if address.is_ipv6() {
+        "[::]:0"
+    } else {
+        "0.0.0.0:0"
+    }
+}
+
 // Supported transport protocols.
 arg_enum! {
-    #[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+    #[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize, Deserialize)]
     pub enum NetworkProtocol {
         Udp,
         Tcp,
     }
 }
 
+/// Options of the transport layer.
+#[derive(StructOpt, Clone, Copy, Debug)]
+pub struct TransportConfig {
+    /// Send small TCP messages immediately instead of waiting to coalesce them (Nagle's
+    /// algorithm). This lowers latency at the cost of more packets on the wire.
+    #[structopt(long, default_value = "true", parse(try_from_str))]
+    pub tcp_nodelay: bool,
+
+    /// Close TCP connections announcing a message larger than this many bytes, before
+    /// reading it (the buffer size is used by default)
+    #[structopt(long)]
+    pub max_frame_size: Option<usize>,
+
+    /// Let the receive buffer of UDP servers grow with the sizes of the received messages,
+    /// starting from the buffer size, up to this many bytes (the buffer size is fixed by
+    /// default)
+    #[structopt(long)]
+    pub udp_max_buffer_size: Option<usize>,
+}
+
+impl Default for TransportConfig {
+    fn default() -> Self {
+        Self {
+            tcp_nodelay: true,
+            max_frame_size: None,
+            udp_max_buffer_size: None,
+        }
+    }
+}
+
+/// Options of the outgoing TCP connections of a `DataStreamPool`.
+#[derive(Clone, Copy, Debug)]
+pub struct PoolConfig {
+    /// If set, how long a connection stays idle before keepalive probes are sent, so that
+    /// connections broken by network failures are eventually detected.
+    pub tcp_keepalive: Option<Duration>,
+    /// Whether to keep one connection per address for all messages, instead of opening a
+    /// new connection for each message.
+    pub reuse_connections: bool,
+}
+
+impl Default for PoolConfig {
+    fn default() -> Self {
+        Self {
+            tcp_keepalive: None,
+            reuse_connections: true,
+        }
+    }
+}
+
+impl TransportConfig {
+    /// Largest message accepted by a server with the given buffer size.
+    fn frame_size_limit(&self, buffer_size: usize) -> usize {
+        match self.max_frame_size {
+            Some(size) => std::cmp::min(size, buffer_size),
+            None => buffer_size,
+        }
+    }
+}
+
 /// How to send and obtain data packets over an "active socket".
 pub trait DataStream: Send {
     fn write_data<'a>(
@@ -49,6 +178,9 @@ pub trait DataStreamPool: Send {
 pub trait MessageHandler {
     fn handle_message<'a>(&'a mut self, buffer: &'a [u8])
         -> future::BoxFuture<'a, Option<Vec<u8>>>;
+
+    /// Called once after the server has stopped, when no message is being handled.
+    fn shutdown(&mut self) {}
 }
 
 /// The result of spawning a server is oneshot channel to kill it and a handle to track completion.
@@ -69,6 +201,40 @@ impl SpawnedServer {
         self.handle.await??;
         Ok(())
     }
+
+    /// Stop accepting new messages, then wait for the messages being handled for at most
+    /// `grace_period`.
+    pub async fn shutdown(self, grace_period: Duration) -> Result<(), std::io::Error> {
+        // The server may have stopped in the meantime.
+        let _ = self.complete.send(());
+        match tokio::time::timeout(grace_period, self.handle).await {
+            Ok(result) => result??,
+            Err(_) => {
+                return Err(io::Error::new(
+                    io::ErrorKind::TimedOut,
+                    format!("Server did not stop within {:?}", grace_period),
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Serve until the server fails or the given signal completes, in which case the server
+    /// is shut down with the given grace period.
+    pub async fn run_until<F>(
+        mut self,
+        signal: F,
+        grace_period: Duration,
+    ) -> Result<(), std::io::Error>
+    where
+        F: future::Future<Output = ()>,
+    {
+        match future::select(&mut self.handle, Box::pin(signal)).await {
+            future::Either::Left((result, _)) => result??,
+            future::Either::Right(_) => self.shutdown(grace_period).await?,
+        }
+        Ok(())
+    }
 }
 
 impl NetworkProtocol {
@@ -77,10 +243,13 @@ impl NetworkProtocol {
         self,
         address: String,
         max_data_size: usize,
+        config: TransportConfig,
     ) -> Result<Box<dyn DataStream>, std::io::Error> {
         let stream: Box<dyn DataStream> = match self {
             NetworkProtocol::Udp => Box::new(UdpDataStream::connect(address, max_data_size).await?),
-            NetworkProtocol::Tcp => Box::new(TcpDataStream::connect(address, max_data_size).await?),
+            NetworkProtocol::Tcp => {
+                Box::new(TcpDataStream::connect(address, max_data_size, config).await?)
+            }
         };
         Ok(stream)
     }
@@ -88,10 +257,21 @@ impl NetworkProtocol {
     /// Create a DataStreamPool for this protocol.
     pub async fn make_outgoing_connection_pool(
         self,
+        config: TransportConfig,
+    ) -> Result<Box<dyn DataStreamPool>, std::io::Error> {
+        self.make_outgoing_connection_pool_with(config, PoolConfig::default())
+            .await
+    }
+
+    /// Same as `make_outgoing_connection_pool` with the given options for TCP connections.
+    pub async fn make_outgoing_connection_pool_with(
+        self,
+        config: TransportConfig,
+        pool_config: PoolConfig,
     ) -> Result<Box<dyn DataStreamPool>, std::io::Error> {
         let pool: Box<dyn DataStreamPool> = match self {
             Self::Udp => Box::new(UdpDataStreamPool::new().await?),
-            Self::Tcp => Box::new(TcpDataStreamPool::new().await?),
+            Self::Tcp => Box::new(TcpDataStreamPool::new(config, pool_config).await?),
         };
         Ok(pool)
     }
@@ -102,6 +282,7 @@ impl NetworkProtocol {
         address: &str,
         state: S,
         buffer_size: usize,
+        config: TransportConfig,
     ) -> Result<SpawnedServer, std::io::Error>
     where
         S: MessageHandler + Send + 'static,
@@ -110,11 +291,19 @@ impl NetworkProtocol {
         let handle = match self {
             Self::Udp => {
                 let socket = UdpSocket::bind(&address).await?;
-                tokio::spawn(Self::run_udp_server(socket, state, receiver, buffer_size))
+                let max_buffer_size = config.udp_max_buffer_size.unwrap_or(buffer_size);
+                let buffer = ReceiveBuffer::new(buffer_size, max_buffer_size);
+                tokio::spawn(Self::run_udp_server(socket, state, receiver, buffer))
             }
             Self::Tcp => {
                 let listener = TcpListener::bind(address).await?;
-                tokio::spawn(Self::run_tcp_server(listener, state, receiver, buffer_size))
+                tokio::spawn(Self::run_tcp_server(
+                    listener,
+                    state,
+                    receiver,
+                    buffer_size,
+                    config,
+                ))
             }
         };
         Ok(SpawnedServer { complete, handle })
@@ -124,13 +313,15 @@ impl NetworkProtocol {
 /// An implementation of DataStream based on UDP.
 struct UdpDataStream {
     socket: UdpSocket,
-    address: String,
+    address: SocketAddr,
     buffer: Vec<u8>,
 }
 
 impl UdpDataStream {
     async fn connect(address: String, max_data_size: usize) -> Result<Self, std::io::Error> {
-        let socket = UdpSocket::bind(&"0.0.0.0:0").await?;
+        // Datagrams are sent to the first address of the host.
+        let address = resolve(&address).await?[0];
+        let socket = UdpSocket::bind(udp_local_address(&address)).await?;
         let buffer = vec![0u8; max_data_size];
         Ok(Self {
             socket,
@@ -146,7 +337,7 @@ impl DataStream for UdpDataStream {
         buffer: &'a [u8],
     ) -> future::BoxFuture<'a, Result<(), std::io::Error>> {
         Box::pin(async move {
-            self.socket.send_to(buffer, &*self.address).await?;
+            self.socket.send_to(buffer, &self.address).await?;
             Ok(())
         })
     }
@@ -161,13 +352,18 @@ impl DataStream for UdpDataStream {
 
 /// An implementation of DataStreamPool based on UDP.
 struct UdpDataStreamPool {
-    socket: UdpSocket,
+    /// One socket for each IP version, created when first needed.
+    sockets: HashMap<&'static str, UdpSocket>,
+    /// Resolved destination addresses.
+    addresses: HashMap<String, SocketAddr>,
 }
 
 impl UdpDataStreamPool {
     async fn new() -> Result<Self, std::io::Error> {
-        let socket = UdpSocket::bind(&"0.0.0.0:0").await?;
-        Ok(Self { socket })
+        Ok(Self {
+            sockets: HashMap::new(),
+            addresses: HashMap::new(),
+        })
     }
 }
 
@@ -178,40 +374,122 @@ impl DataStreamPool for UdpDataStreamPool {
         address: &'a str,
     ) -> future::BoxFuture<'a, Result<(), std::io::Error>> {
         Box::pin(async move {
-            self.socket.send_to(buffer, address).await?;
+            let address = match self.addresses.get(address) {
+                Some(address) => *address,
+                None => {
+                    let resolved = resolve(address).await?[0];
+                    self.addresses.insert(address.to_string(), resolved);
+                    resolved
+                }
+            };
+            let local_address = udp_local_address(&address);
+            if !self.sockets.contains_key(local_address) {
+                let socket = UdpSocket::bind(local_address).await?;
+                self.sockets.insert(local_address, socket);
+            }
+            let socket = self.sockets.get_mut(local_address).unwrap();
+            socket.send_to(buffer, &address).await?;
             Ok(())
         })
     }
 }
 
+/// Receive buffer of a UDP server. It grows towards a high percentile of the sizes of the
+/// last messages received, up to a maximal size. Datagrams larger than the buffer are
+/// truncated by the socket, so they are rejected.
+struct ReceiveBuffer {
+    /// One byte more than the largest message accepted, to detect truncated datagrams.
+    data: Vec<u8>,
+    max_size: usize,
+    /// Sizes of the last messages received. Truncated messages count as twice the buffer size.
+    recent_sizes: VecDeque<usize>,
+}
+
+impl ReceiveBuffer {
+    fn new(size: usize, max_size: usize) -> Self {
+        Self {
+            data: vec![0; size + 1],
+            max_size: std::cmp::max(size, max_size),
+            recent_sizes: VecDeque::new(),
+        }
+    }
+
+    /// Largest message accepted at the moment.
+    fn size(&self) -> usize {
+        self.data.len() - 1
+    }
+
+    /// Record a datagram of `received` bytes written at the start of the buffer, and return
+    /// the message unless it was truncated.
+    fn accept(&mut self, received: usize) -> Option<&[u8]> {
+        let size = self.size();
+        let truncated = received > size;
+        if size < self.max_size {
+            if self.recent_sizes.len() == ADAPTIVE_BUFFER_WINDOW {
+                self.recent_sizes.pop_front();
+            }
+            self.recent_sizes
+                .push_back(if truncated { 2 * size } else { received });
+            let mut sizes: Vec<_> = self.recent_sizes.iter().copied().collect();
+            sizes.sort_unstable();
+            let index = std::cmp::min(
+                sizes.len() * ADAPTIVE_BUFFER_PERCENTILE / 100,
+                sizes.len() - 1,
+            );
+            let target = std::cmp::min(sizes[index], self.max_size);
+            if target > size {
+                self.data.resize(target + 1, 0);
+            }
+        }
+        if truncated {
+            None
+        } else {
+            Some(&self.data[..received])
+        }
+    }
+}
+
 // Server implementation for UDP.
 impl NetworkProtocol {
     async fn run_udp_server<S>(
         mut socket: UdpSocket,
         mut state: S,
         mut exit_future: futures::channel::oneshot::Receiver<()>,
-        buffer_size: usize,
+        mut buffer: ReceiveBuffer,
     ) -> Result<(), std::io::Error>
     where
         S: MessageHandler + Send + 'static,
     {
-        let mut buffer = vec![0; buffer_size];
         loop {
             let (size, peer) =
-                match future::select(exit_future, Box::pin(socket.recv_from(&mut buffer))).await {
+                match future::select(exit_future, Box::pin(socket.recv_from(&mut buffer.data)))
+                    .await
+                {
                     future::Either::Left(_) => break,
                     future::Either::Right((value, new_exit_future)) => {
                         exit_future = new_exit_future;
                         value?
                     }
                 };
-            if let Some(reply) = state.handle_message(&buffer[..size]).await {
+            let message = match buffer.accept(size) {
+                Some(message) => message,
+                None => {
+                    warn!(
+                        "Dropped a message from {} larger than the receive buffer ({} bytes)",
+                        peer,
+                        size - 1
+                    );
+                    continue;
+                }
+            };
+            if let Some(reply) = state.handle_message(message).await {
                 let status = socket.send_to(&reply[..], &peer).await;
                 if let Err(error) = status {
                     error!("Failed to send query response: {}", error);
                 }
             }
         }
+        state.shutdown();
         Ok(())
     }
 }
@@ -223,16 +501,30 @@ struct TcpDataStream {
 }
 
 impl TcpDataStream {
-    async fn connect(address: String, max_data_size: usize) -> Result<Self, std::io::Error> {
-        let stream = TcpStream::connect(address).await?;
-        stream.set_send_buffer_size(max_data_size)?;
-        stream.set_recv_buffer_size(max_data_size)?;
+    async fn connect(
+        address: String,
+        max_data_size: usize,
+        config: TransportConfig,
+    ) -> Result<Self, std::io::Error> {
+        let stream = TcpStream::connect(&resolve(&address).await?[..]).await?;
+        Self::configure(&stream, max_data_size, config)?;
         Ok(Self {
             stream,
             max_data_size,
         })
     }
 
+    /// Apply the socket options of both accepted and established connections.
+    fn configure(
+        stream: &TcpStream,
+        buffer_size: usize,
+        config: TransportConfig,
+    ) -> Result<(), std::io::Error> {
+        stream.set_send_buffer_size(buffer_size)?;
+        stream.set_recv_buffer_size(buffer_size)?;
+        stream.set_nodelay(config.tcp_nodelay)
+    }
+
     async fn tcp_write_data<S>(stream: &mut S, buffer: &[u8]) -> Result<(), std::io::Error>
     where
         S: AsyncWrite + Unpin,
@@ -255,10 +547,11 @@ impl TcpDataStream {
         let mut size_buf = [0u8; 4];
         stream.read_exact(&mut size_buf).await?;
         let size = u32::from_le_bytes(size_buf);
+        // Only the length prefix has been read at this point.
         if size as usize > max_size {
             return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Message size exceeds buffer size",
+                io::ErrorKind::InvalidData,
+                format!("Message size {} exceeds the limit of {}", size, max_size),
             ));
         }
         let mut buf = vec![0u8; size as usize];
@@ -283,25 +576,42 @@ impl DataStream for TcpDataStream {
 /// An implementation of DataStreamPool based on TCP.
 struct TcpDataStreamPool {
     streams: HashMap<String, TcpStream>,
+    config: TransportConfig,
+    pool_config: PoolConfig,
 }
 
 impl TcpDataStreamPool {
-    async fn new() -> Result<Self, std::io::Error> {
+    async fn new(config: TransportConfig, pool_config: PoolConfig) -> Result<Self, std::io::Error> {
         let streams = HashMap::new();
-        Ok(Self { streams })
+        Ok(Self {
+            streams,
+            config,
+            pool_config,
+        })
+    }
+
+    async fn connect(&self, address: &str) -> Result<TcpStream, io::Error> {
+        let result = match resolve(address).await {
+            Ok(addresses) => TcpStream::connect(&addresses[..]).await,
+            Err(error) => Err(error),
+        };
+        match result {
+            Ok(stream) => {
+                stream.set_nodelay(self.config.tcp_nodelay)?;
+                stream.set_keepalive(self.pool_config.tcp_keepalive)?;
+                Ok(stream)
+            }
+            Err(error) => {
+                error!("Failed to open connection to {}: {}", address, error);
+                Err(error)
+            }
+        }
     }
 
     async fn get_stream(&mut self, address: &str) -> Result<&mut TcpStream, io::Error> {
         if !self.streams.contains_key(address) {
-            match TcpStream::connect(address).await {
-                Ok(s) => {
-                    self.streams.insert(address.to_string(), s);
-                }
-                Err(error) => {
-                    error!("Failed to open connection to {}: {}", address, error);
-                    return Err(error);
-                }
-            };
+            let stream = self.connect(address).await?;
+            self.streams.insert(address.to_string(), stream);
         };
         Ok(self.streams.get_mut(address).unwrap())
     }
@@ -314,8 +624,17 @@ impl DataStreamPool for TcpDataStreamPool {
         address: &'a str,
     ) -> future::BoxFuture<'a, Result<(), std::io::Error>> {
         Box::pin(async move {
+            if !self.pool_config.reuse_connections {
+                let mut stream = self.connect(address).await?;
+                return TcpDataStream::tcp_write_data(&mut stream, buffer).await;
+            }
             let stream = self.get_stream(address).await?;
-            TcpDataStream::tcp_write_data(stream, buffer).await
+            let result = TcpDataStream::tcp_write_data(stream, buffer).await;
+            if result.is_err() {
+                // The connection may be broken: open a new one for the next message.
+                self.streams.remove(address);
+            }
+            result
         })
     }
 }
@@ -327,6 +646,7 @@ impl NetworkProtocol {
         state: S,
         mut exit_future: futures::channel::oneshot::Receiver<()>,
         buffer_size: usize,
+        config: TransportConfig,
     ) -> Result<(), std::io::Error>
     where
         S: MessageHandler + Send + 'static,
@@ -341,22 +661,23 @@ impl NetworkProtocol {
                         value?
                     }
                 };
-            socket.set_send_buffer_size(buffer_size)?;
-            socket.set_recv_buffer_size(buffer_size)?;
+            TcpDataStream::configure(&socket, buffer_size, config)?;
+            let max_frame_size = config.frame_size_limit(buffer_size);
             let guarded_state = guarded_state.clone();
             tokio::spawn(async move {
                 loop {
-                    let buffer = match TcpDataStream::tcp_read_data(&mut socket, buffer_size).await
-                    {
-                        Ok(buffer) => buffer,
-                        Err(err) => {
-                            // We expect an EOF error at the end.
-                            if err.kind() != io::ErrorKind::UnexpectedEof {
-                                error!("Error while reading TCP stream: {}", err);
+                    let buffer =
+                        match TcpDataStream::tcp_read_data(&mut socket, max_frame_size).await {
+                            Ok(buffer) => buffer,
+                            Err(err) => {
+                                // We expect an EOF error at the end. Oversized messages close
+                                // the connection (when `socket` is dropped).
+                                if err.kind() != io::ErrorKind::UnexpectedEof {
+                                    error!("Error while reading TCP stream: {}", err);
+                                }
+                                break;
                             }
-                            break;
-                        }
-                    };
+                        };
 
                     if let Some(reply) =
                         guarded_state.lock().await.handle_message(&buffer[..]).await
@@ -369,6 +690,8 @@ impl NetworkProtocol {
                 }
             });
         }
+        // Wait for the message being handled, if any.
+        guarded_state.lock().await.shutdown();
         Ok(())
     }
 }
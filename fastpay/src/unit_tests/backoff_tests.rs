@@ -0,0 +1,53 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+
+fn make_backoff(jitter: Jitter, seed: Option<u64>) -> Backoff {
+    Backoff::new(
+        Duration::from_millis(10),
+        Duration::from_millis(1000),
+        jitter,
+        seed,
+    )
+}
+
+#[test]
+fn test_backoff_without_jitter() {
+    let delays: Vec<_> = make_backoff(Jitter::None, None).take(9).collect();
+    let millis: Vec<_> = delays.iter().map(Duration::as_millis).collect();
+    assert_eq!(millis, vec![10, 20, 40, 80, 160, 320, 640, 1000, 1000]);
+}
+
+#[test]
+fn test_full_jitter_is_bounded() {
+    let mut backoff = make_backoff(Jitter::Full, Some(7));
+    for attempt in 0..100 {
+        let interval = backoff.interval(attempt);
+        let delay = backoff.next_delay();
+        assert!(delay <= interval);
+    }
+}
+
+#[test]
+fn test_decorrelated_jitter_is_bounded() {
+    let mut backoff = make_backoff(Jitter::Decorrelated, Some(7));
+    let mut previous = Duration::from_millis(10);
+    for _ in 0..100 {
+        let delay = backoff.next_delay();
+        assert!(delay >= Duration::from_millis(10));
+        assert!(delay <= std::cmp::min(Duration::from_millis(1000), previous * 3));
+        previous = delay;
+    }
+}
+
+#[test]
+fn test_seeded_jitter_is_reproducible() {
+    for jitter in &[Jitter::Full, Jitter::Decorrelated] {
+        let first: Vec<_> = make_backoff(*jitter, Some(42)).take(20).collect();
+        let second: Vec<_> = make_backoff(*jitter, Some(42)).take(20).collect();
+        let other: Vec<_> = make_backoff(*jitter, Some(43)).take(20).collect();
+        assert_eq!(first, second);
+        assert_ne!(first, other);
+    }
+}
@@ -0,0 +1,869 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use crate::transport::NetworkProtocol;
+use fastpay_core::messages::Transfer;
+use tempfile::tempdir;
+
+fn make_authority_config(port: u32) -> AuthorityConfig {
+    AuthorityConfig {
+        network_protocol: NetworkProtocol::Udp,
+        address: get_key_pair().0,
+        host: "127.0.0.1".to_string(),
+        base_port: port,
+        num_shards: 4,
+        label: None,
+        operator_info: None,
+    }
+}
+
+#[test]
+fn test_read_committee_one_authority_per_line() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("committee.json");
+    let path = path.to_str().unwrap();
+    let authorities = vec![make_authority_config(9100), make_authority_config(9200)];
+    let mut file = File::create(path).unwrap();
+    for authority in &authorities {
+        writeln!(file, "{}", serde_json::to_string(authority).unwrap()).unwrap();
+    }
+
+    let config = CommitteeConfig::read(path).unwrap();
+    assert_eq!(config.authorities.len(), 2);
+    assert_eq!(config.authorities[1].address, authorities[1].address);
+    assert_eq!(config.recipient_policy, RecipientPolicy::AutoCreate);
+}
+
+#[test]
+fn test_write_and_read_committee() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("committee.json");
+    let path = path.to_str().unwrap();
+    let config = CommitteeConfig {
+        version: CONFIG_VERSION,
+        authorities: vec![make_authority_config(9100), make_authority_config(9200)],
+        recipient_policy: RecipientPolicy::Reject,
+        features: vec![Feature::RecipientClaims].into_iter().collect(),
+        order_acceptance_window: Some(10),
+        chain_id: 7,
+        genesis_timestamp: 1000,
+        transfer_cooldown_ms: Some(500),
+        strictly_increasing_transfer_times: true,
+    };
+    config.write(path).unwrap();
+
+    let config = CommitteeConfig::read(path).unwrap();
+    assert_eq!(config.authorities.len(), 2);
+    let committee = config.make_committee();
+    assert_eq!(committee.total_votes, 2);
+    assert_eq!(committee.recipient_policy, RecipientPolicy::Reject);
+    assert!(committee.has_feature(Feature::RecipientClaims));
+    assert_eq!(committee.order_acceptance_window, Some(10));
+    assert_eq!(committee.transfer_cooldown_ms, Some(500));
+    assert!(committee.strictly_increasing_transfer_times);
+    assert_eq!(
+        committee.domain,
+        SigningDomain {
+            chain_id: 7,
+            genesis_timestamp: 1000,
+        }
+    );
+}
+
+#[test]
+fn test_check_committee_features() {
+    let mut committee_config = CommitteeConfig {
+        version: CONFIG_VERSION,
+        authorities: vec![make_authority_config(9100)],
+        recipient_policy: RecipientPolicy::AutoCreate,
+        features: BTreeSet::new(),
+        order_acceptance_window: None,
+        chain_id: 0,
+        genesis_timestamp: 0,
+        transfer_cooldown_ms: None,
+        strictly_increasing_transfer_times: false,
+    };
+    let mut server_config = AuthorityServerConfig {
+        version: CONFIG_VERSION,
+        authority: committee_config.authorities[0].clone(),
+        key: AuthorityKey::Plain(get_key_pair().1),
+        large_transfer_threshold: None,
+        max_transfer_amount: None,
+        allow_non_canonical_encodings: false,
+        deserialization_mode: DeserializationMode::default(),
+        features: BTreeSet::new(),
+        account_filter_false_positive_rate: None,
+        memory_limit: None,
+        sign_cross_shard_updates: false,
+        cross_shard_sources: None,
+        verification_cache_capacity: None,
+        verification_cache_max_age: None,
+        unknown_message_policy: UnknownMessagePolicy::default(),
+        reverify_confirmed_certificates: false,
+        reject_negative_balances: false,
+        log_full_details: false,
+        log_conflicting_orders: false,
+    };
+    assert!(committee_config.check_features(&server_config).is_ok());
+
+    committee_config.features.insert(Feature::RecipientClaims);
+    assert!(committee_config.check_features(&server_config).is_err());
+
+    server_config.features.insert(Feature::RecipientClaims);
+    assert!(committee_config.check_features(&server_config).is_ok());
+
+    // Settings of the committee imply their feature.
+    committee_config.transfer_cooldown_ms = Some(1000);
+    assert!(committee_config.check_features(&server_config).is_err());
+    server_config.features.insert(Feature::TransferCooldown);
+    assert!(committee_config.check_features(&server_config).is_ok());
+    assert!(committee_config
+        .make_committee()
+        .has_feature(Feature::TransferCooldown));
+}
+
+#[test]
+fn test_tenants_isolation() {
+    let dir = tempdir().unwrap();
+    let make_tenant = |id: &str, port| {
+        let server = dir.path().join(format!("{}.json", id));
+        let server = server.to_str().unwrap().to_string();
+        AuthorityServerConfig {
+            version: CONFIG_VERSION,
+            authority: make_authority_config(port),
+            key: AuthorityKey::Plain(get_key_pair().1),
+            large_transfer_threshold: None,
+            max_transfer_amount: None,
+            allow_non_canonical_encodings: false,
+            deserialization_mode: DeserializationMode::default(),
+            features: BTreeSet::new(),
+            account_filter_false_positive_rate: None,
+            memory_limit: None,
+            sign_cross_shard_updates: false,
+            cross_shard_sources: None,
+            verification_cache_capacity: None,
+            verification_cache_max_age: None,
+            unknown_message_policy: UnknownMessagePolicy::default(),
+            reverify_confirmed_certificates: false,
+            reject_negative_balances: false,
+            log_full_details: false,
+            log_conflicting_orders: false,
+        }
+        .write(&server)
+        .unwrap();
+        TenantConfig {
+            id: id.to_string(),
+            server,
+            committee: String::new(),
+            initial_accounts: String::new(),
+        }
+    };
+    let default = make_authority_config(9100);
+
+    let tenants = TenantsConfig {
+        tenants: vec![make_tenant("a", 9200), make_tenant("b", 9204)],
+    };
+    assert!(tenants.check_isolation(Some(&default)).is_ok());
+
+    let tenants = TenantsConfig {
+        tenants: vec![make_tenant("a", 9200), make_tenant("b", 9203)],
+    };
+    assert!(tenants.check_isolation(Some(&default)).is_err());
+
+    let tenants = TenantsConfig {
+        tenants: vec![make_tenant("a", 9200), make_tenant("a", 9300)],
+    };
+    assert!(tenants.check_isolation(None).is_err());
+
+    let tenants = TenantsConfig {
+        tenants: vec![make_tenant("a", 9098)],
+    };
+    assert!(tenants.check_isolation(None).is_ok());
+    assert!(tenants.check_isolation(Some(&default)).is_err());
+
+    let tenants = TenantsConfig {
+        tenants: vec![make_tenant("a", u32::MAX)],
+    };
+    assert!(tenants.check_isolation(None).is_err());
+}
+
+#[test]
+fn test_read_unversioned_committee() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("committee.json");
+    let path = path.to_str().unwrap();
+    let authority = make_authority_config(9100);
+    // Committee files written before the format was versioned.
+    let v1 = serde_json::json!({
+        "authorities": [authority],
+        "recipient_policy": "Reject",
+        "chain_id": 7,
+    });
+    fs::write(path, v1.to_string()).unwrap();
+
+    let config = CommitteeConfig::read(path).unwrap();
+    assert_eq!(config.version, CONFIG_VERSION);
+    assert_eq!(config.authorities[0].address, authority.address);
+    assert_eq!(config.recipient_policy, RecipientPolicy::Reject);
+    assert_eq!(config.chain_id, 7);
+
+    let mut v2 = v1.clone();
+    migrate_v1_to_v2(&mut v2).unwrap();
+    assert_eq!(v2["version"], 2);
+    assert!(migrate_v1_to_v2(&mut serde_json::json!([1, 2])).is_err());
+
+    let mut v3 = v2.clone();
+    v3["version"] = 3.into();
+    fs::write(path, v3.to_string()).unwrap();
+    let error = CommitteeConfig::read(path).err().unwrap().to_string();
+    assert!(
+        error.contains("expected config version 2, found 3"),
+        "{}",
+        error
+    );
+
+    let mut invalid = v2;
+    invalid["chain_id"] = "seven".into();
+    fs::write(path, invalid.to_string()).unwrap();
+    let error = CommitteeConfig::read(path).err().unwrap().to_string();
+    assert!(error.starts_with(path), "{}", error);
+    assert!(error.contains("invalid config of version 2"), "{}", error);
+}
+
+#[test]
+fn test_read_unversioned_server_config() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("server.json");
+    let path = path.to_str().unwrap();
+    let authority = make_authority_config(9100);
+    let v1 = serde_json::json!({
+        "authority": authority,
+        "key": AuthorityKey::Plain(get_key_pair().1),
+        "large_transfer_threshold": 100,
+    });
+    fs::write(path, v1.to_string()).unwrap();
+
+    let config = AuthorityServerConfig::read(path).unwrap();
+    assert_eq!(config.version, CONFIG_VERSION);
+    assert_eq!(config.authority.address, authority.address);
+    assert_eq!(config.large_transfer_threshold, Some(Amount::from(100)));
+
+    let mut v3 = v1;
+    v3["version"] = 3.into();
+    fs::write(path, v3.to_string()).unwrap();
+    let error = AuthorityServerConfig::read(path).err().unwrap().to_string();
+    assert!(
+        error.contains("expected config version 2, found 3"),
+        "{}",
+        error
+    );
+}
+
+#[test]
+fn test_encrypted_server_key() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("server.json");
+    let path = path.to_str().unwrap();
+    let (address, key) = get_key_pair();
+    let mut authority = make_authority_config(9100);
+    authority.address = address;
+    AuthorityServerConfig {
+        version: CONFIG_VERSION,
+        authority,
+        key: AuthorityKey::Encrypted(key.encrypt_with_cost("passphrase", 4)),
+        large_transfer_threshold: None,
+        max_transfer_amount: None,
+        allow_non_canonical_encodings: false,
+        deserialization_mode: DeserializationMode::default(),
+        features: BTreeSet::new(),
+        account_filter_false_positive_rate: None,
+        memory_limit: None,
+        sign_cross_shard_updates: false,
+        cross_shard_sources: None,
+        verification_cache_capacity: None,
+        verification_cache_max_age: None,
+        unknown_message_policy: UnknownMessagePolicy::default(),
+        reverify_confirmed_certificates: false,
+        reject_negative_balances: false,
+        log_full_details: false,
+        log_conflicting_orders: false,
+    }
+    .write(path)
+    .unwrap();
+
+    let config = AuthorityServerConfig::read(path).unwrap();
+    assert!(matches!(config.key, AuthorityKey::Encrypted(_)));
+    assert!(config.key.unlock(None).is_err());
+    assert!(config.key.unlock(Some("wrong")).is_err());
+    let key = config.key.unlock(Some("passphrase")).unwrap();
+    let message = Transfer {
+        sender: address,
+        recipient: Address::FastPay(address),
+        amount: Amount::from(1),
+        sequence_number: SequenceNumber::new(),
+        user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
+    };
+    assert!(Signature::new(&message, &key)
+        .check(&message, address)
+        .is_ok());
+
+    // Keys in clear are still accepted.
+    AuthorityServerConfig {
+        version: CONFIG_VERSION,
+        authority: make_authority_config(9100),
+        key: AuthorityKey::Plain(get_key_pair().1),
+        large_transfer_threshold: None,
+        max_transfer_amount: None,
+        allow_non_canonical_encodings: false,
+        deserialization_mode: DeserializationMode::default(),
+        features: BTreeSet::new(),
+        account_filter_false_positive_rate: None,
+        memory_limit: None,
+        sign_cross_shard_updates: false,
+        cross_shard_sources: None,
+        verification_cache_capacity: None,
+        verification_cache_max_age: None,
+        unknown_message_policy: UnknownMessagePolicy::default(),
+        reverify_confirmed_certificates: false,
+        reject_negative_balances: false,
+        log_full_details: false,
+        log_conflicting_orders: false,
+    }
+    .write(path)
+    .unwrap();
+    let config = AuthorityServerConfig::read(path).unwrap();
+    assert!(config.key.unlock(None).is_ok());
+}
+
+#[test]
+fn test_check_num_shards() {
+    let mut authority = make_authority_config(9100);
+    assert!(authority.check_num_shards(256).is_ok());
+
+    authority.num_shards = 100_000;
+    assert!(authority.check_num_shards(256).is_err());
+
+    authority.num_shards = 0;
+    assert!(authority.check_num_shards(256).is_err());
+
+    authority.num_shards = 200;
+    authority.base_port = 65500;
+    assert!(authority.check_num_shards(256).is_err());
+}
+
+#[test]
+fn test_committee_size() {
+    let make_committee = |size: u32| {
+        CommitteeConfig {
+            version: CONFIG_VERSION,
+            authorities: (0..size)
+                .map(|i| make_authority_config(9100 + 4 * i))
+                .collect(),
+            recipient_policy: RecipientPolicy::default(),
+            features: BTreeSet::new(),
+            order_acceptance_window: None,
+            chain_id: 0,
+            genesis_timestamp: 0,
+            transfer_cooldown_ms: None,
+            strictly_increasing_transfer_times: false,
+        }
+        .make_committee()
+    };
+    let config = CommitteeSizeConfig::from_iter(&["server"]);
+    assert_eq!(config.min_committee_size, 4);
+    assert_eq!(
+        config.check(&make_committee(3)),
+        Err(FastPayError::CommitteeTooSmall {
+            size: 3,
+            minimum: 4
+        })
+    );
+    assert!(config.check(&make_committee(4)).is_ok());
+
+    let config = CommitteeSizeConfig::from_iter(&["server", "--min-committee-size", "7"]);
+    assert!(config.check(&make_committee(4)).is_err());
+
+    let config = CommitteeSizeConfig::from_iter(&["server", "--allow-small-committee"]);
+    assert!(config.check(&make_committee(1)).is_ok());
+}
+
+#[test]
+fn test_check_committee_info() {
+    let keys: Vec<_> = (0..4).map(|_| get_key_pair()).collect();
+    let committee_config = CommitteeConfig {
+        version: CONFIG_VERSION,
+        authorities: keys
+            .iter()
+            .map(|(address, _)| AuthorityConfig {
+                address: *address,
+                ..make_authority_config(9100)
+            })
+            .collect(),
+        recipient_policy: RecipientPolicy::Reject,
+        features: BTreeSet::new(),
+        order_acceptance_window: None,
+        chain_id: 0,
+        genesis_timestamp: 0,
+        transfer_cooldown_ms: None,
+        strictly_increasing_transfer_times: false,
+    };
+    let description = serde_json::to_string(&committee_config).unwrap();
+    let responses: Vec<_> = keys
+        .iter()
+        .map(|(name, secret)| CommitteeInfoResponse::new(description.clone(), *name, secret))
+        .collect();
+
+    let config = committee_config
+        .check_committee_info(&responses[..2])
+        .unwrap();
+    assert_eq!(config.authorities.len(), 4);
+    assert_eq!(config.recipient_policy, RecipientPolicy::Reject);
+    // A single authority may be lying.
+    assert!(committee_config
+        .check_committee_info(&responses[..1])
+        .is_err());
+    assert!(committee_config
+        .check_committee_info(&[responses[0].clone(), responses[0].clone()])
+        .is_err());
+
+    // Diverging descriptions are rejected.
+    let (name, secret) = &keys[1];
+    let other = CommitteeInfoResponse::new("{}".to_string(), *name, secret);
+    assert!(committee_config
+        .check_committee_info(&[responses[0].clone(), other])
+        .is_err());
+
+    // So are forged signatures and unknown authorities.
+    let mut forged = responses[1].clone();
+    forged.authority = keys[2].0;
+    assert!(committee_config
+        .check_committee_info(&[responses[0].clone(), forged])
+        .is_err());
+    let (name, secret) = get_key_pair();
+    let unknown = CommitteeInfoResponse::new(description, name, &secret);
+    assert!(committee_config
+        .check_committee_info(&[responses[0].clone(), unknown])
+        .is_err());
+}
+
+#[test]
+fn test_authority_label() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("committee.json");
+    let path = path.to_str().unwrap();
+    let mut labeled = make_authority_config(9100);
+    labeled.label = Some("alpha".to_string());
+    labeled.operator_info = Some("ops@example.com".to_string());
+    let config = CommitteeConfig {
+        version: CONFIG_VERSION,
+        authorities: vec![labeled.clone(), make_authority_config(9200)],
+        recipient_policy: RecipientPolicy::default(),
+        features: BTreeSet::new(),
+        order_acceptance_window: None,
+        chain_id: 0,
+        genesis_timestamp: 0,
+        transfer_cooldown_ms: None,
+        strictly_increasing_transfer_times: false,
+    };
+    config.write(path).unwrap();
+
+    let config = CommitteeConfig::read(path).unwrap();
+    assert_eq!(config.authorities[0].label.as_deref(), Some("alpha"));
+    assert_eq!(
+        config.authorities[0].operator_info.as_deref(),
+        Some("ops@example.com")
+    );
+    assert_eq!(config.authorities[1].label, None);
+
+    // Labels do not take part in the identity of authorities.
+    let mut relabeled = labeled.clone();
+    relabeled.label = Some("beta".to_string());
+    let committee = |authority: &AuthorityConfig| {
+        CommitteeConfig {
+            version: CONFIG_VERSION,
+            authorities: vec![authority.clone()],
+            recipient_policy: RecipientPolicy::default(),
+            features: BTreeSet::new(),
+            order_acceptance_window: None,
+            chain_id: 0,
+            genesis_timestamp: 0,
+            transfer_cooldown_ms: None,
+            strictly_increasing_transfer_times: false,
+        }
+        .make_committee()
+    };
+    assert_eq!(committee(&labeled), committee(&relabeled));
+}
+
+#[test]
+fn test_genesis() {
+    let dir = tempdir().unwrap();
+    let genesis = GenesisConfig::generate(
+        NetworkProtocol::Tcp,
+        "127.0.0.1",
+        9100,
+        4,
+        2,
+        10,
+        Balance::from(1000),
+    );
+    genesis.write(dir.path()).unwrap();
+    // Files are never overwritten.
+    assert!(genesis.write(dir.path()).is_err());
+
+    let path = |name: &str| dir.path().join(name).to_str().unwrap().to_string();
+    let committee = CommitteeConfig::read(&path(GENESIS_COMMITTEE_FILE)).unwrap();
+    assert_eq!(committee.authorities.len(), 4);
+    committee.check_port_ranges().unwrap();
+    let mut ports = BTreeSet::new();
+    for i in 0..4 {
+        let server = AuthorityServerConfig::read(&path(&format!("server{}.json", i))).unwrap();
+        committee.check_server_config(&server, None).unwrap();
+        server.authority.check_num_shards(256).unwrap();
+        for port in server.authority.base_port..server.authority.base_port + 2 {
+            assert!(ports.insert(port));
+        }
+    }
+
+    let initial_accounts = InitialStateConfig::read(&path(GENESIS_INITIAL_ACCOUNTS_FILE)).unwrap();
+    assert_eq!(initial_accounts.accounts.len(), 10);
+    let accounts = AccountsConfig::read_or_create(&path(GENESIS_ACCOUNTS_FILE)).unwrap();
+    assert_eq!(accounts.num_accounts(), 10);
+    for (address, balance) in &initial_accounts.accounts {
+        assert_eq!(*balance, Balance::from(1000));
+        assert_eq!(accounts.get(address).unwrap().balance, *balance);
+    }
+    let script = fs::read_to_string(path(GENESIS_SCRIPT_FILE)).unwrap();
+    assert!(script.contains("--server server3.json run"));
+    assert!(!script.contains("--allow-small-committee"));
+
+    // Scripts of development committees run them despite their size.
+    let dir = tempdir().unwrap();
+    GenesisConfig::generate(
+        NetworkProtocol::Tcp,
+        "127.0.0.1",
+        9100,
+        1,
+        2,
+        10,
+        Balance::from(1000),
+    )
+    .write(dir.path())
+    .unwrap();
+    let script = fs::read_to_string(dir.path().join(GENESIS_SCRIPT_FILE)).unwrap();
+    assert!(script.contains("--allow-small-committee"));
+}
+
+#[test]
+fn test_genesis_keeps_existing_server_file() {
+    let dir = tempdir().unwrap();
+    let server_path = dir.path().join("server1.json");
+    fs::write(&server_path, "secret").unwrap();
+    let genesis = GenesisConfig::generate(
+        NetworkProtocol::Tcp,
+        "127.0.0.1",
+        9100,
+        4,
+        2,
+        10,
+        Balance::from(1000),
+    );
+    assert!(genesis.write(dir.path()).is_err());
+    assert_eq!(fs::read_to_string(&server_path).unwrap(), "secret");
+    // Nothing else was written either.
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 1);
+}
+
+#[test]
+fn test_genesis_summary() {
+    let dir = tempdir().unwrap();
+    let output_dir = dir.path().join("genesis");
+    let genesis = GenesisConfig::generate(
+        NetworkProtocol::Tcp,
+        "127.0.0.1",
+        9100,
+        4,
+        2,
+        10,
+        Balance::from(1000),
+    );
+    let summary = genesis.summary(&output_dir);
+    assert!(summary.starts_with("Would write the configuration of 4 authorities:\n"));
+    assert!(summary.contains(&format!(
+        "authority3: Tcp 127.0.0.1:9106..9108 (2 shards) -> {}",
+        output_dir.join("server3.json").display()
+    )));
+    assert!(summary.contains(&format!(
+        "keys of 10 accounts -> {}",
+        output_dir.join(GENESIS_ACCOUNTS_FILE).display()
+    )));
+    // Nothing was written.
+    assert!(!output_dir.exists());
+    assert_eq!(fs::read_dir(dir.path()).unwrap().count(), 0);
+}
+
+#[test]
+fn test_genesis_with_seed() {
+    use rand::SeedableRng;
+    use rand_chacha::ChaChaRng;
+
+    let write_genesis = |seed| {
+        let dir = tempdir().unwrap();
+        GenesisConfig::generate_with_rng(
+            NetworkProtocol::Udp,
+            "127.0.0.1",
+            9100,
+            4,
+            2,
+            5,
+            Balance::from(1000),
+            &mut ChaChaRng::seed_from_u64(seed),
+        )
+        .write(dir.path())
+        .unwrap();
+        let mut files = BTreeMap::new();
+        for entry in fs::read_dir(dir.path()).unwrap() {
+            let path = entry.unwrap().path();
+            files.insert(
+                path.file_name().unwrap().to_owned(),
+                fs::read(&path).unwrap(),
+            );
+        }
+        files
+    };
+    let files = write_genesis(42);
+    assert_eq!(files.len(), 4 + 4);
+    assert_eq!(write_genesis(42), files);
+    let other_files = write_genesis(43);
+    assert_eq!(
+        other_files.keys().collect::<Vec<_>>(),
+        files.keys().collect::<Vec<_>>()
+    );
+    assert_ne!(other_files, files);
+}
+
+#[test]
+fn test_check_server_config() {
+    let genesis = GenesisConfig::generate(
+        NetworkProtocol::Udp,
+        "127.0.0.1",
+        9100,
+        2,
+        4,
+        0,
+        Balance::from(0),
+    );
+    let mut committee = genesis.committee;
+    let mut servers = genesis.servers;
+    committee.check_server_config(&servers[0], None).unwrap();
+
+    // Wrong key.
+    servers[0].key = AuthorityKey::Plain(get_key_pair().1);
+    assert!(committee.check_server_config(&servers[0], None).is_err());
+
+    // Port not as listed.
+    servers[1].authority.base_port += 1;
+    assert!(committee.check_server_config(&servers[1], None).is_err());
+    servers[1].authority.base_port -= 1;
+    committee.check_server_config(&servers[1], None).unwrap();
+
+    // Unknown authority.
+    committee.authorities.remove(1);
+    assert!(committee.check_server_config(&servers[1], None).is_err());
+}
+
+fn write_initial_accounts(path: &str, num_accounts: usize) -> InitialStateConfig {
+    let config = InitialStateConfig {
+        accounts: (0..num_accounts)
+            .map(|i| (get_key_pair().0, Balance::from(i as i128)))
+            .collect(),
+    };
+    config.write(path).unwrap();
+    config
+}
+
+#[test]
+fn test_read_sharded_initial_accounts() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("initial_accounts.txt");
+    let path = path.to_str().unwrap();
+    let config = write_initial_accounts(path, 1000);
+
+    let sequential = InitialStateConfig::read_sharded(path, 4, 1).unwrap();
+    let parallel = InitialStateConfig::read_sharded(path, 4, 7).unwrap();
+    assert_eq!(sequential, parallel);
+    assert_eq!(sequential.iter().map(BTreeMap::len).sum::<usize>(), 1000);
+    for (shard, accounts) in sequential.iter().enumerate() {
+        for address in accounts.keys() {
+            assert_eq!(AuthorityState::get_shard(4, address), shard as u32);
+        }
+    }
+    for (address, balance) in &config.accounts {
+        let shard = AuthorityState::get_shard(4, address) as usize;
+        assert_eq!(sequential[shard].get(address), Some(balance));
+    }
+
+    // Duplicates are found within and across the chunks of the file.
+    let mut file = OpenOptions::new().append(true).open(path).unwrap();
+    let (address, _) = config.accounts[0];
+    writeln!(file, "{}:1", encode_address(&address)).unwrap();
+    assert!(InitialStateConfig::read_sharded(path, 4, 1).is_err());
+    assert!(InitialStateConfig::read_sharded(path, 4, 7).is_err());
+}
+
+/// Run with `cargo test --release -- --ignored --nocapture bench_read_sharded`.
+#[test]
+#[ignore]
+fn bench_read_sharded_initial_accounts() {
+    let dir = tempdir().unwrap();
+    let path = dir.path().join("initial_accounts.txt");
+    let path = path.to_str().unwrap();
+    write_initial_accounts(path, 200_000);
+
+    let start = std::time::Instant::now();
+    let sequential = InitialStateConfig::read_sharded(path, 16, 1).unwrap();
+    let sequential_time = start.elapsed();
+    let start = std::time::Instant::now();
+    let parallel = InitialStateConfig::read_sharded(path, 16, 8).unwrap();
+    let parallel_time = start.elapsed();
+    println!(
+        "Loaded 200000 accounts in {:?} with 1 thread and {:?} with 8 threads",
+        sequential_time, parallel_time
+    );
+    assert_eq!(sequential, parallel);
+    assert!(parallel_time < sequential_time);
+}
+
+#[test]
+fn test_check_port_ranges() {
+    let mut config = CommitteeConfig {
+        version: CONFIG_VERSION,
+        authorities: vec![make_authority_config(9100), make_authority_config(9104)],
+        recipient_policy: RecipientPolicy::default(),
+        features: BTreeSet::new(),
+        order_acceptance_window: None,
+        chain_id: 0,
+        genesis_timestamp: 0,
+        transfer_cooldown_ms: None,
+        strictly_increasing_transfer_times: false,
+    };
+    config.check_port_ranges().unwrap();
+
+    config.authorities[1].base_port = 9102;
+    let error = config.check_port_ranges().unwrap_err().to_string();
+    assert!(error.contains("ports 9102..9104 of host 127.0.0.1"));
+    assert!(error.contains(&encode_address(&config.authorities[0].address)));
+    assert!(error.contains(&encode_address(&config.authorities[1].address)));
+
+    // Authorities on different hosts may use the same ports.
+    config.authorities[1].host = "127.0.0.2".to_string();
+    config.check_port_ranges().unwrap();
+
+    // Ports must fit in 16 bits.
+    config.authorities[1].base_port = 65532;
+    config.check_port_ranges().unwrap();
+    config.authorities[1].base_port = 65533;
+    let error = config.check_port_ranges().unwrap_err().to_string();
+    assert!(error.contains("out of range"));
+    config.authorities[1].base_port = u32::MAX;
+    assert!(config.check_port_ranges().is_err());
+}
+
+#[test]
+fn test_check_protocols() {
+    let mut config = CommitteeConfig {
+        version: CONFIG_VERSION,
+        authorities: vec![make_authority_config(9100), make_authority_config(9104)],
+        recipient_policy: RecipientPolicy::default(),
+        features: BTreeSet::new(),
+        order_acceptance_window: None,
+        chain_id: 0,
+        genesis_timestamp: 0,
+        transfer_cooldown_ms: None,
+        strictly_increasing_transfer_times: false,
+    };
+    config.check_protocols().unwrap();
+
+    config.authorities[1].network_protocol = NetworkProtocol::Tcp;
+    let error = config.check_protocols().unwrap_err().to_string();
+    assert!(error.contains("different network protocols (Udp and Tcp)"));
+    assert!(error.contains(&encode_address(&config.authorities[1].address)));
+}
+
+#[test]
+fn test_validate_committee() {
+    let make_config = || CommitteeConfig {
+        version: CONFIG_VERSION,
+        authorities: (0..4)
+            .map(|i| make_authority_config(9100 + 4 * i))
+            .collect(),
+        recipient_policy: RecipientPolicy::default(),
+        features: BTreeSet::new(),
+        order_acceptance_window: None,
+        chain_id: 0,
+        genesis_timestamp: 0,
+        transfer_cooldown_ms: None,
+        strictly_increasing_transfer_times: false,
+    };
+    make_config().validate().unwrap();
+
+    let mut empty = make_config();
+    empty.authorities.clear();
+    let error = empty.validate().unwrap_err().to_string();
+    assert_eq!(
+        error,
+        "inconsistent committee: the committee has no authorities"
+    );
+
+    // A duplicate entry does not count as a vote, so the thresholds would not match the
+    // number of entries.
+    let mut duplicated = make_config();
+    duplicated.authorities[3].address = duplicated.authorities[0].address;
+    assert_eq!(duplicated.make_committee().quorum_threshold(), 3);
+    let error = duplicated.validate().unwrap_err().to_string();
+    assert!(error.contains(&format!(
+        "authority {} is listed 2 times",
+        encode_address(&duplicated.authorities[0].address)
+    )));
+
+    // All the problems are listed.
+    let mut unsharded = make_config();
+    unsharded.authorities[1].num_shards = 0;
+    unsharded.authorities[2].base_port = 9100;
+    let error = unsharded.validate().unwrap_err().to_string();
+    assert!(error.contains(&format!(
+        "authority {} has no shards",
+        encode_address(&unsharded.authorities[1].address)
+    )));
+    assert!(error.contains("ports 9100..9104 of host 127.0.0.1"));
+}
+
+#[test]
+fn test_shard_range() {
+    let range: ShardRange = "4..8".parse().unwrap();
+    assert_eq!(range, ShardRange { start: 4, end: 8 });
+    assert_eq!(range.to_string(), "4..8");
+    assert_eq!(range.shards().collect::<Vec<_>>(), vec![4, 5, 6, 7]);
+    assert!("4-8".parse::<ShardRange>().is_err());
+    assert!("4..".parse::<ShardRange>().is_err());
+    assert!("a..8".parse::<ShardRange>().is_err());
+    assert!("0..4..8".parse::<ShardRange>().is_err());
+
+    range.check(8).unwrap();
+    assert!(range
+        .check(7)
+        .unwrap_err()
+        .to_string()
+        .contains("out of the 7 shards"));
+    assert!(ShardRange { start: 3, end: 3 }
+        .check(8)
+        .unwrap_err()
+        .to_string()
+        .contains("empty"));
+    ShardRange::single(7).check(8).unwrap();
+    assert!(ShardRange::single(8).check(8).is_err());
+    assert_eq!(ShardRange::all(8), "0..8".parse().unwrap());
+}
@@ -0,0 +1,25 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+
+#[test]
+fn test_crypto_benchmark() {
+    let benchmark = CryptoBenchmark::run(32, &[1, 64]);
+    assert!(benchmark.signatures_per_sec > 0.0);
+    assert!(benchmark.verifications_per_sec > 0.0);
+    let sizes: Vec<_> = benchmark
+        .batch_verifications_per_sec
+        .iter()
+        .map(|(size, _)| *size)
+        .collect();
+    assert_eq!(sizes, vec![1, 64]);
+    let batch_rate = benchmark.batch_verifications_per_sec[1].1;
+    // Large batches amortize the cost of batch verification. (They also beat `verify`,
+    // but by a margin too small to check reliably in debug builds.)
+    assert!(batch_rate > benchmark.batch_verifications_per_sec[0].1);
+
+    let table = benchmark.to_string();
+    assert_eq!(table.lines().count(), 5);
+    assert!(table.lines().last().unwrap().starts_with("verify batch"));
+}
@@ -0,0 +1,235 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use crate::{
+    network::{Client, Server},
+    transport::NetworkProtocol,
+};
+use fastpay_core::{
+    authority::*,
+    base_types::*,
+    client::AuthorityClient,
+    committee::{Committee, SigningDomain},
+    messages::*,
+};
+use std::collections::BTreeMap;
+use tokio::runtime::Runtime;
+
+fn receive_lines(listener: &UdpSocket) -> Vec<String> {
+    let mut buf = [0u8; MAX_STATSD_PAYLOAD];
+    let size = listener.recv(&mut buf).unwrap();
+    String::from_utf8(buf[..size].to_vec())
+        .unwrap()
+        .lines()
+        .map(String::from)
+        .collect()
+}
+
+#[test]
+fn test_statsd_export() {
+    let (authority, secret) = get_key_pair();
+    let mut voting_rights = BTreeMap::new();
+    voting_rights.insert(authority, 1);
+    let mut state = AuthorityState::new(Committee::new(voting_rights), authority, secret);
+    let (sender, sender_key) = get_key_pair();
+    let mut account = AccountOffchainState::new();
+    account.balance = Balance::from(10);
+    state.accounts.insert(sender, account);
+    let order = TransferOrder::new(
+        Transfer {
+            sender,
+            recipient: Address::FastPay(get_key_pair().0),
+            amount: Amount::from(3),
+            sequence_number: SequenceNumber::new(),
+            user_data: UserData::default(),
+            account_state: None,
+            domain: SigningDomain::default(),
+        },
+        &sender_key,
+    );
+    let vote = SignedTransferOrder::new(order.clone(), state.name, &state.secret);
+    let certificate = CertifiedTransferOrder {
+        value: order,
+        signatures: vec![(vote.authority, vote.signature)],
+    };
+
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut exporter = StatsdExporter::new(
+        &listener.local_addr().unwrap().to_string(),
+        "fastpay".to_string(),
+    )
+    .unwrap();
+
+    let port = UdpSocket::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap()
+        .port();
+    let server = Server::new(
+        NetworkProtocol::Udp,
+        "127.0.0.1".to_string(),
+        port.into(),
+        state,
+        /* buffer_size */ 65507,
+        /* cross_shard_queue_size */ 10,
+    )
+    .with_label("alpha".to_string());
+    let stats = vec![server.stats()];
+
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let server = server.spawn().await.unwrap();
+        let mut client = Client::new(
+            NetworkProtocol::Udp,
+            "127.0.0.1".to_string(),
+            port.into(),
+            /* num_shards */ 1,
+            /* buffer_size */ 65507,
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+        );
+        client
+            .handle_confirmation_order(ConfirmationOrder::new(certificate))
+            .await
+            .unwrap();
+        server.kill().await.unwrap();
+    });
+
+    exporter.export(&stats).unwrap();
+    let lines = receive_lines(&listener);
+    assert!(lines.contains(&"fastpay.alpha.shard0.transfers_confirmed:1|c".to_string()));
+    assert!(lines.contains(&"fastpay.alpha.shard0.requests_served:1|c".to_string()));
+    assert!(lines.contains(&"fastpay.alpha.shard0.highest_sequence_number:1|g".to_string()));
+    // Counters without increments are not sent again, gauges are.
+    exporter.export(&stats).unwrap();
+    let lines = receive_lines(&listener);
+    assert!(lines
+        .iter()
+        .all(|line| !line.contains("transfers_confirmed")));
+    assert!(lines.contains(&"fastpay.alpha.shard0.highest_sequence_number:1|g".to_string()));
+}
+
+#[test]
+fn test_statsd_payloads_are_split() {
+    let listener = UdpSocket::bind("127.0.0.1:0").unwrap();
+    listener
+        .set_read_timeout(Some(Duration::from_secs(5)))
+        .unwrap();
+    let mut exporter =
+        StatsdExporter::new(&listener.local_addr().unwrap().to_string(), "x".repeat(500)).unwrap();
+    let stats: Vec<_> = (0..4)
+        .map(|shard| {
+            let (authority, secret) = get_key_pair();
+            let mut voting_rights = BTreeMap::new();
+            voting_rights.insert(authority, 1);
+            let state = AuthorityState::new_shard(
+                Committee::new(voting_rights),
+                authority,
+                secret,
+                shard,
+                4,
+            );
+            Server::new(
+                NetworkProtocol::Udp,
+                "127.0.0.1".to_string(),
+                0,
+                state,
+                /* buffer_size */ 65507,
+                /* cross_shard_queue_size */ 10,
+            )
+            .stats()
+        })
+        .collect();
+    exporter.export(&stats).unwrap();
+    // Only gauges are sent, since nothing happened.
+    let mut lines = Vec::new();
+    let mut datagrams = 0;
+    while lines.len() < 4 * 4 {
+        lines.extend(receive_lines(&listener));
+        datagrams += 1;
+    }
+    assert_eq!(lines.len(), 4 * 4);
+    assert!(lines.iter().all(|line| line.ends_with("|g")));
+    assert!(datagrams > 1);
+}
+
+fn scrape(address: std::net::SocketAddr) -> String {
+    let mut stream = TcpStream::connect(address).unwrap();
+    stream
+        .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+        .unwrap();
+    let mut response = String::new();
+    stream.read_to_string(&mut response).unwrap();
+    assert!(response.starts_with("HTTP/1.1 200 OK\r\n"));
+    response
+}
+
+#[test]
+fn test_prometheus_endpoint() {
+    let (authority, secret) = get_key_pair();
+    let mut voting_rights = BTreeMap::new();
+    voting_rights.insert(authority, 1);
+    let stats: Vec<_> = (0..2)
+        .map(|shard| {
+            let state = AuthorityState::new_shard(
+                Committee::new(voting_rights.clone()),
+                authority,
+                secret.copy(),
+                shard,
+                2,
+            );
+            Server::new(
+                NetworkProtocol::Udp,
+                "127.0.0.1".to_string(),
+                0,
+                state,
+                /* buffer_size */ 65507,
+                /* cross_shard_queue_size */ 10,
+            )
+            .with_label("alpha".to_string())
+            .stats()
+        })
+        .collect();
+    let exporter = PrometheusExporter::bind("127.0.0.1:0", "fastpay".to_string()).unwrap();
+    let address = exporter.local_addr().unwrap();
+    exporter.spawn(stats).unwrap();
+
+    let response = scrape(address);
+    for shard in 0..2 {
+        let labels = format!("{{label=\"alpha\",shard=\"{}\"}}", shard);
+        for name in &[
+            "fastpay_requests_served_total",
+            "fastpay_cross_shard_messages_sent_total",
+            "fastpay_cross_shard_messages_received_total",
+            "fastpay_verification_seconds_count",
+        ] {
+            assert!(response.contains(&format!("\n{}{} 0\n", name, labels)));
+        }
+        assert!(response.contains(&format!(
+            "\nfastpay_verification_seconds_bucket{{label=\"alpha\",shard=\"{}\",le=\"+Inf\"}} 0\n",
+            shard
+        )));
+    }
+    assert!(response.contains("\n# TYPE fastpay_requests_served_total counter\n"));
+    assert!(response.contains("\n# TYPE fastpay_verification_seconds histogram\n"));
+    // Each scrape uses a new connection.
+    assert!(scrape(address).contains("fastpay_highest_sequence_number{"));
+}
+
+#[test]
+fn test_histogram() {
+    let histogram = Histogram::default();
+    histogram.observe(Duration::from_micros(80));
+    histogram.observe(Duration::from_micros(100));
+    histogram.observe(Duration::from_secs(1));
+    let snapshot = histogram.snapshot();
+    assert_eq!(snapshot.count, 3);
+    assert_eq!(snapshot.sum_micros, 1_000_180);
+    assert_eq!(snapshot.buckets[0], (50, 0));
+    assert_eq!(snapshot.buckets[1], (100, 2));
+    assert_eq!(snapshot.buckets.last(), Some(&(100_000, 2)));
+}
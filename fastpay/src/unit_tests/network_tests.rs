@@ -0,0 +1,1107 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use fastpay_core::{
+    clock::ManualClock,
+    committee::{Committee, Feature, SigningDomain},
+    messages::Address,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use tokio::runtime::Runtime;
+
+fn get_new_local_port() -> u32 {
+    let socket = std::net::UdpSocket::bind("127.0.0.1:0").unwrap();
+    socket.local_addr().unwrap().port().into()
+}
+
+fn make_state() -> AuthorityState {
+    let (authority, secret) = get_key_pair();
+    let mut voting_rights = BTreeMap::new();
+    voting_rights.insert(authority, 1);
+    AuthorityState::new(Committee::new(voting_rights), authority, secret)
+}
+
+fn make_client(protocol: NetworkProtocol, port: u32) -> Client {
+    Client::new(
+        protocol,
+        "127.0.0.1".to_string(),
+        port,
+        /* num_shards */ 1,
+        /* buffer_size */ 65507,
+        Duration::from_secs(1),
+        Duration::from_secs(1),
+    )
+}
+
+async fn spawn_server(
+    protocol: NetworkProtocol,
+    port: u32,
+    state: AuthorityState,
+) -> SpawnedServer {
+    make_server(protocol, port, state).spawn().await.unwrap()
+}
+
+fn make_server(protocol: NetworkProtocol, port: u32, state: AuthorityState) -> Server {
+    Server::new(
+        protocol,
+        "127.0.0.1".to_string(),
+        port,
+        state,
+        /* buffer_size */ 65507,
+        /* cross_shard_queue_size */ 10,
+    )
+}
+
+fn make_certificate(state: &AuthorityState, order: TransferOrder) -> CertifiedTransferOrder {
+    let vote = SignedTransferOrder::new(order.clone(), state.name, &state.secret);
+    let mut builder = SignatureAggregator::try_new(order, &state.committee).unwrap();
+    builder
+        .append(vote.authority, vote.signature)
+        .unwrap()
+        .unwrap()
+}
+
+async fn get_balance(client: &mut Client, address: FastPayAddress) -> Balance {
+    client
+        .handle_account_info_request(AccountInfoRequest {
+            sender: address,
+            request_sequence_number: None,
+            request_received_transfers_excluding_first_nth: None,
+            observer: false,
+        })
+        .await
+        .unwrap()
+        .balance
+}
+
+#[test]
+fn test_ping() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let port = get_new_local_port();
+        let server = spawn_server(NetworkProtocol::Udp, port, make_state()).await;
+        let mut client = make_client(NetworkProtocol::Udp, port);
+        let measurement = client.ping(0).await.unwrap();
+        assert!(measurement.rtt > Duration::from_micros(0));
+        assert!(measurement.rtt < Duration::from_secs(1));
+        server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn test_tenants_are_isolated() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (sender, sender_key) = get_key_pair();
+        let recipient = get_key_pair().0;
+        let mut states = Vec::new();
+        for _ in 0..2 {
+            let mut state = make_state();
+            state.accounts.insert(sender, AccountOffchainState::new());
+            state.accounts.get_mut(&sender).unwrap().balance = Balance::from(10);
+            states.push(state);
+        }
+        let transfer = Transfer {
+            sender,
+            recipient: Address::FastPay(recipient),
+            amount: Amount::from(3),
+            sequence_number: SequenceNumber::new(),
+            user_data: UserData::default(),
+            account_state: None,
+            domain: SigningDomain::default(),
+        };
+        let certificate = make_certificate(&states[0], TransferOrder::new(transfer, &sender_key));
+
+        let mut servers = Vec::new();
+        let mut clients = Vec::new();
+        for (tenant, state) in states.into_iter().enumerate() {
+            let port = get_new_local_port();
+            let server = make_server(NetworkProtocol::Udp, port, state)
+                .with_tenant(format!("tenant-{}", tenant));
+            servers.push(server.spawn().await.unwrap());
+            clients.push(make_client(NetworkProtocol::Udp, port));
+        }
+
+        clients[0]
+            .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+            .await
+            .unwrap();
+        assert_eq!(get_balance(&mut clients[0], sender).await, Balance::from(7));
+        assert_eq!(
+            get_balance(&mut clients[1], sender).await,
+            Balance::from(10)
+        );
+        // The certificate of one committee is meaningless to the other one.
+        assert!(clients[1]
+            .handle_confirmation_order(ConfirmationOrder::new(certificate))
+            .await
+            .is_err());
+        assert_eq!(
+            get_balance(&mut clients[1], sender).await,
+            Balance::from(10)
+        );
+
+        for server in servers {
+            server.kill().await.unwrap();
+        }
+    });
+}
+
+#[test]
+fn test_recipient_confirmation_with_sender_shard_down() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let num_shards = 2;
+        let (authority, secret) = get_key_pair();
+        let mut voting_rights = BTreeMap::new();
+        voting_rights.insert(authority, 1);
+        let mut committee = Committee::new(voting_rights);
+        committee.features.insert(Feature::RecipientClaims);
+        let make_shard = |shard| {
+            AuthorityState::new_shard(
+                committee.clone(),
+                authority,
+                secret.copy(),
+                shard,
+                num_shards,
+            )
+        };
+        // Find a sender and a recipient handled by different shards.
+        let (sender, sender_key) = get_key_pair();
+        let sender_shard = AuthorityState::get_shard(num_shards, &sender);
+        let recipient = loop {
+            let (address, _) = get_key_pair();
+            if AuthorityState::get_shard(num_shards, &address) != sender_shard {
+                break address;
+            }
+        };
+        let recipient_shard = 1 - sender_shard;
+
+        let mut sender_state = make_shard(sender_shard);
+        sender_state
+            .accounts
+            .insert(sender, AccountOffchainState::new());
+        sender_state.accounts.get_mut(&sender).unwrap().balance = Balance::from(10);
+        let transfer = Transfer {
+            sender,
+            recipient: Address::FastPay(recipient),
+            amount: Amount::from(3),
+            sequence_number: SequenceNumber::new(),
+            user_data: UserData::default(),
+            account_state: None,
+            domain: SigningDomain::default(),
+        };
+        let certificate =
+            make_certificate(&sender_state, TransferOrder::new(transfer, &sender_key));
+        // The sender's shard confirms the transfer then goes offline before any
+        // cross-shard update is delivered.
+        sender_state
+            .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+            .unwrap();
+        drop(sender_state);
+
+        // Only the shard of the recipient is running.
+        let base_port = get_new_local_port() - recipient_shard;
+        let server = Server::new(
+            NetworkProtocol::Udp,
+            "127.0.0.1".to_string(),
+            base_port,
+            make_shard(recipient_shard),
+            /* buffer_size */ 65507,
+            /* cross_shard_queue_size */ 10,
+        )
+        .spawn()
+        .await
+        .unwrap();
+        let mut client = Client::new(
+            NetworkProtocol::Udp,
+            "127.0.0.1".to_string(),
+            base_port,
+            num_shards,
+            /* buffer_size */ 65507,
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+        );
+        let info = client
+            .handle_recipient_confirmation_order(ConfirmationOrder::new(certificate))
+            .await
+            .unwrap();
+        assert_eq!(info.sender, recipient);
+        assert_eq!(info.balance, Balance::from(3));
+        assert_eq!(get_balance(&mut client, recipient).await, Balance::from(3));
+        server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn test_cross_shard_retries_until_shard_is_up() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let num_shards = 2;
+        let (authority, secret) = get_key_pair();
+        let mut voting_rights = BTreeMap::new();
+        voting_rights.insert(authority, 1);
+        let committee = Committee::new(voting_rights);
+        let make_shard = |shard| {
+            AuthorityState::new_shard(
+                committee.clone(),
+                authority,
+                secret.copy(),
+                shard,
+                num_shards,
+            )
+        };
+        let (sender, sender_key) = get_key_pair();
+        let sender_shard = AuthorityState::get_shard(num_shards, &sender);
+        let recipient = loop {
+            let (address, _) = get_key_pair();
+            if AuthorityState::get_shard(num_shards, &address) != sender_shard {
+                break address;
+            }
+        };
+        let recipient_shard = 1 - sender_shard;
+        let base_port = get_new_local_port() - sender_shard;
+        let make_server = |state| {
+            Server::new(
+                NetworkProtocol::Tcp,
+                "127.0.0.1".to_string(),
+                base_port,
+                state,
+                /* buffer_size */ 65507,
+                /* cross_shard_queue_size */ 10,
+            )
+        };
+
+        let mut sender_state = make_shard(sender_shard);
+        sender_state
+            .accounts
+            .insert(sender, AccountOffchainState::new());
+        sender_state.accounts.get_mut(&sender).unwrap().balance = Balance::from(10);
+        let transfer = Transfer {
+            sender,
+            recipient: Address::FastPay(recipient),
+            amount: Amount::from(3),
+            sequence_number: SequenceNumber::new(),
+            user_data: UserData::default(),
+            account_state: None,
+            domain: SigningDomain::default(),
+        };
+        let certificate =
+            make_certificate(&sender_state, TransferOrder::new(transfer, &sender_key));
+        let config = CrossShardConfig {
+            retries: 20,
+            initial_backoff_ms: 50,
+            max_backoff_ms: 100,
+            jitter: Jitter::None,
+            ..CrossShardConfig::default()
+        };
+        let sender_server = make_server(sender_state)
+            .with_cross_shard_config(config)
+            .spawn()
+            .await
+            .unwrap();
+        let mut client = Client::new(
+            NetworkProtocol::Tcp,
+            "127.0.0.1".to_string(),
+            base_port,
+            num_shards,
+            /* buffer_size */ 65507,
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+        );
+        client
+            .handle_confirmation_order(ConfirmationOrder::new(certificate))
+            .await
+            .unwrap();
+
+        // The shard of the recipient starts after a few failed attempts.
+        tokio::time::delay_for(Duration::from_millis(300)).await;
+        let recipient_server = make_server(make_shard(recipient_shard))
+            .spawn()
+            .await
+            .unwrap();
+        let request = AccountInfoRequest {
+            sender: recipient,
+            request_sequence_number: None,
+            request_received_transfers_excluding_first_nth: None,
+            observer: false,
+        };
+        let mut balance = None;
+        for _ in 0..50 {
+            // The account of the recipient is unknown until the update is delivered.
+            let response = client.handle_account_info_request(request.clone()).await;
+            balance = response.ok().map(|info| info.balance);
+            if balance.is_some() {
+                break;
+            }
+            tokio::time::delay_for(Duration::from_millis(50)).await;
+        }
+        assert_eq!(balance, Some(Balance::from(3)));
+        sender_server.kill().await.unwrap();
+        recipient_server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn test_shutdown_report() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (sender, sender_key) = get_key_pair();
+        let mut state = make_state();
+        state.accounts.insert(sender, AccountOffchainState::new());
+        state.accounts.get_mut(&sender).unwrap().balance = Balance::from(10);
+        let transfer = Transfer {
+            sender,
+            recipient: Address::FastPay(get_key_pair().0),
+            amount: Amount::from(3),
+            sequence_number: SequenceNumber::new(),
+            user_data: UserData::default(),
+            account_state: None,
+            domain: SigningDomain::default(),
+        };
+        let certificate = make_certificate(&state, TransferOrder::new(transfer, &sender_key));
+
+        let port = get_new_local_port();
+        let server = make_server(NetworkProtocol::Udp, port, state)
+            .with_tenant("t".to_string())
+            .with_label("alpha".to_string());
+        let stats = server.stats();
+        let server = server.spawn().await.unwrap();
+        let mut client = make_client(NetworkProtocol::Udp, port);
+        client.ping(0).await.unwrap();
+        client
+            .handle_confirmation_order(ConfirmationOrder::new(certificate))
+            .await
+            .unwrap();
+        assert_eq!(get_balance(&mut client, sender).await, Balance::from(7));
+        let unknown = get_key_pair().0;
+        assert!(client
+            .handle_account_info_request(AccountInfoRequest {
+                sender: unknown,
+                request_sequence_number: None,
+                request_received_transfers_excluding_first_nth: None,
+                observer: false,
+            })
+            .await
+            .is_err());
+        server.kill().await.unwrap();
+
+        let report = stats.report();
+        assert_eq!(report.tenant.as_deref(), Some("t"));
+        assert_eq!(report.label.as_deref(), Some("alpha"));
+        assert!(report.to_string().starts_with("[t] alpha shard 0 "));
+        assert_eq!(report.shard, 0);
+        assert_eq!(report.requests_served, 4);
+        assert_eq!(report.user_errors, 1);
+        assert_eq!(report.transfers_confirmed, 1);
+        assert_eq!(report.pending_cross_shard_messages, 0);
+    });
+}
+
+#[test]
+fn test_sequence_number_gauges() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let (sender, sender_key) = get_key_pair();
+        let (stalled, stalled_key) = get_key_pair();
+        let mut state = make_state();
+        for address in &[sender, stalled] {
+            state.accounts.insert(*address, AccountOffchainState::new());
+            state.accounts.get_mut(address).unwrap().balance = Balance::from(10);
+        }
+        let make_order = |sender, key, sequence_number| {
+            let transfer = Transfer {
+                sender,
+                recipient: Address::FastPay(get_key_pair().0),
+                amount: Amount::from(1),
+                sequence_number,
+                user_data: UserData::default(),
+                account_state: None,
+                domain: SigningDomain::default(),
+            };
+            TransferOrder::new(transfer, key)
+        };
+        let certificates: Vec<_> = (0..2)
+            .map(|i| {
+                let order = make_order(sender, &sender_key, SequenceNumber::from(i));
+                make_certificate(&state, order)
+            })
+            .collect();
+        let stalled_order = make_order(stalled, &stalled_key, SequenceNumber::new());
+
+        let port = get_new_local_port();
+        let server = make_server(NetworkProtocol::Udp, port, state);
+        let stats = server.stats();
+        let server = server.spawn().await.unwrap();
+        let mut client = make_client(NetworkProtocol::Udp, port);
+        for certificate in certificates {
+            client
+                .handle_transfer_order(certificate.value.clone())
+                .await
+                .unwrap();
+            client
+                .handle_confirmation_order(ConfirmationOrder::new(certificate))
+                .await
+                .unwrap();
+        }
+        client.handle_transfer_order(stalled_order).await.unwrap();
+        server.kill().await.unwrap();
+
+        assert_eq!(stats.highest_sequence_number(), SequenceNumber::from(2));
+        assert_eq!(stats.stalled_accounts(Duration::from_secs(0)), 1);
+        assert_eq!(stats.stalled_accounts(Duration::from_secs(3600)), 0);
+    });
+}
+
+#[test]
+fn test_committee_info() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let port = get_new_local_port();
+        let state = make_state();
+        let name = state.name;
+        let secret = serde_json::to_string(&state.secret).unwrap();
+        let committee_config = crate::config::CommitteeConfig {
+            version: crate::config::CONFIG_VERSION,
+            authorities: vec![crate::config::AuthorityConfig {
+                network_protocol: NetworkProtocol::Udp,
+                address: name,
+                host: "127.0.0.1".to_string(),
+                base_port: port,
+                num_shards: 1,
+                label: None,
+                operator_info: None,
+            }],
+            recipient_policy: Default::default(),
+            features: vec![Feature::RecipientClaims].into_iter().collect(),
+            order_acceptance_window: Some(5),
+            chain_id: 0,
+            genesis_timestamp: 0,
+            transfer_cooldown_ms: None,
+            strictly_increasing_transfer_times: false,
+        };
+        let description = serde_json::to_string(&committee_config).unwrap();
+
+        // Servers only answer if they were given a description.
+        let server = spawn_server(NetworkProtocol::Udp, port, make_state()).await;
+        let mut client = make_client(NetworkProtocol::Udp, port);
+        assert!(client.committee_info().await.is_err());
+        server.kill().await.unwrap();
+
+        let server = make_server(NetworkProtocol::Udp, port, state)
+            .with_committee_description(description.clone())
+            .spawn()
+            .await
+            .unwrap();
+        let response = client.committee_info().await.unwrap();
+        assert_eq!(response.authority, name);
+        assert_eq!(response.description, description);
+        assert!(!response.description.contains(&secret[1..secret.len() - 1]));
+
+        let config = committee_config.check_committee_info(&[response]).unwrap();
+        assert_eq!(serde_json::to_string(&config).unwrap(), description);
+        server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn test_peer_committee_mismatch() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let ports: Vec<_> = (0..3).map(|_| get_new_local_port()).collect();
+        let states: Vec<_> = (0..2).map(|_| make_state()).collect();
+        let names: Vec<_> = states
+            .iter()
+            .map(|state| state.name)
+            .chain(std::iter::once(get_key_pair().0))
+            .collect();
+        let committee_config = crate::config::CommitteeConfig {
+            version: crate::config::CONFIG_VERSION,
+            authorities: names
+                .iter()
+                .zip(&ports)
+                .map(|(name, port)| crate::config::AuthorityConfig {
+                    network_protocol: NetworkProtocol::Udp,
+                    address: *name,
+                    host: "127.0.0.1".to_string(),
+                    base_port: *port,
+                    num_shards: 1,
+                    label: None,
+                    operator_info: None,
+                })
+                .collect(),
+            recipient_policy: Default::default(),
+            features: BTreeSet::new(),
+            order_acceptance_window: None,
+            chain_id: 0,
+            genesis_timestamp: 0,
+            transfer_cooldown_ms: None,
+            strictly_increasing_transfer_times: false,
+        };
+        let description = serde_json::to_string(&committee_config).unwrap();
+        // The second authority was given a config that drifted.
+        let mut drifted_config: crate::config::CommitteeConfig =
+            serde_json::from_str(&description).unwrap();
+        drifted_config.chain_id = 1;
+        let drifted_description = serde_json::to_string(&drifted_config).unwrap();
+        let mut servers = Vec::new();
+        for ((state, port), description) in states
+            .into_iter()
+            .zip(&ports)
+            .zip(vec![description, drifted_description.clone()])
+        {
+            let server = make_server(NetworkProtocol::Udp, *port, state)
+                .with_committee_description(description)
+                .spawn()
+                .await
+                .unwrap();
+            servers.push(server);
+        }
+
+        // The third authority starts and checks the others.
+        let peers = check_peer_committees(
+            &committee_config,
+            names[2],
+            65507,
+            Duration::from_millis(500),
+        )
+        .await;
+        assert_eq!(
+            peers,
+            vec![
+                (names[0], PeerCommittee::Same),
+                (
+                    names[1],
+                    PeerCommittee::Different {
+                        fingerprint: committee_fingerprint(&drifted_description)
+                    }
+                ),
+            ]
+        );
+
+        // The first one cannot reach the third one, which is not running.
+        let peers = check_peer_committees(
+            &committee_config,
+            names[0],
+            65507,
+            Duration::from_millis(500),
+        )
+        .await;
+        assert_eq!(peers.len(), 2);
+        assert!(matches!(peers[0].1, PeerCommittee::Different { .. }));
+        assert_eq!(peers[1].0, names[2]);
+        assert!(matches!(peers[1].1, PeerCommittee::Unavailable { .. }));
+
+        for server in servers {
+            server.kill().await.unwrap();
+        }
+    });
+}
+
+#[test]
+fn test_isolated_shard_panic_is_contained() {
+    let ports: Vec<_> = (0..2).map(|_| get_new_local_port()).collect();
+    for port in &ports {
+        make_server(NetworkProtocol::Udp, *port, make_state())
+            .spawn_isolated(futures::future::pending(), Duration::from_secs(1))
+            .unwrap();
+    }
+    let faulty = run_isolated("faulty shard".to_string(), || async {
+        panic!("forced failure");
+    })
+    .unwrap();
+    let error = faulty.join().unwrap().unwrap_err();
+    assert_eq!(error, "faulty shard panicked: forced failure");
+
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        // Give the other threads time to bind their sockets.
+        time::delay_for(Duration::from_millis(100)).await;
+        for port in ports {
+            let mut client = make_client(NetworkProtocol::Udp, port);
+            assert!(client.ping(0).await.is_ok());
+        }
+    });
+}
+
+#[test]
+fn test_signed_cross_shard_updates() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let num_shards = 2;
+        let (authority, secret) = get_key_pair();
+        let mut voting_rights = BTreeMap::new();
+        voting_rights.insert(authority, 1);
+        let committee = Committee::new(voting_rights);
+        let (sender, sender_key) = get_key_pair();
+        let sender_shard = AuthorityState::get_shard(num_shards, &sender);
+        let recipient = loop {
+            let (address, _) = get_key_pair();
+            if AuthorityState::get_shard(num_shards, &address) != sender_shard {
+                break address;
+            }
+        };
+
+        let base_port = get_new_local_port();
+        let mut servers = Vec::new();
+        let mut certificate = None;
+        for shard in 0..num_shards {
+            let mut state = AuthorityState::new_shard(
+                committee.clone(),
+                authority,
+                secret.copy(),
+                shard,
+                num_shards,
+            );
+            state.sign_cross_shard_updates = true;
+            if shard == sender_shard {
+                state.accounts.insert(sender, AccountOffchainState::new());
+                state.accounts.get_mut(&sender).unwrap().balance = Balance::from(10);
+                let transfer = Transfer {
+                    sender,
+                    recipient: Address::FastPay(recipient),
+                    amount: Amount::from(3),
+                    sequence_number: SequenceNumber::new(),
+                    user_data: UserData::default(),
+                    account_state: None,
+                    domain: SigningDomain::default(),
+                };
+                certificate = Some(make_certificate(
+                    &state,
+                    TransferOrder::new(transfer, &sender_key),
+                ));
+            }
+            let server = Server::new(
+                NetworkProtocol::Udp,
+                "127.0.0.1".to_string(),
+                base_port,
+                state,
+                /* buffer_size */ 65507,
+                /* cross_shard_queue_size */ 10,
+            );
+            servers.push(server.spawn().await.unwrap());
+        }
+        let mut client = Client::new(
+            NetworkProtocol::Udp,
+            "127.0.0.1".to_string(),
+            base_port,
+            num_shards,
+            /* buffer_size */ 65507,
+            Duration::from_secs(1),
+            Duration::from_secs(1),
+        );
+        client
+            .handle_confirmation_order(ConfirmationOrder::new(certificate.unwrap()))
+            .await
+            .unwrap();
+        // The signed update is accepted by the shard of the recipient.
+        time::delay_for(Duration::from_millis(100)).await;
+        assert_eq!(get_balance(&mut client, recipient).await, Balance::from(3));
+        for server in servers {
+            server.kill().await.unwrap();
+        }
+    });
+}
+
+#[test]
+fn test_unknown_message_type() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let tag = NUM_MESSAGE_TYPES + 5;
+        let mut message = tag.to_le_bytes().to_vec();
+        message.extend_from_slice(b"from the future");
+
+        let port = get_new_local_port();
+        let server = make_server(NetworkProtocol::Udp, port, make_state());
+        let stats = server.stats();
+        let server = server.spawn().await.unwrap();
+        let mut client = make_client(NetworkProtocol::Udp, port);
+        let response = client
+            .send_recv_bytes_internal(0, message.clone())
+            .await
+            .unwrap();
+        match deserialize_message(&response[..]).unwrap() {
+            SerializedMessage::Error(error) => {
+                assert_eq!(*error, FastPayError::UnknownMessageType { tag })
+            }
+            _ => panic!("unexpected response"),
+        }
+        // Garbage with a known tag is only a decoding error.
+        let response = client
+            .send_recv_bytes_internal(0, vec![0, 0, 0, 0, 1])
+            .await
+            .unwrap();
+        match deserialize_message(&response[..]).unwrap() {
+            SerializedMessage::Error(error) => assert_eq!(*error, FastPayError::InvalidDecoding),
+            _ => panic!("unexpected response"),
+        }
+        server.kill().await.unwrap();
+        assert_eq!(stats.report().unknown_message_types, 1);
+
+        let server = make_server(NetworkProtocol::Udp, port, make_state())
+            .with_unknown_message_policy(UnknownMessagePolicy::Ignore);
+        let stats = server.stats();
+        let server = server.spawn().await.unwrap();
+        assert!(client.send_recv_bytes_internal(0, message).await.is_err());
+        server.kill().await.unwrap();
+        assert_eq!(stats.report().unknown_message_types, 1);
+    });
+}
+
+#[test]
+fn test_snapshot_on_shutdown() {
+    let (authority, secret) = get_key_pair();
+    let mut voting_rights = BTreeMap::new();
+    voting_rights.insert(authority, 1);
+    let committee = Committee::new(voting_rights);
+    let clock = Arc::new(ManualClock::new(1000));
+    let (sender, sender_key) = get_key_pair();
+    let recipient = get_key_pair().0;
+    let make_funded_state = || {
+        let mut state = AuthorityState::new(committee.clone(), authority, secret.copy());
+        state.clock = clock.clone();
+        state.accounts.insert(sender, AccountOffchainState::new());
+        state.accounts.get_mut(&sender).unwrap().balance = Balance::from(10);
+        state
+    };
+    let transfer = Transfer {
+        sender,
+        recipient: Address::FastPay(recipient),
+        amount: Amount::from(3),
+        sequence_number: SequenceNumber::new(),
+        user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
+    };
+    let state = make_funded_state();
+    let certificate = make_certificate(&state, TransferOrder::new(transfer, &sender_key));
+    // The same confirmation, applied locally.
+    let mut expected = make_funded_state();
+    expected
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shard0.snapshot");
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let port = get_new_local_port();
+        let server = make_server(NetworkProtocol::Udp, port, state)
+            .with_snapshot_on_shutdown(path.clone())
+            .spawn()
+            .await
+            .unwrap();
+        let mut client = make_client(NetworkProtocol::Udp, port);
+        client
+            .handle_confirmation_order(ConfirmationOrder::new(certificate))
+            .await
+            .unwrap();
+        server
+            .run_until(futures::future::ready(()), Duration::from_secs(1))
+            .await
+            .unwrap();
+    });
+
+    let mut restored = AuthorityState::new(committee.clone(), authority, secret.copy());
+    fastpay_core::persistence::restore_snapshot(&mut restored, &std::fs::read(&path).unwrap())
+        .unwrap();
+    assert_eq!(restored.accounts, expected.accounts);
+    assert_eq!(restored.accounts[&recipient].balance, Balance::from(3));
+    assert_eq!(
+        restored.last_transaction_index,
+        expected.last_transaction_index
+    );
+}
+
+#[test]
+fn test_state_file() {
+    let (authority, secret) = get_key_pair();
+    let mut voting_rights = BTreeMap::new();
+    voting_rights.insert(authority, 1);
+    let committee = Committee::new(voting_rights);
+    let address = get_key_pair().0;
+    let make_state_with_balance = |balance: i128| {
+        let mut state = AuthorityState::new(committee.clone(), authority, secret.copy());
+        state.accounts.insert(address, AccountOffchainState::new());
+        state.accounts.get_mut(&address).unwrap().balance = Balance::from(balance);
+        state
+    };
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shard0.snapshot");
+
+    // First boot: the initial accounts are written.
+    let server = make_server(NetworkProtocol::Udp, 0, make_state_with_balance(10))
+        .with_state_file(path.clone())
+        .unwrap();
+    assert_eq!(server.snapshot_path, Some(path.clone()));
+    let mut state = make_state_with_balance(0);
+    state.load(&path).unwrap();
+    assert_eq!(state.accounts[&address].balance, Balance::from(10));
+
+    // Later boots ignore the initial accounts.
+    let server = make_server(NetworkProtocol::Udp, 0, make_state_with_balance(20))
+        .with_state_file(path)
+        .unwrap();
+    assert_eq!(server.state.accounts[&address].balance, Balance::from(10));
+}
+
+#[test]
+fn test_dev_accounts_reload() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = get_key_pair().0;
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("initial_accounts.txt");
+    let initial_accounts = crate::config::InitialStateConfig {
+        accounts: vec![(sender, Balance::from(10))],
+    };
+    initial_accounts.write(path.to_str().unwrap()).unwrap();
+    let mut state = make_state();
+    state.reset_accounts(initial_accounts.accounts.clone());
+    let certificate = make_certificate(
+        &state,
+        TransferOrder::new(
+            Transfer {
+                sender,
+                recipient: Address::FastPay(recipient),
+                amount: Amount::from(3),
+                sequence_number: SequenceNumber::new(),
+                user_data: UserData::default(),
+                account_state: None,
+                domain: SigningDomain::default(),
+            },
+            &sender_key,
+        ),
+    );
+
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        // Servers reject the command unless they run in development mode.
+        let port = get_new_local_port();
+        let server = spawn_server(NetworkProtocol::Udp, port, make_state()).await;
+        let mut client = make_client(NetworkProtocol::Udp, port);
+        assert_eq!(
+            client.reload_accounts(0).await,
+            Err(FastPayError::DevModeRequired)
+        );
+        server.kill().await.unwrap();
+
+        let port = get_new_local_port();
+        let server = make_server(NetworkProtocol::Udp, port, state)
+            .with_dev_accounts_reload(path.to_str().unwrap().to_string())
+            .spawn()
+            .await
+            .unwrap();
+        let mut client = make_client(NetworkProtocol::Udp, port);
+        client
+            .handle_confirmation_order(ConfirmationOrder::new(certificate))
+            .await
+            .unwrap();
+        assert_eq!(get_balance(&mut client, sender).await, Balance::from(7));
+
+        let response = client.reload_accounts(0).await.unwrap();
+        assert_eq!(response.num_accounts, 1);
+        assert_eq!(get_balance(&mut client, sender).await, Balance::from(10));
+        let info = client
+            .handle_account_info_request(AccountInfoRequest {
+                sender,
+                request_sequence_number: None,
+                request_received_transfers_excluding_first_nth: None,
+                observer: false,
+            })
+            .await
+            .unwrap();
+        assert_eq!(info.next_sequence_number, SequenceNumber::new());
+        assert_eq!(
+            client
+                .handle_account_info_request(AccountInfoRequest {
+                    sender: recipient,
+                    request_sequence_number: None,
+                    request_received_transfers_excluding_first_nth: None,
+                    observer: false,
+                })
+                .await,
+            Err(FastPayError::UnknownSenderAccount)
+        );
+        server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn test_conflicting_orders_are_counted() {
+    let (sender, sender_key) = get_key_pair();
+    let mut state = make_state();
+    state.accounts.entry(sender).or_default().balance = Balance::from(10);
+    let make_order = |amount, sequence_number| {
+        TransferOrder::new(
+            Transfer {
+                sender,
+                recipient: Address::FastPay(get_key_pair().0),
+                amount: Amount::from(amount),
+                sequence_number: SequenceNumber::from(sequence_number),
+                user_data: UserData::default(),
+                account_state: None,
+                domain: SigningDomain::default(),
+            },
+            &sender_key,
+        )
+    };
+    let first_order = make_order(3, 0);
+
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let port = get_new_local_port();
+        let server = make_server(NetworkProtocol::Udp, port, state);
+        let stats = server.stats();
+        let server = server.spawn().await.unwrap();
+        let mut client = make_client(NetworkProtocol::Udp, port);
+        client
+            .handle_transfer_order(first_order.clone())
+            .await
+            .unwrap();
+        // Replaying the same order is fine, and an order for the next sequence number is
+        // only early.
+        client
+            .handle_transfer_order(first_order.clone())
+            .await
+            .unwrap();
+        assert!(client
+            .handle_transfer_order(make_order(3, 1))
+            .await
+            .is_err());
+        assert_eq!(stats.report().conflicting_orders, 0);
+
+        let error = client
+            .handle_transfer_order(make_order(4, 0))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            error,
+            FastPayError::PreviousTransferMustBeConfirmedFirst {
+                pending_confirmation: Box::new(first_order)
+            }
+        );
+        assert_eq!(stats.report().conflicting_orders, 1);
+        server.kill().await.unwrap();
+    });
+}
+
+/// Log of changes kept in memory, whose writes fail on demand.
+#[derive(Clone, Default)]
+struct FailingLog {
+    records: Arc<Mutex<Vec<WalRecord>>>,
+    failing: Arc<AtomicBool>,
+}
+
+impl RecordLog for FailingLog {
+    fn append(&mut self, record: &WalRecord) -> Result<(), io::Error> {
+        if self.failing.load(Ordering::Relaxed) {
+            return Err(io::Error::new(io::ErrorKind::WriteZero, "disk full"));
+        }
+        self.records.lock().unwrap().push(record.clone());
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), io::Error> {
+        self.records.lock().unwrap().clear();
+        Ok(())
+    }
+
+    fn compact(&mut self) -> Result<(), io::Error> {
+        Ok(())
+    }
+}
+
+#[test]
+fn test_read_only_on_storage_failure() {
+    let (sender, sender_key) = get_key_pair();
+    let mut state = make_state();
+    state.accounts.entry(sender).or_default().balance = Balance::from(10);
+    let make_order = |sequence_number| {
+        TransferOrder::new(
+            Transfer {
+                sender,
+                recipient: Address::FastPay(get_key_pair().0),
+                amount: Amount::from(3),
+                sequence_number: SequenceNumber::from(sequence_number),
+                user_data: UserData::default(),
+                account_state: None,
+                domain: SigningDomain::default(),
+            },
+            &sender_key,
+        )
+    };
+    let first_order = make_order(0);
+    let certificate = make_certificate(&state, first_order.clone());
+    let second_order = make_order(1);
+    let log = FailingLog::default();
+
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let port = get_new_local_port();
+        let server =
+            make_server(NetworkProtocol::Udp, port, state).with_record_log(Box::new(log.clone()));
+        let stats = server.stats();
+        let server = server.spawn().await.unwrap();
+        let mut client = make_client(NetworkProtocol::Udp, port);
+        client.handle_transfer_order(first_order).await.unwrap();
+        assert_eq!(log.records.lock().unwrap().len(), 1);
+
+        // The disk fills up while confirming the transfer.
+        log.failing.store(true, Ordering::Relaxed);
+        assert_eq!(
+            client
+                .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+                .await,
+            Err(FastPayError::StorageUnavailable)
+        );
+        assert!(stats.read_only());
+        assert!(client.ping(0).await.unwrap().read_only);
+        let metrics = stats.metrics();
+        let metric = |name| {
+            metrics
+                .iter()
+                .find(|metric| metric.name == name)
+                .unwrap()
+                .value
+        };
+        assert_eq!(metric("read_only"), 1);
+        assert_eq!(metric("storage_failures"), 1);
+
+        // Reads are still served, writes are rejected without being applied.
+        assert_eq!(get_balance(&mut client, sender).await, Balance::from(7));
+        assert_eq!(
+            client.handle_transfer_order(second_order.clone()).await,
+            Err(FastPayError::StorageUnavailable)
+        );
+        let info = client
+            .handle_account_info_request(AccountInfoRequest {
+                sender,
+                request_sequence_number: None,
+                request_received_transfers_excluding_first_nth: None,
+                observer: false,
+            })
+            .await
+            .unwrap();
+        assert!(info.pending_confirmation.is_none());
+        assert_eq!(log.records.lock().unwrap().len(), 1);
+
+        // Once the storage recovers, the pending confirmation is written first.
+        log.failing.store(false, Ordering::Relaxed);
+        client
+            .handle_transfer_order(second_order.clone())
+            .await
+            .unwrap();
+        assert!(!stats.read_only());
+        assert!(!client.ping(0).await.unwrap().read_only);
+        assert_eq!(
+            *log.records.lock().unwrap(),
+            vec![
+                WalRecord::Order(certificate.value.clone()),
+                WalRecord::Confirmation(certificate),
+                WalRecord::Order(second_order),
+            ]
+        );
+        server.kill().await.unwrap();
+    });
+}
@@ -0,0 +1,74 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use fastpay_core::{
+    authority::AuthorityState,
+    base_types::*,
+    client::{Client, ClientState, SimulatedAuthorityClient},
+    committee::Committee,
+};
+use std::collections::BTreeMap;
+use tokio::runtime::Runtime;
+
+fn make_simulated_client() -> ClientState<SimulatedAuthorityClient> {
+    let key_pairs: Vec<_> = (0..4).map(|_| get_key_pair()).collect();
+    let voting_rights: BTreeMap<_, _> = key_pairs.iter().map(|(name, _)| (*name, 1)).collect();
+    let committee = Committee::new(voting_rights);
+    let authorities: Vec<_> = key_pairs
+        .into_iter()
+        .map(|(name, secret)| AuthorityState::new(committee.clone(), name, secret))
+        .collect();
+    let (address, secret) = get_key_pair();
+    ClientState::new_simulated(address, secret, committee, authorities, Balance::from(10))
+}
+
+#[test]
+fn test_receipts_survive_client_restart() {
+    let dir = tempfile::tempdir().unwrap();
+    let mut client = make_simulated_client()
+        .with_receipt_store(Box::new(FileReceiptStore::open(dir.path()).unwrap()));
+    let address = client.address();
+    let mut rt = Runtime::new().unwrap();
+    let mut certificates = Vec::new();
+    for amount in 1..=2 {
+        certificates.push(
+            rt.block_on(client.transfer_to_fastpay(
+                Amount::from(amount),
+                get_key_pair().0,
+                UserData::default(),
+            ))
+            .unwrap(),
+        );
+    }
+    let receipt = client.receipt(SequenceNumber::from(1)).unwrap().unwrap();
+    assert_eq!(receipt.certificate, certificates[1]);
+    assert!(receipt.confirmed);
+    drop(client);
+
+    let store = FileReceiptStore::open(dir.path()).unwrap();
+    let receipt = store
+        .get(address, SequenceNumber::from(0))
+        .unwrap()
+        .unwrap();
+    assert_eq!(receipt.certificate, certificates[0]);
+    assert!(receipt.confirmed);
+    assert_eq!(store.get(address, SequenceNumber::from(2)).unwrap(), None);
+    assert_eq!(
+        store.get(get_key_pair().0, SequenceNumber::new()).unwrap(),
+        None
+    );
+    let receipts: Vec<_> = store
+        .list(address)
+        .unwrap()
+        .into_iter()
+        .map(|receipt| receipt.certificate)
+        .collect();
+    assert_eq!(receipts, certificates);
+}
+
+#[test]
+fn test_receipt_query_requires_store() {
+    let client = make_simulated_client();
+    assert!(client.receipt(SequenceNumber::new()).is_err());
+}
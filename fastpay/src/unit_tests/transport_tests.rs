@@ -42,17 +42,26 @@ async fn test_server(protocol: NetworkProtocol) -> Result<(usize, usize), std::i
     let mut received = 0;
 
     let server = protocol
-        .spawn_server(&address, TestService::new(counter.clone()), 100)
+        .spawn_server(
+            &address,
+            TestService::new(counter.clone()),
+            100,
+            TransportConfig::default(),
+        )
         .await?;
 
-    let mut client = protocol.connect(address.clone(), 1000).await?;
+    let mut client = protocol
+        .connect(address.clone(), 1000, TransportConfig::default())
+        .await?;
     client.write_data(b"abcdef").await?;
     received += client.read_data().await?.len();
     client.write_data(b"abcd").await?;
     received += client.read_data().await?.len();
 
     // Use a second connection (here pooled).
-    let mut pool = protocol.make_outgoing_connection_pool().await?;
+    let mut pool = protocol
+        .make_outgoing_connection_pool(TransportConfig::default())
+        .await?;
     pool.send_data_to(b"abc", &address).await?;
 
     // Try to read data on the first connection (should fail).
@@ -91,3 +100,369 @@ fn tcp_server() {
     assert_eq!(processed, 17);
     assert_eq!(received, 14);
 }
+
+#[test]
+fn tcp_nodelay_is_applied() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        for &tcp_nodelay in &[true, false] {
+            let config = TransportConfig {
+                tcp_nodelay,
+                ..TransportConfig::default()
+            };
+            let client = TcpDataStream::connect(address.clone(), 1000, config)
+                .await
+                .unwrap();
+            let (socket, _) = listener.accept().await.unwrap();
+            TcpDataStream::configure(&socket, 1000, config).unwrap();
+            assert_eq!(client.stream.nodelay().unwrap(), tcp_nodelay);
+            assert_eq!(socket.nodelay().unwrap(), tcp_nodelay);
+        }
+    });
+}
+
+#[test]
+fn tcp_pool_reuses_connections() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let pool_config = PoolConfig {
+            tcp_keepalive: Some(Duration::from_secs(30)),
+            reuse_connections: true,
+        };
+        let mut pool = TcpDataStreamPool::new(TransportConfig::default(), pool_config)
+            .await
+            .unwrap();
+        pool.send_data_to(b"abc", &address).await.unwrap();
+        pool.send_data_to(b"defg", &address).await.unwrap();
+        assert_eq!(
+            pool.streams[&address].keepalive().unwrap(),
+            Some(Duration::from_secs(30))
+        );
+
+        // Both messages arrive on the same connection, and no other one is opened.
+        let (mut socket, _) = listener.accept().await.unwrap();
+        assert_eq!(
+            TcpDataStream::tcp_read_data(&mut socket, 100)
+                .await
+                .unwrap(),
+            b"abc"
+        );
+        assert_eq!(
+            TcpDataStream::tcp_read_data(&mut socket, 100)
+                .await
+                .unwrap(),
+            b"defg"
+        );
+        assert!(timeout(Duration::from_millis(200), listener.accept())
+            .await
+            .is_err());
+
+        // Once the connection is broken, the next message goes through a new one.
+        drop(socket);
+        let mut writes = 0;
+        while pool.send_data_to(b"hij", &address).await.is_ok() {
+            writes += 1;
+            assert!(writes < 50, "writes to a closed connection should fail");
+            tokio::time::delay_for(Duration::from_millis(10)).await;
+        }
+        assert!(pool.streams.is_empty());
+        pool.send_data_to(b"klm", &address).await.unwrap();
+        let (mut socket, _) = listener.accept().await.unwrap();
+        assert_eq!(
+            TcpDataStream::tcp_read_data(&mut socket, 100)
+                .await
+                .unwrap(),
+            b"klm"
+        );
+    });
+}
+
+#[test]
+fn tcp_pool_without_reuse_opens_a_connection_per_message() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let mut listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap().to_string();
+        let pool_config = PoolConfig {
+            tcp_keepalive: None,
+            reuse_connections: false,
+        };
+        let mut pool = NetworkProtocol::Tcp
+            .make_outgoing_connection_pool_with(TransportConfig::default(), pool_config)
+            .await
+            .unwrap();
+        pool.send_data_to(b"abc", &address).await.unwrap();
+        pool.send_data_to(b"defg", &address).await.unwrap();
+        for expected in &[&b"abc"[..], &b"defg"[..]] {
+            let (mut socket, _) = listener.accept().await.unwrap();
+            assert_eq!(
+                TcpDataStream::tcp_read_data(&mut socket, 100)
+                    .await
+                    .unwrap(),
+                *expected
+            );
+        }
+    });
+}
+
+async fn measure_round_trips(config: TransportConfig) -> Duration {
+    let address = get_new_local_address().await.unwrap();
+    let counter = Arc::new(AtomicUsize::new(0));
+    let server = NetworkProtocol::Tcp
+        .spawn_server(&address, TestService::new(counter), 100, config)
+        .await
+        .unwrap();
+    let mut client = NetworkProtocol::Tcp
+        .connect(address, 1000, config)
+        .await
+        .unwrap();
+    let start = std::time::Instant::now();
+    // Messages are written in two parts (length and data) so the second part is
+    // delayed by Nagle's algorithm until the first one is acknowledged.
+    for _ in 0..5 {
+        client.write_data(b"ping").await.unwrap();
+        client.read_data().await.unwrap();
+    }
+    let elapsed = start.elapsed();
+    server.kill().await.unwrap();
+    elapsed
+}
+
+#[test]
+fn tcp_nodelay_reduces_latency() {
+    let mut rt = Runtime::new().unwrap();
+    let with_nodelay = rt.block_on(measure_round_trips(TransportConfig::default()));
+    let without_nodelay = rt.block_on(measure_round_trips(TransportConfig {
+        tcp_nodelay: false,
+        ..TransportConfig::default()
+    }));
+    assert!(with_nodelay < without_nodelay);
+}
+
+#[test]
+fn tcp_oversized_frame_is_rejected() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let address = get_new_local_address().await.unwrap();
+        let counter = Arc::new(AtomicUsize::new(0));
+        let config = TransportConfig {
+            max_frame_size: Some(10),
+            ..TransportConfig::default()
+        };
+        let server = NetworkProtocol::Tcp
+            .spawn_server(&address, TestService::new(counter.clone()), 100, config)
+            .await
+            .unwrap();
+
+        // Messages within the limit are served.
+        let mut client = NetworkProtocol::Tcp
+            .connect(address.clone(), 1000, config)
+            .await
+            .unwrap();
+        client.write_data(b"abcdef").await.unwrap();
+        assert_eq!(client.read_data().await.unwrap(), b"abcdef");
+
+        // Announce a larger message but never send its body: the server must close the
+        // connection right after reading the length prefix.
+        let mut stream = TcpStream::connect(&address).await.unwrap();
+        stream.write_all(&u32::to_le_bytes(11)).await.unwrap();
+        let mut buf = [0u8; 1];
+        let read = timeout(Duration::from_secs(1), stream.read(&mut buf))
+            .await
+            .expect("connection should be closed without waiting for the body");
+        assert!(matches!(read, Ok(0) | Err(_)));
+        assert_eq!(counter.load(Ordering::Relaxed), 6);
+
+        server.kill().await.unwrap();
+    });
+}
+
+/// Answers each message after a delay.
+struct SlowService {
+    delay: Duration,
+}
+
+impl MessageHandler for SlowService {
+    fn handle_message<'a>(
+        &'a mut self,
+        buffer: &'a [u8],
+    ) -> future::BoxFuture<'a, Option<Vec<u8>>> {
+        Box::pin(async move {
+            tokio::time::delay_for(self.delay).await;
+            Some(Vec::from(buffer))
+        })
+    }
+}
+
+#[test]
+fn shutdown_completes_within_grace_period() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let address = get_new_local_address().await.unwrap();
+        let service = SlowService {
+            delay: Duration::from_millis(100),
+        };
+        let server = NetworkProtocol::Udp
+            .spawn_server(&address, service, 100, TransportConfig::default())
+            .await
+            .unwrap();
+        let mut client = NetworkProtocol::Udp
+            .connect(address, 1000, TransportConfig::default())
+            .await
+            .unwrap();
+        client.write_data(b"ping").await.unwrap();
+        // Let the server start handling the message before stopping it.
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+        let grace_period = Duration::from_secs(2);
+        let start = std::time::Instant::now();
+        server.shutdown(grace_period).await.unwrap();
+        assert!(start.elapsed() < grace_period);
+        // The message in flight was still answered.
+        assert_eq!(client.read_data().await.unwrap(), b"ping");
+    });
+}
+
+#[test]
+fn shutdown_gives_up_after_grace_period() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let address = get_new_local_address().await.unwrap();
+        let service = SlowService {
+            delay: Duration::from_secs(10),
+        };
+        let server = NetworkProtocol::Udp
+            .spawn_server(&address, service, 100, TransportConfig::default())
+            .await
+            .unwrap();
+        let mut client = NetworkProtocol::Udp
+            .connect(address, 1000, TransportConfig::default())
+            .await
+            .unwrap();
+        client.write_data(b"ping").await.unwrap();
+        tokio::time::delay_for(Duration::from_millis(20)).await;
+        let error = timeout(
+            Duration::from_secs(2),
+            server.shutdown(Duration::from_millis(100)),
+        )
+        .await
+        .expect("shutdown should not wait past the grace period")
+        .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    });
+}
+
+#[test]
+fn receive_buffer_grows_up_to_max_size() {
+    let mut buffer = ReceiveBuffer::new(100, 1000);
+    assert_eq!(buffer.accept(50), Some(&[0u8; 50][..]));
+    assert_eq!(buffer.size(), 100);
+    // Each truncated message is rejected and doubles the buffer.
+    for &(received, size) in &[(101, 200), (201, 400), (401, 800), (801, 1000)] {
+        assert_eq!(buffer.accept(received), None);
+        assert_eq!(buffer.size(), size);
+        assert!(buffer.accept(received - 1).is_some());
+    }
+    assert!(buffer.accept(1000).is_some());
+    // Messages beyond the maximal size keep being rejected.
+    assert_eq!(buffer.accept(1001), None);
+    assert_eq!(buffer.size(), 1000);
+}
+
+#[test]
+fn receive_buffer_of_fixed_size() {
+    let mut buffer = ReceiveBuffer::new(100, 100);
+    assert!(buffer.accept(100).is_some());
+    assert_eq!(buffer.accept(101), None);
+    assert_eq!(buffer.size(), 100);
+}
+
+#[test]
+fn udp_server_adapts_its_buffer() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let address = get_new_local_address().await.unwrap();
+        let config = TransportConfig {
+            udp_max_buffer_size: Some(1000),
+            ..TransportConfig::default()
+        };
+        let counter = Arc::new(AtomicUsize::new(0));
+        let server = NetworkProtocol::Udp
+            .spawn_server(&address, TestService::new(counter.clone()), 100, config)
+            .await
+            .unwrap();
+        let mut client = NetworkProtocol::Udp
+            .connect(address, 2000, config)
+            .await
+            .unwrap();
+        // Dropped, but the buffer grows.
+        client.write_data(&[1; 150]).await.unwrap();
+        client.write_data(&[2; 150]).await.unwrap();
+        assert_eq!(client.read_data().await.unwrap(), vec![2; 150]);
+        // Beyond the maximal size.
+        client.write_data(&[3; 1500]).await.unwrap();
+        client.write_data(&[4; 10]).await.unwrap();
+        assert_eq!(client.read_data().await.unwrap(), vec![4; 10]);
+        assert_eq!(counter.load(Ordering::Relaxed), 160);
+        server.kill().await.unwrap();
+    });
+}
+
+#[test]
+fn socket_address_of_hosts() {
+    assert_eq!(socket_address("127.0.0.1", 9100), "127.0.0.1:9100");
+    assert_eq!(socket_address("example.com", 9100), "example.com:9100");
+    assert_eq!(socket_address("::1", 9100), "[::1]:9100");
+    assert_eq!(socket_address("[::1]", 9100), "[::1]:9100");
+    assert_eq!(socket_address("2001:db8::7", 9101), "[2001:db8::7]:9101");
+}
+
+#[test]
+fn resolve_host_names_and_literals() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        let addresses = resolve(&socket_address("localhost", 9100)).await.unwrap();
+        assert!(addresses
+            .iter()
+            .all(|address| address.ip().is_loopback() && address.port() == 9100));
+        let addresses = resolve(&socket_address("::1", 9100)).await.unwrap();
+        assert_eq!(addresses, vec!["[::1]:9100".parse().unwrap()]);
+        let addresses = resolve(&socket_address("127.0.0.1", 9100)).await.unwrap();
+        assert_eq!(addresses, vec!["127.0.0.1:9100".parse().unwrap()]);
+    });
+}
+
+#[test]
+fn connect_to_host_name() {
+    let mut rt = Runtime::new().unwrap();
+    rt.block_on(async {
+        for protocol in &[NetworkProtocol::Udp, NetworkProtocol::Tcp] {
+            let address = get_new_local_address().await.unwrap();
+            let port: u32 = address.rsplit(':').next().unwrap().parse().unwrap();
+            let counter = Arc::new(AtomicUsize::new(0));
+            let server = protocol
+                .spawn_server(
+                    &socket_address("127.0.0.1", port),
+                    TestService::new(counter.clone()),
+                    100,
+                    TransportConfig::default(),
+                )
+                .await
+                .unwrap();
+            let mut client = protocol
+                .connect(
+                    socket_address("localhost", port),
+                    100,
+                    TransportConfig::default(),
+                )
+                .await
+                .unwrap();
+            client.write_data(&[7; 10]).await.unwrap();
+            assert_eq!(client.read_data().await.unwrap(), vec![7; 10]);
+            server.kill().await.unwrap();
+        }
+    });
+}
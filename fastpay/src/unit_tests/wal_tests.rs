@@ -0,0 +1,197 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use fastpay_core::committee::{Committee, SigningDomain};
+use std::collections::BTreeMap;
+
+fn get_key_pair_in_shard(shard: u32) -> (FastPayAddress, KeyPair) {
+    loop {
+        let (address, key) = get_key_pair();
+        if AuthorityState::get_shard(2, &address) == shard {
+            return (address, key);
+        }
+    }
+}
+
+/// A shard of the single authority of a committee, out of two, where the sender has funds.
+fn init_shard(
+    authority: FastPayAddress,
+    secret: &KeyPair,
+    shard: u32,
+    sender: FastPayAddress,
+) -> AuthorityState {
+    let mut voting_rights = BTreeMap::new();
+    voting_rights.insert(authority, 1);
+    let mut state = AuthorityState::new_shard(
+        Committee::new(voting_rights),
+        authority,
+        secret.copy(),
+        shard,
+        2,
+    );
+    if state.in_shard(&sender) {
+        let mut account = AccountOffchainState::new();
+        account.balance = Balance::from(10);
+        state.accounts.insert(sender, account);
+    }
+    state
+}
+
+fn init_certificate(
+    state: &AuthorityState,
+    sender: FastPayAddress,
+    sender_key: &KeyPair,
+    recipient: FastPayAddress,
+) -> CertifiedTransferOrder {
+    let order = TransferOrder::new(
+        Transfer {
+            sender,
+            recipient: Address::FastPay(recipient),
+            amount: Amount::from(3),
+            sequence_number: SequenceNumber::new(),
+            user_data: UserData::default(),
+            account_state: None,
+            domain: SigningDomain::default(),
+        },
+        sender_key,
+    );
+    let vote = SignedTransferOrder::new(order.clone(), state.name, &state.secret);
+    CertifiedTransferOrder {
+        value: order,
+        signatures: vec![(vote.authority, vote.signature)],
+    }
+}
+
+fn balance(state: &AuthorityState, address: &FastPayAddress) -> Balance {
+    state.accounts.get(address).unwrap().balance
+}
+
+#[test]
+fn test_replay_after_crash_before_cross_shard_update() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shard0.wal");
+    let (sender, sender_key) = get_key_pair_in_shard(0);
+    let (recipient, _) = get_key_pair_in_shard(1);
+    let (authority, secret) = get_key_pair();
+    let mut shard0 = init_shard(authority, &secret, 0, sender);
+    let mut shard1 = init_shard(authority, &secret, 1, sender);
+    let certificate = init_certificate(&shard0, sender, &sender_key, recipient);
+
+    // The sender is debited, then the shard crashes before sending the update.
+    let (mut wal, records) = WriteAheadLog::open(&path).unwrap();
+    assert!(records.is_empty());
+    wal.append(&WalRecord::Confirmation(certificate.clone()))
+        .unwrap();
+    shard0
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+    drop(wal);
+
+    // On restart, the debit is restored and the update is sent again.
+    let mut shard0 = init_shard(authority, &secret, 0, sender);
+    let (mut wal, records) = WriteAheadLog::open(&path).unwrap();
+    let updates = replay(&mut shard0, &records);
+    assert_eq!(balance(&shard0, &sender), Balance::from(7));
+    assert_eq!(
+        updates,
+        vec![CrossShardUpdate {
+            shard_id: 1,
+            source_shard_id: 0,
+            transfer_certificate: certificate.clone(),
+        }]
+    );
+
+    // The update may also have been sent before the crash: the recipient is only
+    // credited once.
+    shard1
+        .handle_cross_shard_recipient_commit(updates[0].clone())
+        .unwrap();
+    shard1
+        .handle_cross_shard_recipient_commit(updates[0].clone())
+        .unwrap();
+    assert_eq!(balance(&shard1, &recipient), Balance::from(3));
+
+    // Replaying again has no effect, and nothing is left to send once the update is sent.
+    assert_eq!(replay(&mut shard0, &records), updates);
+    assert_eq!(balance(&shard0, &sender), Balance::from(7));
+    wal.append(&WalRecord::CrossShardSent {
+        sender,
+        sequence_number: SequenceNumber::new(),
+    })
+    .unwrap();
+    drop(wal);
+    let (_, records) = WriteAheadLog::open(&path).unwrap();
+    assert_eq!(records.len(), 2);
+    assert!(replay(&mut shard0, &records).is_empty());
+}
+
+#[test]
+fn test_torn_record_is_discarded() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shard0.wal");
+    let (sender, sender_key) = get_key_pair_in_shard(0);
+    let (recipient, _) = get_key_pair_in_shard(1);
+    let (authority, secret) = get_key_pair();
+    let shard0 = init_shard(authority, &secret, 0, sender);
+    let certificate = init_certificate(&shard0, sender, &sender_key, recipient);
+
+    let (mut wal, _) = WriteAheadLog::open(&path).unwrap();
+    wal.append(&WalRecord::Confirmation(certificate.clone()))
+        .unwrap();
+    drop(wal);
+    let length = fs::metadata(&path).unwrap().len();
+    // Crash in the middle of writing the second record.
+    let data = WriteAheadLog::encode(&WalRecord::Order(certificate.value.clone()));
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&data[..data.len() / 2]).unwrap();
+    drop(file);
+
+    let (mut wal, records) = WriteAheadLog::open(&path).unwrap();
+    assert_eq!(records, vec![WalRecord::Confirmation(certificate.clone())]);
+    assert_eq!(fs::metadata(&path).unwrap().len(), length);
+    // New records follow the valid ones.
+    wal.append(&WalRecord::Order(certificate.value.clone()))
+        .unwrap();
+    drop(wal);
+    let (_, records) = WriteAheadLog::open(&path).unwrap();
+    assert_eq!(records.len(), 2);
+}
+
+#[test]
+fn test_compact() {
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shard0.wal");
+    let (sender, sender_key) = get_key_pair_in_shard(0);
+    let (recipient, _) = get_key_pair_in_shard(1);
+    let (authority, secret) = get_key_pair();
+    let shard0 = init_shard(authority, &secret, 0, sender);
+    let sent = init_certificate(&shard0, sender, &sender_key, recipient);
+    let mut unsent = init_certificate(&shard0, sender, &sender_key, recipient);
+    unsent.value.transfer.sequence_number = SequenceNumber::from(1);
+
+    let (mut wal, _) = WriteAheadLog::open(&path).unwrap();
+    for record in &[
+        WalRecord::Order(sent.value.clone()),
+        WalRecord::Confirmation(sent.clone()),
+        WalRecord::Confirmation(unsent.clone()),
+        WalRecord::CrossShardSent {
+            sender,
+            sequence_number: SequenceNumber::new(),
+        },
+    ] {
+        wal.append(record).unwrap();
+    }
+    wal.compact().unwrap();
+    wal.append(&WalRecord::Order(sent.value.clone())).unwrap();
+    drop(wal);
+
+    let (_, records) = WriteAheadLog::open(&path).unwrap();
+    assert_eq!(
+        records,
+        vec![
+            WalRecord::Confirmation(unsent),
+            WalRecord::Order(sent.value)
+        ]
+    );
+}
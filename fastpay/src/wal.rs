@@ -0,0 +1,212 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use fastpay_core::{authority::*, base_types::*, messages::*};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::BTreeSet,
+    convert::TryInto,
+    fs::{self, File, OpenOptions},
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+#[cfg(test)]
+#[path = "unit_tests/wal_tests.rs"]
+mod wal_tests;
+
+/// A change to the state of a shard, recorded in the write-ahead log.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub enum WalRecord {
+    /// A transfer order that the shard voted for.
+    Order(TransferOrder),
+    /// A certificate confirmed by the shard, debiting the sender.
+    Confirmation(CertifiedTransferOrder),
+    /// A credit received from another shard.
+    CrossShard(CrossShardUpdate),
+    /// Same as `CrossShard` for updates signed by the authority.
+    SignedCrossShard(SignedCrossShardUpdate),
+    /// A credit claimed by the recipient of a certificate.
+    RecipientConfirmation(CertifiedTransferOrder),
+    Ownership(OwnershipOrder),
+    /// The cross-shard update crediting the recipient of a confirmed transfer was sent.
+    CrossShardSent {
+        sender: FastPayAddress,
+        sequence_number: SequenceNumber,
+    },
+}
+
+/// Durable storage of the changes made to the state of a shard.
+pub trait RecordLog: Send {
+    /// Append a record, and wait until it is durable.
+    fn append(&mut self, record: &WalRecord) -> Result<(), io::Error>;
+
+    /// Forget all the records, e.g. when the state of the shard is reset.
+    fn clear(&mut self) -> Result<(), io::Error>;
+
+    /// Once the state of the shard is saved elsewhere (e.g. in a snapshot), only keep the
+    /// confirmations whose cross-shard update may not have been sent.
+    fn compact(&mut self) -> Result<(), io::Error>;
+}
+
+/// Append-only file of the changes made to the state of a shard, which is otherwise only
+/// kept in memory. Changes are recorded before their effects leave the shard (votes,
+/// responses, cross-shard updates), so that replaying the log after a crash restores
+/// everything that other parties may have relied on. Records are prefixed with their
+/// length and synced to disk one by one.
+#[derive(Debug)]
+pub struct WriteAheadLog {
+    path: PathBuf,
+    file: File,
+}
+
+impl WriteAheadLog {
+    /// Open the log at the given path, creating it if needed, and read its records. An
+    /// incomplete record at the end, from a crash while writing it, is discarded.
+    pub fn open(path: &Path) -> Result<(Self, Vec<WalRecord>), io::Error> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut data = Vec::new();
+        file.read_to_end(&mut data)?;
+        let (records, valid_length) = Self::parse(&data);
+        if valid_length < data.len() {
+            warn!(
+                "Discarding {} bytes at the end of the write-ahead log {}",
+                data.len() - valid_length,
+                path.display()
+            );
+            file.set_len(valid_length as u64)?;
+        }
+        let log = Self {
+            path: path.to_path_buf(),
+            file,
+        };
+        Ok((log, records))
+    }
+
+    /// The complete records at the start of `data`, and their total length.
+    fn parse(data: &[u8]) -> (Vec<WalRecord>, usize) {
+        let mut records = Vec::new();
+        let mut position = 0;
+        while let Some(header) = data.get(position..position + 4) {
+            let length = u32::from_le_bytes(header.try_into().unwrap()) as usize;
+            let body = match data.get(position + 4..position + 4 + length) {
+                Some(body) => body,
+                None => break,
+            };
+            match bincode::deserialize(body) {
+                Ok(record) => records.push(record),
+                Err(_) => break,
+            }
+            position += 4 + length;
+        }
+        (records, position)
+    }
+
+    fn encode(record: &WalRecord) -> Vec<u8> {
+        let body = bincode::serialize(record).expect("Serializing to a buffer should not fail");
+        let mut data = (body.len() as u32).to_le_bytes().to_vec();
+        data.extend(body);
+        data
+    }
+}
+
+impl RecordLog for WriteAheadLog {
+    fn append(&mut self, record: &WalRecord) -> Result<(), io::Error> {
+        self.file.write_all(&Self::encode(record))?;
+        self.file.sync_data()
+    }
+
+    fn clear(&mut self) -> Result<(), io::Error> {
+        self.file.set_len(0)?;
+        self.file.sync_data()
+    }
+
+    fn compact(&mut self) -> Result<(), io::Error> {
+        let mut data = Vec::new();
+        File::open(&self.path)?.read_to_end(&mut data)?;
+        let (records, _) = Self::parse(&data);
+        let temp_path = self.path.with_extension("compacting");
+        let mut file = File::create(&temp_path)?;
+        for certificate in unsent_confirmations(&records) {
+            file.write_all(&Self::encode(&WalRecord::Confirmation(certificate.clone())))?;
+        }
+        file.sync_data()?;
+        fs::rename(&temp_path, &self.path)?;
+        self.file = OpenOptions::new().append(true).open(&self.path)?;
+        Ok(())
+    }
+}
+
+/// Confirmations of transfers to FastPay accounts without a record that the cross-shard
+/// update was sent. Recipients ignore the credits that they already received, so these
+/// updates may safely be sent again.
+fn unsent_confirmations(records: &[WalRecord]) -> Vec<&CertifiedTransferOrder> {
+    let sent: BTreeSet<_> = records
+        .iter()
+        .filter_map(|record| match record {
+            WalRecord::CrossShardSent {
+                sender,
+                sequence_number,
+            } => Some((*sender, *sequence_number)),
+            _ => None,
+        })
+        .collect();
+    let mut seen = BTreeSet::new();
+    records
+        .iter()
+        .filter_map(|record| match record {
+            WalRecord::Confirmation(certificate)
+                if matches!(certificate.value.transfer.recipient, Address::FastPay(_))
+                    && !sent.contains(&certificate.key())
+                    && seen.insert(certificate.key()) =>
+            {
+                Some(certificate)
+            }
+            _ => None,
+        })
+        .collect()
+}
+
+/// Apply the records of a log to the state of a shard, in order, and return the
+/// cross-shard updates that may not have been sent. Records that were already applied
+/// (e.g. from a snapshot) have no effect.
+pub fn replay(state: &mut AuthorityState, records: &[WalRecord]) -> Vec<CrossShardUpdate> {
+    for record in records {
+        let result = match record.clone() {
+            WalRecord::Order(order) => state.handle_transfer_order(order).map(|_| ()),
+            WalRecord::Confirmation(certificate) => state
+                .handle_confirmation_order(ConfirmationOrder::new(certificate))
+                .map(|_| ()),
+            WalRecord::CrossShard(update) => state.handle_cross_shard_recipient_commit(update),
+            WalRecord::SignedCrossShard(update) => {
+                state.handle_signed_cross_shard_recipient_commit(update)
+            }
+            WalRecord::RecipientConfirmation(certificate) => state
+                .handle_recipient_confirmation_order(ConfirmationOrder::new(certificate))
+                .map(|_| ()),
+            WalRecord::Ownership(order) => state.handle_ownership_order(order).map(|_| ()),
+            WalRecord::CrossShardSent { .. } => Ok(()),
+        };
+        if let Err(error) = result {
+            // E.g. a vote for a transfer that was confirmed since.
+            debug!("Skipped a record of the write-ahead log: {}", error);
+        }
+    }
+    unsent_confirmations(records)
+        .into_iter()
+        .filter_map(|certificate| match certificate.value.transfer.recipient {
+            Address::FastPay(recipient) if !state.in_shard(&recipient) => Some(CrossShardUpdate {
+                shard_id: state.which_shard(&recipient),
+                source_shard_id: state.shard_id,
+                transfer_certificate: certificate.clone(),
+            }),
+            _ => None,
+        })
+        .collect()
+}
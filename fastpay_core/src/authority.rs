@@ -1,13 +1,42 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{base_types::*, committee::Committee, error::FastPayError, messages::*};
-use std::{collections::BTreeMap, convert::TryInto};
+use crate::{
+    base_types::*,
+    bloom::BloomFilter,
+    clock::{Clock, SystemClock},
+    committee::{Committee, Feature, RecipientPolicy},
+    error::FastPayError,
+    merkle::{leaf_hash, MerkleAccumulator},
+    messages::*,
+    redaction::LogDetail,
+    verification_cache::{certificate_digest, VerificationCache},
+};
+use log::warn;
+use std::{
+    collections::{btree_map::Entry, BTreeMap, BTreeSet},
+    convert::TryInto,
+    ops::Bound::{Excluded, Unbounded},
+    sync::Arc,
+};
+
+/// Maximal number of accounts returned by a `ListAccountsRequest`.
+pub const MAX_LIST_ACCOUNTS_LIMIT: usize = 1000;
+
+/// Minimal number of accounts that an account filter is sized for.
+const MIN_ACCOUNT_FILTER_CAPACITY: usize = 1024;
+
+/// Rough estimate of the memory used by an account, including a few certificates.
+pub const ESTIMATED_ACCOUNT_SIZE: usize = 4096;
 
 #[cfg(test)]
 #[path = "unit_tests/authority_tests.rs"]
 mod authority_tests;
 
+#[cfg(test)]
+#[path = "unit_tests/scenario_tests.rs"]
+mod scenario_tests;
+
 #[derive(Eq, PartialEq, Debug)]
 pub struct AccountOffchainState {
     /// Balance of the FastPay account.
@@ -18,10 +47,22 @@ pub struct AccountOffchainState {
     pub pending_confirmation: Option<SignedTransferOrder>,
     /// All confirmed certificates for this sender.
     pub confirmed_log: Vec<CertifiedTransferOrder>,
+    /// Merkle accumulator over the transfers of `confirmed_log`.
+    pub confirmed_history: MerkleAccumulator,
     /// All executed Primary synchronization orders for this recipient.
     pub synchronization_log: Vec<PrimarySynchronizationOrder>,
     /// All confirmed certificates as a receiver.
     pub received_log: Vec<CertifiedTransferOrder>,
+    /// Senders and sequence numbers of the transfers in `received_log`.
+    pub received_transfers: BTreeSet<(FastPayAddress, SequenceNumber)>,
+    /// Time of the last confirmed debit or credit (milliseconds since the Unix epoch).
+    pub last_activity: Option<u64>,
+    /// Time of the last confirmed debit (milliseconds since the Unix epoch).
+    pub last_transfer: Option<u64>,
+    /// Keys allowed to authorize transfers, if changed from the key of the address.
+    pub ownership: Option<Ownership>,
+    /// Number of changes of ownership applied to this account.
+    pub ownership_version: VersionNumber,
 }
 
 pub struct AuthorityState {
@@ -39,6 +80,46 @@ pub struct AuthorityState {
     pub shard_id: ShardId,
     /// The number of shards. 1 if single shard.
     pub number_of_shards: u32,
+    /// Whether responses to read-only queries are signed. Votes are always signed.
+    pub sign_read_responses: bool,
+    /// Confirmed transfers above this amount are logged at warning level.
+    pub large_transfer_threshold: Option<Amount>,
+    /// If set, transfer orders above this amount are not signed. Certificates are still
+    /// confirmed, since a quorum of authorities approved them.
+    pub max_transfer_amount: Option<Amount>,
+    /// Whether to reject orders containing non-canonical keys or signatures.
+    pub reject_non_canonical_encodings: bool,
+    /// Optional filter over the keys of `accounts` to answer negative lookups quickly.
+    /// Must be rebuilt whenever `accounts` is modified directly.
+    pub account_filter: Option<BloomFilter>,
+    /// Soft limit on the (estimated) memory used by the accounts, in bytes. Above it, new
+    /// accounts are rejected while existing ones are still served.
+    pub memory_limit: Option<usize>,
+    /// Whether cross-shard updates are signed with the authority key, and required to
+    /// be, for shards running on hosts that do not trust each other.
+    pub sign_cross_shard_updates: bool,
+    /// Source of the time recorded in accounts.
+    pub clock: Arc<dyn Clock>,
+    /// If set, the only shards allowed to send cross-shard updates to this one. In any
+    /// case, updates must come from another shard of this authority.
+    pub cross_shard_sources: Option<BTreeSet<ShardId>>,
+    /// If set, certificates already verified, so that their signatures are not checked
+    /// again when they are submitted several times.
+    pub verification_cache: Option<VerificationCache>,
+    /// Whether to answer replays of a confirmed certificate without verifying it again.
+    pub reuse_confirmed_certificates: bool,
+    /// Whether confirming a transfer may leave the sender with a negative balance. When
+    /// not, such confirmations fail with `NegativeBalance`, to surface accounting bugs.
+    pub allow_negative_balances: bool,
+    /// How much of the addresses and user data of requests appear in logs.
+    pub log_detail: LogDetail,
+    /// Whether to log transfer orders that differ from the pending order of their account
+    /// at the same sequence number, a sign of a client bug. Such orders are rejected anyway.
+    pub log_conflicting_orders: bool,
+    /// Whether to accept orders and certificates without checking their signatures.
+    /// Only for simulations and benchmarks: this trusts every client and authority.
+    #[cfg(feature = "unchecked-signatures")]
+    pub skip_signature_checks: bool,
 }
 
 /// Interface provided by each (shard of an) authority.
@@ -69,11 +150,42 @@ pub trait Authority {
         request: AccountInfoRequest,
     ) -> Result<AccountInfoResponse, FastPayError>;
 
+    /// List the accounts of this shard, in order of their addresses.
+    fn handle_list_accounts_request(
+        &self,
+        request: ListAccountsRequest,
+    ) -> Result<ListAccountsResponse, FastPayError>;
+
+    /// Change the keys allowed to authorize the transfers of an account.
+    fn handle_ownership_order(
+        &mut self,
+        order: OwnershipOrder,
+    ) -> Result<AccountInfoResponse, FastPayError>;
+
+    /// Attest what happened at a sequence number of an account.
+    fn handle_sequence_number_request(
+        &self,
+        request: SequenceNumberRequest,
+    ) -> Result<SequenceNumberStatement, FastPayError>;
+
+    /// Credit the recipient of a confirmed transfer. Only the shard of the recipient is
+    /// involved, so this does not depend on the sender's shard being available.
+    fn handle_recipient_confirmation_order(
+        &mut self,
+        order: ConfirmationOrder,
+    ) -> Result<AccountInfoResponse, FastPayError>;
+
     /// Handle cross updates from another shard of the same authority.
     /// This relies on deliver-once semantics of a trusted channel between shards.
     fn handle_cross_shard_recipient_commit(
         &mut self,
-        certificate: CertifiedTransferOrder,
+        update: CrossShardUpdate,
+    ) -> Result<(), FastPayError>;
+
+    /// Same as `handle_cross_shard_recipient_commit` for updates signed by the authority.
+    fn handle_signed_cross_shard_recipient_commit(
+        &mut self,
+        update: SignedCrossShardUpdate,
     ) -> Result<(), FastPayError>;
 }
 
@@ -88,7 +200,15 @@ impl Authority for AuthorityState {
             self.in_shard(&order.transfer.sender),
             FastPayError::WrongShard
         );
-        order.check_signature()?;
+        self.committee.check_domain(&order.transfer.domain)?;
+        self.check_encodings(
+            &order,
+            std::iter::once(&order.signature)
+                .chain(order.co_signatures.iter().map(|(_, signature)| signature)),
+        )?;
+        if !self.skips_signature_checks() {
+            order.check_signature()?;
+        }
         let transfer = &order.transfer;
         let sender = transfer.sender;
         fp_ensure!(
@@ -99,14 +219,45 @@ impl Authority for AuthorityState {
             transfer.amount > Amount::zero(),
             FastPayError::IncorrectTransferAmount
         );
+        if let Some(limit) = self.max_transfer_amount {
+            fp_ensure!(
+                transfer.amount <= limit,
+                FastPayError::TransferExceedsLimit {
+                    limit,
+                    requested: transfer.amount
+                }
+            );
+        }
+        self.check_recipient(transfer)?;
+        if let Address::FastPay(recipient) = &transfer.recipient {
+            self.check_memory(recipient)?;
+        }
+        if !self.may_contain_account(&sender) {
+            fp_bail!(FastPayError::UnknownSenderAccount);
+        }
         match self.accounts.get_mut(&sender) {
             None => fp_bail!(FastPayError::UnknownSenderAccount),
             Some(account) => {
+                account.check_owners(sender, &order.owner_signatures())?;
                 if let Some(pending_confirmation) = &account.pending_confirmation {
+                    let pending_transfer = &pending_confirmation.value.transfer;
+                    if self.log_conflicting_orders
+                        && pending_transfer != transfer
+                        && pending_transfer.key() == transfer.key()
+                    {
+                        warn!(
+                            "conflicting transfer orders from {} at sequence number {}: \
+                             {} is pending, {} was rejected",
+                            self.log_detail.address(&sender),
+                            u64::from(transfer.sequence_number),
+                            hex::encode(leaf_hash(pending_transfer)),
+                            hex::encode(leaf_hash(transfer))
+                        );
+                    }
                     fp_ensure!(
-                        &pending_confirmation.value.transfer == transfer,
+                        pending_transfer == transfer,
                         FastPayError::PreviousTransferMustBeConfirmedFirst {
-                            pending_confirmation: pending_confirmation.value.clone()
+                            pending_confirmation: Box::new(pending_confirmation.value.clone())
                         }
                     );
                     // This exact transfer order was already signed. Return the previous value.
@@ -116,6 +267,15 @@ impl Authority for AuthorityState {
                     account.next_sequence_number == transfer.sequence_number,
                     FastPayError::UnexpectedSequenceNumber
                 );
+                if let Some(window) = self.committee.order_acceptance_window {
+                    account.check_state_reference(transfer, window)?;
+                }
+                if let Some(cooldown) = self.committee.transfer_cooldown_ms {
+                    account.check_cooldown(cooldown, self.clock.now_millis())?;
+                }
+                if self.committee.strictly_increasing_transfer_times {
+                    account.check_increasing_time(self.clock.now_millis())?;
+                }
                 fp_ensure!(
                     account.balance >= transfer.amount.into(),
                     FastPayError::InsufficientFunding {
@@ -140,14 +300,40 @@ impl Authority for AuthorityState {
             self.in_shard(&certificate.value.transfer.sender),
             FastPayError::WrongShard
         );
-        certificate.check(&self.committee)?;
+        self.check_encodings(
+            &certificate.value,
+            certificate
+                .signatures
+                .iter()
+                .map(|(_, signature)| signature),
+        )?;
+        if self.reuse_confirmed_certificates {
+            // Replays of a confirmed certificate need not be verified again.
+            let sender = certificate.value.transfer.sender;
+            if let Some(account) = self.accounts.get(&sender) {
+                let sequence_number = certificate.value.transfer.sequence_number;
+                // Certificates compare equal regardless of their signature bytes.
+                if let Some(confirmed) = account.confirmed_certificate(sequence_number) {
+                    if confirmed.value == certificate.value
+                        && confirmed.signatures == certificate.signatures
+                    {
+                        return Ok((account.make_account_info(sender), None));
+                    }
+                }
+            }
+        }
+        self.check_certificate(&certificate)?;
         let transfer = certificate.value.transfer.clone();
+        self.check_recipient(&transfer)?;
+        if let Address::FastPay(recipient) = &transfer.recipient {
+            self.check_memory(recipient)?;
+        }
 
         // First we copy all relevant data from sender.
-        let mut sender_account = self
-            .accounts
-            .entry(transfer.sender)
-            .or_insert_with(AccountOffchainState::new);
+        let now = self.clock.now_millis();
+        let allow_negative_balances = self.allow_negative_balances;
+        let strictly_increasing_transfer_times = self.committee.strictly_increasing_transfer_times;
+        let sender_account = self.account_or_insert(transfer.sender);
         let mut sender_sequence_number = sender_account.next_sequence_number;
         let mut sender_balance = sender_account.balance;
 
@@ -158,18 +344,58 @@ impl Authority for AuthorityState {
             });
         }
         if sender_sequence_number > transfer.sequence_number {
-            // Transfer was already confirmed.
+            // Transfer was already confirmed, unless a different one was.
+            if let Some(confirmed) = sender_account.confirmed_certificate(transfer.sequence_number)
+            {
+                fp_ensure!(
+                    confirmed.value.transfer == transfer,
+                    FastPayError::ConflictingConfirmation {
+                        sequence_number: transfer.sequence_number
+                    }
+                );
+            }
             return Ok((sender_account.make_account_info(transfer.sender), None));
         }
         sender_balance = sender_balance.try_sub(transfer.amount.into())?;
+        fp_ensure!(
+            allow_negative_balances || sender_balance >= Balance::zero(),
+            FastPayError::NegativeBalance {
+                balance: sender_balance
+            }
+        );
         sender_sequence_number = sender_sequence_number.increment()?;
 
         // Commit sender state back to the database (Must never fail!)
         sender_account.balance = sender_balance;
         sender_account.next_sequence_number = sender_sequence_number;
         sender_account.pending_confirmation = None;
+        sender_account.last_activity = Some(now);
+        match sender_account.last_transfer {
+            // The clock went back since the order was signed. Keep the time of the last
+            // transfer, so that times stay in order without making one up.
+            Some(last_transfer) if strictly_increasing_transfer_times && last_transfer >= now => {
+                warn!(
+                    "clock went back to {} since the last transfer at {}",
+                    now, last_transfer
+                );
+            }
+            _ => sender_account.last_transfer = Some(now),
+        }
         sender_account.confirmed_log.push(certificate.clone());
+        sender_account
+            .confirmed_history
+            .append(leaf_hash(&certificate.value.transfer));
         let info = sender_account.make_account_info(transfer.sender);
+        if let Some(threshold) = self.large_transfer_threshold {
+            if transfer.amount > threshold {
+                warn!(
+                    "large transfer: {} from {} to {}",
+                    transfer.amount,
+                    self.log_detail.address(&transfer.sender),
+                    self.log_detail.recipient(&transfer.recipient)
+                );
+            }
+        }
 
         // Update FastPay recipient state locally or issue a cross-shard update (Must never fail!)
         let recipient = match transfer.recipient {
@@ -181,51 +407,71 @@ impl Authority for AuthorityState {
         };
         // If the recipient is in the same shard, read and update the account.
         if self.in_shard(&recipient) {
-            let recipient_account = self
-                .accounts
-                .entry(recipient)
-                .or_insert_with(AccountOffchainState::new);
-            recipient_account.balance = recipient_account
-                .balance
-                .try_add(transfer.amount.into())
-                .unwrap_or_else(|_| Balance::max());
-            recipient_account.received_log.push(certificate);
+            self.account_or_insert(recipient).receive(certificate, now);
             // Done updating recipient.
             return Ok((info, None));
         }
         // Otherwise, we need to send a cross-shard update.
         let cross_shard = Some(CrossShardUpdate {
             shard_id: self.which_shard(&recipient),
+            source_shard_id: self.shard_id,
             transfer_certificate: certificate,
         });
         Ok((info, cross_shard))
     }
 
-    // NOTE: Need to rely on deliver-once semantics from comms channel
-    fn handle_cross_shard_recipient_commit(
+    fn handle_recipient_confirmation_order(
         &mut self,
-        certificate: CertifiedTransferOrder,
-    ) -> Result<(), FastPayError> {
-        // TODO: check certificate again?
-        let transfer = &certificate.value.transfer;
-
-        let recipient = match transfer.recipient {
+        confirmation_order: ConfirmationOrder,
+    ) -> Result<AccountInfoResponse, FastPayError> {
+        fp_ensure!(
+            self.committee.has_feature(Feature::RecipientClaims),
+            FastPayError::FeatureDisabled {
+                feature: Feature::RecipientClaims
+            }
+        );
+        let certificate = confirmation_order.transfer_certificate;
+        let recipient = match certificate.value.transfer.recipient {
             Address::FastPay(recipient) => recipient,
             Address::Primary(_) => {
-                fp_bail!(FastPayError::InvalidCrossShardUpdate);
+                fp_bail!(FastPayError::UnexpectedPrimaryRecipient);
             }
         };
         fp_ensure!(self.in_shard(&recipient), FastPayError::WrongShard);
-        let recipient_account = self
-            .accounts
-            .entry(recipient)
-            .or_insert_with(AccountOffchainState::new);
-        recipient_account.balance = recipient_account
-            .balance
-            .try_add(transfer.amount.into())
-            .unwrap_or_else(|_| Balance::max());
-        recipient_account.received_log.push(certificate);
-        Ok(())
+        self.check_encodings(
+            &certificate.value,
+            certificate
+                .signatures
+                .iter()
+                .map(|(_, signature)| signature),
+        )?;
+        self.check_certificate(&certificate)?;
+        // The sender may be on another shard, so the recipient policy does not apply.
+        self.check_memory(&recipient)?;
+        let now = self.clock.now_millis();
+        let recipient_account = self.account_or_insert(recipient);
+        recipient_account.receive(certificate, now);
+        Ok(recipient_account.make_account_info(recipient))
+    }
+
+    // NOTE: Need to rely on deliver-once semantics from comms channel
+    fn handle_cross_shard_recipient_commit(
+        &mut self,
+        update: CrossShardUpdate,
+    ) -> Result<(), FastPayError> {
+        fp_ensure!(
+            !self.sign_cross_shard_updates,
+            FastPayError::MissingCrossShardSignature
+        );
+        self.commit_cross_shard_update(update)
+    }
+
+    fn handle_signed_cross_shard_recipient_commit(
+        &mut self,
+        update: SignedCrossShardUpdate,
+    ) -> Result<(), FastPayError> {
+        update.check(self.name)?;
+        self.commit_cross_shard_update(update.update)
     }
 
     /// Finalize a transfer from Primary.
@@ -236,46 +482,148 @@ impl Authority for AuthorityState {
         // Update recipient state; note that the blockchain client is trusted.
         let recipient = order.recipient;
         fp_ensure!(self.in_shard(&recipient), FastPayError::WrongShard);
+        self.check_memory(&recipient)?;
 
-        let recipient_account = self
-            .accounts
-            .entry(recipient)
-            .or_insert_with(AccountOffchainState::new);
-        if order.transaction_index <= self.last_transaction_index {
+        let current_transaction_index = self.last_transaction_index;
+        let recipient_account = self.account_or_insert(recipient);
+        if order.transaction_index <= current_transaction_index {
             // Ignore old transaction index.
             return Ok(recipient_account.make_account_info(recipient));
         }
         fp_ensure!(
-            order.transaction_index == self.last_transaction_index.increment()?,
+            order.transaction_index == current_transaction_index.increment()?,
             FastPayError::UnexpectedTransactionIndex
         );
         let recipient_balance = recipient_account.balance.try_add(order.amount.into())?;
-        let last_transaction_index = self.last_transaction_index.increment()?;
+        let last_transaction_index = current_transaction_index.increment()?;
         recipient_account.balance = recipient_balance;
         recipient_account.synchronization_log.push(order);
+        let info = recipient_account.make_account_info(recipient);
         self.last_transaction_index = last_transaction_index;
-        Ok(recipient_account.make_account_info(recipient))
+        Ok(info)
     }
 
     fn handle_account_info_request(
         &self,
         request: AccountInfoRequest,
     ) -> Result<AccountInfoResponse, FastPayError> {
+        if request.observer {
+            return Ok(self.make_observer_info(request.sender));
+        }
         fp_ensure!(self.in_shard(&request.sender), FastPayError::WrongShard);
         let account = self.account_state(&request.sender)?;
         let mut response = account.make_account_info(request.sender);
         if let Some(seq) = request.request_sequence_number {
             if let Some(cert) = account.confirmed_log.get(usize::from(seq)) {
                 response.requested_certificate = Some(cert.clone());
+                response.requested_certificate_proof =
+                    account.confirmed_history.prove(usize::from(seq));
             } else {
                 fp_bail!(FastPayError::CertificateNotfound)
             }
         }
         if let Some(idx) = request.request_received_transfers_excluding_first_nth {
+            // Copying the received transfers may be expensive.
+            fp_ensure!(
+                !self.is_under_memory_pressure(),
+                FastPayError::MemoryPressure
+            );
             response.requested_received_transfers = account.received_log[idx..].to_vec();
         }
+        if self.sign_read_responses {
+            response.sign(&self.secret);
+        }
         Ok(response)
     }
+
+    fn handle_list_accounts_request(
+        &self,
+        request: ListAccountsRequest,
+    ) -> Result<ListAccountsResponse, FastPayError> {
+        fp_ensure!(
+            request.limit > 0 && request.limit <= MAX_LIST_ACCOUNTS_LIMIT,
+            FastPayError::InvalidListLimit {
+                max: MAX_LIST_ACCOUNTS_LIMIT
+            }
+        );
+        let range = match request.after {
+            Some(after) => self.accounts.range((Excluded(after), Unbounded)),
+            None => self.accounts.range(..),
+        };
+        let mut accounts = range.map(|(address, _)| *address);
+        let page: Vec<_> = accounts.by_ref().take(request.limit).collect();
+        let next_cursor = match accounts.next() {
+            Some(_) => page.last().cloned(),
+            None => None,
+        };
+        Ok(ListAccountsResponse {
+            accounts: page,
+            next_cursor,
+        })
+    }
+
+    fn handle_ownership_order(
+        &mut self,
+        order: OwnershipOrder,
+    ) -> Result<AccountInfoResponse, FastPayError> {
+        let change = &order.change;
+        fp_ensure!(self.in_shard(&change.account), FastPayError::WrongShard);
+        self.committee.check_domain(&change.domain)?;
+        if !self.skips_signature_checks() {
+            order.check_signatures()?;
+        }
+        change.ownership.check_valid()?;
+        if !self.may_contain_account(&change.account) {
+            fp_bail!(FastPayError::UnknownSenderAccount);
+        }
+        let account = self
+            .accounts
+            .get_mut(&change.account)
+            .ok_or(FastPayError::UnknownSenderAccount)?;
+        if account.ownership.as_ref() == Some(&change.ownership)
+            && change.version.increment()? == account.ownership_version
+        {
+            // This change was already applied.
+            return Ok(account.make_account_info(change.account));
+        }
+        account.check_owners(change.account, &order.signatures)?;
+        fp_ensure!(
+            change.version == account.ownership_version,
+            FastPayError::UnexpectedSequenceNumber
+        );
+        account.ownership = Some(change.ownership.clone());
+        account.ownership_version = account.ownership_version.increment()?;
+        Ok(account.make_account_info(change.account))
+    }
+
+    fn handle_sequence_number_request(
+        &self,
+        request: SequenceNumberRequest,
+    ) -> Result<SequenceNumberStatement, FastPayError> {
+        fp_ensure!(self.in_shard(&request.sender), FastPayError::WrongShard);
+        let mut status = SequenceNumberStatus::Unused;
+        // Unknown accounts never spent anything.
+        if self.may_contain_account(&request.sender) {
+            if let Some(account) = self.accounts.get(&request.sender) {
+                let index = usize::from(request.sequence_number);
+                if let Some(certificate) = account.confirmed_log.get(index) {
+                    status =
+                        SequenceNumberStatus::Confirmed(leaf_hash(&certificate.value.transfer));
+                } else if let Some(vote) = &account.pending_confirmation {
+                    if vote.value.transfer.sequence_number == request.sequence_number {
+                        status = SequenceNumberStatus::Voted(leaf_hash(&vote.value.transfer));
+                    }
+                }
+            }
+        }
+        Ok(SequenceNumberStatement::new(
+            request.sender,
+            request.sequence_number,
+            status,
+            self.name,
+            &self.secret,
+        ))
+    }
 }
 
 impl Default for AccountOffchainState {
@@ -285,8 +633,14 @@ impl Default for AccountOffchainState {
             next_sequence_number: SequenceNumber::new(),
             pending_confirmation: None,
             confirmed_log: Vec::new(),
+            confirmed_history: MerkleAccumulator::new(),
             synchronization_log: Vec::new(),
             received_log: Vec::new(),
+            received_transfers: BTreeSet::new(),
+            last_activity: None,
+            last_transfer: None,
+            ownership: None,
+            ownership_version: VersionNumber::new(),
         }
     }
 }
@@ -304,18 +658,124 @@ impl AccountOffchainState {
             pending_confirmation: self.pending_confirmation.clone(),
             requested_certificate: None,
             requested_received_transfers: Vec::new(),
+            history_root: self.confirmed_history.root(),
+            requested_certificate_proof: None,
+            last_activity: self.last_activity,
+            observer_info: None,
+            signature: None,
         }
     }
 
+    /// The certificate confirmed for the given sequence number of this account, if any.
+    fn confirmed_certificate(
+        &self,
+        sequence_number: SequenceNumber,
+    ) -> Option<&CertifiedTransferOrder> {
+        let index = self
+            .confirmed_log
+            .binary_search_by_key(&sequence_number, |certificate| {
+                certificate.value.transfer.sequence_number
+            })
+            .ok()?;
+        Some(&self.confirmed_log[index])
+    }
+
+    /// Credit the transfer of the given certificate, unless it was received already.
+    fn receive(&mut self, certificate: CertifiedTransferOrder, now: u64) {
+        let transfer = &certificate.value.transfer;
+        if !self
+            .received_transfers
+            .insert((transfer.sender, transfer.sequence_number))
+        {
+            return;
+        }
+        self.balance = self
+            .balance
+            .try_add(transfer.amount.into())
+            .unwrap_or_else(|_| Balance::max());
+        self.last_activity = Some(now);
+        self.received_log.push(certificate);
+    }
+
+    /// Check that the keys of the given (verified) signatures own this account.
+    fn check_owners(
+        &self,
+        address: FastPayAddress,
+        signatures: &[(FastPayAddress, Signature)],
+    ) -> Result<(), FastPayError> {
+        let keys = signatures.iter().map(|(key, _)| key);
+        match &self.ownership {
+            Some(ownership) => ownership.check_signers(keys),
+            None => Ownership::Single(address).check_signers(keys),
+        }
+    }
+
+    /// Check that the transfer references a state of this account that is at most
+    /// `window` sequence numbers old.
+    fn check_state_reference(&self, transfer: &Transfer, window: u64) -> Result<(), FastPayError> {
+        let reference = transfer
+            .account_state
+            .as_ref()
+            .ok_or(FastPayError::MissingAccountStateReference)?;
+        let current = u64::from(self.next_sequence_number);
+        let referenced = u64::from(reference.sequence_number);
+        fp_ensure!(
+            referenced <= current,
+            FastPayError::InvalidAccountStateReference
+        );
+        fp_ensure!(
+            current - referenced <= window,
+            FastPayError::StaleAccountStateReference {
+                current_sequence_number: self.next_sequence_number
+            }
+        );
+        fp_ensure!(
+            self.confirmed_history.root_at(referenced as usize) == Some(reference.history_root),
+            FastPayError::InvalidAccountStateReference
+        );
+        Ok(())
+    }
+
+    /// Check that at least `cooldown` milliseconds passed since the last transfer from
+    /// this account.
+    fn check_cooldown(&self, cooldown: u64, now: u64) -> Result<(), FastPayError> {
+        if let Some(last_transfer) = self.last_transfer {
+            let next_allowed = last_transfer.saturating_add(cooldown);
+            fp_ensure!(
+                now >= next_allowed,
+                FastPayError::TransferCooldown {
+                    retry_after_ms: next_allowed - now
+                }
+            );
+        }
+        Ok(())
+    }
+
+    /// Check that `now` is after the last transfer from this account.
+    fn check_increasing_time(&self, now: u64) -> Result<(), FastPayError> {
+        if let Some(last_transfer) = self.last_transfer {
+            fp_ensure!(
+                now > last_transfer,
+                FastPayError::NonMonotonicTimestamps { last_transfer }
+            );
+        }
+        Ok(())
+    }
+
     #[cfg(test)]
     pub fn new_with_balance(balance: Balance, received_log: Vec<CertifiedTransferOrder>) -> Self {
+        let received_transfers = received_log
+            .iter()
+            .map(|certificate| {
+                let transfer = &certificate.value.transfer;
+                (transfer.sender, transfer.sequence_number)
+            })
+            .collect();
         Self {
             balance,
-            next_sequence_number: SequenceNumber::new(),
-            pending_confirmation: None,
-            confirmed_log: Vec::new(),
-            synchronization_log: Vec::new(),
             received_log,
+            received_transfers,
+            ..Self::new()
         }
     }
 }
@@ -330,6 +790,22 @@ impl AuthorityState {
             last_transaction_index: VersionNumber::new(),
             shard_id: 0,
             number_of_shards: 1,
+            sign_read_responses: true,
+            large_transfer_threshold: None,
+            max_transfer_amount: None,
+            reject_non_canonical_encodings: true,
+            account_filter: None,
+            memory_limit: None,
+            sign_cross_shard_updates: false,
+            clock: Arc::new(SystemClock),
+            cross_shard_sources: None,
+            verification_cache: None,
+            reuse_confirmed_certificates: true,
+            allow_negative_balances: true,
+            log_detail: LogDetail::default(),
+            log_conflicting_orders: false,
+            #[cfg(feature = "unchecked-signatures")]
+            skip_signature_checks: false,
         }
     }
 
@@ -348,6 +824,22 @@ impl AuthorityState {
             last_transaction_index: VersionNumber::new(),
             shard_id,
             number_of_shards,
+            sign_read_responses: true,
+            large_transfer_threshold: None,
+            max_transfer_amount: None,
+            reject_non_canonical_encodings: true,
+            account_filter: None,
+            memory_limit: None,
+            sign_cross_shard_updates: false,
+            clock: Arc::new(SystemClock),
+            cross_shard_sources: None,
+            verification_cache: None,
+            reuse_confirmed_certificates: true,
+            allow_negative_balances: true,
+            log_detail: LogDetail::default(),
+            log_conflicting_orders: false,
+            #[cfg(feature = "unchecked-signatures")]
+            skip_signature_checks: false,
         }
     }
 
@@ -355,6 +847,23 @@ impl AuthorityState {
         self.which_shard(address) == self.shard_id
     }
 
+    /// Answer an observer query with whatever this shard holds for the address, which is
+    /// an empty account unless the shard owns it.
+    fn make_observer_info(&self, address: FastPayAddress) -> AccountInfoResponse {
+        let mut response = match self.accounts.get(&address) {
+            Some(account) => account.make_account_info(address),
+            None => AccountOffchainState::new().make_account_info(address),
+        };
+        response.observer_info = Some(ObserverInfo {
+            shard_id: self.shard_id,
+            owning_shard: self.which_shard(&address),
+        });
+        if self.sign_read_responses {
+            response.sign(&self.secret);
+        }
+        response
+    }
+
     pub fn get_shard(num_shards: u32, address: &FastPayAddress) -> u32 {
         const LAST_INTEGER_INDEX: usize = std::mem::size_of::<FastPayAddress>() - 4;
         u32::from_le_bytes(address.0[LAST_INTEGER_INDEX..].try_into().expect("4 bytes"))
@@ -365,15 +874,209 @@ impl AuthorityState {
         Self::get_shard(self.number_of_shards, address)
     }
 
+    fn check_encodings<'a, I>(
+        &self,
+        order: &TransferOrder,
+        signatures: I,
+    ) -> Result<(), FastPayError>
+    where
+        I: IntoIterator<Item = &'a Signature>,
+    {
+        if !self.reject_non_canonical_encodings {
+            return Ok(());
+        }
+        order.transfer.sender.check_canonical()?;
+        if let Address::FastPay(recipient) = &order.transfer.recipient {
+            recipient.check_canonical()?;
+        }
+        for signature in signatures {
+            signature.check_canonical()?;
+        }
+        Ok(())
+    }
+
+    /// Check that the FastPay recipient of a transfer may be credited according to the
+    /// recipient policy of the committee. Recipients on other shards are not checked.
+    fn check_recipient(&self, transfer: &Transfer) -> Result<(), FastPayError> {
+        if let Address::FastPay(recipient) = &transfer.recipient {
+            fp_ensure!(
+                self.committee.recipient_policy == RecipientPolicy::AutoCreate
+                    || !self.in_shard(recipient)
+                    || (self.may_contain_account(recipient)
+                        && self.accounts.contains_key(recipient)),
+                FastPayError::RecipientNotFound
+            );
+        }
+        Ok(())
+    }
+
+    fn commit_cross_shard_update(&mut self, update: CrossShardUpdate) -> Result<(), FastPayError> {
+        // TODO: check certificate again?
+        self.check_cross_shard_source(&update)?;
+        let certificate = update.transfer_certificate;
+        let transfer = &certificate.value.transfer;
+
+        let recipient = match transfer.recipient {
+            Address::FastPay(recipient) => recipient,
+            Address::Primary(_) => {
+                fp_bail!(FastPayError::InvalidCrossShardUpdate);
+            }
+        };
+        fp_ensure!(self.in_shard(&recipient), FastPayError::WrongShard);
+        // The sender was already debited, so the credit must not be rejected.
+        let now = self.clock.now_millis();
+        self.account_or_insert(recipient).receive(certificate, now);
+        Ok(())
+    }
+
+    /// Verify a certificate, unless the verification cache says it was already.
+    fn check_certificate(
+        &mut self,
+        certificate: &CertifiedTransferOrder,
+    ) -> Result<(), FastPayError> {
+        if self.skips_signature_checks() {
+            return Ok(());
+        }
+        let cache = match &mut self.verification_cache {
+            Some(cache) => cache,
+            None => return certificate.check(&self.committee),
+        };
+        let digest = certificate_digest(certificate);
+        let now = self.clock.now_millis();
+        if cache.contains(&digest, now) {
+            return Ok(());
+        }
+        certificate.check(&self.committee)?;
+        cache.insert(digest, now);
+        Ok(())
+    }
+
+    #[cfg(feature = "unchecked-signatures")]
+    fn skips_signature_checks(&self) -> bool {
+        self.skip_signature_checks
+    }
+
+    #[cfg(not(feature = "unchecked-signatures"))]
+    fn skips_signature_checks(&self) -> bool {
+        false
+    }
+
+    /// Check that a cross-shard update comes from another shard of this authority, allowed
+    /// to send updates, and which handles the sender of the transfer.
+    fn check_cross_shard_source(&self, update: &CrossShardUpdate) -> Result<(), FastPayError> {
+        let shard = update.source_shard_id;
+        let allowed = match &self.cross_shard_sources {
+            Some(sources) => sources.contains(&shard),
+            None => true,
+        };
+        let sender = &update.transfer_certificate.value.transfer.sender;
+        fp_ensure!(
+            shard < self.number_of_shards
+                && shard != self.shard_id
+                && allowed
+                && shard == self.which_shard(sender),
+            FastPayError::InvalidCrossShardSource { shard }
+        );
+        Ok(())
+    }
+
+    /// Estimate the memory used by the accounts of this shard, in bytes.
+    pub fn estimated_memory_usage(&self) -> usize {
+        self.accounts.len() * ESTIMATED_ACCOUNT_SIZE
+    }
+
+    pub fn is_under_memory_pressure(&self) -> bool {
+        match self.memory_limit {
+            Some(limit) => self.estimated_memory_usage() >= limit,
+            None => false,
+        }
+    }
+
+    /// Check that the given account may be used, i.e. that it exists already or that
+    /// there is enough memory left to create it. Accounts of other shards are ignored.
+    fn check_memory(&self, address: &FastPayAddress) -> Result<(), FastPayError> {
+        fp_ensure!(
+            !self.is_under_memory_pressure()
+                || !self.in_shard(address)
+                || (self.may_contain_account(address) && self.accounts.contains_key(address)),
+            FastPayError::MemoryPressure
+        );
+        Ok(())
+    }
+
     fn account_state(
         &self,
         address: &FastPayAddress,
     ) -> Result<&AccountOffchainState, FastPayError> {
+        fp_ensure!(
+            self.may_contain_account(address),
+            FastPayError::UnknownSenderAccount
+        );
         self.accounts
             .get(address)
             .ok_or(FastPayError::UnknownSenderAccount)
     }
 
+    /// Maintain a filter over the accounts of this shard with the given false positive
+    /// rate, so that lookups of missing accounts do not need to probe the store.
+    pub fn enable_account_filter(&mut self, false_positive_rate: f64) {
+        let capacity = std::cmp::max(2 * self.accounts.len(), MIN_ACCOUNT_FILTER_CAPACITY);
+        let mut filter = BloomFilter::new(capacity, false_positive_rate);
+        for address in self.accounts.keys() {
+            filter.insert(&address.0);
+        }
+        self.account_filter = Some(filter);
+    }
+
+    /// Replace all the accounts of the shard with fresh accounts holding the given
+    /// balances, discarding their history.
+    pub fn reset_accounts<I>(&mut self, balances: I)
+    where
+        I: IntoIterator<Item = (FastPayAddress, Balance)>,
+    {
+        self.accounts = balances
+            .into_iter()
+            .map(|(address, balance)| {
+                let mut account = AccountOffchainState::new();
+                account.balance = balance;
+                (address, account)
+            })
+            .collect();
+        self.last_transaction_index = VersionNumber::new();
+        self.rebuild_account_filter();
+    }
+
+    /// Rebuild the account filter (if any) from `accounts`, e.g. after restoring a snapshot.
+    pub fn rebuild_account_filter(&mut self) {
+        if let Some(filter) = &self.account_filter {
+            self.enable_account_filter(filter.false_positive_rate());
+        }
+    }
+
+    /// Returns false only if the account does not exist.
+    fn may_contain_account(&self, address: &FastPayAddress) -> bool {
+        match &self.account_filter {
+            Some(filter) => filter.may_contain(&address.0),
+            None => true,
+        }
+    }
+
+    fn account_or_insert(&mut self, address: FastPayAddress) -> &mut AccountOffchainState {
+        if let Entry::Vacant(entry) = self.accounts.entry(address) {
+            entry.insert(AccountOffchainState::new());
+            if let Some(filter) = &mut self.account_filter {
+                filter.insert(&address.0);
+                if filter.is_full() {
+                    // Grow the filter to keep its false positive rate within bounds.
+                    self.rebuild_account_filter();
+                }
+            }
+        }
+        self.accounts
+            .get_mut(&address)
+            .expect("account should exist")
+    }
+
     #[cfg(test)]
     pub fn accounts_mut(&mut self) -> &mut BTreeMap<FastPayAddress, AccountOffchainState> {
         &mut self.accounts
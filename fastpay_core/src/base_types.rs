@@ -1,12 +1,18 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
+use aes_gcm::{
+    aead::{Aead, NewAead},
+    Aes256Gcm,
+};
+use curve25519_dalek::{edwards::CompressedEdwardsY, scalar::Scalar};
 use ed25519_dalek as dalek;
 use ed25519_dalek::{Signer, Verifier};
 
-use rand::rngs::OsRng;
+use rand::{rngs::OsRng, CryptoRng, RngCore};
 use serde::{Deserialize, Serialize};
 use std::convert::{TryFrom, TryInto};
+use subtle::ConstantTimeEq;
 
 use crate::error::FastPayError;
 
@@ -14,13 +20,9 @@ use crate::error::FastPayError;
 #[path = "unit_tests/base_types_tests.rs"]
 mod base_types_tests;
 
-#[derive(
-    Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Default, Debug, Serialize, Deserialize,
-)]
+#[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Default, Debug)]
 pub struct Amount(u64);
-#[derive(
-    Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Default, Debug, Serialize, Deserialize,
-)]
+#[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Default, Debug)]
 pub struct Balance(i128);
 #[derive(
     Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Default, Debug, Serialize, Deserialize,
@@ -44,8 +46,16 @@ pub type FastPayAddress = PublicKeyBytes;
 pub type AuthorityName = PublicKeyBytes;
 
 pub fn get_key_pair() -> (FastPayAddress, KeyPair) {
-    let mut csprng = OsRng;
-    let keypair = dalek::Keypair::generate(&mut csprng);
+    get_key_pair_from_rng(&mut OsRng)
+}
+
+/// Generate a key pair from the given source of randomness, e.g. a seeded generator to
+/// obtain the same keys on every run.
+pub fn get_key_pair_from_rng<R>(rng: &mut R) -> (FastPayAddress, KeyPair)
+where
+    R: CryptoRng + RngCore,
+{
+    let keypair = dalek::Keypair::generate(rng);
     (PublicKeyBytes(keypair.public.to_bytes()), KeyPair(keypair))
 }
 
@@ -65,14 +75,66 @@ where
     Ok(value)
 }
 
+/// Serialize an address as hex, for configs read by tools that expect hex keys.
+pub fn address_as_hex<S>(key: &PublicKeyBytes, serializer: S) -> Result<S::Ok, S::Error>
+where
+    S: serde::ser::Serializer,
+{
+    serializer.serialize_str(&encode_address_hex(key))
+}
+
+pub fn address_from_hex<'de, D>(deserializer: D) -> Result<PublicKeyBytes, D::Error>
+where
+    D: serde::de::Deserializer<'de>,
+{
+    let s = String::deserialize(deserializer)?;
+    let value = decode_address_hex(&s).map_err(|err| serde::de::Error::custom(err.to_string()))?;
+    Ok(value)
+}
+
 pub fn encode_address(key: &PublicKeyBytes) -> String {
     base64::encode(&key.0[..])
 }
 
+/// Decode a base64 address, checking that it is a valid ed25519 public key.
 pub fn decode_address(s: &str) -> Result<PublicKeyBytes, failure::Error> {
-    let value = base64::decode(s)?;
-    let mut address = [0u8; dalek::PUBLIC_KEY_LENGTH];
-    address.copy_from_slice(&value[..dalek::PUBLIC_KEY_LENGTH]);
+    address_from_bytes(base64::decode(s)?)
+}
+
+/// Lowercase hex encoding of an address, without prefix.
+pub fn encode_address_hex(key: &PublicKeyBytes) -> String {
+    hex::encode(&key.0[..])
+}
+
+/// Decode a hex address, with or without a `0x` prefix and in any case, checking that it
+/// is a valid ed25519 public key.
+pub fn decode_address_hex(s: &str) -> Result<PublicKeyBytes, failure::Error> {
+    let digits = s
+        .strip_prefix("0x")
+        .or_else(|| s.strip_prefix("0X"))
+        .unwrap_or(s);
+    let expected = 2 * dalek::PUBLIC_KEY_LENGTH;
+    if digits.len() != expected {
+        failure::bail!(
+            "invalid hex address length: expected {} digits, got {}",
+            expected,
+            digits.len()
+        );
+    }
+    address_from_bytes(hex::decode(digits)?)
+}
+
+fn address_from_bytes(value: Vec<u8>) -> Result<PublicKeyBytes, failure::Error> {
+    let address: [u8; dalek::PUBLIC_KEY_LENGTH] = value.as_slice().try_into().map_err(|_| {
+        failure::format_err!(
+            "invalid address length: expected {} bytes, got {}",
+            dalek::PUBLIC_KEY_LENGTH,
+            value.len()
+        )
+    })?;
+    if CompressedEdwardsY(address).decompress().is_none() {
+        failure::bail!("invalid address: not a point of the ed25519 curve");
+    }
     Ok(PublicKeyBytes(address))
 }
 
@@ -82,9 +144,59 @@ pub fn dbg_addr(name: u8) -> FastPayAddress {
     PublicKeyBytes(addr)
 }
 
-#[derive(Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[derive(Eq, Copy, Clone, Serialize, Deserialize)]
 pub struct Signature(dalek::Signature);
 
+/// Number of votes verified together by each task of `Signature::verify_batch_parallel`.
+#[cfg(feature = "parallel-verification")]
+const PARALLEL_VERIFICATION_CHUNK_SIZE: usize = 64;
+
+/// Signatures are compared in constant time, so that the comparison does not reveal how
+/// many leading bytes match.
+impl PartialEq for Signature {
+    fn eq(&self, other: &Self) -> bool {
+        self.0.to_bytes().ct_eq(&other.0.to_bytes()).into()
+    }
+}
+
+#[cfg(test)]
+/// Add the order of the curve's prime subgroup to the `s` component of a signature.
+pub fn dbg_non_canonical(signature: &Signature) -> Signature {
+    const L: [u8; 32] = [
+        0xed, 0xd3, 0xf5, 0x5c, 0x1a, 0x63, 0x12, 0x58, 0xd6, 0x9c, 0xf7, 0xa2, 0xde, 0xf9, 0xde,
+        0x14, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x10,
+    ];
+    let mut bytes = signature.0.to_bytes();
+    let mut carry = 0u16;
+    for i in 0..32 {
+        let sum = bytes[32 + i] as u16 + L[i] as u16 + carry;
+        bytes[32 + i] = sum as u8;
+        carry = sum >> 8;
+    }
+    Signature(dalek::Signature::try_from(&bytes[..]).unwrap())
+}
+
+/// Whether the given bytes are the canonical encoding of a curve point. Bytes that do not
+/// encode a point at all have no canonical equivalent and are left to signature checks.
+fn is_canonical_point(bytes: &[u8; 32]) -> bool {
+    let compressed = CompressedEdwardsY(*bytes);
+    match compressed.decompress() {
+        Some(point) => point.compress() == compressed,
+        None => true,
+    }
+}
+
+impl PublicKeyBytes {
+    /// Reject keys that decode to the same point as a different encoding.
+    pub fn check_canonical(&self) -> Result<(), FastPayError> {
+        fp_ensure!(
+            is_canonical_point(&self.0),
+            FastPayError::NonCanonicalEncoding
+        );
+        Ok(())
+    }
+}
+
 impl KeyPair {
     /// Avoid implementing `clone` on secret keys to prevent mistakes.
     pub fn copy(&self) -> KeyPair {
@@ -93,6 +205,97 @@ impl KeyPair {
             public: dalek::PublicKey::from_bytes(self.0.public.as_bytes()).unwrap(),
         })
     }
+
+    pub fn public(&self) -> PublicKeyBytes {
+        PublicKeyBytes(self.0.public.to_bytes())
+    }
+
+    /// Compare the secret keys in constant time. There is deliberately no `PartialEq`
+    /// on key pairs, so that secrets are not compared by accident.
+    pub fn ct_eq(&self, other: &KeyPair) -> bool {
+        self.0
+            .secret
+            .as_bytes()
+            .ct_eq(other.0.secret.as_bytes())
+            .into()
+    }
+}
+
+/// A key pair encrypted with a key derived from a passphrase (scrypt and AES-256-GCM).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EncryptedKeyPair {
+    /// Scrypt cost parameters.
+    pub log_n: u8,
+    pub r: u32,
+    pub p: u32,
+    /// Base64-encoded scrypt salt, AES-GCM nonce and ciphertext.
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// Default scrypt cost for new encrypted keys (2^15 iterations).
+const DEFAULT_SCRYPT_LOG_N: u8 = 15;
+
+fn derive_encryption_key(
+    passphrase: &str,
+    salt: &[u8],
+    log_n: u8,
+    r: u32,
+    p: u32,
+) -> Result<Aes256Gcm, failure::Error> {
+    let params = scrypt::ScryptParams::new(log_n, r, p)
+        .map_err(|_| failure::format_err!("invalid scrypt parameters"))?;
+    let mut key = [0u8; 32];
+    scrypt::scrypt(passphrase.as_bytes(), salt, &params, &mut key)
+        .map_err(|_| failure::format_err!("invalid scrypt output length"))?;
+    Ok(Aes256Gcm::new_varkey(&key).expect("Key length should be valid"))
+}
+
+impl KeyPair {
+    /// Encrypt the key pair so that it can be stored at rest.
+    pub fn encrypt(&self, passphrase: &str) -> EncryptedKeyPair {
+        self.encrypt_with_cost(passphrase, DEFAULT_SCRYPT_LOG_N)
+    }
+
+    /// Encrypt the key pair using 2^log_n scrypt iterations. Lower costs make brute-forcing
+    /// the passphrase easier.
+    pub fn encrypt_with_cost(&self, passphrase: &str, log_n: u8) -> EncryptedKeyPair {
+        let (r, p) = (8, 1);
+        let mut salt = [0u8; 16];
+        let mut nonce = [0u8; 12];
+        OsRng.fill_bytes(&mut salt);
+        OsRng.fill_bytes(&mut nonce);
+        let cipher = derive_encryption_key(passphrase, &salt, log_n, r, p)
+            .expect("Default scrypt parameters should be valid");
+        let ciphertext = cipher
+            .encrypt(&nonce.into(), &self.0.to_bytes()[..])
+            .expect("Encryption should not fail");
+        EncryptedKeyPair {
+            log_n,
+            r,
+            p,
+            salt: base64::encode(salt),
+            nonce: base64::encode(nonce),
+            ciphertext: base64::encode(ciphertext),
+        }
+    }
+}
+
+impl EncryptedKeyPair {
+    /// Decrypt the key pair. Fails if the passphrase is wrong or the data was altered.
+    pub fn decrypt(&self, passphrase: &str) -> Result<KeyPair, failure::Error> {
+        let salt = base64::decode(&self.salt)?;
+        let nonce: [u8; 12] = base64::decode(&self.nonce)?[..]
+            .try_into()
+            .map_err(|_| failure::format_err!("invalid nonce length"))?;
+        let ciphertext = base64::decode(&self.ciphertext)?;
+        let cipher = derive_encryption_key(passphrase, &salt, self.log_n, self.r, self.p)?;
+        let bytes = cipher
+            .decrypt(&nonce.into(), &ciphertext[..])
+            .map_err(|_| failure::format_err!("wrong passphrase or corrupted key"))?;
+        Ok(KeyPair(dalek::Keypair::from_bytes(&bytes)?))
+    }
 }
 
 impl Serialize for KeyPair {
@@ -133,6 +336,9 @@ impl std::fmt::Debug for PublicKeyBytes {
     }
 }
 
+/// Number of decimal places used when amounts are written as a number of tokens.
+pub const AMOUNT_DECIMALS: u32 = 6;
+
 impl Amount {
     pub fn zero() -> Self {
         Amount(0)
@@ -153,6 +359,25 @@ impl Amount {
             Some(val) => Ok(Self(val)),
         }
     }
+
+    pub fn try_mul(self, factor: u64) -> Result<Self, FastPayError> {
+        let val = self.0.checked_mul(factor);
+        match val {
+            None => Err(FastPayError::AmountOverflow),
+            Some(val) => Ok(Self(val)),
+        }
+    }
+
+    /// Compute `self * numerator / denominator`, rounded down. The product is computed
+    /// on 128 bits so that it does not overflow before the division.
+    pub fn try_mul_ratio(self, numerator: u64, denominator: u64) -> Result<Self, FastPayError> {
+        fp_ensure!(denominator != 0, FastPayError::DivisionByZero);
+        let val = u128::from(self.0) * u128::from(numerator) / u128::from(denominator);
+        match u64::try_from(val) {
+            Err(_) => Err(FastPayError::AmountOverflow),
+            Ok(val) => Ok(Self(val)),
+        }
+    }
 }
 
 impl Balance {
@@ -179,19 +404,124 @@ impl Balance {
             Some(val) => Ok(Self(val)),
         }
     }
+
+    /// Subtract `other`, returning the (possibly negative) result together with the
+    /// shortfall `other - self` when `other` exceeds `self`. The result saturates at the
+    /// bounds of `Balance` and the shortfall at the largest `Amount`.
+    pub fn sub_with_deficit(&self, other: Self) -> (Self, Option<Amount>) {
+        let balance = Self(self.0.saturating_sub(other.0));
+        if other <= *self {
+            return (balance, None);
+        }
+        // The difference is positive and below 2^128, so it is exact modulo 2^128.
+        let deficit = (other.0 as u128).wrapping_sub(self.0 as u128);
+        let deficit = Amount(u64::try_from(deficit).unwrap_or(u64::MAX));
+        (balance, Some(deficit))
+    }
+
+    /// Same as `Amount::try_from`, but telling a negative balance apart from one that is
+    /// too large.
+    pub fn to_amount(&self) -> Result<Amount, FastPayError> {
+        fp_ensure!(self.0 >= 0, FastPayError::BalanceUnderflow);
+        u64::try_from(self.0)
+            .map(Amount)
+            .map_err(|_| FastPayError::AmountOverflow)
+    }
 }
 
-impl std::fmt::Display for Balance {
+impl Amount {
+    /// Parse a number of tokens such as "1000" or "1.50", where a token is worth
+    /// `10^decimals` base units (`decimals` is at most 38).
+    pub fn parse_decimal(src: &str, decimals: u32) -> Result<Self, failure::Error> {
+        let (integer, fraction) = match src.find('.') {
+            Some(index) => (&src[..index], Some(&src[index + 1..])),
+            None => (src, None),
+        };
+        let is_number =
+            |digits: &str| !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit());
+        failure::ensure!(
+            !src.starts_with('-'),
+            "invalid amount {:?}: amounts cannot be negative",
+            src
+        );
+        let well_formed = match fraction {
+            Some(fraction) => is_number(integer) && is_number(fraction),
+            None => is_number(integer),
+        };
+        failure::ensure!(
+            well_formed,
+            "invalid amount {:?}: expected a decimal number such as 1000 or 1.50",
+            src
+        );
+        let fraction = fraction.unwrap_or("");
+        failure::ensure!(
+            fraction.len() <= decimals as usize,
+            "invalid amount {:?}: at most {} decimal places are allowed",
+            src,
+            decimals
+        );
+        let too_large =
+            || failure::format_err!("invalid amount {:?}: the amount is too large", src);
+        let scale = 10u128.checked_pow(decimals).ok_or_else(too_large)?;
+        let integer = integer.parse::<u128>().map_err(|_| too_large())?;
+        let mut fraction_units = 0u128;
+        for (position, digit) in fraction.bytes().enumerate() {
+            fraction_units += u128::from(digit - b'0') * 10u128.pow(decimals - 1 - position as u32);
+        }
+        let value = integer
+            .checked_mul(scale)
+            .and_then(|value| value.checked_add(fraction_units))
+            .ok_or_else(too_large)?;
+        Ok(Self(u64::try_from(value).map_err(|_| too_large())?))
+    }
+
+    /// Write the amount as a number of tokens, without trailing zeros.
+    pub fn to_decimal_string(self, decimals: u32) -> String {
+        decimal_string(u128::from(self.0), decimals)
+    }
+}
+
+impl Balance {
+    /// Write the balance as a number of tokens, without trailing zeros.
+    pub fn to_decimal_string(self, decimals: u32) -> String {
+        let tokens = decimal_string(self.0.unsigned_abs(), decimals);
+        if self.0 < 0 {
+            format!("-{}", tokens)
+        } else {
+            tokens
+        }
+    }
+}
+
+fn decimal_string(value: u128, decimals: u32) -> String {
+    let scale = 10u128.pow(decimals);
+    let fraction = value % scale;
+    if fraction == 0 {
+        return format!("{}", value / scale);
+    }
+    let fraction = format!("{:0width$}", fraction, width = decimals as usize);
+    format!("{}.{}", value / scale, fraction.trim_end_matches('0'))
+}
+
+/// Amounts are written as a number of tokens with `AMOUNT_DECIMALS` decimal places.
+impl std::fmt::Display for Amount {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "{}", self.0)
+        write!(f, "{}", self.to_decimal_string(AMOUNT_DECIMALS))
     }
 }
 
-impl std::str::FromStr for Balance {
-    type Err = std::num::ParseIntError;
+impl std::str::FromStr for Amount {
+    type Err = failure::Error;
 
     fn from_str(src: &str) -> Result<Self, Self::Err> {
-        Ok(Self(i128::from_str(src)?))
+        Self::parse_decimal(src, AMOUNT_DECIMALS)
+    }
+}
+
+/// Balances are written as a number of tokens, like amounts.
+impl std::fmt::Display for Balance {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.to_decimal_string(AMOUNT_DECIMALS))
     }
 }
 
@@ -232,6 +562,15 @@ impl SequenceNumber {
         }
     }
 
+    /// Same as `increment` but stays at `SequenceNumber::max()` instead of failing.
+    pub fn saturating_increment(self) -> SequenceNumber {
+        if self < Self::max() {
+            Self(self.0 + 1)
+        } else {
+            self
+        }
+    }
+
     pub fn decrement(self) -> Result<SequenceNumber, FastPayError> {
         let val = self.0.checked_sub(1);
         match val {
@@ -239,6 +578,40 @@ impl SequenceNumber {
             Some(val) => Ok(Self(val)),
         }
     }
+
+    /// Sequence numbers from `start` (inclusive) to `end` (exclusive), stopping after
+    /// `SequenceNumber::max()`.
+    pub fn range(start: SequenceNumber, end: SequenceNumber) -> SequenceNumberRange {
+        SequenceNumberRange {
+            next: Some(start),
+            end,
+        }
+    }
+}
+
+/// Iterator returned by `SequenceNumber::range`.
+#[derive(Clone, Debug)]
+pub struct SequenceNumberRange {
+    next: Option<SequenceNumber>,
+    end: SequenceNumber,
+}
+
+impl Iterator for SequenceNumberRange {
+    type Item = SequenceNumber;
+
+    fn next(&mut self) -> Option<SequenceNumber> {
+        let current = self.next?;
+        if current >= self.end || current > SequenceNumber::max() {
+            self.next = None;
+            return None;
+        }
+        self.next = if current < SequenceNumber::max() {
+            Some(SequenceNumber(current.0 + 1))
+        } else {
+            None
+        };
+        Some(current)
+    }
 }
 
 impl From<SequenceNumber> for u64 {
@@ -259,6 +632,95 @@ impl From<i128> for Balance {
     }
 }
 
+impl From<Balance> for i128 {
+    fn from(value: Balance) -> Self {
+        value.0
+    }
+}
+
+/// Serialize an integer newtype as a string of base units in human-readable formats such
+/// as JSON, so that large values survive parsers that use floating-point numbers. Unlike
+/// `Display` for amounts, configuration files count base units, not tokens. Numbers are
+/// still accepted when deserializing. Binary formats keep the plain integer.
+macro_rules! serde_as_integer_string {
+    ($name:ident, $inner:ty) => {
+        impl Serialize for $name {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: serde::ser::Serializer,
+            {
+                if serializer.is_human_readable() {
+                    serializer.serialize_str(&self.0.to_string())
+                } else {
+                    serializer.serialize_newtype_struct(stringify!($name), &self.0)
+                }
+            }
+        }
+
+        impl<'de> Deserialize<'de> for $name {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: serde::de::Deserializer<'de>,
+            {
+                use serde::de::Error;
+
+                struct Visitor;
+
+                impl<'de> serde::de::Visitor<'de> for Visitor {
+                    type Value = $name;
+
+                    fn expecting(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                        write!(f, "an integer or a string of digits")
+                    }
+
+                    fn visit_newtype_struct<D>(
+                        self,
+                        deserializer: D,
+                    ) -> Result<Self::Value, D::Error>
+                    where
+                        D: serde::de::Deserializer<'de>,
+                    {
+                        <$inner>::deserialize(deserializer).map($name)
+                    }
+
+                    fn visit_str<E: Error>(self, value: &str) -> Result<Self::Value, E> {
+                        value.parse::<$inner>().map($name).map_err(E::custom)
+                    }
+
+                    fn visit_i64<E: Error>(self, value: i64) -> Result<Self::Value, E> {
+                        self.visit_i128(value.into())
+                    }
+
+                    fn visit_u64<E: Error>(self, value: u64) -> Result<Self::Value, E> {
+                        self.visit_u128(value.into())
+                    }
+
+                    fn visit_i128<E: Error>(self, value: i128) -> Result<Self::Value, E> {
+                        <$inner>::try_from(value)
+                            .map($name)
+                            .map_err(|_| E::custom(format!("{} is out of range", value)))
+                    }
+
+                    fn visit_u128<E: Error>(self, value: u128) -> Result<Self::Value, E> {
+                        <$inner>::try_from(value)
+                            .map($name)
+                            .map_err(|_| E::custom(format!("{} is out of range", value)))
+                    }
+                }
+
+                if deserializer.is_human_readable() {
+                    deserializer.deserialize_any(Visitor)
+                } else {
+                    deserializer.deserialize_newtype_struct(stringify!($name), Visitor)
+                }
+            }
+        }
+    };
+}
+
+serde_as_integer_string!(Amount, u64);
+serde_as_integer_string!(Balance, i128);
+
 impl From<u64> for SequenceNumber {
     fn from(value: u64) -> Self {
         SequenceNumber(value)
@@ -281,15 +743,22 @@ pub trait Signable<Hasher> {
 /// * We use `BCS` to generate canonical bytes suitable for hashing and signing.
 pub trait BcsSignable: Serialize + serde::de::DeserializeOwned {}
 
+/// Seed written before the BCS bytes of a `BcsSignable` value.
+pub fn signable_name_prefix<T: BcsSignable>() -> String {
+    let name = serde_name::trace_name::<T>().expect("Self must be a struct or an enum");
+    // Note: This assumes that names never contain the separator `::`.
+    format!("{}::", name)
+}
+
 impl<T, Hasher> Signable<Hasher> for T
 where
     T: BcsSignable,
     Hasher: std::io::Write,
 {
     fn write(&self, hasher: &mut Hasher) {
-        let name = serde_name::trace_name::<Self>().expect("Self must be a struct or an enum");
-        // Note: This assumes that names never contain the separator `::`.
-        write!(hasher, "{}::", name).expect("Hasher should not fail");
+        hasher
+            .write_all(signable_name_prefix::<Self>().as_bytes())
+            .expect("Hasher should not fail");
         bcs::serialize_into(hasher, &self).expect("Message serialization should not fail");
     }
 }
@@ -305,6 +774,21 @@ impl Signature {
         Signature(signature)
     }
 
+    /// Reject signatures whose components are not canonically encoded, so that a
+    /// signature cannot be altered into a different but equivalent one.
+    pub fn check_canonical(&self) -> Result<(), FastPayError> {
+        let bytes = self.0.to_bytes();
+        let mut r = [0u8; 32];
+        let mut s = [0u8; 32];
+        r.copy_from_slice(&bytes[..32]);
+        s.copy_from_slice(&bytes[32..]);
+        fp_ensure!(
+            is_canonical_point(&r) && Scalar::from_canonical_bytes(s).is_some(),
+            FastPayError::NonCanonicalEncoding
+        );
+        Ok(())
+    }
+
     fn check_internal<T>(
         &self,
         value: &T,
@@ -347,6 +831,31 @@ impl Signature {
         dalek::verify_batch(&messages[..], &signatures[..], &public_keys[..])
     }
 
+    fn verify_messages_internal<'a, I>(items: I) -> Result<(), dalek::SignatureError>
+    where
+        I: IntoIterator<Item = (&'a [u8], FastPayAddress, Signature)>,
+    {
+        let mut messages: Vec<&[u8]> = Vec::new();
+        let mut signatures: Vec<dalek::Signature> = Vec::new();
+        let mut public_keys: Vec<dalek::PublicKey> = Vec::new();
+        for (message, addr, sig) in items.into_iter() {
+            messages.push(message);
+            signatures.push(sig.0);
+            public_keys.push(dalek::PublicKey::from_bytes(&addr.0)?);
+        }
+        dalek::verify_batch(&messages[..], &signatures[..], &public_keys[..])
+    }
+
+    /// Verify signatures over different messages at once.
+    pub fn verify_messages<'a, I>(items: I) -> Result<(), FastPayError>
+    where
+        I: IntoIterator<Item = (&'a [u8], FastPayAddress, Signature)>,
+    {
+        Signature::verify_messages_internal(items).map_err(|error| FastPayError::InvalidSignature {
+            error: format!("{}", error),
+        })
+    }
+
     pub fn verify_batch<'a, T, I>(value: &'a T, votes: I) -> Result<(), FastPayError>
     where
         T: Signable<Vec<u8>>,
@@ -358,4 +867,31 @@ impl Signature {
             }
         })
     }
+
+    /// Same as `verify_batch`, verifying chunks of the votes on the rayon thread pool.
+    /// Verification stops as soon as a chunk fails.
+    #[cfg(feature = "parallel-verification")]
+    pub fn verify_batch_parallel<'a, T, I>(value: &'a T, votes: I) -> Result<(), FastPayError>
+    where
+        T: Signable<Vec<u8>>,
+        I: IntoIterator<Item = &'a (FastPayAddress, Signature)>,
+    {
+        use rayon::prelude::*;
+
+        let mut message = Vec::new();
+        value.write(&mut message);
+        let votes: Vec<_> = votes.into_iter().collect();
+        votes
+            .par_chunks(PARALLEL_VERIFICATION_CHUNK_SIZE)
+            .try_for_each(|chunk| {
+                Signature::verify_messages_internal(
+                    chunk
+                        .iter()
+                        .map(|(address, signature)| (&message[..], *address, *signature)),
+                )
+            })
+            .map_err(|error| FastPayError::InvalidSignature {
+                error: format!("{}", error),
+            })
+    }
 }
@@ -0,0 +1,88 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use sha2::{Digest, Sha256};
+use std::convert::TryInto;
+
+#[cfg(test)]
+#[path = "unit_tests/bloom_tests.rs"]
+mod bloom_tests;
+
+/// Probabilistic set that may report false positives but never false negatives.
+#[derive(Clone, Debug)]
+pub struct BloomFilter {
+    bits: Vec<u64>,
+    num_bits: u64,
+    num_hashes: u32,
+    false_positive_rate: f64,
+    /// Number of items for which the false positive rate is guaranteed.
+    capacity: usize,
+    len: usize,
+}
+
+impl BloomFilter {
+    /// Create a filter holding up to `capacity` items with a false positive rate of at
+    /// most `false_positive_rate`.
+    pub fn new(capacity: usize, false_positive_rate: f64) -> Self {
+        let capacity = std::cmp::max(capacity, 1);
+        let rate = false_positive_rate.clamp(f64::MIN_POSITIVE, 0.5);
+        let ln2 = std::f64::consts::LN_2;
+        let num_bits = (-(capacity as f64) * rate.ln() / (ln2 * ln2)).ceil() as u64;
+        // Use whole words.
+        let num_words = num_bits / 64 + 1;
+        let num_bits = num_words * 64;
+        let num_hashes = ((num_bits as f64 / capacity as f64) * ln2).round() as u32;
+        Self {
+            bits: vec![0; num_words as usize],
+            num_bits,
+            num_hashes: std::cmp::max(num_hashes, 1),
+            false_positive_rate,
+            capacity,
+            len: 0,
+        }
+    }
+
+    pub fn false_positive_rate(&self) -> f64 {
+        self.false_positive_rate
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Whether more items were inserted than the false positive rate allows for.
+    pub fn is_full(&self) -> bool {
+        self.len > self.capacity
+    }
+
+    fn positions(&self, item: &[u8]) -> impl Iterator<Item = u64> {
+        // Double hashing: the i-th position is h1 + i * h2.
+        let digest = Sha256::digest(item);
+        let h1 = u64::from_le_bytes(digest[..8].try_into().expect("8 bytes"));
+        let h2 = u64::from_le_bytes(digest[8..16].try_into().expect("8 bytes"));
+        let num_bits = self.num_bits;
+        (0..self.num_hashes as u64).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    pub fn insert(&mut self, item: &[u8]) {
+        let positions: Vec<_> = self.positions(item).collect();
+        for position in positions {
+            self.bits[(position / 64) as usize] |= 1 << (position % 64);
+        }
+        self.len += 1;
+    }
+
+    /// Returns false only if the item was never inserted.
+    pub fn may_contain(&self, item: &[u8]) -> bool {
+        self.positions(item)
+            .all(|position| self.bits[(position / 64) as usize] & (1 << (position % 64)) != 0)
+    }
+}
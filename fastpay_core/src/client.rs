@@ -1,13 +1,25 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{base_types::*, committee::Committee, downloader::*, error::FastPayError, messages::*};
+use crate::{
+    authority::{AccountOffchainState, Authority, AuthorityState},
+    base_types::*,
+    committee::Committee,
+    downloader::*,
+    error::FastPayError,
+    merkle::{leaf_hash, MerkleAccumulator},
+    messages::*,
+};
 use failure::{bail, ensure};
-use futures::{future, StreamExt};
+use futures::{future, lock::Mutex, StreamExt};
+use log::warn;
 use rand::seq::SliceRandom;
+use serde::{Deserialize, Serialize};
 use std::{
-    collections::{btree_map, BTreeMap, BTreeSet, HashMap},
+    collections::{btree_map, BTreeMap, BTreeSet, HashMap, HashSet},
     convert::TryFrom,
+    sync::Arc,
+    time::Duration,
 };
 
 #[cfg(test)]
@@ -36,6 +48,204 @@ pub trait AuthorityClient {
     ) -> AsyncResult<AccountInfoResponse, FastPayError>;
 }
 
+/// Wraps an authority client so that requests which take longer than the given timeout
+/// fail with `RequestTimeout`. Network clients have their own send and receive timeouts.
+#[derive(Clone)]
+pub struct TimeoutAuthorityClient<A> {
+    inner: A,
+    timeout: Duration,
+}
+
+impl<A> TimeoutAuthorityClient<A> {
+    pub fn new(inner: A, timeout: Duration) -> Self {
+        Self { inner, timeout }
+    }
+
+    fn with_timeout<'a>(
+        timeout: Duration,
+        request: AsyncResult<'a, AccountInfoResponse, FastPayError>,
+    ) -> AsyncResult<'a, AccountInfoResponse, FastPayError> {
+        Box::pin(async move {
+            tokio::time::timeout(timeout, request)
+                .await
+                .unwrap_or(Err(FastPayError::RequestTimeout))
+        })
+    }
+}
+
+impl<A> AuthorityClient for TimeoutAuthorityClient<A>
+where
+    A: AuthorityClient,
+{
+    fn handle_transfer_order(
+        &mut self,
+        order: TransferOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        Self::with_timeout(self.timeout, self.inner.handle_transfer_order(order))
+    }
+
+    fn handle_confirmation_order(
+        &mut self,
+        order: ConfirmationOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        Self::with_timeout(self.timeout, self.inner.handle_confirmation_order(order))
+    }
+
+    fn handle_account_info_request(
+        &mut self,
+        request: AccountInfoRequest,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        Self::with_timeout(
+            self.timeout,
+            self.inner.handle_account_info_request(request),
+        )
+    }
+}
+
+/// An authority running in the same process, used to simulate a committee without
+/// networking. Cross-shard updates are not delivered, so the simulated authorities
+/// should consist of a single shard.
+#[derive(Clone)]
+pub struct SimulatedAuthorityClient(Arc<Mutex<AuthorityState>>);
+
+impl SimulatedAuthorityClient {
+    pub fn new(state: AuthorityState) -> Self {
+        Self(Arc::new(Mutex::new(state)))
+    }
+
+    /// Shared access to the state of the simulated authority.
+    pub fn state(&self) -> Arc<Mutex<AuthorityState>> {
+        self.0.clone()
+    }
+}
+
+impl AuthorityClient for SimulatedAuthorityClient {
+    fn handle_transfer_order(
+        &mut self,
+        order: TransferOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        let state = self.0.clone();
+        Box::pin(async move { state.lock().await.handle_transfer_order(order) })
+    }
+
+    fn handle_confirmation_order(
+        &mut self,
+        order: ConfirmationOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        let state = self.0.clone();
+        Box::pin(async move {
+            state
+                .lock()
+                .await
+                .handle_confirmation_order(order)
+                .map(|(info, _)| info)
+        })
+    }
+
+    fn handle_account_info_request(
+        &mut self,
+        request: AccountInfoRequest,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        let state = self.0.clone();
+        Box::pin(async move { state.lock().await.handle_account_info_request(request) })
+    }
+}
+
+/// How the authorities answered the last request sent to the committee.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct QuorumReport {
+    /// Authorities that answered successfully before the outcome was decided.
+    pub responded: Vec<AuthorityName>,
+    /// Authorities that answered with an error other than a timeout.
+    pub errors: Vec<(AuthorityName, FastPayError)>,
+    /// Authorities that did not answer a request in time.
+    pub timed_out: Vec<AuthorityName>,
+}
+
+/// Evidence kept by a client that one of its transfers was certified by a quorum of
+/// authorities, e.g. for reconciliation or disputes.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TransferReceipt {
+    pub certificate: CertifiedTransferOrder,
+    /// Whether the certificate was also sent to a quorum of authorities for confirmation.
+    pub confirmed: bool,
+}
+
+impl TransferReceipt {
+    /// The sender and sequence number of the transfer.
+    pub fn key(&self) -> (FastPayAddress, SequenceNumber) {
+        self.certificate.key()
+    }
+}
+
+/// Local storage of transfer receipts, indexed by sender and sequence number.
+pub trait ReceiptStore: Send + Sync {
+    /// Save a receipt, replacing any previous receipt of the same transfer.
+    fn save(&mut self, receipt: &TransferReceipt) -> Result<(), failure::Error>;
+
+    /// The receipt of the given transfer, if any.
+    fn get(
+        &self,
+        sender: FastPayAddress,
+        sequence_number: SequenceNumber,
+    ) -> Result<Option<TransferReceipt>, failure::Error>;
+}
+
+/// The votes gathered so far for a transfer order. Saving it while votes come in lets a
+/// client resume an interrupted transfer by only asking the authorities that did not vote.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct PartialCertificate {
+    pub order: TransferOrder,
+    pub votes: Vec<(AuthorityName, Signature)>,
+}
+
+impl PartialCertificate {
+    pub fn new(order: TransferOrder) -> Self {
+        Self {
+            order,
+            votes: Vec::new(),
+        }
+    }
+
+    /// The vote of the given authority, if we have it.
+    pub fn vote(&self, authority: AuthorityName) -> Option<SignedTransferOrder> {
+        self.votes
+            .iter()
+            .find(|(name, _)| *name == authority)
+            .map(|(_, signature)| SignedTransferOrder {
+                value: self.order.clone(),
+                authority,
+                signature: *signature,
+            })
+    }
+
+    /// Verify the order and that each vote is a signature of it by a distinct member of
+    /// the committee. Unlike certificates, no quorum is required.
+    pub fn check(&self, committee: &Committee) -> Result<(), FastPayError> {
+        committee.check_domain(&self.order.transfer.domain)?;
+        self.order.check_signature()?;
+        let mut used_authorities = HashSet::new();
+        for (authority, signature) in &self.votes {
+            fp_ensure!(
+                used_authorities.insert(*authority),
+                FastPayError::CertificateAuthorityReuse
+            );
+            fp_ensure!(committee.weight(authority) > 0, FastPayError::UnknownSigner);
+            signature.check(&self.order.transfer, *authority)?;
+        }
+        Ok(())
+    }
+}
+
+/// Local storage of the votes gathered for our pending transfer.
+pub trait VoteStore: Send + Sync {
+    /// Save the votes gathered so far, replacing the previous ones.
+    fn save(&mut self, partial: &PartialCertificate) -> Result<(), failure::Error>;
+
+    /// Forget the votes once the transfer is certified or abandoned.
+    fn clear(&mut self) -> Result<(), failure::Error>;
+}
+
 pub struct ClientState<AuthorityClient> {
     /// Our FastPay address.
     address: FastPayAddress,
@@ -50,6 +260,8 @@ pub struct ClientState<AuthorityClient> {
     next_sequence_number: SequenceNumber,
     /// Pending transfer.
     pending_transfer: Option<TransferOrder>,
+    /// Votes already gathered for the pending transfer.
+    pending_votes: Vec<(AuthorityName, Signature)>,
 
     // The remaining fields are used to minimize networking, and may not always be persisted locally.
     /// Transfer certificates that we have created ("sent").
@@ -61,6 +273,17 @@ pub struct ClientState<AuthorityClient> {
     /// The known spendable balance (including a possible initial funding, excluding unknown sent
     /// or received certificates).
     balance: Balance,
+    /// How many times a failed transfer may be resubmitted after catching up with the
+    /// sequence number known to the authorities (0 to disable).
+    auto_resync_attempts: usize,
+    /// Answers of the authorities to the last request sent to the committee.
+    last_quorum_report: QuorumReport,
+    /// Where to keep the receipts of our transfers, if anywhere.
+    receipt_store: Option<Box<dyn ReceiptStore>>,
+    /// Where to save the votes of the pending transfer as they come in, if anywhere.
+    vote_store: Option<Arc<Mutex<Box<dyn VoteStore>>>>,
+    /// Whether to trust answers to account queries that carry no signature.
+    accept_unsigned_responses: bool,
 }
 
 // Operations are considered successful when they successfully reach a quorum of authorities.
@@ -122,15 +345,51 @@ impl<A> ClientState<A> {
             authority_clients,
             next_sequence_number,
             pending_transfer: None,
+            pending_votes: Vec::new(),
             sent_certificates,
             received_certificates: received_certificates
                 .into_iter()
                 .map(|cert| (cert.key(), cert))
                 .collect(),
             balance,
+            auto_resync_attempts: 0,
+            last_quorum_report: QuorumReport::default(),
+            receipt_store: None,
+            vote_store: None,
+            accept_unsigned_responses: false,
         }
     }
 
+    /// When a transfer fails and a quorum of authorities has seen more transfers from this
+    /// account than we know of (e.g. made with the same key from another device), download
+    /// the missing certificates and resubmit the transfer with the next sequence number,
+    /// at most `attempts` times.
+    pub fn with_auto_resync(mut self, attempts: usize) -> Self {
+        self.auto_resync_attempts = attempts;
+        self
+    }
+
+    /// Save a receipt in `store` for each transfer that we make from now on.
+    pub fn with_receipt_store(mut self, store: Box<dyn ReceiptStore>) -> Self {
+        self.receipt_store = Some(store);
+        self
+    }
+
+    /// Save the votes gathered for each transfer in `store`, so that an interrupted
+    /// transfer can be completed with `resume_transfer`.
+    pub fn with_vote_store(mut self, store: Box<dyn VoteStore>) -> Self {
+        self.vote_store = Some(Arc::new(Mutex::new(store)));
+        self
+    }
+
+    /// Also trust answers to account queries that authorities did not sign, e.g. when
+    /// they run with `--unsigned-read-responses`. Signatures that are present are still
+    /// checked.
+    pub fn with_unsigned_responses(mut self) -> Self {
+        self.accept_unsigned_responses = true;
+        self
+    }
+
     pub fn address(&self) -> FastPayAddress {
         self.address
     }
@@ -151,9 +410,98 @@ impl<A> ClientState<A> {
         &self.sent_certificates
     }
 
+    /// How the authorities answered the last request sent to the committee, whether it
+    /// succeeded or not.
+    pub fn last_quorum_report(&self) -> &QuorumReport {
+        &self.last_quorum_report
+    }
+
     pub fn received_certificates(&self) -> impl Iterator<Item = &CertifiedTransferOrder> {
         self.received_certificates.values()
     }
+
+    /// The saved receipt of our transfer with the given sequence number, if any.
+    pub fn receipt(
+        &self,
+        sequence_number: SequenceNumber,
+    ) -> Result<Option<TransferReceipt>, failure::Error> {
+        match &self.receipt_store {
+            Some(store) => store.get(self.address, sequence_number),
+            None => bail!("No receipt store was configured"),
+        }
+    }
+
+    /// Save the receipt of a transfer. Failures are only logged since the transfer
+    /// itself went through.
+    fn save_receipt(&mut self, certificate: &CertifiedTransferOrder, confirmed: bool) {
+        if let Some(store) = &mut self.receipt_store {
+            let receipt = TransferReceipt {
+                certificate: certificate.clone(),
+                confirmed,
+            };
+            if let Err(error) = store.save(&receipt) {
+                warn!(
+                    "Failed to save the receipt of transfer {:?}: {}",
+                    receipt.key().1,
+                    error
+                );
+            }
+        }
+    }
+
+    /// Forget the votes of the pending transfer. Failures are only logged.
+    async fn clear_votes(&mut self) {
+        self.pending_votes.clear();
+        if let Some(store) = &self.vote_store {
+            if let Err(error) = store.lock().await.clear() {
+                warn!("Failed to clear the saved votes: {}", error);
+            }
+        }
+    }
+}
+
+impl ClientState<SimulatedAuthorityClient> {
+    /// Create a client for a new account that talks to the given in-memory authorities
+    /// of `committee` instead of the network. The account is funded with `balance` by
+    /// each authority.
+    pub fn new_simulated<I>(
+        address: FastPayAddress,
+        secret: KeyPair,
+        committee: Committee,
+        authorities: I,
+        balance: Balance,
+    ) -> Self
+    where
+        I: IntoIterator<Item = AuthorityState>,
+    {
+        let authority_clients = authorities
+            .into_iter()
+            .map(|mut state| {
+                state
+                    .accounts
+                    .entry(address)
+                    .or_insert_with(AccountOffchainState::new)
+                    .balance = balance;
+                state.rebuild_account_filter();
+                (state.name, SimulatedAuthorityClient::new(state))
+            })
+            .collect();
+        Self::new(
+            address,
+            secret,
+            committee,
+            authority_clients,
+            SequenceNumber::new(),
+            Vec::new(),
+            Vec::new(),
+            balance,
+        )
+    }
+
+    /// The simulated authorities, e.g. to fund accounts or inspect their states.
+    pub fn simulated_authorities(&self) -> &HashMap<AuthorityName, SimulatedAuthorityClient> {
+        &self.authority_clients
+    }
 }
 
 #[derive(Clone)]
@@ -190,6 +538,7 @@ where
                 sender: self.sender,
                 request_sequence_number: Some(sequence_number),
                 request_received_transfers_excluding_first_nth: None,
+                observer: false,
             };
             // Sequentially try each authority in random order.
             self.authority_clients.shuffle(&mut rand::thread_rng());
@@ -215,13 +564,38 @@ where
     }
 }
 
+/// Verify that an answer to an account query was signed by the authority that we asked.
+/// Unsigned answers are only accepted if `accept_unsigned` is set.
+fn check_account_info(
+    response: AccountInfoResponse,
+    authority: AuthorityName,
+    accept_unsigned: bool,
+) -> Result<AccountInfoResponse, FastPayError> {
+    if response.signature.is_some() || !accept_unsigned {
+        response.check_signature(authority)?;
+    }
+    Ok(response)
+}
+
 /// Used for communicate_transfers
 #[derive(Clone)]
+#[allow(clippy::large_enum_variant)]
 enum CommunicateAction {
-    SendOrder(TransferOrder),
+    /// Send the order to the authorities that did not vote for it yet.
+    SendOrder(PartialCertificate),
     SynchronizeNextSequenceNumber(SequenceNumber),
 }
 
+/// Outcome of `resync`.
+enum Resync {
+    /// Our local sequence number was not behind.
+    UpToDate,
+    /// We downloaded the certificates that we were missing.
+    CaughtUp,
+    /// Same, and one of them was our pending order.
+    Certified(Box<CertifiedTransferOrder>),
+}
+
 impl<A> ClientState<A>
 where
     A: AuthorityClient + Send + Sync + 'static + Clone,
@@ -243,7 +617,6 @@ where
 
     /// Find the highest sequence number that is known to a quorum of authorities.
     /// NOTE: This is only reliable in the synchronous model, with a sufficient timeout value.
-    #[cfg(test)]
     async fn get_strong_majority_sequence_number(
         &mut self,
         sender: FastPayAddress,
@@ -252,14 +625,19 @@ where
             sender,
             request_sequence_number: None,
             request_received_transfers_excluding_first_nth: None,
+            observer: false,
         };
+        let accept_unsigned = self.accept_unsigned_responses;
         let numbers: futures::stream::FuturesUnordered<_> = self
             .authority_clients
             .iter_mut()
             .map(|(name, client)| {
                 let fut = client.handle_account_info_request(request.clone());
                 async move {
-                    match fut.await {
+                    match fut
+                        .await
+                        .and_then(|info| check_account_info(info, *name, accept_unsigned))
+                    {
                         Ok(info) => Some((*name, info.next_sequence_number)),
                         _ => None,
                     }
@@ -279,14 +657,19 @@ where
             sender: self.address,
             request_sequence_number: None,
             request_received_transfers_excluding_first_nth: None,
+            observer: false,
         };
+        let accept_unsigned = self.accept_unsigned_responses;
         let numbers: futures::stream::FuturesUnordered<_> = self
             .authority_clients
             .iter_mut()
             .map(|(name, client)| {
                 let fut = client.handle_account_info_request(request.clone());
                 async move {
-                    match fut.await {
+                    match fut
+                        .await
+                        .and_then(|info| check_account_info(info, *name, accept_unsigned))
+                    {
                         Ok(info) => Some((*name, info.balance)),
                         _ => None,
                     }
@@ -308,6 +691,8 @@ where
     {
         let committee = &self.committee;
         let authority_clients = &mut self.authority_clients;
+        let report = &mut self.last_quorum_report;
+        *report = QuorumReport::default();
         let mut responses: futures::stream::FuturesUnordered<_> = authority_clients
             .iter_mut()
             .map(|(name, client)| {
@@ -323,6 +708,7 @@ where
             match result {
                 Ok(value) => {
                     values.push(value);
+                    report.responded.push(name);
                     value_score += committee.weight(&name);
                     if value_score >= committee.quorum_threshold() {
                         // Success!
@@ -330,6 +716,11 @@ where
                     }
                 }
                 Err(err) => {
+                    if err == FastPayError::RequestTimeout {
+                        report.timed_out.push(name);
+                    } else {
+                        report.errors.push((name, err.clone()));
+                    }
                     let entry = error_scores.entry(err.clone()).or_insert(0);
                     *entry += committee.weight(&name);
                     if *entry >= committee.validity_threshold() {
@@ -344,7 +735,12 @@ where
             }
         }
 
-        bail!("Failed to communicate with a quorum of authorities (multiple errors)");
+        bail!(
+            "Failed to communicate with a quorum of authorities \
+             ({} errors, {} timeouts)",
+            report.errors.len(),
+            report.timed_out.len()
+        );
     }
 
     /// Broadcast confirmation orders and optionally one more transfer order.
@@ -356,7 +752,7 @@ where
         action: CommunicateAction,
     ) -> Result<Vec<CertifiedTransferOrder>, failure::Error> {
         let target_sequence_number = match &action {
-            CommunicateAction::SendOrder(order) => order.transfer.sequence_number,
+            CommunicateAction::SendOrder(partial) => partial.order.transfer.sequence_number,
             CommunicateAction::SynchronizeNextSequenceNumber(seq) => *seq,
         };
         let requester = CertificateRequester::new(
@@ -375,19 +771,38 @@ where
             }),
         );
         let committee = self.committee.clone();
+        let gathered_votes = match &action {
+            CommunicateAction::SendOrder(partial) => Some(Arc::new(Mutex::new(partial.clone()))),
+            CommunicateAction::SynchronizeNextSequenceNumber(_) => None,
+        };
+        let vote_store = self.vote_store.clone();
+        let accept_unsigned = self.accept_unsigned_responses;
         let votes = self
             .communicate_with_quorum(|name, client| {
                 let mut handle = handle.clone();
                 let action = action.clone();
                 let committee = &committee;
+                let gathered_votes = gathered_votes.clone();
+                let vote_store = vote_store.clone();
                 Box::pin(async move {
+                    // Authorities that already voted have seen all the previous certificates.
+                    if let CommunicateAction::SendOrder(partial) = &action {
+                        if let Some(signed_order) = partial.vote(name) {
+                            return Ok(Some(signed_order));
+                        }
+                    }
                     // Figure out which certificates this authority is missing.
                     let request = AccountInfoRequest {
                         sender,
                         request_sequence_number: None,
                         request_received_transfers_excluding_first_nth: None,
+                        observer: false,
                     };
-                    let response = client.handle_account_info_request(request).await?;
+                    let response = check_account_info(
+                        client.handle_account_info_request(request).await?,
+                        name,
+                        accept_unsigned,
+                    )?;
                     let current_sequence_number = response.next_sequence_number;
                     // Download each missing certificate in reverse order using the downloader.
                     let mut missing_certificates = Vec::new();
@@ -411,8 +826,8 @@ where
                             .await?;
                     }
                     // Send the transfer order (if any) and return a vote.
-                    if let CommunicateAction::SendOrder(order) = action {
-                        let result = client.handle_transfer_order(order).await;
+                    if let CommunicateAction::SendOrder(partial) = action {
+                        let result = client.handle_transfer_order(partial.order).await;
                         match result {
                             Ok(AccountInfoResponse {
                                 pending_confirmation: Some(signed_order),
@@ -423,6 +838,16 @@ where
                                     FastPayError::ErrorWhileProcessingTransferOrder
                                 );
                                 signed_order.check(committee)?;
+                                if let Some(gathered_votes) = gathered_votes {
+                                    let mut gathered_votes = gathered_votes.lock().await;
+                                    gathered_votes.votes.push((name, signed_order.signature));
+                                    if let Some(store) = vote_store {
+                                        if let Err(error) = store.lock().await.save(&gathered_votes)
+                                        {
+                                            warn!("Failed to save a vote: {}", error);
+                                        }
+                                    }
+                                }
                                 return Ok(Some(signed_order));
                             }
                             Err(err) => return Err(err),
@@ -436,9 +861,9 @@ where
         // Terminate downloader task and retrieve the content of the cache.
         handle.stop().await?;
         let mut certificates: Vec<_> = task.await.unwrap().filter_map(Result::ok).collect();
-        if let CommunicateAction::SendOrder(order) = action {
+        if let CommunicateAction::SendOrder(partial) = action {
             let certificate = CertifiedTransferOrder {
-                value: order,
+                value: partial.order,
                 signatures: votes
                     .into_iter()
                     .filter_map(|vote| match vote {
@@ -473,13 +898,11 @@ where
             .map(|cert| cert.value.transfer.sequence_number)
             .collect();
         let mut sent_certificates = self.sent_certificates.clone();
-        let mut number = SequenceNumber::from(0);
-        while number < self.next_sequence_number {
+        for number in SequenceNumber::range(SequenceNumber::new(), self.next_sequence_number) {
             if !known_sequence_numbers.contains(&number) {
                 let certificate = requester.query(number).await?;
                 sent_certificates.push(certificate);
             }
-            number = number.increment().unwrap_or_else(|_| SequenceNumber::max());
         }
         sent_certificates.sort_by_key(|cert| cert.value.transfer.sequence_number);
         Ok(sent_certificates)
@@ -492,27 +915,93 @@ where
         recipient: Address,
         user_data: UserData,
     ) -> Result<CertifiedTransferOrder, failure::Error> {
-        // Trying to overspend may block the account. To prevent this, we compare with
-        // the balance as we know it.
-        let safe_amount = self.get_spendable_amount().await?;
-        ensure!(
-            amount <= safe_amount,
-            "Requested amount ({:?}) is not backed by sufficient funds ({:?})",
-            amount,
-            safe_amount
+        let mut attempts = 0;
+        loop {
+            // Trying to overspend may block the account. To prevent this, we compare with
+            // the balance as we know it.
+            let safe_amount = self.get_spendable_amount().await?;
+            ensure!(
+                amount <= safe_amount,
+                "Requested amount ({:?}) is not backed by sufficient funds ({:?})",
+                amount,
+                safe_amount
+            );
+            let transfer = Transfer {
+                sender: self.address,
+                recipient,
+                amount,
+                sequence_number: self.next_sequence_number,
+                user_data: user_data.clone(),
+                account_state: self.account_state_reference(),
+                domain: self.committee.domain,
+            };
+            let order = TransferOrder::new(transfer, &self.secret);
+            match self
+                .execute_transfer(order, /* with_confirmation */ true)
+                .await
+            {
+                Ok(certificate) => return Ok(certificate),
+                Err(error) => {
+                    if attempts >= self.auto_resync_attempts {
+                        return Err(error);
+                    }
+                    match self.resync().await? {
+                        Resync::UpToDate => return Err(error),
+                        Resync::CaughtUp => attempts += 1,
+                        // Submitting a new order would pay a second time.
+                        Resync::Certified(certificate) => {
+                            self.save_receipt(&certificate, true);
+                            return Ok(*certificate);
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /// Catch up with the sequence number of our account known to a quorum of authorities
+    /// by downloading the certificates that we are missing.
+    async fn resync(&mut self) -> Result<Resync, failure::Error> {
+        let sequence_number = self.get_strong_majority_sequence_number(self.address).await;
+        if sequence_number <= self.next_sequence_number {
+            return Ok(Resync::UpToDate);
+        }
+        let mut requester = CertificateRequester::new(
+            self.committee.clone(),
+            self.authority_clients.values().cloned().collect(),
+            self.address,
         );
-        let transfer = Transfer {
-            sender: self.address,
-            recipient,
-            amount,
-            sequence_number: self.next_sequence_number,
-            user_data,
-        };
-        let order = TransferOrder::new(transfer, &self.secret);
-        let certificate = self
-            .execute_transfer(order, /* with_confirmation */ true)
-            .await?;
-        Ok(certificate)
+        let mut sent_certificates = self.sent_certificates.clone();
+        for number in SequenceNumber::range(self.next_sequence_number, sequence_number) {
+            sent_certificates.push(requester.query(number).await?);
+        }
+        // The pending transfer used a sequence number that is now taken, possibly by
+        // the pending transfer itself.
+        let certified = self.pending_transfer.take().and_then(|order| {
+            sent_certificates
+                .iter()
+                .find(|certificate| certificate.value == order)
+                .cloned()
+        });
+        self.clear_votes().await;
+        self.update_sent_certificates(sent_certificates)?;
+        match certified {
+            Some(certificate) => Ok(Resync::Certified(Box::new(certificate))),
+            None => Ok(Resync::CaughtUp),
+        }
+    }
+
+    /// Reference to our current account state, if the committee requires it in orders.
+    fn account_state_reference(&self) -> Option<AccountStateReference> {
+        self.committee.order_acceptance_window?;
+        let mut history = MerkleAccumulator::new();
+        for certificate in &self.sent_certificates {
+            history.append(leaf_hash(&certificate.value.transfer));
+        }
+        Some(AccountStateReference {
+            sequence_number: SequenceNumber::from(self.sent_certificates.len() as u64),
+            history_root: history.root(),
+        })
     }
 
     /// Update our view of sent certificates. Adjust the local balance and the next sequence number accordingly.
@@ -545,11 +1034,40 @@ where
         // Sanity check
         assert_eq!(
             self.sent_certificates.len(),
-            self.next_sequence_number.into()
+            usize::from(self.next_sequence_number)
         );
         Ok(())
     }
 
+    /// Complete a transfer from the votes saved by a `VoteStore`, only asking the
+    /// authorities that did not vote yet.
+    pub async fn resume_transfer(
+        &mut self,
+        partial: PartialCertificate,
+        with_confirmation: bool,
+    ) -> Result<CertifiedTransferOrder, failure::Error> {
+        let transfer = &partial.order.transfer;
+        ensure!(
+            transfer.sender == self.address,
+            "The saved votes are for a transfer from another account"
+        );
+        ensure!(
+            transfer.sequence_number == self.next_sequence_number,
+            "The saved votes are for sequence number {:?} instead of {:?}",
+            transfer.sequence_number,
+            self.next_sequence_number
+        );
+        partial.check(&self.committee)?;
+        ensure!(
+            self.pending_transfer.is_none()
+                || self.pending_transfer.as_ref() == Some(&partial.order),
+            "Client state has a different pending transfer",
+        );
+        self.pending_votes = partial.votes;
+        self.execute_transfer(partial.order, with_confirmation)
+            .await
+    }
+
     /// Execute (or retry) a transfer order. Update local balance.
     async fn execute_transfer(
         &mut self,
@@ -565,11 +1083,15 @@ where
             "Unexpected sequence number"
         );
         self.pending_transfer = Some(order.clone());
+        let partial = PartialCertificate {
+            order: order.clone(),
+            votes: self.pending_votes.clone(),
+        };
         let new_sent_certificates = self
             .communicate_transfers(
                 self.address,
                 self.sent_certificates.clone(),
-                CommunicateAction::SendOrder(order.clone()),
+                CommunicateAction::SendOrder(partial),
             )
             .await?;
         assert_eq!(new_sent_certificates.last().unwrap().value, order);
@@ -577,7 +1099,10 @@ where
         // `balance`, and `next_sequence_number`. (Note that if we were using persistent
         // storage, we should ensure update atomicity in the eventuality of a crash.)
         self.pending_transfer = None;
+        self.clear_votes().await;
         self.update_sent_certificates(new_sent_certificates)?;
+        let certificate = self.sent_certificates.last().unwrap().clone();
+        self.save_receipt(&certificate, false);
         // Confirm last transfer certificate if needed.
         if with_confirmation {
             self.communicate_transfers(
@@ -586,8 +1111,9 @@ where
                 CommunicateAction::SynchronizeNextSequenceNumber(self.next_sequence_number),
             )
             .await?;
+            self.save_receipt(&certificate, true);
         }
-        Ok(self.sent_certificates.last().unwrap().clone())
+        Ok(certificate)
     }
 }
 
@@ -678,6 +1204,8 @@ where
                 amount,
                 sequence_number: self.next_sequence_number,
                 user_data,
+                account_state: self.account_state_reference(),
+                domain: self.committee.domain,
             };
             let order = TransferOrder::new(transfer, &self.secret);
             let new_certificate = self
@@ -1,13 +1,78 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
-use super::base_types::*;
-use std::collections::BTreeMap;
+use super::{base_types::*, error::FastPayError};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// How authorities handle transfers to FastPay accounts that they do not know.
+#[derive(Eq, PartialEq, Copy, Clone, Hash, Debug, Serialize, Deserialize)]
+pub enum RecipientPolicy {
+    /// Create missing recipient accounts when crediting them.
+    AutoCreate,
+    /// Reject transfers to missing recipient accounts of the shard of the sender. Other
+    /// shards cannot know which accounts exist, so certified transfers to them are always
+    /// credited, creating the recipient account if needed.
+    Reject,
+}
+
+/// Optional protocol behaviors. All authorities of a committee must agree on the
+/// features that are enabled. Behaviors with parameters are enabled by the corresponding
+/// field of the committee (e.g. `transfer_cooldown_ms`), which implies their feature, so
+/// that authorities can still check that they support them.
+#[derive(Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Debug, Serialize, Deserialize)]
+pub enum Feature {
+    /// Recipients may claim confirmed transfers directly from their own shard.
+    RecipientClaims,
+    /// Implied by `RecipientPolicy::Reject`.
+    RejectMissingRecipients,
+    /// Implied by `order_acceptance_window`.
+    OrderAcceptanceWindow,
+    /// Implied by `transfer_cooldown_ms`.
+    TransferCooldown,
+    /// Implied by `strictly_increasing_transfer_times`.
+    StrictlyIncreasingTransferTimes,
+}
+
+/// Identifies a deployment of FastPay. Transfers carry the domain of the committee that
+/// they are meant for, so that the signatures of an order or a certificate made for one
+/// deployment (e.g. a testnet) are not valid in another.
+#[derive(
+    Eq, PartialEq, Ord, PartialOrd, Copy, Clone, Hash, Debug, Default, Serialize, Deserialize,
+)]
+pub struct SigningDomain {
+    pub chain_id: u64,
+    /// When the deployment started, in milliseconds since the Unix epoch.
+    pub genesis_timestamp: u64,
+}
+
+/// Smallest committee that tolerates one Byzantine authority (N = 3f + 1 with f = 1).
+pub const DEFAULT_MIN_COMMITTEE_SIZE: usize = 4;
 
 #[derive(Eq, PartialEq, Clone, Hash, Debug)]
 pub struct Committee {
     pub voting_rights: BTreeMap<AuthorityName, usize>,
     pub total_votes: usize,
+    pub recipient_policy: RecipientPolicy,
+    pub features: BTreeSet<Feature>,
+    /// If set, transfer orders must reference a state of the sender's account that is at
+    /// most this many sequence numbers old.
+    pub order_acceptance_window: Option<u64>,
+    pub domain: SigningDomain,
+    /// If set, the minimal time between two transfers from the same account, in
+    /// milliseconds. Credits are not limited.
+    pub transfer_cooldown_ms: Option<u64>,
+    /// Whether the confirmed transfers of each account must be recorded at strictly
+    /// increasing times, so that auditors can order them by time. If the clock of an
+    /// authority goes back before a confirmation, the time of the previous transfer is
+    /// kept.
+    pub strictly_increasing_transfer_times: bool,
+}
+
+impl Default for RecipientPolicy {
+    fn default() -> Self {
+        RecipientPolicy::AutoCreate
+    }
 }
 
 impl Committee {
@@ -16,9 +81,41 @@ impl Committee {
         Committee {
             voting_rights,
             total_votes,
+            recipient_policy: RecipientPolicy::default(),
+            features: BTreeSet::new(),
+            order_acceptance_window: None,
+            domain: SigningDomain::default(),
+            transfer_cooldown_ms: None,
+            strictly_increasing_transfer_times: false,
         }
     }
 
+    pub fn has_feature(&self, feature: Feature) -> bool {
+        self.features.contains(&feature)
+    }
+
+    /// Check that the committee has at least the given number of authorities.
+    pub fn check_size(&self, minimum: usize) -> Result<(), FastPayError> {
+        let size = self.voting_rights.len();
+        fp_ensure!(
+            size >= minimum,
+            FastPayError::CommitteeTooSmall { size, minimum }
+        );
+        Ok(())
+    }
+
+    /// Check that a transfer was made for this deployment.
+    pub fn check_domain(&self, domain: &SigningDomain) -> Result<(), FastPayError> {
+        fp_ensure!(
+            *domain == self.domain,
+            FastPayError::WrongSigningDomain {
+                expected: self.domain,
+                received: *domain,
+            }
+        );
+        Ok(())
+    }
+
     pub fn weight(&self, author: &AuthorityName) -> usize {
         *self.voting_rights.get(author).unwrap_or(&0)
     }
@@ -1,7 +1,11 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
-use crate::{base_types::*, messages::*};
+use crate::{
+    base_types::*,
+    committee::{Feature, SigningDomain},
+    messages::*,
+};
 use failure::Fail;
 use serde::{Deserialize, Serialize};
 
@@ -48,7 +52,9 @@ pub enum FastPayError {
         display = "Cannot initiate transfer while a transfer order is still pending confirmation: {:?}",
         pending_confirmation
     )]
-    PreviousTransferMustBeConfirmedFirst { pending_confirmation: TransferOrder },
+    PreviousTransferMustBeConfirmedFirst {
+        pending_confirmation: Box<TransferOrder>,
+    },
     #[fail(display = "Transfer order was processed but no signature was produced by authority")]
     ErrorWhileProcessingTransferOrder,
     #[fail(
@@ -96,4 +102,111 @@ pub enum FastPayError {
     UnexpectedMessage,
     #[fail(display = "Network error while querying service: {:?}.", error)]
     ClientIoError { error: String },
+    #[fail(display = "The response of the authority was not signed")]
+    MissingResponseSignature,
+    #[fail(display = "The recipient's account does not exist")]
+    RecipientNotFound,
+    #[fail(
+        display = "The number of items requested must be between 1 and {}",
+        max
+    )]
+    InvalidListLimit { max: usize },
+    #[fail(display = "Signatures and public keys must be canonically encoded")]
+    NonCanonicalEncoding,
+    #[fail(display = "Transfers to Primary accounts are not credited by FastPay authorities")]
+    UnexpectedPrimaryRecipient,
+    #[fail(display = "Feature {:?} is not enabled in this committee", feature)]
+    FeatureDisabled { feature: Feature },
+    #[fail(
+        display = "Stored record has version {} which is not supported",
+        version
+    )]
+    UnsupportedRecordVersion { version: u32 },
+    #[fail(display = "Transfer orders must reference the state of the sender's account")]
+    MissingAccountStateReference,
+    #[fail(
+        display = "The account state referenced by the order is too old (current sequence number: {:?})",
+        current_sequence_number
+    )]
+    StaleAccountStateReference {
+        current_sequence_number: SequenceNumber,
+    },
+    #[fail(display = "The account state referenced by the order does not exist")]
+    InvalidAccountStateReference,
+    #[fail(display = "Statements about a sequence number must agree")]
+    InconsistentSequenceNumberStatements,
+    #[fail(display = "The authority is low on memory and does not accept new accounts")]
+    MemoryPressure,
+    #[fail(display = "Cross-shard updates must be signed by the authority")]
+    MissingCrossShardSignature,
+    #[fail(
+        display = "Shard {} may not send cross-shard updates to this shard",
+        shard
+    )]
+    InvalidCrossShardSource { shard: ShardId },
+    #[fail(display = "Unknown message type {}", tag)]
+    UnknownMessageType { tag: u32 },
+    #[fail(
+        display = "A different transfer was already confirmed for sequence number {:?}",
+        sequence_number
+    )]
+    ConflictingConfirmation { sequence_number: SequenceNumber },
+    #[fail(display = "Division by zero.")]
+    DivisionByZero,
+    #[fail(display = "The authority did not answer within the timeout")]
+    RequestTimeout,
+    #[fail(
+        display = "The committee has {} authorities but at least {} are required",
+        size, minimum
+    )]
+    CommitteeTooSmall { size: usize, minimum: usize },
+    #[fail(
+        display = "The transfer would leave the sender with a negative balance: {:?}",
+        balance
+    )]
+    NegativeBalance { balance: Balance },
+    #[fail(display = "This command is only available on servers running in development mode")]
+    DevModeRequired,
+    #[fail(display = "Failed to reload the initial accounts: {}", error)]
+    AccountsReloadFailed { error: String },
+    #[fail(
+        display = "Multisig ownership requires distinct signers and a threshold between 1 and their number"
+    )]
+    InvalidOwnership,
+    #[fail(display = "Orders must be signed by owners of the account")]
+    UnknownOwner,
+    #[fail(display = "Each owner of the account may only sign an order once")]
+    OwnerSignatureReuse,
+    #[fail(
+        display = "Orders of this account require {} owner signatures",
+        threshold
+    )]
+    InsufficientOwnerSignatures { threshold: usize },
+    #[fail(
+        display = "The transfer was made for {:?} but this committee is {:?}",
+        received, expected
+    )]
+    WrongSigningDomain {
+        expected: SigningDomain,
+        received: SigningDomain,
+    },
+    #[fail(
+        display = "The authority cannot write to its storage: it only serves reads until the storage recovers"
+    )]
+    StorageUnavailable,
+    #[fail(
+        display = "Transfers from this account are too frequent: retry in {} ms",
+        retry_after_ms
+    )]
+    TransferCooldown { retry_after_ms: u64 },
+    #[fail(
+        display = "The transfer amount {:?} exceeds the limit of {:?} set by this authority",
+        requested, limit
+    )]
+    TransferExceedsLimit { limit: Amount, requested: Amount },
+    #[fail(
+        display = "The last transfer of this account was recorded at {} ms: wait until the clock of the authority is past it",
+        last_transfer
+    )]
+    NonMonotonicTimestamps { last_transfer: u64 },
 }
@@ -1,30 +1,17 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
-use fastpay_core::{error, messages, serialize};
-use serde_reflection::{Registry, Result, Samples, Tracer, TracerConfig};
+use fastpay_core::schema::ProtocolSchema;
 use std::{fs::File, io::Write};
 use structopt::{clap::arg_enum, StructOpt};
 
-fn get_registry() -> Result<Registry> {
-    let mut tracer = Tracer::new(TracerConfig::default());
-    let samples = Samples::new();
-    // 1. Record samples for types with custom deserializers.
-    // tracer.trace_value(&mut samples, ...)?;
-
-    // 2. Trace the main entry point(s) + every enum separately.
-    tracer.trace_type::<messages::Address>(&samples)?;
-    tracer.trace_type::<error::FastPayError>(&samples)?;
-    tracer.trace_type::<serialize::SerializedMessage>(&samples)?;
-    tracer.registry()
-}
-
 arg_enum! {
 #[derive(Debug, StructOpt, Clone, Copy)]
 enum Action {
     Print,
     Test,
     Record,
+    Schema,
 }
 }
 
@@ -42,7 +29,8 @@ const FILE_PATH: &str = "fastpay_core/tests/staged/fastpay.yaml";
 
 fn main() {
     let options = Options::from_args();
-    let registry = get_registry().unwrap();
+    let schema = ProtocolSchema::current().unwrap();
+    let registry = &schema.types;
     match options.action {
         Action::Print => {
             let content = serde_yaml::to_string(&registry).unwrap();
@@ -58,5 +46,10 @@ fn main() {
             let content = serde_yaml::to_string(&registry).unwrap() + "\n";
             similar_asserts::assert_str_eq!(&reference, &content);
         }
+        Action::Schema => {
+            // The formats together with the protocol version, for code generators.
+            let content = serde_json::to_string_pretty(&schema).unwrap();
+            println!("{}", content);
+        }
     }
 }
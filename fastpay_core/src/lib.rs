@@ -3,14 +3,27 @@
 
 #![deny(warnings)]
 
+#[cfg(all(feature = "unchecked-signatures", not(debug_assertions)))]
+compile_error!(
+    "the `unchecked-signatures` feature disables signature checks and is for tests only; \
+     it must never be enabled in a release build"
+);
+
 #[macro_use]
 pub mod error;
 
 pub mod authority;
 pub mod base_types;
+pub mod bloom;
 pub mod client;
+pub mod clock;
 pub mod committee;
 pub mod downloader;
 pub mod fastpay_smart_contract;
+pub mod merkle;
 pub mod messages;
+pub mod persistence;
+pub mod redaction;
+pub mod schema;
 pub mod serialize;
+pub mod verification_cache;
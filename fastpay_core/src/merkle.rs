@@ -0,0 +1,171 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::base_types::Signable;
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+#[cfg(test)]
+#[path = "unit_tests/merkle_tests.rs"]
+mod merkle_tests;
+
+pub type HashValue = [u8; 32];
+
+/// Append-only Merkle tree following the construction of RFC 6962.
+#[derive(Eq, PartialEq, Clone, Debug, Default)]
+pub struct MerkleAccumulator {
+    /// Hashes of all the leaves, used to produce inclusion proofs.
+    leaves: Vec<HashValue>,
+    /// Roots of the maximal perfect subtrees, from left to right.
+    peaks: Vec<HashValue>,
+}
+
+/// Proof that a leaf is part of a Merkle tree of a given size.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct InclusionProof {
+    pub leaf_index: u64,
+    pub tree_size: u64,
+    /// Sibling hashes, from the leaf up to the root.
+    pub path: Vec<HashValue>,
+}
+
+/// Hash of a leaf containing the given value.
+pub fn leaf_hash<T>(value: &T) -> HashValue
+where
+    T: Signable<Sha256>,
+{
+    let mut hasher = Sha256::new();
+    hasher.update([0u8]);
+    value.write(&mut hasher);
+    hasher.finalize().into()
+}
+
+fn node_hash(left: &HashValue, right: &HashValue) -> HashValue {
+    let mut hasher = Sha256::new();
+    hasher.update([1u8]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Root of the tree over the given leaves.
+fn subtree_root(leaves: &[HashValue]) -> HashValue {
+    match leaves.len() {
+        0 => Sha256::digest(&[]).into(),
+        1 => leaves[0],
+        n => {
+            let k = split_point(n);
+            node_hash(&subtree_root(&leaves[..k]), &subtree_root(&leaves[k..]))
+        }
+    }
+}
+
+/// Largest power of two strictly smaller than `n` (assuming n > 1).
+fn split_point(n: usize) -> usize {
+    let mut k = 1;
+    while k * 2 < n {
+        k *= 2;
+    }
+    k
+}
+
+fn subtree_path(index: usize, leaves: &[HashValue], path: &mut Vec<HashValue>) {
+    if leaves.len() <= 1 {
+        return;
+    }
+    let k = split_point(leaves.len());
+    if index < k {
+        subtree_path(index, &leaves[..k], path);
+        path.push(subtree_root(&leaves[k..]));
+    } else {
+        subtree_path(index - k, &leaves[k..], path);
+        path.push(subtree_root(&leaves[..k]));
+    }
+}
+
+impl MerkleAccumulator {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.leaves.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.leaves.is_empty()
+    }
+
+    pub fn append(&mut self, leaf: HashValue) {
+        let mut size = self.leaves.len();
+        self.leaves.push(leaf);
+        self.peaks.push(leaf);
+        // Merge the perfect subtrees of equal sizes.
+        while size & 1 == 1 {
+            let right = self.peaks.pop().unwrap();
+            let left = self.peaks.pop().unwrap();
+            self.peaks.push(node_hash(&left, &right));
+            size >>= 1;
+        }
+    }
+
+    pub fn root(&self) -> HashValue {
+        let mut peaks = self.peaks.iter().rev();
+        match peaks.next() {
+            None => subtree_root(&[]),
+            Some(last) => peaks.fold(*last, |root, peak| node_hash(peak, &root)),
+        }
+    }
+
+    /// Root of the tree made of the first `size` leaves, if there are that many.
+    pub fn root_at(&self, size: usize) -> Option<HashValue> {
+        if size > self.leaves.len() {
+            return None;
+        }
+        Some(subtree_root(&self.leaves[..size]))
+    }
+
+    /// Prove that the leaf at the given index is included in the current tree.
+    pub fn prove(&self, leaf_index: usize) -> Option<InclusionProof> {
+        if leaf_index >= self.leaves.len() {
+            return None;
+        }
+        let mut path = Vec::new();
+        subtree_path(leaf_index, &self.leaves, &mut path);
+        Some(InclusionProof {
+            leaf_index: leaf_index as u64,
+            tree_size: self.leaves.len() as u64,
+            path,
+        })
+    }
+}
+
+impl InclusionProof {
+    /// Check the proof for the given leaf hash against the root of a tree.
+    pub fn verify(&self, leaf: &HashValue, root: &HashValue) -> bool {
+        if self.leaf_index >= self.tree_size {
+            return false;
+        }
+        let mut index = self.leaf_index;
+        let mut last = self.tree_size - 1;
+        let mut hash = *leaf;
+        for sibling in &self.path {
+            if last == 0 {
+                return false;
+            }
+            if index & 1 == 1 || index == last {
+                hash = node_hash(sibling, &hash);
+                while index & 1 == 0 && index != 0 {
+                    index >>= 1;
+                    last >>= 1;
+                }
+            } else {
+                hash = node_hash(&hash, sibling);
+            }
+            index >>= 1;
+            last >>= 1;
+        }
+        last == 0 && &hash == root
+    }
+}
@@ -1,13 +1,19 @@
 // Copyright (c) Facebook, Inc. and its affiliates.
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{base_types::*, committee::Committee, error::*};
+use super::{
+    base_types::*,
+    committee::{Committee, SigningDomain},
+    error::*,
+    merkle::{HashValue, InclusionProof},
+};
 
 #[cfg(test)]
 #[path = "unit_tests/messages_tests.rs"]
 mod messages_tests;
 
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 use std::{
     collections::HashSet,
     hash::{Hash, Hasher},
@@ -33,6 +39,15 @@ pub enum Address {
     FastPay(FastPayAddress),
 }
 
+/// State of an account that a transfer order was built against.
+#[derive(Debug, PartialEq, Eq, Hash, Copy, Clone, Serialize, Deserialize)]
+pub struct AccountStateReference {
+    /// Number of transfers confirmed by the sender at that time.
+    pub sequence_number: SequenceNumber,
+    /// Root of the history of these confirmed transfers.
+    pub history_root: HashValue,
+}
+
 #[derive(Debug, PartialEq, Eq, Hash, Clone, Serialize, Deserialize)]
 pub struct Transfer {
     pub sender: FastPayAddress,
@@ -40,12 +55,49 @@ pub struct Transfer {
     pub amount: Amount,
     pub sequence_number: SequenceNumber,
     pub user_data: UserData,
+    /// Required by committees that limit how long a signed order remains acceptable.
+    pub account_state: Option<AccountStateReference>,
+    /// Deployment that the transfer is meant for, covered by all its signatures.
+    pub domain: SigningDomain,
 }
 
 #[derive(Eq, Clone, Debug, Serialize, Deserialize)]
 pub struct TransferOrder {
     pub transfer: Transfer,
+    /// Signature of the sender, or a copy of the first co-signature.
     pub signature: Signature,
+    /// Signatures of the owners of a sender whose ownership was changed, e.g. to a
+    /// multisig. Empty for accounts owned by the key of their address.
+    pub co_signatures: Vec<(FastPayAddress, Signature)>,
+}
+
+/// Keys allowed to authorize the transfers of an account.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub enum Ownership {
+    /// A single key. Accounts are owned by the key of their address until changed.
+    Single(FastPayAddress),
+    /// Any `threshold` distinct keys among `signers`.
+    Multisig {
+        threshold: usize,
+        signers: Vec<FastPayAddress>,
+    },
+}
+
+/// Change of the keys allowed to authorize the transfers of an account.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct OwnershipChange {
+    pub account: FastPayAddress,
+    pub ownership: Ownership,
+    /// Number of changes of ownership applied to the account before this one.
+    pub version: VersionNumber,
+    pub domain: SigningDomain,
+}
+
+/// Change of ownership signed by the current owners of the account.
+#[derive(Debug, PartialEq, Eq, Clone, Serialize, Deserialize)]
+pub struct OwnershipOrder {
+    pub change: OwnershipChange,
+    pub signatures: Vec<(FastPayAddress, Signature)>,
 }
 
 #[derive(Eq, Clone, Debug, Serialize, Deserialize)]
@@ -76,6 +128,23 @@ pub struct AccountInfoRequest {
     pub sender: FastPayAddress,
     pub request_sequence_number: Option<SequenceNumber>,
     pub request_received_transfers_excluding_first_nth: Option<usize>,
+    /// Answer with what the shard knows locally even if it does not own the sender, instead
+    /// of failing with `WrongShard`. Meant for debugging.
+    pub observer: bool,
+}
+
+/// Where an observer query was answered, and which shard owns the account.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct ObserverInfo {
+    pub shard_id: u32,
+    pub owning_shard: u32,
+}
+
+impl ObserverInfo {
+    /// Whether the account data comes from the shard that owns the account.
+    pub fn is_owner(&self) -> bool {
+        self.shard_id == self.owning_shard
+    }
 }
 
 #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
@@ -86,14 +155,136 @@ pub struct AccountInfoResponse {
     pub pending_confirmation: Option<SignedTransferOrder>,
     pub requested_certificate: Option<CertifiedTransferOrder>,
     pub requested_received_transfers: Vec<CertifiedTransferOrder>,
+    /// Root of the Merkle accumulator over the transfers confirmed for this sender, in
+    /// order of sequence numbers.
+    pub history_root: HashValue,
+    /// Proof that the transfer of `requested_certificate` is included in `history_root`.
+    pub requested_certificate_proof: Option<InclusionProof>,
+    /// Time of the last confirmed debit or credit of the account, as recorded by the
+    /// authority (milliseconds since the Unix epoch).
+    pub last_activity: Option<u64>,
+    /// Set in answers to observer queries.
+    pub observer_info: Option<ObserverInfo>,
+    /// Signature of the authority over the rest of the response. Responses to read-only
+    /// queries may be left unsigned by the authority, in which case the data should not
+    /// be trusted beyond what certificates prove.
+    pub signature: Option<Signature>,
+}
+
+/// Request a page of the accounts known to an authority shard.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct ListAccountsRequest {
+    /// Only list accounts whose address comes strictly after this one.
+    pub after: Option<FastPayAddress>,
+    /// Maximal number of accounts to return.
+    pub limit: usize,
 }
 
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct ListAccountsResponse {
+    /// Addresses of the accounts, in increasing order.
+    pub accounts: Vec<FastPayAddress>,
+    /// Cursor to use as `after` to obtain the next page, if there are more accounts.
+    pub next_cursor: Option<FastPayAddress>,
+}
+
+/// Lightweight message used to measure the round-trip time to an authority shard.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Ping {
+    /// Local time of the sender when sending the ping (microseconds since the Unix epoch).
+    pub sent_at: u64,
+}
+
+/// Reply to a `Ping`.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct Pong {
+    /// The time at which the ping was sent, as given by the sender.
+    pub sent_at: u64,
+    /// Local time of the authority when receiving the ping (microseconds since the Unix epoch).
+    pub received_at: u64,
+    /// Whether the shard only serves reads, e.g. after a storage failure.
+    pub read_only: bool,
+}
+
+/// Query for the description of the committee, as configured on an authority.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CommitteeInfoRequest;
+
+/// Public description of the committee (JSON), as served by one of its authorities.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct CommitteeInfoResponse {
+    pub description: String,
+    pub authority: AuthorityName,
+    pub signature: Option<Signature>,
+}
+
+/// Development command resetting a shard to the accounts of its initial accounts file.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct ReloadAccountsRequest;
+
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct ReloadAccountsResponse {
+    pub shard_id: ShardId,
+    /// Number of accounts of the shard after the reload.
+    pub num_accounts: usize,
+}
+
+/// Ask an authority what happened at a given sequence number of an account.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct SequenceNumberRequest {
+    pub sender: FastPayAddress,
+    pub sequence_number: SequenceNumber,
+}
+
+/// What an authority knows about a sequence number of an account. Transfers are
+/// identified by their Merkle leaf hash, as in `AccountInfoResponse::history_root`.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub enum SequenceNumberStatus {
+    /// A certificate for this transfer was confirmed.
+    Confirmed(HashValue),
+    /// The authority voted for this transfer but has not seen its certificate yet.
+    Voted(HashValue),
+    /// The authority neither voted for nor confirmed any transfer.
+    Unused,
+}
+
+/// Statement of an authority about a sequence number, signed by the authority.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct SequenceNumberStatement {
+    pub sender: FastPayAddress,
+    pub sequence_number: SequenceNumber,
+    pub status: SequenceNumberStatus,
+    pub authority: AuthorityName,
+    pub signature: Option<Signature>,
+}
+
+/// Statements of a quorum of authorities agreeing on the status of a sequence number.
+/// Since any certificate needs a quorum of votes, a proof that the sequence number is
+/// `Unused` shows that no transfer was certified there so far.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct SequenceNumberProof {
+    pub statements: Vec<SequenceNumberStatement>,
+}
+
+/// Credit of a confirmed transfer, sent by the shard of the sender to the shard of the
+/// recipient.
 #[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
 pub struct CrossShardUpdate {
+    /// Shard of the recipient, receiving the update.
     pub shard_id: ShardId,
+    /// Shard of the sender, sending the update.
+    pub source_shard_id: ShardId,
     pub transfer_certificate: CertifiedTransferOrder,
 }
 
+/// Cross-shard update signed by the authority, so that the receiving shard can check
+/// that it comes from a sibling shard.
+#[derive(Eq, PartialEq, Clone, Debug, Serialize, Deserialize)]
+pub struct SignedCrossShardUpdate {
+    pub update: CrossShardUpdate,
+    pub signature: Signature,
+}
+
 impl Hash for TransferOrder {
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.transfer.hash(state);
@@ -153,11 +344,173 @@ impl TransferOrder {
         Self {
             transfer,
             signature,
+            co_signatures: Vec::new(),
+        }
+    }
+
+    /// Order signed by the given owners of the sender, e.g. the signers of a multisig.
+    pub fn new_co_signed(transfer: Transfer, secrets: &[&KeyPair]) -> Self {
+        let co_signatures: Vec<_> = secrets
+            .iter()
+            .map(|secret| (secret.public(), Signature::new(&transfer, secret)))
+            .collect();
+        let signature = co_signatures
+            .first()
+            .expect("co-signed orders need at least one signature")
+            .1;
+        Self {
+            transfer,
+            signature,
+            co_signatures,
         }
     }
 
+    /// Keys that authorized this order, with their signatures.
+    pub fn owner_signatures(&self) -> Vec<(FastPayAddress, Signature)> {
+        if self.co_signatures.is_empty() {
+            vec![(self.transfer.sender, self.signature)]
+        } else {
+            self.co_signatures.clone()
+        }
+    }
+
+    /// Check that the signatures are valid. Whether the signers own the sender is up to
+    /// the authorities, which know the ownership of accounts.
     pub fn check_signature(&self) -> Result<(), FastPayError> {
-        self.signature.check(&self.transfer, self.transfer.sender)
+        if self.co_signatures.is_empty() {
+            self.signature.check(&self.transfer, self.transfer.sender)
+        } else {
+            Signature::verify_batch(&self.transfer, &self.co_signatures)
+        }
+    }
+}
+
+impl Ownership {
+    /// Check that the ownership can be satisfied and names each signer once.
+    pub fn check_valid(&self) -> Result<(), FastPayError> {
+        if let Ownership::Multisig { threshold, signers } = self {
+            let distinct: HashSet<_> = signers.iter().collect();
+            fp_ensure!(
+                *threshold > 0 && *threshold <= signers.len() && distinct.len() == signers.len(),
+                FastPayError::InvalidOwnership
+            );
+        }
+        Ok(())
+    }
+
+    /// Check that the given keys, whose signatures were verified, are enough to act on
+    /// behalf of the account.
+    pub fn check_signers<'a, I>(&self, keys: I) -> Result<(), FastPayError>
+    where
+        I: IntoIterator<Item = &'a FastPayAddress>,
+    {
+        let mut used_keys = HashSet::new();
+        for key in keys {
+            let known = match self {
+                Ownership::Single(owner) => owner == key,
+                Ownership::Multisig { signers, .. } => signers.contains(key),
+            };
+            fp_ensure!(known, FastPayError::UnknownOwner);
+            fp_ensure!(used_keys.insert(*key), FastPayError::OwnerSignatureReuse);
+        }
+        let threshold = match self {
+            Ownership::Single(_) => 1,
+            Ownership::Multisig { threshold, .. } => *threshold,
+        };
+        fp_ensure!(
+            used_keys.len() >= threshold,
+            FastPayError::InsufficientOwnerSignatures { threshold }
+        );
+        Ok(())
+    }
+}
+
+impl OwnershipOrder {
+    pub fn new(change: OwnershipChange, secrets: &[&KeyPair]) -> Self {
+        let signatures = secrets
+            .iter()
+            .map(|secret| (secret.public(), Signature::new(&change, secret)))
+            .collect();
+        Self { change, signatures }
+    }
+
+    pub fn check_signatures(&self) -> Result<(), FastPayError> {
+        Signature::verify_batch(&self.change, &self.signatures)
+    }
+}
+
+/// Common part of transfers to the same recipient for the same amount, so that the
+/// signatures of many such orders can be verified without encoding them from scratch.
+/// Ed25519 hashes the signature and the public key before the message, so the hashing
+/// itself cannot be shared: what is reused is the encoding of the common fields, which
+/// makes the signed bytes identical to those of `Signable::write`.
+pub struct TransferTemplate {
+    recipient: Address,
+    amount: Amount,
+    /// Seed of the signed bytes of a `Transfer`.
+    prefix: Vec<u8>,
+    /// BCS encoding of `recipient` and `amount`, which follow the sender.
+    common_fields: Vec<u8>,
+}
+
+impl TransferTemplate {
+    pub fn new(recipient: Address, amount: Amount) -> Self {
+        let mut common_fields =
+            bcs::to_bytes(&recipient).expect("Message serialization should not fail");
+        bcs::serialize_into(&mut common_fields, &amount)
+            .expect("Message serialization should not fail");
+        Self {
+            recipient,
+            amount,
+            prefix: signable_name_prefix::<Transfer>().into_bytes(),
+            common_fields,
+        }
+    }
+
+    pub fn matches(&self, transfer: &Transfer) -> bool {
+        transfer.recipient == self.recipient && transfer.amount == self.amount
+    }
+
+    /// Signed bytes of a transfer matching the template. BCS encodes the fields of a
+    /// struct one after the other.
+    fn write(&self, transfer: &Transfer, message: &mut Vec<u8>) {
+        message.extend_from_slice(&self.prefix);
+        bcs::serialize_into(&mut *message, &transfer.sender)
+            .expect("Message serialization should not fail");
+        message.extend_from_slice(&self.common_fields);
+        bcs::serialize_into(
+            &mut *message,
+            &(
+                &transfer.sequence_number,
+                &transfer.user_data,
+                &transfer.account_state,
+                &transfer.domain,
+            ),
+        )
+        .expect("Message serialization should not fail");
+    }
+
+    /// Check the signatures of independent orders at once. Orders that do not match the
+    /// template are encoded in full. Succeeds if and only if every signature is valid.
+    pub fn check_orders(&self, orders: &[TransferOrder]) -> Result<(), FastPayError> {
+        let messages: Vec<_> = orders
+            .iter()
+            .map(|order| {
+                let mut message = Vec::new();
+                if self.matches(&order.transfer) {
+                    self.write(&order.transfer, &mut message);
+                } else {
+                    order.transfer.write(&mut message);
+                }
+                message
+            })
+            .collect();
+        Signature::verify_messages(orders.iter().zip(&messages).flat_map(|(order, message)| {
+            order
+                .owner_signatures()
+                .into_iter()
+                .map(move |(key, signature)| (&message[..], key, signature))
+        }))
     }
 }
 
@@ -174,6 +527,7 @@ impl SignedTransferOrder {
 
     /// Verify the signature and return the non-zero voting right of the authority.
     pub fn check(&self, committee: &Committee) -> Result<usize, FastPayError> {
+        committee.check_domain(&self.value.transfer.domain)?;
         self.value.check_signature()?;
         let weight = committee.weight(&self.authority);
         fp_ensure!(weight > 0, FastPayError::UnknownSigner);
@@ -247,6 +601,7 @@ impl CertifiedTransferOrder {
 
     /// Verify the certificate.
     pub fn check(&self, committee: &Committee) -> Result<(), FastPayError> {
+        committee.check_domain(&self.value.transfer.domain)?;
         // Check the quorum.
         let mut weight = 0;
         let mut used_authorities = HashSet::new();
@@ -267,14 +622,145 @@ impl CertifiedTransferOrder {
             FastPayError::CertificateRequiresQuorum
         );
         // All what is left is checking signatures!
-        let inner_sig = (self.value.transfer.sender, self.value.signature);
+        let owner_signatures = self.value.owner_signatures();
         Signature::verify_batch(
             &self.value.transfer,
-            std::iter::once(&inner_sig).chain(&self.signatures),
+            owner_signatures.iter().chain(&self.signatures),
         )
     }
 }
 
+impl AccountInfoResponse {
+    /// Sign the content of the response with the authority key.
+    pub fn sign(&mut self, secret: &KeyPair) {
+        self.signature = None;
+        self.signature = Some(Signature::new(self, secret));
+    }
+
+    /// Verify that the response was signed by the given authority.
+    pub fn check_signature(&self, authority: AuthorityName) -> Result<(), FastPayError> {
+        match self.signature {
+            Some(signature) => {
+                let mut unsigned = self.clone();
+                unsigned.signature = None;
+                signature.check(&unsigned, authority)
+            }
+            None => Err(FastPayError::MissingResponseSignature),
+        }
+    }
+}
+
+/// Digest of a committee description, as served in `CommitteeInfoResponse`s.
+pub fn committee_fingerprint(description: &str) -> HashValue {
+    Sha256::digest(description.as_bytes()).into()
+}
+
+impl CommitteeInfoResponse {
+    pub fn new(description: String, authority: AuthorityName, secret: &KeyPair) -> Self {
+        let mut response = Self {
+            description,
+            authority,
+            signature: None,
+        };
+        response.signature = Some(Signature::new(&response, secret));
+        response
+    }
+
+    /// Verify that the response was signed by the authority that it names.
+    pub fn check_signature(&self) -> Result<(), FastPayError> {
+        match self.signature {
+            Some(signature) => {
+                let mut unsigned = self.clone();
+                unsigned.signature = None;
+                signature.check(&unsigned, self.authority)
+            }
+            None => Err(FastPayError::MissingResponseSignature),
+        }
+    }
+
+    /// Digest of the description, to compare the answers of several authorities.
+    pub fn fingerprint(&self) -> HashValue {
+        committee_fingerprint(&self.description)
+    }
+}
+
+impl SequenceNumberStatement {
+    pub fn new(
+        sender: FastPayAddress,
+        sequence_number: SequenceNumber,
+        status: SequenceNumberStatus,
+        authority: AuthorityName,
+        secret: &KeyPair,
+    ) -> Self {
+        let mut statement = Self {
+            sender,
+            sequence_number,
+            status,
+            authority,
+            signature: None,
+        };
+        statement.signature = Some(Signature::new(&statement, secret));
+        statement
+    }
+
+    /// Verify that the statement was signed by the authority that it names.
+    pub fn check_signature(&self) -> Result<(), FastPayError> {
+        match self.signature {
+            Some(signature) => {
+                let mut unsigned = self.clone();
+                unsigned.signature = None;
+                signature.check(&unsigned, self.authority)
+            }
+            None => Err(FastPayError::MissingResponseSignature),
+        }
+    }
+}
+
+impl SequenceNumberProof {
+    /// Verify the proof and return the status attested by the quorum.
+    pub fn check(&self, committee: &Committee) -> Result<&SequenceNumberStatus, FastPayError> {
+        let first = self
+            .statements
+            .first()
+            .ok_or(FastPayError::CertificateRequiresQuorum)?;
+        let mut used_authorities = HashSet::new();
+        let mut weight = 0;
+        for statement in &self.statements {
+            fp_ensure!(
+                statement.sender == first.sender
+                    && statement.sequence_number == first.sequence_number
+                    && statement.status == first.status,
+                FastPayError::InconsistentSequenceNumberStatements
+            );
+            fp_ensure!(
+                used_authorities.insert(statement.authority),
+                FastPayError::CertificateAuthorityReuse
+            );
+            let voting_rights = committee.weight(&statement.authority);
+            fp_ensure!(voting_rights > 0, FastPayError::UnknownSigner);
+            statement.check_signature()?;
+            weight += voting_rights;
+        }
+        fp_ensure!(
+            weight >= committee.quorum_threshold(),
+            FastPayError::CertificateRequiresQuorum
+        );
+        Ok(&first.status)
+    }
+}
+
+impl SignedCrossShardUpdate {
+    pub fn new(update: CrossShardUpdate, secret: &KeyPair) -> Self {
+        let signature = Signature::new(&update, secret);
+        Self { update, signature }
+    }
+
+    /// Verify that the update was signed by the given authority.
+    pub fn check(&self, authority: AuthorityName) -> Result<(), FastPayError> {
+        self.signature.check(&self.update, authority)
+    }
+}
+
 impl RedeemTransaction {
     pub fn new(transfer_certificate: CertifiedTransferOrder) -> Self {
         Self {
@@ -292,3 +778,9 @@ impl ConfirmationOrder {
 }
 
 impl BcsSignable for Transfer {}
+impl BcsSignable for OwnershipChange {}
+impl BcsSignable for AccountInfoResponse {}
+impl BcsSignable for CommitteeInfoResponse {}
+impl BcsSignable for SequenceNumberStatement {}
+impl BcsSignable for CertifiedTransferOrder {}
+impl BcsSignable for CrossShardUpdate {}
@@ -0,0 +1,387 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{
+    authority::{AccountOffchainState, AuthorityState},
+    base_types::*,
+    error::FastPayError,
+    merkle::*,
+    messages::*,
+};
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, BTreeSet},
+    fs::{self, File},
+    io::Write,
+    path::Path,
+};
+
+#[cfg(test)]
+#[path = "unit_tests/persistence_tests.rs"]
+mod persistence_tests;
+
+/// Version of the account records written by this binary.
+pub const ACCOUNT_RECORD_VERSION: u32 = 4;
+
+/// Version of the shard snapshots written by this binary.
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// Persisted fields of an account, version 1. Indexes such as the Merkle accumulator of
+/// confirmed transfers are rebuilt when loading the account.
+#[derive(Serialize, Deserialize)]
+struct AccountRecordV1<'a> {
+    balance: Balance,
+    next_sequence_number: SequenceNumber,
+    pending_confirmation: Cow<'a, Option<SignedTransferOrder>>,
+    confirmed_log: Cow<'a, [CertifiedTransferOrder]>,
+    synchronization_log: Cow<'a, [PrimarySynchronizationOrder]>,
+    received_log: Cow<'a, [CertifiedTransferOrder]>,
+}
+
+/// Persisted fields of an account, version 2: version 1 followed by the time of the last
+/// activity.
+#[derive(Serialize, Deserialize)]
+struct AccountRecordV2<'a> {
+    #[serde(borrow)]
+    base: AccountRecordV1<'a>,
+    last_activity: Option<u64>,
+}
+
+/// Persisted fields of an account, version 3: version 2 followed by the ownership.
+#[derive(Serialize, Deserialize)]
+struct AccountRecordV3<'a> {
+    #[serde(borrow)]
+    base: AccountRecordV2<'a>,
+    ownership: Cow<'a, Option<Ownership>>,
+    ownership_version: VersionNumber,
+}
+
+/// Persisted fields of an account, version 4: version 3 followed by the time of the last
+/// transfer.
+#[derive(Serialize, Deserialize)]
+struct AccountRecordV4<'a> {
+    #[serde(borrow)]
+    base: AccountRecordV3<'a>,
+    last_transfer: Option<u64>,
+}
+
+/// Encode an account as a record tagged with the current version.
+pub fn serialize_account(account: &AccountOffchainState) -> Vec<u8> {
+    let record = AccountRecordV4 {
+        base: AccountRecordV3 {
+            base: AccountRecordV2 {
+                base: AccountRecordV1 {
+                    balance: account.balance,
+                    next_sequence_number: account.next_sequence_number,
+                    pending_confirmation: Cow::Borrowed(&account.pending_confirmation),
+                    confirmed_log: Cow::Borrowed(&account.confirmed_log),
+                    synchronization_log: Cow::Borrowed(&account.synchronization_log),
+                    received_log: Cow::Borrowed(&account.received_log),
+                },
+                last_activity: account.last_activity,
+            },
+            ownership: Cow::Borrowed(&account.ownership),
+            ownership_version: account.ownership_version,
+        },
+        last_transfer: account.last_transfer,
+    };
+    bincode::serialize(&(ACCOUNT_RECORD_VERSION, record))
+        .expect("Serializing to a resizable buffer should not fail.")
+}
+
+/// Decode an account record written by this or an earlier version of the software.
+pub fn deserialize_account(bytes: &[u8]) -> Result<AccountOffchainState, FastPayError> {
+    let version: u32 = bincode::deserialize(bytes).map_err(|_| FastPayError::InvalidDecoding)?;
+    match version {
+        1 => {
+            let (_, record): (u32, AccountRecordV1) =
+                bincode::deserialize(bytes).map_err(|_| FastPayError::InvalidDecoding)?;
+            Ok(migrate_v1(record))
+        }
+        2 => {
+            let (_, record): (u32, AccountRecordV2) =
+                bincode::deserialize(bytes).map_err(|_| FastPayError::InvalidDecoding)?;
+            let mut account = migrate_v1(record.base);
+            account.last_activity = record.last_activity;
+            Ok(account)
+        }
+        3 => {
+            let (_, record): (u32, AccountRecordV3) =
+                bincode::deserialize(bytes).map_err(|_| FastPayError::InvalidDecoding)?;
+            let mut account = migrate_v1(record.base.base);
+            account.last_activity = record.base.last_activity;
+            account.ownership = record.ownership.into_owned();
+            account.ownership_version = record.ownership_version;
+            Ok(account)
+        }
+        4 => {
+            let (_, record): (u32, AccountRecordV4) =
+                bincode::deserialize(bytes).map_err(|_| FastPayError::InvalidDecoding)?;
+            let mut account = migrate_v1(record.base.base.base);
+            account.last_activity = record.base.base.last_activity;
+            account.ownership = record.base.ownership.into_owned();
+            account.ownership_version = record.base.ownership_version;
+            account.last_transfer = record.last_transfer;
+            Ok(account)
+        }
+        version => Err(FastPayError::UnsupportedRecordVersion { version }),
+    }
+}
+
+fn migrate_v1(record: AccountRecordV1) -> AccountOffchainState {
+    let confirmed_log = record.confirmed_log.into_owned();
+    let received_log = record.received_log.into_owned();
+    let mut confirmed_history = MerkleAccumulator::new();
+    for certificate in &confirmed_log {
+        confirmed_history.append(leaf_hash(&certificate.value.transfer));
+    }
+    let received_transfers: BTreeSet<_> = received_log
+        .iter()
+        .map(|certificate| {
+            let transfer = &certificate.value.transfer;
+            (transfer.sender, transfer.sequence_number)
+        })
+        .collect();
+    AccountOffchainState {
+        balance: record.balance,
+        next_sequence_number: record.next_sequence_number,
+        pending_confirmation: record.pending_confirmation.into_owned(),
+        confirmed_log,
+        confirmed_history,
+        synchronization_log: record.synchronization_log.into_owned(),
+        received_log,
+        received_transfers,
+        last_activity: None,
+        last_transfer: None,
+        ownership: None,
+        ownership_version: VersionNumber::new(),
+    }
+}
+
+/// Identifiers of the fields of an account in its canonical encoding. Identifiers are
+/// never reused, and fields added later receive new identifiers.
+pub mod canonical_field {
+    pub const BALANCE: u32 = 1;
+    pub const NEXT_SEQUENCE_NUMBER: u32 = 2;
+    pub const PENDING_CONFIRMATION: u32 = 3;
+    pub const CONFIRMED_LOG: u32 = 4;
+    pub const SYNCHRONIZATION_LOG: u32 = 5;
+    pub const RECEIVED_LOG: u32 = 6;
+    // 7 and 10 were the times of the last activity and of the last transfer, which are
+    // read from the clock of each authority and are no longer encoded.
+    pub const OWNERSHIP: u32 = 8;
+    pub const OWNERSHIP_VERSION: u32 = 9;
+}
+
+/// Append the BCS encoding of a field, unless it has its default value, which is what
+/// versions that do not know the field assume.
+fn push_canonical_field<T>(fields: &mut Vec<(u32, Vec<u8>)>, id: u32, value: &T)
+where
+    T: Serialize + Default + PartialEq,
+{
+    if *value != T::default() {
+        let bytes = bcs::to_bytes(value).expect("Message serialization should not fail");
+        fields.push((id, bytes));
+    }
+}
+
+/// Encoding of an account that does not depend on the declaration order of its fields
+/// nor on the version of the software, as long as the logical state is the same: fields
+/// are sorted by identifier, fields with a default value are omitted, and indexes that
+/// can be rebuilt from the logs are left out. So are the times read from the local clock,
+/// so that authorities holding the same accounts compute the same digests.
+pub fn canonical_account_bytes(account: &AccountOffchainState) -> Vec<u8> {
+    let mut fields = Vec::new();
+    push_canonical_field(&mut fields, canonical_field::BALANCE, &account.balance);
+    push_canonical_field(
+        &mut fields,
+        canonical_field::NEXT_SEQUENCE_NUMBER,
+        &account.next_sequence_number,
+    );
+    push_canonical_field(
+        &mut fields,
+        canonical_field::PENDING_CONFIRMATION,
+        &account.pending_confirmation,
+    );
+    push_canonical_field(
+        &mut fields,
+        canonical_field::CONFIRMED_LOG,
+        &account.confirmed_log,
+    );
+    push_canonical_field(
+        &mut fields,
+        canonical_field::SYNCHRONIZATION_LOG,
+        &account.synchronization_log,
+    );
+    push_canonical_field(
+        &mut fields,
+        canonical_field::RECEIVED_LOG,
+        &account.received_log,
+    );
+    push_canonical_field(&mut fields, canonical_field::OWNERSHIP, &account.ownership);
+    push_canonical_field(
+        &mut fields,
+        canonical_field::OWNERSHIP_VERSION,
+        &account.ownership_version,
+    );
+    fields.sort_by_key(|(id, _)| *id);
+    bcs::to_bytes(&fields).expect("Message serialization should not fail")
+}
+
+/// Digest of the canonical encoding of an account.
+pub fn account_digest(account: &AccountOffchainState) -> HashValue {
+    Sha256::digest(&canonical_account_bytes(account)).into()
+}
+
+/// Digest of the accounts of a shard, in increasing order of addresses.
+pub fn accounts_digest(accounts: &BTreeMap<FastPayAddress, AccountOffchainState>) -> HashValue {
+    let mut hasher = Sha256::new();
+    for (address, account) in accounts {
+        hasher.update(address.0);
+        hasher.update(account_digest(account));
+    }
+    hasher.finalize().into()
+}
+
+/// Accounts of a shard, each encoded as an account record.
+#[derive(Serialize, Deserialize)]
+struct SnapshotV1 {
+    shard_id: ShardId,
+    number_of_shards: u32,
+    last_transaction_index: VersionNumber,
+    accounts: Vec<(FastPayAddress, Vec<u8>)>,
+}
+
+/// Encode the accounts of a shard, so that the shard can be restored without replaying
+/// its history.
+pub fn serialize_snapshot(state: &AuthorityState) -> Vec<u8> {
+    let snapshot = SnapshotV1 {
+        shard_id: state.shard_id,
+        number_of_shards: state.number_of_shards,
+        last_transaction_index: state.last_transaction_index,
+        accounts: state
+            .accounts
+            .iter()
+            .map(|(address, account)| (*address, serialize_account(account)))
+            .collect(),
+    };
+    bincode::serialize(&(SNAPSHOT_VERSION, snapshot))
+        .expect("Serializing to a resizable buffer should not fail.")
+}
+
+fn deserialize_snapshot(bytes: &[u8]) -> Result<SnapshotV1, FastPayError> {
+    let version: u32 = bincode::deserialize(bytes).map_err(|_| FastPayError::InvalidDecoding)?;
+    fp_ensure!(
+        version == SNAPSHOT_VERSION,
+        FastPayError::UnsupportedRecordVersion { version }
+    );
+    let (_, snapshot): (u32, SnapshotV1) =
+        bincode::deserialize(bytes).map_err(|_| FastPayError::InvalidDecoding)?;
+    Ok(snapshot)
+}
+
+fn snapshot_accounts(
+    snapshot: SnapshotV1,
+) -> Result<BTreeMap<FastPayAddress, AccountOffchainState>, FastPayError> {
+    let mut accounts = BTreeMap::new();
+    for (address, record) in snapshot.accounts {
+        accounts.insert(address, deserialize_account(&record)?);
+    }
+    Ok(accounts)
+}
+
+/// Replace the accounts of a shard with those of a snapshot of the same shard.
+pub fn restore_snapshot(state: &mut AuthorityState, bytes: &[u8]) -> Result<(), FastPayError> {
+    let snapshot = deserialize_snapshot(bytes)?;
+    fp_ensure!(
+        snapshot.shard_id == state.shard_id && snapshot.number_of_shards == state.number_of_shards,
+        FastPayError::WrongShard
+    );
+    let last_transaction_index = snapshot.last_transaction_index;
+    state.accounts = snapshot_accounts(snapshot)?;
+    state.last_transaction_index = last_transaction_index;
+    state.rebuild_account_filter();
+    Ok(())
+}
+
+impl AuthorityState {
+    /// Write a snapshot of the shard to the given file. The file is replaced atomically, so
+    /// a crash leaves either the previous snapshot or the new one.
+    pub fn persist(&self, path: &Path) -> Result<(), failure::Error> {
+        let temp_path = path.with_extension("tmp");
+        let mut file = File::create(&temp_path)?;
+        file.write_all(&serialize_snapshot(self))?;
+        file.sync_all()?;
+        fs::rename(&temp_path, path)?;
+        Ok(())
+    }
+
+    /// Replace the accounts of the shard with those of a snapshot written by `persist`.
+    pub fn load(&mut self, path: &Path) -> Result<(), failure::Error> {
+        let bytes = fs::read(path)?;
+        restore_snapshot(self, &bytes)?;
+        Ok(())
+    }
+}
+
+/// How an account differs between two snapshots.
+#[derive(Eq, PartialEq, Clone, Debug)]
+pub enum AccountDiff {
+    Added {
+        balance: Balance,
+        next_sequence_number: SequenceNumber,
+    },
+    Removed {
+        balance: Balance,
+        next_sequence_number: SequenceNumber,
+    },
+    /// Any field of the account differs, possibly only its history.
+    Changed {
+        old_balance: Balance,
+        new_balance: Balance,
+        old_sequence_number: SequenceNumber,
+        new_sequence_number: SequenceNumber,
+    },
+}
+
+/// Accounts that differ between two snapshots, in order of their addresses.
+pub fn diff_snapshots(
+    old: &[u8],
+    new: &[u8],
+) -> Result<Vec<(FastPayAddress, AccountDiff)>, FastPayError> {
+    let old = snapshot_accounts(deserialize_snapshot(old)?)?;
+    let mut new = snapshot_accounts(deserialize_snapshot(new)?)?;
+    let mut diffs = Vec::new();
+    for (address, old_account) in old {
+        let diff = match new.remove(&address) {
+            None => AccountDiff::Removed {
+                balance: old_account.balance,
+                next_sequence_number: old_account.next_sequence_number,
+            },
+            Some(new_account) if account_digest(&new_account) != account_digest(&old_account) => {
+                AccountDiff::Changed {
+                    old_balance: old_account.balance,
+                    new_balance: new_account.balance,
+                    old_sequence_number: old_account.next_sequence_number,
+                    new_sequence_number: new_account.next_sequence_number,
+                }
+            }
+            Some(_) => continue,
+        };
+        diffs.push((address, diff));
+    }
+    for (address, account) in new {
+        diffs.push((
+            address,
+            AccountDiff::Added {
+                balance: account.balance,
+                next_sequence_number: account.next_sequence_number,
+            },
+        ));
+    }
+    diffs.sort_by_key(|(address, _)| *address);
+    Ok(diffs)
+}
@@ -0,0 +1,77 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{base_types::*, error::FastPayError, messages::Address};
+
+#[cfg(test)]
+#[path = "unit_tests/redaction_tests.rs"]
+mod redaction_tests;
+
+/// Number of base64 characters of an address kept in redacted logs, enough to tell
+/// accounts apart while debugging.
+pub const REDACTED_ADDRESS_LENGTH: usize = 8;
+
+/// How much of the addresses and user data of requests appear in logs. Logs may be
+/// shipped to third parties, so full details must be opted in.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum LogDetail {
+    /// Addresses are shortened to a prefix and user data is left out.
+    Redacted,
+    /// Addresses and user data are logged in full.
+    Full,
+}
+
+impl Default for LogDetail {
+    fn default() -> Self {
+        LogDetail::Redacted
+    }
+}
+
+impl LogDetail {
+    pub fn address(self, address: &FastPayAddress) -> String {
+        let encoded = encode_address(address);
+        match self {
+            LogDetail::Full => encoded,
+            LogDetail::Redacted => format!("{}..", &encoded[..REDACTED_ADDRESS_LENGTH]),
+        }
+    }
+
+    pub fn recipient(self, recipient: &Address) -> String {
+        match recipient {
+            Address::FastPay(address) => format!("FastPay({})", self.address(address)),
+            Address::Primary(address) => format!("Primary({})", self.address(address)),
+        }
+    }
+
+    pub fn user_data(self, user_data: &UserData) -> String {
+        match (self, &user_data.0) {
+            (_, None) => "none".to_string(),
+            (LogDetail::Full, Some(data)) => hex::encode(data),
+            (LogDetail::Redacted, Some(_)) => "<redacted>".to_string(),
+        }
+    }
+
+    /// Description of an error, where the orders that it may contain are redacted.
+    pub fn error(self, error: &FastPayError) -> String {
+        match (self, error) {
+            (
+                LogDetail::Redacted,
+                FastPayError::PreviousTransferMustBeConfirmedFirst {
+                    pending_confirmation,
+                },
+            ) => {
+                let transfer = &pending_confirmation.transfer;
+                format!(
+                    "Cannot initiate transfer while a transfer order is still pending confirmation: \
+                     {} from {} to {} (sequence number {}, user data {})",
+                    u64::from(transfer.amount),
+                    self.address(&transfer.sender),
+                    self.recipient(&transfer.recipient),
+                    u64::from(transfer.sequence_number),
+                    self.user_data(&transfer.user_data)
+                )
+            }
+            _ => error.to_string(),
+        }
+    }
+}
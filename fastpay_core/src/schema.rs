@@ -0,0 +1,188 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{committee, error, messages, serialize};
+use failure::{bail, ensure, format_err};
+use serde::{Deserialize, Serialize};
+use serde_reflection::{
+    ContainerFormat, Format, Named, Registry, Samples, Tracer, TracerConfig, VariantFormat,
+};
+use std::convert::TryInto;
+
+#[cfg(test)]
+#[path = "unit_tests/schema_tests.rs"]
+mod schema_tests;
+
+/// Version of the wire format. Bump it whenever the format of a message changes, i.e.
+/// whenever `tests/staged/fastpay.yaml` needs to be recorded again.
+pub const PROTOCOL_VERSION: u32 = 4;
+
+/// Machine-readable description of the messages exchanged with authorities, for clients
+/// written in other languages. Messages are `SerializedMessage` values encoded with
+/// bincode: little-endian integers, `u64` lengths, `u32` enum tags, and one byte for
+/// options and booleans.
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct ProtocolSchema {
+    pub protocol_version: u32,
+    pub encoding: String,
+    /// Name of the type of every message.
+    pub entry_point: String,
+    /// Format of each type, by name.
+    pub types: Registry,
+}
+
+impl ProtocolSchema {
+    /// The schema of the current protocol, traced from the Rust types.
+    pub fn current() -> Result<Self, failure::Error> {
+        let types = trace_registry().map_err(|e| format_err!("{}", e))?;
+        Ok(Self::new(types))
+    }
+
+    /// A schema of the current version with the given types.
+    pub fn new(types: Registry) -> Self {
+        Self {
+            protocol_version: PROTOCOL_VERSION,
+            encoding: "bincode".to_string(),
+            entry_point: "SerializedMessage".to_string(),
+            types,
+        }
+    }
+
+    /// Check that `bytes` is exactly one value of the given type, using only the schema.
+    pub fn validate(&self, type_name: &str, bytes: &[u8]) -> Result<(), failure::Error> {
+        let mut reader = SchemaReader {
+            types: &self.types,
+            input: bytes,
+        };
+        reader.read(&Format::TypeName(type_name.to_string()))?;
+        ensure!(
+            reader.input.is_empty(),
+            "{} unexpected bytes after a value of type {}",
+            reader.input.len(),
+            type_name
+        );
+        Ok(())
+    }
+}
+
+// Formats hold `Rc`s while tracing, so `serde_reflection::Error` cannot become a
+// `failure::Error` directly.
+fn trace_registry() -> serde_reflection::Result<Registry> {
+    let mut tracer = Tracer::new(TracerConfig::default());
+    let samples = Samples::new();
+    // Trace the main entry point + every enum separately.
+    tracer.trace_type::<messages::Address>(&samples)?;
+    tracer.trace_type::<messages::SequenceNumberStatus>(&samples)?;
+    tracer.trace_type::<messages::Ownership>(&samples)?;
+    tracer.trace_type::<committee::Feature>(&samples)?;
+    tracer.trace_type::<error::FastPayError>(&samples)?;
+    tracer.trace_type::<serialize::SerializedMessage>(&samples)?;
+    tracer.registry()
+}
+
+/// Decodes bincode values by following their format in a registry.
+struct SchemaReader<'a> {
+    types: &'a Registry,
+    input: &'a [u8],
+}
+
+impl<'a> SchemaReader<'a> {
+    fn take(&mut self, size: usize) -> Result<&'a [u8], failure::Error> {
+        ensure!(self.input.len() >= size, "unexpected end of input");
+        let (value, rest) = self.input.split_at(size);
+        self.input = rest;
+        Ok(value)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, failure::Error> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into()?))
+    }
+
+    fn read_len(&mut self) -> Result<usize, failure::Error> {
+        let len = u64::from_le_bytes(self.take(8)?.try_into()?);
+        Ok(len.try_into()?)
+    }
+
+    fn read_all(&mut self, formats: &[Format]) -> Result<(), failure::Error> {
+        formats.iter().try_for_each(|format| self.read(format))
+    }
+
+    fn read_fields(&mut self, fields: &[Named<Format>]) -> Result<(), failure::Error> {
+        fields.iter().try_for_each(|field| self.read(&field.value))
+    }
+
+    fn read(&mut self, format: &Format) -> Result<(), failure::Error> {
+        match format {
+            Format::Variable(_) => bail!("incomplete format"),
+            Format::TypeName(name) => match self.types.get(name) {
+                None => bail!("unknown type {}", name),
+                Some(ContainerFormat::UnitStruct) => Ok(()),
+                Some(ContainerFormat::NewTypeStruct(format)) => self.read(format),
+                Some(ContainerFormat::TupleStruct(formats)) => self.read_all(formats),
+                Some(ContainerFormat::Struct(fields)) => self.read_fields(fields),
+                Some(ContainerFormat::Enum(variants)) => {
+                    let index = self.read_u32()?;
+                    let variant = match variants.get(&index) {
+                        Some(variant) => variant,
+                        None => bail!("unknown variant {} of {}", index, name),
+                    };
+                    match &variant.value {
+                        VariantFormat::Variable(_) => {
+                            bail!("incomplete format")
+                        }
+                        VariantFormat::Unit => Ok(()),
+                        VariantFormat::NewType(format) => self.read(format),
+                        VariantFormat::Tuple(formats) => self.read_all(formats),
+                        VariantFormat::Struct(fields) => self.read_fields(fields),
+                    }
+                }
+            },
+            Format::Unit => Ok(()),
+            Format::Bool => match self.take(1)?[0] {
+                0 | 1 => Ok(()),
+                value => bail!("invalid boolean {}", value),
+            },
+            Format::I8 | Format::U8 => self.take(1).map(|_| ()),
+            Format::I16 | Format::U16 => self.take(2).map(|_| ()),
+            Format::I32 | Format::U32 | Format::F32 => self.take(4).map(|_| ()),
+            Format::I64 | Format::U64 | Format::F64 => self.take(8).map(|_| ()),
+            Format::I128 | Format::U128 => self.take(16).map(|_| ()),
+            Format::Char => {
+                let value = self.read_u32()?;
+                ensure!(
+                    std::char::from_u32(value).is_some(),
+                    "invalid character {}",
+                    value
+                );
+                Ok(())
+            }
+            Format::Str => {
+                let len = self.read_len()?;
+                std::str::from_utf8(self.take(len)?)?;
+                Ok(())
+            }
+            Format::Bytes => {
+                let len = self.read_len()?;
+                self.take(len).map(|_| ())
+            }
+            Format::Option(format) => match self.take(1)?[0] {
+                0 => Ok(()),
+                1 => self.read(format),
+                value => bail!("invalid option tag {}", value),
+            },
+            Format::Seq(format) => {
+                let len = self.read_len()?;
+                (0..len).try_for_each(|_| self.read(format))
+            }
+            Format::Map { key, value } => {
+                let len = self.read_len()?;
+                (0..len).try_for_each(|_| {
+                    self.read(key)?;
+                    self.read(value)
+                })
+            }
+            Format::Tuple(formats) => self.read_all(formats),
+            Format::TupleArray { content, size } => (0..*size).try_for_each(|_| self.read(content)),
+        }
+    }
+}
@@ -4,22 +4,82 @@
 use super::messages::*;
 use crate::error::*;
 
+use bincode::Options;
 use failure::format_err;
 use serde::{Deserialize, Serialize};
+use std::convert::TryInto;
 
 #[cfg(test)]
 #[path = "unit_tests/serialize_tests.rs"]
 mod serialize_tests;
 
+/// How strictly to decode incoming messages.
+///
+/// Messages are encoded positionally, so fields added by a newer version of the protocol
+/// at the end of a message show up as trailing bytes.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum DeserializationMode {
+    /// Reject messages followed by unexpected bytes.
+    Strict,
+    /// Ignore bytes following a message. This eases rolling upgrades but the ignored data
+    /// is neither validated nor acted upon: a peer relying on it (e.g. a newer restriction
+    /// on a transfer) will not be honored, and version drift goes unnoticed.
+    Lenient,
+}
+
+impl Default for DeserializationMode {
+    fn default() -> Self {
+        DeserializationMode::Strict
+    }
+}
+
+/// How to answer messages of a type that this version of the protocol does not know.
+#[derive(Eq, PartialEq, Clone, Copy, Debug, Serialize, Deserialize)]
+pub enum UnknownMessagePolicy {
+    /// Reply with `FastPayError::UnknownMessageType`.
+    Reply,
+    /// Do not reply, e.g. to give nothing away to scanners.
+    Ignore,
+}
+
+impl Default for UnknownMessagePolicy {
+    fn default() -> Self {
+        UnknownMessagePolicy::Reply
+    }
+}
+
+/// Number of message types, i.e. of variants of `SerializedMessage`.
+pub const NUM_MESSAGE_TYPES: u32 = 20;
+
+/// Encoded messages start with a tag giving their type: the index of their variant in
+/// `SerializedMessage`.
+pub fn message_tag(buffer: &[u8]) -> Option<u32> {
+    let tag = buffer.get(..4)?;
+    Some(u32::from_le_bytes(tag.try_into().expect("4 bytes")))
+}
+
 #[derive(Serialize, Deserialize)]
 pub enum SerializedMessage {
     Order(Box<TransferOrder>),
     Vote(Box<SignedTransferOrder>),
     Cert(Box<CertifiedTransferOrder>),
-    CrossShard(Box<CertifiedTransferOrder>),
+    CrossShard(Box<CrossShardUpdate>),
     Error(Box<FastPayError>),
     InfoReq(Box<AccountInfoRequest>),
     InfoResp(Box<AccountInfoResponse>),
+    Ping(Box<Ping>),
+    Pong(Box<Pong>),
+    ListAccountsReq(Box<ListAccountsRequest>),
+    ListAccountsResp(Box<ListAccountsResponse>),
+    RecipientCert(Box<CertifiedTransferOrder>),
+    CommitteeInfoReq(Box<CommitteeInfoRequest>),
+    CommitteeInfoResp(Box<CommitteeInfoResponse>),
+    SequenceNumberReq(Box<SequenceNumberRequest>),
+    SequenceNumberResp(Box<SequenceNumberStatement>),
+    SignedCrossShard(Box<SignedCrossShardUpdate>),
+    ReloadAccountsReq(Box<ReloadAccountsRequest>),
+    ReloadAccountsResp(Box<ReloadAccountsResponse>),
+    OwnershipOrder(Box<OwnershipOrder>),
 }
 
 // This helper structure is only here to avoid cloning while serializing commands.
@@ -30,10 +90,23 @@ enum ShallowSerializedMessage<'a> {
     Order(&'a TransferOrder),
     Vote(&'a SignedTransferOrder),
     Cert(&'a CertifiedTransferOrder),
-    CrossShard(&'a CertifiedTransferOrder),
+    CrossShard(&'a CrossShardUpdate),
     Error(&'a FastPayError),
     InfoReq(&'a AccountInfoRequest),
     InfoResp(&'a AccountInfoResponse),
+    Ping(&'a Ping),
+    Pong(&'a Pong),
+    ListAccountsReq(&'a ListAccountsRequest),
+    ListAccountsResp(&'a ListAccountsResponse),
+    RecipientCert(&'a CertifiedTransferOrder),
+    CommitteeInfoReq(&'a CommitteeInfoRequest),
+    CommitteeInfoResp(&'a CommitteeInfoResponse),
+    SequenceNumberReq(&'a SequenceNumberRequest),
+    SequenceNumberResp(&'a SequenceNumberStatement),
+    SignedCrossShard(&'a SignedCrossShardUpdate),
+    ReloadAccountsReq(&'a ReloadAccountsRequest),
+    ReloadAccountsResp(&'a ReloadAccountsResponse),
+    OwnershipOrder(&'a OwnershipOrder),
 }
 
 fn serialize_into<T, W>(writer: W, msg: &T) -> Result<(), failure::Error>
@@ -98,7 +171,11 @@ pub fn serialize_info_response(value: &AccountInfoResponse) -> Vec<u8> {
     serialize(&ShallowSerializedMessage::InfoResp(value))
 }
 
-pub fn serialize_cross_shard(value: &CertifiedTransferOrder) -> Vec<u8> {
+pub fn serialize_recipient_cert(value: &CertifiedTransferOrder) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::RecipientCert(value))
+}
+
+pub fn serialize_cross_shard(value: &CrossShardUpdate) -> Vec<u8> {
     serialize(&ShallowSerializedMessage::CrossShard(value))
 }
 
@@ -113,9 +190,70 @@ where
     serialize_into(writer, &ShallowSerializedMessage::Vote(value))
 }
 
+pub fn serialize_ping(value: &Ping) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::Ping(value))
+}
+
+pub fn serialize_pong(value: &Pong) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::Pong(value))
+}
+
+pub fn serialize_list_accounts_request(value: &ListAccountsRequest) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::ListAccountsReq(value))
+}
+
+pub fn serialize_list_accounts_response(value: &ListAccountsResponse) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::ListAccountsResp(value))
+}
+
+pub fn serialize_committee_info_request(value: &CommitteeInfoRequest) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::CommitteeInfoReq(value))
+}
+
+pub fn serialize_committee_info_response(value: &CommitteeInfoResponse) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::CommitteeInfoResp(value))
+}
+
+pub fn serialize_sequence_number_request(value: &SequenceNumberRequest) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::SequenceNumberReq(value))
+}
+
+pub fn serialize_sequence_number_statement(value: &SequenceNumberStatement) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::SequenceNumberResp(value))
+}
+
+pub fn serialize_signed_cross_shard(value: &SignedCrossShardUpdate) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::SignedCrossShard(value))
+}
+
+pub fn serialize_reload_accounts_request(value: &ReloadAccountsRequest) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::ReloadAccountsReq(value))
+}
+
+pub fn serialize_reload_accounts_response(value: &ReloadAccountsResponse) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::ReloadAccountsResp(value))
+}
+
+pub fn serialize_ownership_order(value: &OwnershipOrder) -> Vec<u8> {
+    serialize(&ShallowSerializedMessage::OwnershipOrder(value))
+}
+
 pub fn deserialize_message<R>(reader: R) -> Result<SerializedMessage, failure::Error>
 where
     R: std::io::Read,
 {
     bincode::deserialize_from(reader).map_err(|err| format_err!("{}", err))
 }
+
+/// Decode a message from a buffer, treating trailing bytes according to `mode`.
+pub fn deserialize_message_with_mode(
+    buffer: &[u8],
+    mode: DeserializationMode,
+) -> Result<SerializedMessage, failure::Error> {
+    let options = bincode::DefaultOptions::new().with_fixint_encoding();
+    let result = match mode {
+        DeserializationMode::Strict => options.reject_trailing_bytes().deserialize(buffer),
+        DeserializationMode::Lenient => options.allow_trailing_bytes().deserialize(buffer),
+    };
+    result.map_err(|err| format_err!("{}", err))
+}
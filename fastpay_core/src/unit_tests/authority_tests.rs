@@ -2,6 +2,8 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::*;
+use crate::{clock::ManualClock, committee::SigningDomain, redaction::REDACTED_ADDRESS_LENGTH};
+use std::cell::RefCell;
 
 #[test]
 fn test_handle_transfer_order_bad_signature() {
@@ -226,6 +228,48 @@ fn test_handle_confirmation_order_exceed_balance() {
     assert!(authority_state.accounts.get(&recipient).is_some());
 }
 
+#[test]
+fn test_handle_confirmation_order_reject_negative_balance() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.allow_negative_balances = false;
+
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(6),
+        &authority_state,
+    );
+    assert_eq!(
+        authority_state.handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order)),
+        Err(FastPayError::NegativeBalance {
+            balance: Balance::from(-1)
+        })
+    );
+    let account = authority_state.accounts.get(&sender).unwrap();
+    assert_eq!(Balance::from(5), account.balance);
+    assert_eq!(SequenceNumber::from(0), account.next_sequence_number);
+    assert!(!authority_state.accounts.contains_key(&recipient));
+
+    // Spending the whole balance is fine.
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order))
+        .unwrap();
+    assert_eq!(
+        Balance::zero(),
+        authority_state.accounts.get(&sender).unwrap().balance
+    );
+}
+
 #[test]
 fn test_handle_confirmation_order_receiver_balance_overflow() {
     let (sender, sender_key) = get_key_pair();
@@ -279,10 +323,11 @@ fn test_handle_confirmation_order_receiver_equal_sender() {
 
 #[test]
 fn test_handle_cross_shard_recipient_commit() {
-    let (sender, sender_key) = get_key_pair();
-    let (recipient, _) = get_key_pair();
+    let (sender, sender_key) = get_key_pair_in_shard(1);
+    let (recipient, _) = get_key_pair_in_shard(0);
     // Sender has no account on this shard.
     let mut authority_state = init_state_with_account(recipient, Balance::from(1));
+    authority_state.number_of_shards = 2;
     let certified_transfer_order = init_certified_transfer_order(
         sender,
         &sender_key,
@@ -291,7 +336,7 @@ fn test_handle_cross_shard_recipient_commit() {
         &authority_state,
     );
     assert!(authority_state
-        .handle_cross_shard_recipient_commit(certified_transfer_order)
+        .handle_cross_shard_recipient_commit(cross_shard_update(certified_transfer_order, 2))
         .is_ok());
     let account = authority_state.accounts.get(&recipient).unwrap();
     assert_eq!(Balance::from(11), account.balance);
@@ -300,154 +345,1717 @@ fn test_handle_cross_shard_recipient_commit() {
 }
 
 #[test]
-fn test_handle_confirmation_order_ok() {
-    let (sender, sender_key) = get_key_pair();
-    let recipient = dbg_addr(2);
-    let mut authority_state = init_state_with_account(sender, Balance::from(5));
-    let certified_transfer_order = init_certified_transfer_order(
+fn test_handle_cross_shard_recipient_commit_source() {
+    let (sender, sender_key) = get_key_pair_in_shard(1);
+    let (local_sender, local_sender_key) = get_key_pair_in_shard(0);
+    let (recipient, _) = get_key_pair_in_shard(0);
+    let mut authority_state = init_state_with_account(recipient, Balance::from(0));
+    authority_state.number_of_shards = 2;
+    let make_certificate = |sender, key: &KeyPair, state: &AuthorityState| {
+        init_certified_transfer_order(
+            sender,
+            key,
+            Address::FastPay(recipient),
+            Amount::from(1),
+            state,
+        )
+    };
+
+    // Shards never send cross-shard updates to themselves.
+    let certificate = make_certificate(local_sender, &local_sender_key, &authority_state);
+    assert_eq!(
+        authority_state.handle_cross_shard_recipient_commit(cross_shard_update(certificate, 2)),
+        Err(FastPayError::InvalidCrossShardSource { shard: 0 })
+    );
+
+    // Sources must be shards of this authority, and handle the sender.
+    let update = cross_shard_update(make_certificate(sender, &sender_key, &authority_state), 2);
+    for shard in &[2, 7, u32::MAX] {
+        let mut out_of_range = update.clone();
+        out_of_range.source_shard_id = *shard;
+        assert_eq!(
+            authority_state.handle_cross_shard_recipient_commit(out_of_range),
+            Err(FastPayError::InvalidCrossShardSource { shard: *shard })
+        );
+    }
+    // A sibling shard in range, but not the one of the sender.
+    authority_state.number_of_shards = 3;
+    let source = match AuthorityState::get_shard(3, &sender) {
+        1 => 2,
+        _ => 1,
+    };
+    let mut unexpected = update.clone();
+    unexpected.source_shard_id = source;
+    assert_eq!(
+        authority_state.handle_cross_shard_recipient_commit(unexpected),
+        Err(FastPayError::InvalidCrossShardSource { shard: source })
+    );
+    authority_state.number_of_shards = 2;
+
+    // Only allowed siblings may send updates.
+    authority_state.cross_shard_sources = Some(BTreeSet::new());
+    assert_eq!(
+        authority_state.handle_cross_shard_recipient_commit(update.clone()),
+        Err(FastPayError::InvalidCrossShardSource { shard: 1 })
+    );
+    authority_state.cross_shard_sources = Some(std::iter::once(1).collect());
+    authority_state
+        .handle_cross_shard_recipient_commit(update)
+        .unwrap();
+    assert_eq!(
+        authority_state.accounts.get(&recipient).unwrap().balance,
+        Balance::from(1)
+    );
+}
+
+#[test]
+fn test_handle_signed_cross_shard_recipient_commit() {
+    let (sender, sender_key) = get_key_pair_in_shard(1);
+    let (recipient, _) = get_key_pair_in_shard(0);
+    let mut authority_state = init_state_with_account(recipient, Balance::from(1));
+    authority_state.number_of_shards = 2;
+    authority_state.sign_cross_shard_updates = true;
+    let certificate = init_certified_transfer_order(
         sender,
         &sender_key,
         Address::FastPay(recipient),
-        Amount::from(5),
+        Amount::from(10),
         &authority_state,
     );
 
-    let old_account = authority_state.accounts.get_mut(&sender).unwrap();
-    let mut next_sequence_number = old_account.next_sequence_number;
-    next_sequence_number = next_sequence_number.increment().unwrap();
-    let mut remaining_balance = old_account.balance;
-    remaining_balance = remaining_balance
-        .try_sub(certified_transfer_order.value.transfer.amount.into())
-        .unwrap();
+    let update = cross_shard_update(certificate, 2);
 
-    let (info, _) = authority_state
-        .handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order.clone()))
-        .unwrap();
-    assert_eq!(sender, info.sender);
-    assert_eq!(remaining_balance, info.balance);
-    assert_eq!(next_sequence_number, info.next_sequence_number);
-    assert_eq!(None, info.pending_confirmation);
+    // Unsigned and forged updates are rejected.
     assert_eq!(
-        authority_state.accounts.get(&sender).unwrap().confirmed_log,
-        vec![certified_transfer_order.clone()]
+        authority_state.handle_cross_shard_recipient_commit(update.clone()),
+        Err(FastPayError::MissingCrossShardSignature)
     );
-
-    let recipient_account = authority_state.accounts.get(&recipient).unwrap();
+    let (_, other_key) = get_key_pair();
+    let forged = SignedCrossShardUpdate::new(update.clone(), &other_key);
+    assert!(authority_state
+        .handle_signed_cross_shard_recipient_commit(forged)
+        .is_err());
+    let mut tampered = SignedCrossShardUpdate::new(update.clone(), &authority_state.secret);
+    tampered.update.transfer_certificate.value.transfer.amount = Amount::from(1000);
+    assert!(authority_state
+        .handle_signed_cross_shard_recipient_commit(tampered)
+        .is_err());
     assert_eq!(
-        recipient_account.balance,
-        certified_transfer_order.value.transfer.amount.into()
+        authority_state.accounts.get(&recipient).unwrap().balance,
+        Balance::from(1)
     );
 
-    let info_request = AccountInfoRequest {
-        sender: recipient,
-        request_sequence_number: None,
-        request_received_transfers_excluding_first_nth: Some(0),
-    };
-    let response = authority_state
-        .handle_account_info_request(info_request)
+    // Updates signed by the authority are credited.
+    let update = SignedCrossShardUpdate::new(update, &authority_state.secret);
+    authority_state
+        .handle_signed_cross_shard_recipient_commit(update)
         .unwrap();
-    assert_eq!(response.requested_received_transfers.len(), 1);
     assert_eq!(
-        response.requested_received_transfers[0]
-            .value
-            .transfer
-            .amount,
-        Amount::from(5)
+        authority_state.accounts.get(&recipient).unwrap().balance,
+        Balance::from(11)
     );
 }
 
 #[test]
-fn test_handle_primary_synchronization_order_update() {
-    let mut state = init_state();
-    let mut updated_transaction_index = state.last_transaction_index;
-    let address = dbg_addr(1);
-    let order = init_primary_synchronization_order(address);
-
-    assert!(state
-        .handle_primary_synchronization_order(order.clone())
+fn test_handle_confirmation_order_auto_create_recipient() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    assert_eq!(
+        authority_state.committee.recipient_policy,
+        RecipientPolicy::AutoCreate
+    );
+    let transfer_order = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+    );
+    assert!(authority_state
+        .handle_transfer_order(transfer_order)
         .is_ok());
-    updated_transaction_index = updated_transaction_index.increment().unwrap();
-    assert_eq!(state.last_transaction_index, updated_transaction_index);
-    let account = state.accounts.get(&address).unwrap();
-    assert_eq!(account.balance, order.amount.into());
-    assert_eq!(state.accounts.len(), 1);
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+        &authority_state,
+    );
+    assert!(authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order))
+        .is_ok());
+    let recipient_account = authority_state.accounts.get(&recipient).unwrap();
+    assert_eq!(recipient_account.balance, Balance::from(5));
+    assert_eq!(recipient_account.received_log.len(), 1);
 }
 
 #[test]
-fn test_handle_primary_synchronization_order_double_spend() {
-    let mut state = init_state();
-    let mut updated_transaction_index = state.last_transaction_index;
-    let address = dbg_addr(1);
-    let order = init_primary_synchronization_order(address);
+fn test_handle_transfer_order_reject_missing_recipient() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.committee.recipient_policy = RecipientPolicy::Reject;
+    let transfer_order = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+    );
+    assert_eq!(
+        authority_state.handle_transfer_order(transfer_order.clone()),
+        Err(FastPayError::RecipientNotFound)
+    );
+    assert!(authority_state
+        .accounts
+        .get(&sender)
+        .unwrap()
+        .pending_confirmation
+        .is_none());
 
-    assert!(state
-        .handle_primary_synchronization_order(order.clone())
+    // Transfers to known recipients and Primary addresses are unaffected.
+    authority_state
+        .accounts
+        .insert(recipient, AccountOffchainState::new());
+    assert!(authority_state
+        .handle_transfer_order(transfer_order)
         .is_ok());
-    updated_transaction_index = updated_transaction_index.increment().unwrap();
-    // Replays are ignored.
-    assert!(state
-        .handle_primary_synchronization_order(order.clone())
+    let (other_sender, other_sender_key) = get_key_pair();
+    authority_state.accounts.insert(
+        other_sender,
+        AccountOffchainState::new_with_balance(Balance::from(5), Vec::new()),
+    );
+    let primary_transfer_order = init_transfer_order(
+        other_sender,
+        &other_sender_key,
+        Address::Primary(dbg_addr(3)),
+        Amount::from(5),
+    );
+    assert!(authority_state
+        .handle_transfer_order(primary_transfer_order)
         .is_ok());
-    assert_eq!(state.last_transaction_index, updated_transaction_index);
-    let account = state.accounts.get(&address).unwrap();
-    assert_eq!(account.balance, order.amount.into());
-    assert_eq!(state.accounts.len(), 1);
 }
 
 #[test]
-fn test_account_state_ok() {
-    let sender = dbg_addr(1);
-    let authority_state = init_state_with_account(sender, Balance::from(5));
+fn test_handle_confirmation_order_reject_missing_recipient() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.committee.recipient_policy = RecipientPolicy::Reject;
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+        &authority_state,
+    );
     assert_eq!(
-        authority_state.accounts.get(&sender).unwrap(),
-        authority_state.account_state(&sender).unwrap()
+        authority_state.handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order)),
+        Err(FastPayError::RecipientNotFound)
     );
+    let sender_account = authority_state.accounts.get(&sender).unwrap();
+    assert_eq!(sender_account.balance, Balance::from(5));
+    assert_eq!(sender_account.next_sequence_number, SequenceNumber::new());
+    assert!(sender_account.confirmed_log.is_empty());
+    assert!(!authority_state.accounts.contains_key(&recipient));
 }
 
 #[test]
-fn test_account_state_unknown_account() {
-    let sender = dbg_addr(1);
-    let unknown_address = dbg_addr(99);
-    let authority_state = init_state_with_account(sender, Balance::from(5));
-    assert!(authority_state.account_state(&unknown_address).is_err());
+fn test_handle_recipient_confirmation_order() {
+    let (sender, sender_key) = get_key_pair_in_shard(1);
+    let (recipient, _) = get_key_pair_in_shard(0);
+    // Sender has no account on this shard.
+    let mut authority_state = init_state_with_account(recipient, Balance::from(1));
+    authority_state.number_of_shards = 2;
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(10),
+        &authority_state,
+    );
+    let info = authority_state
+        .handle_recipient_confirmation_order(ConfirmationOrder::new(
+            certified_transfer_order.clone(),
+        ))
+        .unwrap();
+    assert_eq!(info.sender, recipient);
+    assert_eq!(info.balance, Balance::from(11));
+
+    // Neither repeating the order nor a late cross-shard update credits the transfer again.
+    authority_state
+        .handle_recipient_confirmation_order(ConfirmationOrder::new(
+            certified_transfer_order.clone(),
+        ))
+        .unwrap();
+    authority_state
+        .handle_cross_shard_recipient_commit(cross_shard_update(certified_transfer_order, 2))
+        .unwrap();
+    let account = authority_state.accounts.get(&recipient).unwrap();
+    assert_eq!(account.balance, Balance::from(11));
+    assert_eq!(account.received_log.len(), 1);
+    assert!(!authority_state.accounts.contains_key(&sender));
 }
 
 #[test]
-fn test_get_shards() {
-    let num_shards = 16u32;
-    let mut found = vec![false; num_shards as usize];
-    let mut left = num_shards;
-    loop {
-        let (address, _) = get_key_pair();
-        let shard = AuthorityState::get_shard(num_shards, &address) as usize;
-        println!("found {}", shard);
-        if !found[shard] {
-            found[shard] = true;
-            left -= 1;
-            if left == 0 {
-                break;
-            }
-        }
-    }
+fn test_handle_recipient_confirmation_order_feature_disabled() {
+    let (sender, sender_key) = get_key_pair();
+    let (recipient, _) = get_key_pair();
+    let mut authority_state = init_state_with_account(recipient, Balance::from(1));
+    authority_state.committee.features.clear();
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(10),
+        &authority_state,
+    );
+    assert_eq!(
+        authority_state
+            .handle_recipient_confirmation_order(ConfirmationOrder::new(certified_transfer_order)),
+        Err(FastPayError::FeatureDisabled {
+            feature: Feature::RecipientClaims
+        })
+    );
+    let account = authority_state.accounts.get(&recipient).unwrap();
+    assert_eq!(account.balance, Balance::from(1));
 }
 
-// helpers
-
-#[cfg(test)]
-fn init_state() -> AuthorityState {
-    let (authority_address, authority_key) = get_key_pair();
-    let mut authorities = BTreeMap::new();
-    authorities.insert(
-        /* address */ authority_address,
-        /* voting right */ 1,
+#[test]
+fn test_handle_recipient_confirmation_order_after_confirmation() {
+    let (sender, sender_key) = get_key_pair();
+    let (recipient, _) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+        &authority_state,
     );
-    let committee = Committee::new(authorities);
-    AuthorityState::new(committee, authority_address, authority_key)
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order.clone()))
+        .unwrap();
+    let info = authority_state
+        .handle_recipient_confirmation_order(ConfirmationOrder::new(certified_transfer_order))
+        .unwrap();
+    assert_eq!(info.balance, Balance::from(5));
 }
 
-#[cfg(test)]
-fn init_state_with_accounts<I: IntoIterator<Item = (FastPayAddress, Balance)>>(
-    balances: I,
+#[test]
+fn test_handle_recipient_confirmation_order_bad_certificate() {
+    let (sender, sender_key) = get_key_pair();
+    let (recipient, _) = get_key_pair();
+    let mut authority_state = init_state();
+    // Certificate issued by another committee.
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+        &init_state(),
+    );
+    assert!(authority_state
+        .handle_recipient_confirmation_order(ConfirmationOrder::new(certified_transfer_order))
+        .is_err());
+    assert!(!authority_state.accounts.contains_key(&recipient));
+
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::Primary(recipient),
+        Amount::from(5),
+        &authority_state,
+    );
+    assert_eq!(
+        authority_state
+            .handle_recipient_confirmation_order(ConfirmationOrder::new(certified_transfer_order)),
+        Err(FastPayError::UnexpectedPrimaryRecipient)
+    );
+}
+
+#[test]
+fn test_account_filter_tracks_created_accounts() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.enable_account_filter(0.01);
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order))
+        .unwrap();
+    let filter = authority_state.account_filter.as_ref().unwrap();
+    assert!(filter.may_contain(&sender.0));
+    assert!(filter.may_contain(&recipient.0));
+    assert!(authority_state
+        .handle_account_info_request(AccountInfoRequest {
+            sender: recipient,
+            request_sequence_number: None,
+            request_received_transfers_excluding_first_nth: None,
+            observer: false,
+        })
+        .is_ok());
+    assert_eq!(
+        authority_state
+            .handle_account_info_request(AccountInfoRequest {
+                sender: dbg_addr(3),
+                request_sequence_number: None,
+                request_received_transfers_excluding_first_nth: None,
+                observer: false,
+            })
+            .unwrap_err(),
+        FastPayError::UnknownSenderAccount
+    );
+}
+
+#[test]
+fn test_account_filter_grows_with_accounts() {
+    let mut authority_state = init_state();
+    authority_state.enable_account_filter(0.01);
+    let mut addresses = Vec::new();
+    for _ in 0..3000 {
+        let (address, _) = get_key_pair();
+        let transaction_index = authority_state.last_transaction_index.increment().unwrap();
+        authority_state
+            .handle_primary_synchronization_order(PrimarySynchronizationOrder {
+                recipient: address,
+                amount: Amount::from(1),
+                transaction_index,
+            })
+            .unwrap();
+        addresses.push(address);
+    }
+    let filter = authority_state.account_filter.as_ref().unwrap();
+    assert!(!filter.is_full());
+    assert!(addresses
+        .iter()
+        .all(|address| filter.may_contain(&address.0)));
+
+    // Accounts inserted directly are only tracked after a rebuild.
+    let address = dbg_addr(4);
+    authority_state
+        .accounts
+        .insert(address, AccountOffchainState::new());
+    authority_state.rebuild_account_filter();
+    let filter = authority_state.account_filter.as_ref().unwrap();
+    assert!(filter.may_contain(&address.0));
+    assert_eq!(filter.len(), 3001);
+}
+
+#[test]
+fn test_handle_orders_signed_for_another_chain() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(10));
+    let mainnet = SigningDomain {
+        chain_id: 1,
+        genesis_timestamp: 1000,
+    };
+    let testnet = SigningDomain {
+        chain_id: 2,
+        genesis_timestamp: 1000,
+    };
+    authority_state.committee.domain = mainnet;
+    let make_order = |domain| {
+        let transfer = Transfer {
+            sender,
+            recipient,
+            amount: Amount::from(1),
+            sequence_number: SequenceNumber::new(),
+            user_data: UserData::default(),
+            account_state: None,
+            domain,
+        };
+        TransferOrder::new(transfer, &sender_key)
+    };
+    let wrong_domain = FastPayError::WrongSigningDomain {
+        expected: mainnet,
+        received: testnet,
+    };
+
+    // An order signed for the testnet.
+    let order = make_order(testnet);
+    assert_eq!(
+        authority_state.handle_transfer_order(order.clone()),
+        Err(wrong_domain.clone())
+    );
+    // Its domain cannot be changed without the key of the sender.
+    let mut replayed = order.clone();
+    replayed.transfer.domain = mainnet;
+    assert!(matches!(
+        authority_state.handle_transfer_order(replayed),
+        Err(FastPayError::InvalidSignature { .. })
+    ));
+    // Nor can a certificate from the testnet be confirmed, even by the same authorities.
+    let vote =
+        SignedTransferOrder::new(order.clone(), authority_state.name, &authority_state.secret);
+    assert_eq!(
+        vote.check(&authority_state.committee),
+        Err(wrong_domain.clone())
+    );
+    let certificate = CertifiedTransferOrder {
+        value: order,
+        signatures: vec![(vote.authority, vote.signature)],
+    };
+    assert_eq!(
+        authority_state
+            .handle_confirmation_order(ConfirmationOrder::new(certificate))
+            .map(|_| ()),
+        Err(wrong_domain)
+    );
+    assert_eq!(authority_state.accounts[&sender].balance, Balance::from(10));
+
+    assert!(authority_state
+        .handle_transfer_order(make_order(mainnet))
+        .is_ok());
+}
+
+#[test]
+fn test_handle_transfer_order_acceptance_window() {
+    let (sender, sender_key) = get_key_pair();
+    let (other, other_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_accounts(vec![
+        (sender, Balance::from(10)),
+        (other, Balance::from(10)),
+    ]);
+    authority_state.committee.order_acceptance_window = Some(2);
+    for i in 0..4 {
+        let certificate = init_certified_transfer_order_with_sequence_number(
+            sender,
+            &sender_key,
+            recipient,
+            Amount::from(1),
+            SequenceNumber::from(i),
+            &authority_state,
+        );
+        authority_state
+            .handle_confirmation_order(ConfirmationOrder::new(certificate))
+            .unwrap();
+    }
+    let history = authority_state.accounts[&sender].confirmed_history.clone();
+    let make_order = |sender, secret, sequence_number, reference| {
+        let transfer = Transfer {
+            sender,
+            recipient,
+            amount: Amount::from(1),
+            sequence_number: SequenceNumber::from(sequence_number),
+            user_data: UserData::default(),
+            account_state: reference,
+            domain: SigningDomain::default(),
+        };
+        TransferOrder::new(transfer, secret)
+    };
+    let reference = |sequence_number: u64| {
+        Some(AccountStateReference {
+            sequence_number: SequenceNumber::from(sequence_number),
+            history_root: history.root_at(sequence_number as usize).unwrap(),
+        })
+    };
+
+    // Fresh order.
+    let order = make_order(
+        other,
+        &other_key,
+        0,
+        Some(AccountStateReference {
+            sequence_number: SequenceNumber::new(),
+            history_root: MerkleAccumulator::new().root(),
+        }),
+    );
+    assert!(authority_state.handle_transfer_order(order).is_ok());
+
+    // Orders without a reference, or with a wrong one.
+    let order = make_order(sender, &sender_key, 4, None);
+    assert_eq!(
+        authority_state.handle_transfer_order(order),
+        Err(FastPayError::MissingAccountStateReference)
+    );
+    let mut wrong_reference = reference(3);
+    wrong_reference.as_mut().unwrap().history_root = history.root_at(2).unwrap();
+    let order = make_order(sender, &sender_key, 4, wrong_reference);
+    assert_eq!(
+        authority_state.handle_transfer_order(order),
+        Err(FastPayError::InvalidAccountStateReference)
+    );
+
+    // Very stale order.
+    let order = make_order(sender, &sender_key, 4, reference(1));
+    assert_eq!(
+        authority_state.handle_transfer_order(order),
+        Err(FastPayError::StaleAccountStateReference {
+            current_sequence_number: SequenceNumber::from(4)
+        })
+    );
+
+    // Slightly stale order.
+    let order = make_order(sender, &sender_key, 4, reference(2));
+    assert!(authority_state.handle_transfer_order(order).is_ok());
+}
+
+#[test]
+fn test_cross_shard_transfer_to_missing_recipient() {
+    let (sender, sender_key) = get_key_pair_in_shard(0);
+    let (recipient, _) = get_key_pair_in_shard(1);
+    let mut sender_shard = init_state_with_account(sender, Balance::from(5));
+    sender_shard.number_of_shards = 2;
+    sender_shard.committee.recipient_policy = RecipientPolicy::Reject;
+    let mut recipient_shard = AuthorityState::new_shard(
+        sender_shard.committee.clone(),
+        sender_shard.name,
+        sender_shard.secret.copy(),
+        1,
+        2,
+    );
+
+    let order = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+    );
+    sender_shard.handle_transfer_order(order).unwrap();
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+        &sender_shard,
+    );
+    let (info, update) = sender_shard
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+    assert_eq!(info.balance, Balance::from(0));
+    let update = update.unwrap();
+    assert_eq!(update.shard_id, 1);
+
+    // The recipient shard creates the account instead of losing the credit.
+    recipient_shard
+        .handle_cross_shard_recipient_commit(update)
+        .unwrap();
+    let account = recipient_shard.accounts.get(&recipient).unwrap();
+    assert_eq!(account.balance, Balance::from(5));
+    assert_eq!(account.received_log.len(), 1);
+}
+
+#[test]
+fn test_handle_confirmation_order_ok() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+        &authority_state,
+    );
+
+    let old_account = authority_state.accounts.get_mut(&sender).unwrap();
+    let mut next_sequence_number = old_account.next_sequence_number;
+    next_sequence_number = next_sequence_number.increment().unwrap();
+    let mut remaining_balance = old_account.balance;
+    remaining_balance = remaining_balance
+        .try_sub(certified_transfer_order.value.transfer.amount.into())
+        .unwrap();
+
+    let (info, _) = authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order.clone()))
+        .unwrap();
+    assert_eq!(sender, info.sender);
+    assert_eq!(remaining_balance, info.balance);
+    assert_eq!(next_sequence_number, info.next_sequence_number);
+    assert_eq!(None, info.pending_confirmation);
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().confirmed_log,
+        vec![certified_transfer_order.clone()]
+    );
+
+    let recipient_account = authority_state.accounts.get(&recipient).unwrap();
+    assert_eq!(
+        recipient_account.balance,
+        certified_transfer_order.value.transfer.amount.into()
+    );
+
+    let info_request = AccountInfoRequest {
+        sender: recipient,
+        request_sequence_number: None,
+        request_received_transfers_excluding_first_nth: Some(0),
+        observer: false,
+    };
+    let response = authority_state
+        .handle_account_info_request(info_request)
+        .unwrap();
+    assert_eq!(response.requested_received_transfers.len(), 1);
+    assert_eq!(
+        response.requested_received_transfers[0]
+            .value
+            .transfer
+            .amount,
+        Amount::from(5)
+    );
+}
+
+#[test]
+fn test_handle_account_info_request_signed() {
+    let (sender, sender_key) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let info_request = AccountInfoRequest {
+        sender,
+        request_sequence_number: None,
+        request_received_transfers_excluding_first_nth: None,
+        observer: false,
+    };
+    let response = authority_state
+        .handle_account_info_request(info_request.clone())
+        .unwrap();
+    assert!(response.check_signature(authority_state.name).is_ok());
+    let (other_authority, _) = get_key_pair();
+    assert!(response.check_signature(other_authority).is_err());
+
+    let mut tampered_response = response;
+    tampered_response.balance = Balance::from(1000);
+    assert!(tampered_response
+        .check_signature(authority_state.name)
+        .is_err());
+
+    // Votes are signed as usual.
+    let transfer_order = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(dbg_addr(2)),
+        Amount::from(5),
+    );
+    let account_info = authority_state
+        .handle_transfer_order(transfer_order)
+        .unwrap();
+    assert!(account_info
+        .pending_confirmation
+        .unwrap()
+        .check(&authority_state.committee)
+        .is_ok());
+}
+
+#[test]
+fn test_handle_account_info_request_unsigned() {
+    let (sender, sender_key) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.sign_read_responses = false;
+    let info_request = AccountInfoRequest {
+        sender,
+        request_sequence_number: None,
+        request_received_transfers_excluding_first_nth: None,
+        observer: false,
+    };
+    let response = authority_state
+        .handle_account_info_request(info_request)
+        .unwrap();
+    assert_eq!(response.signature, None);
+    assert_eq!(
+        response.check_signature(authority_state.name),
+        Err(FastPayError::MissingResponseSignature)
+    );
+
+    // Votes are still signed.
+    let transfer_order = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(dbg_addr(2)),
+        Amount::from(5),
+    );
+    let account_info = authority_state
+        .handle_transfer_order(transfer_order)
+        .unwrap();
+    assert!(account_info
+        .pending_confirmation
+        .unwrap()
+        .check(&authority_state.committee)
+        .is_ok());
+}
+
+#[test]
+fn test_handle_account_info_request_observer() {
+    let (local, _) = get_key_pair_in_shard(0);
+    let (remote, _) = get_key_pair_in_shard(1);
+    let mut authority_state = init_state_with_account(local, Balance::from(5));
+    authority_state.number_of_shards = 2;
+    let request = |sender, observer| AccountInfoRequest {
+        sender,
+        request_sequence_number: None,
+        request_received_transfers_excluding_first_nth: None,
+        observer,
+    };
+    assert_eq!(
+        authority_state.handle_account_info_request(request(remote, false)),
+        Err(FastPayError::WrongShard)
+    );
+
+    // Observers get an empty answer pointing to the owning shard.
+    let response = authority_state
+        .handle_account_info_request(request(remote, true))
+        .unwrap();
+    let info = response.observer_info.clone().unwrap();
+    assert_eq!(info.shard_id, 0);
+    assert_eq!(info.owning_shard, 1);
+    assert!(!info.is_owner());
+    assert_eq!(response.balance, Balance::zero());
+    assert_eq!(response.next_sequence_number, SequenceNumber::new());
+    assert!(response.check_signature(authority_state.name).is_ok());
+
+    // Observing an account of the shard gives the same data as a regular query.
+    let response = authority_state
+        .handle_account_info_request(request(local, true))
+        .unwrap();
+    assert!(response.observer_info.unwrap().is_owner());
+    assert_eq!(response.balance, Balance::from(5));
+}
+
+#[test]
+fn test_handle_transfer_order_logs_conflicting_orders() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.log_conflicting_orders = true;
+    start_capturing_logs();
+
+    let first_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(5));
+    authority_state
+        .handle_transfer_order(first_order.clone())
+        .unwrap();
+    // The next sequence number is not a conflict, only an early order.
+    let next_order = init_transfer_order_with_sequence_number(
+        sender,
+        &sender_key,
+        recipient,
+        Amount::from(1),
+        SequenceNumber::from(1),
+    );
+    assert!(authority_state.handle_transfer_order(next_order).is_err());
+    assert!(captured_logs().is_empty());
+
+    let second_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(4));
+    assert_eq!(
+        authority_state.handle_transfer_order(second_order.clone()),
+        Err(FastPayError::PreviousTransferMustBeConfirmedFirst {
+            pending_confirmation: Box::new(first_order.clone())
+        })
+    );
+    assert_eq!(
+        captured_logs(),
+        vec![format!(
+            "WARN conflicting transfer orders from {}.. at sequence number 0: \
+             {} is pending, {} was rejected",
+            &encode_address(&sender)[..REDACTED_ADDRESS_LENGTH],
+            hex::encode(leaf_hash(&first_order.transfer)),
+            hex::encode(leaf_hash(&second_order.transfer))
+        )]
+    );
+}
+
+#[test]
+fn test_handle_transfer_order_exceeding_limit() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(10));
+
+    // Without a limit, any funded amount is accepted.
+    let order = init_transfer_order(sender, &sender_key, recipient, Amount::from(10));
+    let mut unlimited_state = init_state_with_account(sender, Balance::from(10));
+    assert!(unlimited_state.handle_transfer_order(order).is_ok());
+
+    authority_state.max_transfer_amount = Some(Amount::from(5));
+    let order = init_transfer_order(sender, &sender_key, recipient, Amount::from(6));
+    assert_eq!(
+        authority_state.handle_transfer_order(order),
+        Err(FastPayError::TransferExceedsLimit {
+            limit: Amount::from(5),
+            requested: Amount::from(6)
+        })
+    );
+    let account = authority_state.accounts.get(&sender).unwrap();
+    assert!(account.pending_confirmation.is_none());
+    assert_eq!(account.balance, Balance::from(10));
+
+    let order = init_transfer_order(sender, &sender_key, recipient, Amount::from(5));
+    let info = authority_state.handle_transfer_order(order).unwrap();
+    assert!(info.pending_confirmation.is_some());
+
+    // Certificates above the limit are still confirmed.
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        recipient,
+        Amount::from(6),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+}
+
+#[test]
+fn test_handle_confirmation_order_logs_large_transfer() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(10));
+    authority_state.large_transfer_threshold = Some(Amount::from(5));
+    start_capturing_logs();
+
+    let certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order))
+        .unwrap();
+    assert!(captured_logs().is_empty());
+
+    let certified_transfer_order = init_certified_transfer_order_with_sequence_number(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(6),
+        SequenceNumber::from(1),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order))
+        .unwrap();
+    // Addresses are redacted by default.
+    assert_eq!(
+        captured_logs(),
+        vec![format!(
+            "WARN large transfer: 0.000006 from {}.. to FastPay({}..)",
+            &encode_address(&sender)[..REDACTED_ADDRESS_LENGTH],
+            &encode_address(&recipient)[..REDACTED_ADDRESS_LENGTH]
+        )]
+    );
+
+    authority_state.log_detail = LogDetail::Full;
+    start_capturing_logs();
+    let certified_transfer_order = init_certified_transfer_order_with_sequence_number(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(7),
+        SequenceNumber::from(2),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order))
+        .unwrap();
+    assert_eq!(
+        captured_logs(),
+        vec![format!(
+            "WARN large transfer: 0.000007 from {} to FastPay({})",
+            encode_address(&sender),
+            encode_address(&recipient)
+        )]
+    );
+}
+
+#[test]
+fn test_handle_list_accounts_request_pages() {
+    let addresses: Vec<_> = (0..5).map(|_| get_key_pair().0).collect();
+    let authority_state =
+        init_state_with_accounts(addresses.iter().map(|address| (*address, Balance::from(1))));
+
+    let first = authority_state
+        .handle_list_accounts_request(ListAccountsRequest {
+            after: None,
+            limit: 3,
+        })
+        .unwrap();
+    assert_eq!(first.accounts.len(), 3);
+    assert_eq!(first.next_cursor, first.accounts.last().cloned());
+    let second = authority_state
+        .handle_list_accounts_request(ListAccountsRequest {
+            after: first.next_cursor,
+            limit: 3,
+        })
+        .unwrap();
+    assert_eq!(second.accounts.len(), 2);
+    assert_eq!(second.next_cursor, None);
+
+    let mut listed = first.accounts;
+    listed.extend(second.accounts);
+    let mut expected = addresses;
+    expected.sort();
+    assert_eq!(listed, expected);
+}
+
+#[test]
+fn test_handle_list_accounts_request_limit() {
+    let authority_state = init_state_with_account(dbg_addr(1), Balance::from(1));
+    for limit in &[0, MAX_LIST_ACCOUNTS_LIMIT + 1] {
+        assert_eq!(
+            authority_state.handle_list_accounts_request(ListAccountsRequest {
+                after: None,
+                limit: *limit,
+            }),
+            Err(FastPayError::InvalidListLimit {
+                max: MAX_LIST_ACCOUNTS_LIMIT
+            })
+        );
+    }
+    let response = authority_state
+        .handle_list_accounts_request(ListAccountsRequest {
+            after: None,
+            limit: MAX_LIST_ACCOUNTS_LIMIT,
+        })
+        .unwrap();
+    assert_eq!(response.accounts, vec![dbg_addr(1)]);
+    assert_eq!(response.next_cursor, None);
+}
+
+#[test]
+fn test_handle_transfer_order_non_canonical_signature() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let mut transfer_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(5));
+    transfer_order.signature = dbg_non_canonical(&transfer_order.signature);
+
+    assert_eq!(
+        authority_state.handle_transfer_order(transfer_order.clone()),
+        Err(FastPayError::NonCanonicalEncoding)
+    );
+    authority_state.reject_non_canonical_encodings = false;
+    assert!(matches!(
+        authority_state.handle_transfer_order(transfer_order),
+        Err(FastPayError::InvalidSignature { .. })
+    ));
+}
+
+#[test]
+fn test_handle_transfer_order_non_canonical_recipient() {
+    let (sender, sender_key) = get_key_pair();
+    let mut recipient = [0xff; 32];
+    recipient[0] = 0xee;
+    recipient[31] = 0x7f;
+    let recipient = Address::FastPay(PublicKeyBytes(recipient));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let transfer_order = init_transfer_order(sender, &sender_key, recipient, Amount::from(5));
+
+    assert_eq!(
+        authority_state.handle_transfer_order(transfer_order.clone()),
+        Err(FastPayError::NonCanonicalEncoding)
+    );
+    authority_state.reject_non_canonical_encodings = false;
+    assert!(authority_state
+        .handle_transfer_order(transfer_order)
+        .is_ok());
+}
+
+#[test]
+fn test_handle_confirmation_order_non_canonical_signature() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let mut certified_transfer_order = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(5),
+        &authority_state,
+    );
+    let signature = &mut certified_transfer_order.signatures[0].1;
+    *signature = dbg_non_canonical(signature);
+
+    assert_eq!(
+        authority_state
+            .handle_confirmation_order(ConfirmationOrder::new(certified_transfer_order))
+            .map(|_| ()),
+        Err(FastPayError::NonCanonicalEncoding)
+    );
+}
+
+#[test]
+fn test_account_info_history_proof() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(10));
+    let mut certificates = Vec::new();
+    let mut roots = Vec::new();
+    for i in 0..3 {
+        let certificate = init_certified_transfer_order_with_sequence_number(
+            sender,
+            &sender_key,
+            recipient,
+            Amount::from(1),
+            SequenceNumber::from(i),
+            &authority_state,
+        );
+        let (info, _) = authority_state
+            .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+            .unwrap();
+        assert!(!roots.contains(&info.history_root));
+        roots.push(info.history_root);
+        certificates.push(certificate);
+    }
+
+    let info = authority_state
+        .handle_account_info_request(AccountInfoRequest {
+            sender,
+            request_sequence_number: Some(SequenceNumber::from(1)),
+            request_received_transfers_excluding_first_nth: None,
+            observer: false,
+        })
+        .unwrap();
+    assert_eq!(info.history_root, roots[2]);
+    let proof = info.requested_certificate_proof.unwrap();
+    let leaf = leaf_hash(&certificates[1].value.transfer);
+    assert!(proof.verify(&leaf, &info.history_root));
+    assert!(!proof.verify(&leaf, &roots[1]));
+    assert!(!proof.verify(
+        &leaf_hash(&certificates[0].value.transfer),
+        &info.history_root
+    ));
+}
+
+#[test]
+fn test_handle_primary_synchronization_order_update() {
+    let mut state = init_state();
+    let mut updated_transaction_index = state.last_transaction_index;
+    let address = dbg_addr(1);
+    let order = init_primary_synchronization_order(address);
+
+    assert!(state
+        .handle_primary_synchronization_order(order.clone())
+        .is_ok());
+    updated_transaction_index = updated_transaction_index.increment().unwrap();
+    assert_eq!(state.last_transaction_index, updated_transaction_index);
+    let account = state.accounts.get(&address).unwrap();
+    assert_eq!(account.balance, order.amount.into());
+    assert_eq!(state.accounts.len(), 1);
+}
+
+#[test]
+fn test_handle_primary_synchronization_order_double_spend() {
+    let mut state = init_state();
+    let mut updated_transaction_index = state.last_transaction_index;
+    let address = dbg_addr(1);
+    let order = init_primary_synchronization_order(address);
+
+    assert!(state
+        .handle_primary_synchronization_order(order.clone())
+        .is_ok());
+    updated_transaction_index = updated_transaction_index.increment().unwrap();
+    // Replays are ignored.
+    assert!(state
+        .handle_primary_synchronization_order(order.clone())
+        .is_ok());
+    assert_eq!(state.last_transaction_index, updated_transaction_index);
+    let account = state.accounts.get(&address).unwrap();
+    assert_eq!(account.balance, order.amount.into());
+    assert_eq!(state.accounts.len(), 1);
+}
+
+#[test]
+fn test_account_state_ok() {
+    let sender = dbg_addr(1);
+    let authority_state = init_state_with_account(sender, Balance::from(5));
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap(),
+        authority_state.account_state(&sender).unwrap()
+    );
+}
+
+#[test]
+fn test_account_state_unknown_account() {
+    let sender = dbg_addr(1);
+    let unknown_address = dbg_addr(99);
+    let authority_state = init_state_with_account(sender, Balance::from(5));
+    assert!(authority_state.account_state(&unknown_address).is_err());
+}
+
+#[test]
+fn test_get_shards() {
+    let num_shards = 16u32;
+    let mut found = vec![false; num_shards as usize];
+    let mut left = num_shards;
+    loop {
+        let (address, _) = get_key_pair();
+        let shard = AuthorityState::get_shard(num_shards, &address) as usize;
+        println!("found {}", shard);
+        if !found[shard] {
+            found[shard] = true;
+            left -= 1;
+            if left == 0 {
+                break;
+            }
+        }
+    }
+}
+
+#[test]
+fn test_last_activity() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let clock = Arc::new(ManualClock::new(1000));
+    authority_state.clock = clock.clone();
+    let info = authority_state
+        .handle_account_info_request(AccountInfoRequest {
+            sender,
+            request_sequence_number: None,
+            request_received_transfers_excluding_first_nth: None,
+            observer: false,
+        })
+        .unwrap();
+    assert_eq!(info.last_activity, None);
+
+    // Votes are not activity.
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(1),
+        &authority_state,
+    );
+    authority_state
+        .handle_transfer_order(certificate.value.clone())
+        .unwrap();
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().last_activity,
+        None
+    );
+
+    clock.advance(500);
+    let (info, _) = authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+    assert_eq!(info.last_activity, Some(1500));
+    assert_eq!(
+        authority_state
+            .accounts
+            .get(&recipient)
+            .unwrap()
+            .last_activity,
+        Some(1500)
+    );
+
+    // Replays do not count.
+    clock.advance(500);
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().last_activity,
+        Some(1500)
+    );
+    assert_eq!(
+        authority_state
+            .accounts
+            .get(&recipient)
+            .unwrap()
+            .last_activity,
+        Some(1500)
+    );
+}
+
+#[test]
+fn test_transfer_cooldown() {
+    let (sender, sender_key) = get_key_pair();
+    let (payer, payer_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state =
+        init_state_with_accounts(vec![(sender, Balance::from(5)), (payer, Balance::from(5))]);
+    authority_state.committee.transfer_cooldown_ms = Some(1000);
+    let clock = Arc::new(ManualClock::new(1000));
+    authority_state.clock = clock.clone();
+
+    // The first transfer of an account is not limited.
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(1),
+        &authority_state,
+    );
+    authority_state
+        .handle_transfer_order(certificate.value.clone())
+        .unwrap();
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+
+    // A transfer within the cooldown is rejected with the remaining time.
+    clock.advance(400);
+    let order = init_transfer_order_with_sequence_number(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(1),
+        SequenceNumber::from(1),
+    );
+    assert_eq!(
+        authority_state.handle_transfer_order(order.clone()),
+        Err(FastPayError::TransferCooldown {
+            retry_after_ms: 600
+        })
+    );
+
+    // Credits are still accepted, and do not extend the cooldown.
+    clock.advance(100);
+    let credit = init_certified_transfer_order(
+        payer,
+        &payer_key,
+        Address::FastPay(sender),
+        Amount::from(2),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(credit))
+        .unwrap();
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().balance,
+        Balance::from(6)
+    );
+
+    // Once the cooldown has passed, the transfer is signed.
+    clock.advance(500);
+    let info = authority_state.handle_transfer_order(order).unwrap();
+    assert!(info.pending_confirmation.is_some());
+}
+
+#[test]
+fn test_strictly_increasing_transfer_times() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    authority_state.committee.strictly_increasing_transfer_times = true;
+    let clock = Arc::new(ManualClock::new(1000));
+    authority_state.clock = clock.clone();
+
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        recipient,
+        Amount::from(1),
+        &authority_state,
+    );
+    authority_state
+        .handle_transfer_order(certificate.value.clone())
+        .unwrap();
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+
+    // An order at the time of the last transfer is rejected.
+    let certificate = init_certified_transfer_order_with_sequence_number(
+        sender,
+        &sender_key,
+        recipient,
+        Amount::from(1),
+        SequenceNumber::from(1),
+        &authority_state,
+    );
+    assert_eq!(
+        authority_state.handle_transfer_order(certificate.value.clone()),
+        Err(FastPayError::NonMonotonicTimestamps {
+            last_transfer: 1000
+        })
+    );
+
+    // Later orders are accepted.
+    clock.advance(1);
+    authority_state
+        .handle_transfer_order(certificate.value.clone())
+        .unwrap();
+
+    // When the clock goes back before the confirmation, the time of the previous
+    // transfer is kept and the confirmation still succeeds.
+    clock.set(500);
+    start_capturing_logs();
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+    let account = authority_state.accounts.get(&sender).unwrap();
+    assert_eq!(account.last_transfer, Some(1000));
+    assert_eq!(account.next_sequence_number, SequenceNumber::from(2));
+    assert_eq!(
+        captured_logs(),
+        vec!["WARN clock went back to 500 since the last transfer at 1000".to_string()]
+    );
+}
+
+#[test]
+fn test_transfer_times_are_not_checked_by_default() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let clock = Arc::new(ManualClock::new(1000));
+    authority_state.clock = clock.clone();
+
+    for sequence_number in 0..2 {
+        let certificate = init_certified_transfer_order_with_sequence_number(
+            sender,
+            &sender_key,
+            recipient,
+            Amount::from(1),
+            SequenceNumber::from(sequence_number),
+            &authority_state,
+        );
+        authority_state
+            .handle_transfer_order(certificate.value.clone())
+            .unwrap();
+        authority_state
+            .handle_confirmation_order(ConfirmationOrder::new(certificate))
+            .unwrap();
+    }
+    let account = authority_state.accounts.get(&sender).unwrap();
+    assert_eq!(account.next_sequence_number, SequenceNumber::from(2));
+    assert_eq!(account.last_transfer, Some(1000));
+}
+
+#[test]
+fn test_memory_pressure_rejects_new_accounts() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_accounts(vec![
+        (sender, Balance::from(5)),
+        (recipient, Balance::from(0)),
+    ]);
+    // Simulate memory pressure: the existing accounts use up the limit.
+    authority_state.memory_limit = Some(2 * ESTIMATED_ACCOUNT_SIZE);
+    assert!(authority_state.is_under_memory_pressure());
+
+    // Transfers to new accounts are rejected.
+    let order = init_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(dbg_addr(3)),
+        Amount::from(1),
+    );
+    assert_eq!(
+        authority_state.handle_transfer_order(order),
+        Err(FastPayError::MemoryPressure)
+    );
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(dbg_addr(3)),
+        Amount::from(1),
+        &authority_state,
+    );
+    assert_eq!(
+        authority_state.handle_confirmation_order(ConfirmationOrder::new(certificate)),
+        Err(FastPayError::MemoryPressure)
+    );
+    assert_eq!(
+        authority_state
+            .handle_primary_synchronization_order(init_primary_synchronization_order(dbg_addr(4))),
+        Err(FastPayError::MemoryPressure)
+    );
+    assert_eq!(authority_state.accounts.len(), 2);
+
+    // Transfers between existing accounts still go through.
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(1),
+        &authority_state,
+    );
+    authority_state
+        .handle_transfer_order(certificate.value.clone())
+        .unwrap();
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+    assert_eq!(
+        authority_state.accounts.get(&recipient).unwrap().balance,
+        Balance::from(1)
+    );
+
+    authority_state.memory_limit = Some(3 * ESTIMATED_ACCOUNT_SIZE);
+    assert!(!authority_state.is_under_memory_pressure());
+}
+
+#[test]
+fn test_handle_sequence_number_request() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        recipient,
+        Amount::from(1),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+    let request = |sequence_number: u64| SequenceNumberRequest {
+        sender,
+        sequence_number: SequenceNumber::from(sequence_number),
+    };
+
+    // The existing transfer is attested by a quorum (of one).
+    let statement = authority_state
+        .handle_sequence_number_request(request(0))
+        .unwrap();
+    let proof = SequenceNumberProof {
+        statements: vec![statement],
+    };
+    assert_eq!(
+        proof.check(&authority_state.committee).unwrap(),
+        &SequenceNumberStatus::Confirmed(leaf_hash(&certificate.value.transfer))
+    );
+
+    // So is the absence of a transfer at the next sequence number.
+    let statement = authority_state
+        .handle_sequence_number_request(request(1))
+        .unwrap();
+    let proof = SequenceNumberProof {
+        statements: vec![statement],
+    };
+    assert_eq!(
+        proof.check(&authority_state.committee).unwrap(),
+        &SequenceNumberStatus::Unused
+    );
+
+    // Once voted, the sequence number is no longer unused.
+    let order = init_transfer_order_with_sequence_number(
+        sender,
+        &sender_key,
+        recipient,
+        Amount::from(1),
+        SequenceNumber::from(1),
+    );
+    authority_state
+        .handle_transfer_order(order.clone())
+        .unwrap();
+    let statement = authority_state
+        .handle_sequence_number_request(request(1))
+        .unwrap();
+    assert_eq!(
+        statement.status,
+        SequenceNumberStatus::Voted(leaf_hash(&order.transfer))
+    );
+
+    // Accounts that do not exist have not spent anything.
+    let statement = authority_state
+        .handle_sequence_number_request(SequenceNumberRequest {
+            sender: dbg_addr(3),
+            sequence_number: SequenceNumber::new(),
+        })
+        .unwrap();
+    assert_eq!(statement.status, SequenceNumberStatus::Unused);
+}
+
+// helpers
+
+#[test]
+fn test_handle_ownership_order() {
+    let (sender, sender_key) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let signers: Vec<_> = (0..3).map(|_| get_key_pair()).collect();
+    let change = OwnershipChange {
+        account: sender,
+        ownership: Ownership::Multisig {
+            threshold: 2,
+            signers: signers.iter().map(|(address, _)| *address).collect(),
+        },
+        version: VersionNumber::new(),
+        domain: SigningDomain::default(),
+    };
+
+    // Only the current owner may change the ownership.
+    let order = OwnershipOrder::new(change.clone(), &[&signers[0].1]);
+    assert_eq!(
+        authority_state.handle_ownership_order(order),
+        Err(FastPayError::UnknownOwner)
+    );
+    let mut invalid_change = change.clone();
+    invalid_change.ownership = Ownership::Multisig {
+        threshold: 2,
+        signers: vec![signers[0].0, signers[0].0],
+    };
+    let order = OwnershipOrder::new(invalid_change, &[&sender_key]);
+    assert_eq!(
+        authority_state.handle_ownership_order(order),
+        Err(FastPayError::InvalidOwnership)
+    );
+
+    let order = OwnershipOrder::new(change.clone(), &[&sender_key]);
+    authority_state
+        .handle_ownership_order(order.clone())
+        .unwrap();
+    let account = authority_state.accounts.get(&sender).unwrap();
+    assert_eq!(account.ownership, Some(change.ownership.clone()));
+    assert_eq!(account.ownership_version, SequenceNumber::from(1));
+    // Replaying the order changes nothing.
+    authority_state.handle_ownership_order(order).unwrap();
+    assert_eq!(
+        authority_state
+            .accounts
+            .get(&sender)
+            .unwrap()
+            .ownership_version,
+        SequenceNumber::from(1)
+    );
+
+    // The key of the address no longer owns the account.
+    let change_back = OwnershipChange {
+        account: sender,
+        ownership: Ownership::Single(sender),
+        version: SequenceNumber::from(1),
+        domain: SigningDomain::default(),
+    };
+    let order = OwnershipOrder::new(change_back.clone(), &[&sender_key]);
+    assert_eq!(
+        authority_state.handle_ownership_order(order),
+        Err(FastPayError::UnknownOwner)
+    );
+    let order = OwnershipOrder::new(change_back, &[&signers[1].1, &signers[2].1]);
+    authority_state.handle_ownership_order(order).unwrap();
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().ownership,
+        Some(Ownership::Single(sender))
+    );
+}
+
+#[test]
+fn test_handle_ownership_order_signed_for_another_chain() {
+    let (sender, sender_key) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let mainnet = SigningDomain {
+        chain_id: 1,
+        genesis_timestamp: 1000,
+    };
+    let testnet = SigningDomain {
+        chain_id: 2,
+        genesis_timestamp: 1000,
+    };
+    authority_state.committee.domain = mainnet;
+    let (owner, _) = get_key_pair();
+    let change = OwnershipChange {
+        account: sender,
+        ownership: Ownership::Single(owner),
+        version: VersionNumber::new(),
+        domain: testnet,
+    };
+
+    let order = OwnershipOrder::new(change.clone(), &[&sender_key]);
+    assert_eq!(
+        authority_state.handle_ownership_order(order.clone()),
+        Err(FastPayError::WrongSigningDomain {
+            expected: mainnet,
+            received: testnet,
+        })
+    );
+    // The domain is signed, so the order cannot be replayed on the mainnet.
+    let mut replayed = order;
+    replayed.change.domain = mainnet;
+    assert!(matches!(
+        authority_state.handle_ownership_order(replayed),
+        Err(FastPayError::InvalidSignature { .. })
+    ));
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().ownership,
+        None
+    );
+
+    let order = OwnershipOrder::new(
+        OwnershipChange {
+            domain: mainnet,
+            ..change
+        },
+        &[&sender_key],
+    );
+    authority_state.handle_ownership_order(order).unwrap();
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().ownership,
+        Some(Ownership::Single(owner))
+    );
+}
+
+#[test]
+fn test_handle_transfer_order_multisig() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let signers: Vec<_> = (0..3).map(|_| get_key_pair()).collect();
+    authority_state.accounts.get_mut(&sender).unwrap().ownership = Some(Ownership::Multisig {
+        threshold: 2,
+        signers: signers.iter().map(|(address, _)| *address).collect(),
+    });
+    let transfer = init_transfer_order(sender, &sender_key, recipient, Amount::from(5)).transfer;
+
+    let order = TransferOrder::new(transfer.clone(), &sender_key);
+    assert_eq!(
+        authority_state.handle_transfer_order(order),
+        Err(FastPayError::UnknownOwner)
+    );
+    let order = TransferOrder::new_co_signed(transfer.clone(), &[&signers[0].1]);
+    assert_eq!(
+        authority_state.handle_transfer_order(order),
+        Err(FastPayError::InsufficientOwnerSignatures { threshold: 2 })
+    );
+    let order = TransferOrder::new_co_signed(transfer.clone(), &[&signers[0].1, &signers[0].1]);
+    assert_eq!(
+        authority_state.handle_transfer_order(order),
+        Err(FastPayError::OwnerSignatureReuse)
+    );
+    let mut order = TransferOrder::new_co_signed(transfer.clone(), &[&signers[0].1]);
+    order
+        .co_signatures
+        .push((signers[1].0, Signature::new(&transfer, &signers[0].1)));
+    assert!(authority_state.handle_transfer_order(order).is_err());
+    assert!(authority_state
+        .accounts
+        .get(&sender)
+        .unwrap()
+        .pending_confirmation
+        .is_none());
+
+    let order = TransferOrder::new_co_signed(transfer, &[&signers[2].1, &signers[0].1]);
+    let info = authority_state
+        .handle_transfer_order(order.clone())
+        .unwrap();
+    let vote = info.pending_confirmation.unwrap();
+    let mut builder = SignatureAggregator::try_new(order, &authority_state.committee).unwrap();
+    let certificate = builder
+        .append(vote.authority, vote.signature)
+        .unwrap()
+        .unwrap();
+    certificate.check(&authority_state.committee).unwrap();
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().balance,
+        Balance::from(0)
+    );
+}
+
+#[cfg(test)]
+fn init_state() -> AuthorityState {
+    let (authority_address, authority_key) = get_key_pair();
+    let mut authorities = BTreeMap::new();
+    authorities.insert(
+        /* address */ authority_address,
+        /* voting right */ 1,
+    );
+    let mut committee = Committee::new(authorities);
+    committee.features.insert(Feature::RecipientClaims);
+    AuthorityState::new(committee, authority_address, authority_key)
+}
+
+#[cfg(test)]
+fn init_state_with_accounts<I: IntoIterator<Item = (FastPayAddress, Balance)>>(
+    balances: I,
 ) -> AuthorityState {
     let mut state = init_state();
     for (address, balance) in balances {
@@ -465,19 +2073,65 @@ fn init_state_with_account(address: FastPayAddress, balance: Balance) -> Authori
     init_state_with_accounts(std::iter::once((address, balance)))
 }
 
+/// The update sent to the shard of the recipient of a certificate by the shard of its
+/// sender, out of the given number of shards.
+#[cfg(test)]
+fn cross_shard_update(certificate: CertifiedTransferOrder, num_shards: u32) -> CrossShardUpdate {
+    let transfer = &certificate.value.transfer;
+    let recipient = match &transfer.recipient {
+        Address::FastPay(recipient) => recipient,
+        Address::Primary(_) => panic!("cross-shard updates credit FastPay accounts"),
+    };
+    CrossShardUpdate {
+        shard_id: AuthorityState::get_shard(num_shards, recipient),
+        source_shard_id: AuthorityState::get_shard(num_shards, &transfer.sender),
+        transfer_certificate: certificate,
+    }
+}
+
+/// Key pair of an account handled by the given shard, out of two.
+#[cfg(test)]
+fn get_key_pair_in_shard(shard: ShardId) -> (FastPayAddress, KeyPair) {
+    loop {
+        let (address, key) = get_key_pair();
+        if AuthorityState::get_shard(2, &address) == shard {
+            return (address, key);
+        }
+    }
+}
+
 #[cfg(test)]
 fn init_transfer_order(
     sender: FastPayAddress,
     secret: &KeyPair,
     recipient: Address,
     amount: Amount,
+) -> TransferOrder {
+    init_transfer_order_with_sequence_number(
+        sender,
+        secret,
+        recipient,
+        amount,
+        SequenceNumber::new(),
+    )
+}
+
+#[cfg(test)]
+fn init_transfer_order_with_sequence_number(
+    sender: FastPayAddress,
+    secret: &KeyPair,
+    recipient: Address,
+    amount: Amount,
+    sequence_number: SequenceNumber,
 ) -> TransferOrder {
     let transfer = Transfer {
         sender,
         recipient,
         amount,
-        sequence_number: SequenceNumber::new(),
+        sequence_number,
         user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
     };
     TransferOrder::new(transfer, secret)
 }
@@ -490,7 +2144,32 @@ fn init_certified_transfer_order(
     amount: Amount,
     authority_state: &AuthorityState,
 ) -> CertifiedTransferOrder {
-    let transfer_order = init_transfer_order(sender, secret, recipient, amount);
+    init_certified_transfer_order_with_sequence_number(
+        sender,
+        secret,
+        recipient,
+        amount,
+        SequenceNumber::new(),
+        authority_state,
+    )
+}
+
+#[cfg(test)]
+fn init_certified_transfer_order_with_sequence_number(
+    sender: FastPayAddress,
+    secret: &KeyPair,
+    recipient: Address,
+    amount: Amount,
+    sequence_number: SequenceNumber,
+    authority_state: &AuthorityState,
+) -> CertifiedTransferOrder {
+    let transfer_order = init_transfer_order_with_sequence_number(
+        sender,
+        secret,
+        recipient,
+        amount,
+        sequence_number,
+    );
     let vote = SignedTransferOrder::new(
         transfer_order.clone(),
         authority_state.name,
@@ -514,3 +2193,229 @@ fn init_primary_synchronization_order(recipient: FastPayAddress) -> PrimarySynch
         transaction_index,
     }
 }
+
+thread_local! {
+    static CAPTURED_LOGS: RefCell<Option<Vec<String>>> = RefCell::new(None);
+}
+
+/// Logger recording the messages emitted by the current test thread, if requested.
+struct CapturingLogger;
+
+impl log::Log for CapturingLogger {
+    fn enabled(&self, metadata: &log::Metadata) -> bool {
+        metadata.level() <= log::Level::Warn
+    }
+
+    fn log(&self, record: &log::Record) {
+        if self.enabled(record.metadata()) {
+            CAPTURED_LOGS.with(|logs| {
+                if let Some(logs) = logs.borrow_mut().as_mut() {
+                    logs.push(format!("{} {}", record.level(), record.args()));
+                }
+            });
+        }
+    }
+
+    fn flush(&self) {}
+}
+
+static LOGGER: CapturingLogger = CapturingLogger;
+
+#[cfg(test)]
+fn start_capturing_logs() {
+    // Another test may have installed the logger already.
+    let _ = log::set_logger(&LOGGER);
+    log::set_max_level(log::LevelFilter::Warn);
+    CAPTURED_LOGS.with(|logs| *logs.borrow_mut() = Some(Vec::new()));
+}
+
+#[cfg(test)]
+fn captured_logs() -> Vec<String> {
+    CAPTURED_LOGS.with(|logs| logs.borrow().clone().unwrap_or_default())
+}
+
+#[test]
+fn test_verification_cache() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let clock = Arc::new(ManualClock::new(1000));
+    authority_state.clock = clock.clone();
+    authority_state.verification_cache = Some(VerificationCache::new(10, Some(1000)));
+    // Make replays go through the verification of certificates.
+    authority_state.reuse_confirmed_certificates = false;
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        Address::FastPay(recipient),
+        Amount::from(1),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+    assert_eq!(
+        authority_state.verification_cache.as_ref().unwrap().len(),
+        1
+    );
+
+    // Without the cache, the certificate would now be rejected.
+    authority_state.committee = Committee::new(BTreeMap::new());
+    clock.advance(1000);
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+
+    // Expired entries are verified again.
+    clock.advance(1);
+    assert!(authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .is_err());
+    assert!(authority_state.verification_cache.unwrap().is_empty());
+}
+
+#[test]
+fn test_handle_confirmation_order_replays() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        recipient,
+        Amount::from(1),
+        &authority_state,
+    );
+    for _ in 0..3 {
+        let (info, _) = authority_state
+            .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+            .unwrap();
+        assert_eq!(info.balance, Balance::from(4));
+        assert_eq!(info.next_sequence_number, SequenceNumber::from(1));
+    }
+
+    // Replays are compared to the confirmed certificate instead of being verified.
+    let committee = authority_state.committee.clone();
+    authority_state.committee = Committee::new(BTreeMap::new());
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+    authority_state.reuse_confirmed_certificates = false;
+    assert!(authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .is_err());
+    authority_state.committee = committee;
+
+    // A different transfer with the same sequence number is rejected either way.
+    let conflicting = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        recipient,
+        Amount::from(2),
+        &authority_state,
+    );
+    for reuse in &[false, true] {
+        authority_state.reuse_confirmed_certificates = *reuse;
+        assert_eq!(
+            authority_state.handle_confirmation_order(ConfirmationOrder::new(conflicting.clone())),
+            Err(FastPayError::ConflictingConfirmation {
+                sequence_number: SequenceNumber::from(0)
+            })
+        );
+    }
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().balance,
+        Balance::from(4)
+    );
+}
+
+#[test]
+fn test_handle_confirmation_order_replays_with_tampered_signatures() {
+    let (sender, sender_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let certificate = init_certified_transfer_order(
+        sender,
+        &sender_key,
+        recipient,
+        Amount::from(1),
+        &authority_state,
+    );
+    authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+        .unwrap();
+
+    // Same value and signers, but signatures made with another key.
+    let mut tampered = certificate.clone();
+    let (_, other_key) = get_key_pair();
+    for (_, signature) in &mut tampered.signatures {
+        *signature = Signature::new(&certificate.value.transfer, &other_key);
+    }
+    assert_eq!(tampered, certificate);
+    assert!(authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(tampered))
+        .is_err());
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().balance,
+        Balance::from(4)
+    );
+}
+
+/// A transfer order and a certificate for it, with signatures from the wrong keys.
+fn forged_transfer(
+    sender: FastPayAddress,
+    sender_key: &KeyPair,
+    authority_state: &AuthorityState,
+) -> (TransferOrder, CertifiedTransferOrder) {
+    let (_, forger_key) = get_key_pair();
+    let recipient = Address::FastPay(dbg_addr(2));
+    let mut order = init_transfer_order(sender, sender_key, recipient, Amount::from(5));
+    let mut certificate = init_certified_transfer_order(
+        sender,
+        sender_key,
+        recipient,
+        Amount::from(5),
+        authority_state,
+    );
+    order.signature = Signature::new(&order.transfer, &forger_key);
+    for (_, signature) in &mut certificate.signatures {
+        *signature = Signature::new(&certificate.value.transfer, &forger_key);
+    }
+    (order, certificate)
+}
+
+#[cfg(not(feature = "unchecked-signatures"))]
+#[test]
+fn test_signature_checks_are_not_skipped_by_default() {
+    let (sender, sender_key) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let (order, certificate) = forged_transfer(sender, &sender_key, &authority_state);
+    assert!(authority_state.handle_transfer_order(order).is_err());
+    assert!(authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .is_err());
+    assert_eq!(
+        authority_state.accounts.get(&sender).unwrap().balance,
+        Balance::from(5)
+    );
+}
+
+#[cfg(feature = "unchecked-signatures")]
+#[test]
+fn test_skip_signature_checks() {
+    let (sender, sender_key) = get_key_pair();
+    let mut authority_state = init_state_with_account(sender, Balance::from(5));
+    let (order, certificate) = forged_transfer(sender, &sender_key, &authority_state);
+    assert!(authority_state
+        .handle_transfer_order(order.clone())
+        .is_err());
+
+    authority_state.skip_signature_checks = true;
+    let info = authority_state.handle_transfer_order(order).unwrap();
+    assert!(info.pending_confirmation.is_some());
+    let (info, _) = authority_state
+        .handle_confirmation_order(ConfirmationOrder::new(certificate))
+        .unwrap();
+    assert_eq!(info.balance, Balance::from(0));
+    assert_eq!(info.next_sequence_number, SequenceNumber::from(1));
+}
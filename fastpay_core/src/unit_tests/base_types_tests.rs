@@ -31,8 +31,571 @@ fn test_signatures() {
     assert!(s.check(&bar, addr1).is_err());
 }
 
+#[test]
+fn test_signatures_are_domain_separated() {
+    let (addr, sec) = get_key_pair();
+    let foo = Foo("hello".into());
+    let bar = Bar("hello".into());
+    // Both values have the same BCS bytes: only the name of their type tells them apart.
+    assert_eq!(bcs::to_bytes(&foo).unwrap(), bcs::to_bytes(&bar).unwrap());
+    assert_ne!(signable_name_prefix::<Foo>(), signable_name_prefix::<Bar>());
+
+    let votes = [(addr, Signature::new(&foo, &sec))];
+    assert!(Signature::verify_batch(&foo, &votes).is_ok());
+    assert!(Signature::verify_batch(&bar, &votes).is_err());
+    let mut message = Vec::new();
+    bar.write(&mut message);
+    assert!(Signature::verify_messages(std::iter::once((&message[..], addr, votes[0].1))).is_err());
+}
+
+#[test]
+fn test_constant_time_equality() {
+    let (_, sec1) = get_key_pair();
+    let (_, sec2) = get_key_pair();
+    let foo = Foo("hello".into());
+    let s1 = Signature::new(&foo, &sec1);
+    assert!(s1 == Signature::new(&foo, &sec1.copy()));
+    assert!(s1 != Signature::new(&foo, &sec2));
+    assert!(s1 != Signature::new(&Foo("hellox".into()), &sec1));
+    // Signatures that share their first half.
+    assert!(s1 != dbg_non_canonical(&s1));
+
+    assert!(sec1.ct_eq(&sec1.copy()));
+    assert!(!sec1.ct_eq(&sec2));
+}
+
+#[cfg(feature = "parallel-verification")]
+#[test]
+fn test_verify_batch_parallel() {
+    let foo = Foo("hello".into());
+    let mut votes: Vec<_> = (0..1000)
+        .map(|_| {
+            let (address, key) = get_key_pair();
+            (address, Signature::new(&foo, &key))
+        })
+        .collect();
+
+    let start = std::time::Instant::now();
+    assert!(Signature::verify_batch(&foo, &votes).is_ok());
+    let sequential_time = start.elapsed();
+    let start = std::time::Instant::now();
+    assert!(Signature::verify_batch_parallel(&foo, &votes).is_ok());
+    let parallel_time = start.elapsed();
+    println!(
+        "Verified 1000 votes in {:?} sequentially and {:?} in parallel",
+        sequential_time, parallel_time
+    );
+    assert!(Signature::verify_batch_parallel(&foo, &votes[..0]).is_ok());
+
+    // A single bad vote in any chunk fails the whole batch.
+    let (_, key) = get_key_pair();
+    votes[700].1 = Signature::new(&foo, &key);
+    assert!(Signature::verify_batch(&foo, &votes).is_err());
+    assert!(matches!(
+        Signature::verify_batch_parallel(&foo, &votes),
+        Err(FastPayError::InvalidSignature { .. })
+    ));
+    assert!(Signature::verify_batch_parallel(&Bar("hello".into()), &votes[..10]).is_err());
+}
+
 #[test]
 fn test_max_sequence_number() {
     let max = SequenceNumber::max();
     assert_eq!(max.0 * 2 + 1, std::u64::MAX);
 }
+
+#[test]
+fn test_sequence_number_range() {
+    let range = |start, end| {
+        SequenceNumber::range(SequenceNumber::from(start), SequenceNumber::from(end))
+            .map(u64::from)
+            .collect::<Vec<_>>()
+    };
+    assert_eq!(range(3, 3), Vec::<u64>::new());
+    assert_eq!(range(4, 3), Vec::<u64>::new());
+    assert_eq!(range(3, 4), vec![3]);
+    assert_eq!(range(0, 3), vec![0, 1, 2]);
+
+    let max = u64::from(SequenceNumber::max());
+    assert_eq!(range(max - 1, u64::MAX), vec![max - 1, max]);
+    assert_eq!(range(max + 1, u64::MAX), Vec::<u64>::new());
+
+    assert_eq!(
+        SequenceNumber::from(3).saturating_increment(),
+        SequenceNumber::from(4)
+    );
+    assert_eq!(
+        SequenceNumber::max().saturating_increment(),
+        SequenceNumber::max()
+    );
+}
+
+#[test]
+fn test_canonical_signatures() {
+    let (addr, sec) = get_key_pair();
+    let foo = Foo("hello".into());
+    let s = Signature::new(&foo, &sec);
+    assert!(s.check_canonical().is_ok());
+    assert!(addr.check_canonical().is_ok());
+
+    let s = dbg_non_canonical(&s);
+    assert_eq!(s.check_canonical(), Err(FastPayError::NonCanonicalEncoding));
+    assert!(s.check(&foo, addr).is_err());
+}
+
+#[test]
+fn test_canonical_public_keys() {
+    // The neutral element is encoded as y = 1 or, non-canonically, as y = p + 1.
+    let mut identity = [0u8; 32];
+    identity[0] = 1;
+    assert!(PublicKeyBytes(identity).check_canonical().is_ok());
+    let mut identity = [0xff; 32];
+    identity[0] = 0xee;
+    identity[31] = 0x7f;
+    assert_eq!(
+        PublicKeyBytes(identity).check_canonical(),
+        Err(FastPayError::NonCanonicalEncoding)
+    );
+}
+
+#[test]
+fn test_encrypted_key_pair() {
+    let (address, key) = get_key_pair();
+    let encrypted = key.encrypt_with_cost("correct horse", 4);
+    let decrypted = encrypted.decrypt("correct horse").unwrap();
+    let foo = Foo("hello".into());
+    assert!(Signature::new(&foo, &decrypted)
+        .check(&foo, address)
+        .is_ok());
+    assert!(encrypted.decrypt("battery staple").is_err());
+}
+
+#[test]
+fn test_decode_address() {
+    let (addr, _) = get_key_pair();
+    assert_eq!(decode_address(&encode_address(&addr)).unwrap(), addr);
+
+    let too_short = base64::encode(&addr.0[..31]);
+    assert!(decode_address(&too_short)
+        .unwrap_err()
+        .to_string()
+        .contains("expected 32 bytes, got 31"));
+    let too_long = base64::encode([&addr.0[..], &[0u8]].concat());
+    assert!(decode_address(&too_long)
+        .unwrap_err()
+        .to_string()
+        .contains("expected 32 bytes, got 33"));
+    assert!(decode_address(&base64::encode([0u8; 16]))
+        .unwrap_err()
+        .to_string()
+        .contains("expected 32 bytes, got 16"));
+    assert!(
+        decode_address(&base64::encode([&addr.0[..], &addr.0[..]].concat()))
+            .unwrap_err()
+            .to_string()
+            .contains("expected 32 bytes, got 64")
+    );
+    assert!(decode_address("not base64!").is_err());
+
+    // y = 2 is not the coordinate of a point of the curve.
+    let mut off_curve = [0u8; 32];
+    off_curve[0] = 2;
+    assert!(decode_address(&base64::encode(off_curve))
+        .unwrap_err()
+        .to_string()
+        .contains("not a point"));
+}
+
+#[test]
+fn test_decode_address_hex() {
+    let (addr, _) = get_key_pair();
+    let encoded = encode_address_hex(&addr);
+    assert_eq!(encoded, encoded.to_lowercase());
+    assert_eq!(decode_address_hex(&encoded).unwrap(), addr);
+    assert_eq!(decode_address_hex(&encoded.to_uppercase()).unwrap(), addr);
+    assert_eq!(decode_address_hex(&format!("0x{}", encoded)).unwrap(), addr);
+    assert_eq!(
+        decode_address_hex(&format!("0X{}", encoded.to_uppercase())).unwrap(),
+        addr
+    );
+
+    assert!(decode_address_hex(&encoded[..62])
+        .unwrap_err()
+        .to_string()
+        .contains("expected 64 digits, got 62"));
+    assert!(decode_address_hex(&format!("0x{}00", encoded))
+        .unwrap_err()
+        .to_string()
+        .contains("expected 64 digits, got 66"));
+    assert!(decode_address_hex(&"g".repeat(64)).is_err());
+    // Both encodings describe the same key.
+    assert_eq!(
+        decode_address_hex(&hex::encode(base64::decode(encode_address(&addr)).unwrap())).unwrap(),
+        addr
+    );
+
+    #[derive(Serialize, Deserialize)]
+    struct Config {
+        #[serde(
+            serialize_with = "address_as_hex",
+            deserialize_with = "address_from_hex"
+        )]
+        key: PublicKeyBytes,
+    }
+    let json = serde_json::to_string(&Config { key: addr }).unwrap();
+    assert_eq!(json, format!("{{\"key\":\"{}\"}}", encoded));
+    assert_eq!(serde_json::from_str::<Config>(&json).unwrap().key, addr);
+}
+
+#[test]
+fn test_amount_multiplication() {
+    let max = Amount::from(u64::MAX);
+    assert_eq!(Amount::from(3).try_mul(4), Ok(Amount::from(12)));
+    assert_eq!(max.try_mul(1), Ok(max));
+    assert_eq!(max.try_mul(2), Err(FastPayError::AmountOverflow));
+    assert_eq!(
+        Amount::from(u64::MAX / 2 + 1).try_mul(2),
+        Err(FastPayError::AmountOverflow)
+    );
+
+    // A 2.5% fee on the largest amount does not overflow in the intermediate product.
+    assert_eq!(max.try_mul_ratio(25, 1000), Ok(Amount::from(u64::MAX / 40)));
+    assert_eq!(Amount::from(999).try_mul_ratio(1, 100), Ok(Amount::from(9)));
+    assert_eq!(max.try_mul_ratio(3, 2), Err(FastPayError::AmountOverflow));
+    assert_eq!(
+        Amount::from(10).try_mul_ratio(1, 0),
+        Err(FastPayError::DivisionByZero)
+    );
+    assert_eq!(
+        Amount::zero().try_mul_ratio(0, 0),
+        Err(FastPayError::DivisionByZero)
+    );
+}
+
+#[test]
+fn test_balance_sub_with_deficit() {
+    let sub = |x: i128, y: i128| Balance::from(x).sub_with_deficit(Balance::from(y));
+    assert_eq!(sub(5, 5), (Balance::zero(), None));
+    assert_eq!(sub(5, 3), (Balance::from(2), None));
+    assert_eq!(sub(0, -5), (Balance::from(5), None));
+    assert_eq!(sub(3, 5), (Balance::from(-2), Some(Amount::from(2))));
+    // The shortfall is counted from the current balance, even when already negative.
+    assert_eq!(sub(-2, 3), (Balance::from(-5), Some(Amount::from(5))));
+    assert_eq!(
+        sub(0, u64::MAX.into()),
+        (
+            Balance::from(-i128::from(u64::MAX)),
+            Some(Amount::from(u64::MAX))
+        )
+    );
+
+    // Near the bounds, the result and the shortfall saturate.
+    assert_eq!(
+        sub(i128::MIN + 1, 1),
+        (Balance::from(i128::MIN), Some(Amount::from(u64::MAX)))
+    );
+    assert_eq!(
+        sub(i128::MIN, 1),
+        (Balance::from(i128::MIN), Some(Amount::from(u64::MAX)))
+    );
+    assert_eq!(
+        sub(i128::MIN, i128::MAX),
+        (Balance::from(i128::MIN), Some(Amount::from(u64::MAX)))
+    );
+    assert_eq!(
+        sub(i128::MIN, i128::MIN + i128::from(u64::MAX)),
+        (
+            Balance::from(-i128::from(u64::MAX)),
+            Some(Amount::from(u64::MAX))
+        )
+    );
+    assert_eq!(sub(i128::MAX, -1), (Balance::max(), None));
+}
+
+#[test]
+fn test_balance_to_amount() {
+    assert_eq!(Balance::from(0).to_amount(), Ok(Amount::zero()));
+    assert_eq!(Balance::from(42).to_amount(), Ok(Amount::from(42)));
+    assert_eq!(
+        Balance::from(i128::from(u64::MAX)).to_amount(),
+        Ok(Amount::from(u64::MAX))
+    );
+    assert_eq!(
+        Balance::from(-1).to_amount(),
+        Err(FastPayError::BalanceUnderflow)
+    );
+    assert_eq!(
+        Balance::from(i128::from(u64::MAX) + 1).to_amount(),
+        Err(FastPayError::AmountOverflow)
+    );
+    assert_eq!(
+        Balance::max().to_amount(),
+        Err(FastPayError::AmountOverflow)
+    );
+    // The `TryFrom` conversion agrees, without the details.
+    assert!(Amount::try_from(Balance::from(-1)).is_err());
+    assert_eq!(Amount::try_from(Balance::from(42)), Ok(Amount::from(42)));
+}
+
+#[test]
+fn test_amount_decimal_notation() {
+    let parse = |src: &str| src.parse::<Amount>();
+    assert_eq!(parse("0").unwrap(), Amount::zero());
+    assert_eq!(parse("0.000001").unwrap(), Amount::from(1));
+    assert_eq!(parse("1000").unwrap(), Amount::from(1_000_000_000));
+    assert_eq!(parse("1.50").unwrap(), Amount::from(1_500_000));
+    assert_eq!(parse("007.5").unwrap(), Amount::from(7_500_000));
+    assert_eq!(
+        parse("18446744073709.551615").unwrap(),
+        Amount::from(u64::MAX)
+    );
+
+    let error = |src: &str| parse(src).unwrap_err().to_string();
+    assert!(error("18446744073709.551616").contains("too large"));
+    assert!(error("18446744073710").contains("too large"));
+    assert!(error("99999999999999999999999999999999999999999").contains("too large"));
+    assert!(error("0.0000001").contains("at most 6 decimal places"));
+    assert!(error("-1").contains("cannot be negative"));
+    for garbage in &[
+        "", ".", "1.", ".5", "+1", " 1", "1 ", "1.5x", "1,5", "1.2.3", "0x10",
+    ] {
+        assert!(error(garbage).contains("expected a decimal number"));
+    }
+
+    assert_eq!(Amount::from(1).to_string(), "0.000001");
+    assert_eq!(Amount::from(1_500_000).to_string(), "1.5");
+    assert_eq!(Amount::from(2_000_000).to_string(), "2");
+    assert_eq!(Amount::zero().to_string(), "0");
+    assert_eq!(Amount::from(u64::MAX).to_string(), "18446744073709.551615");
+    assert_eq!(Balance::from(1_500_000).to_string(), "1.5");
+    assert_eq!(Balance::from(-1).to_string(), "-0.000001");
+    assert_eq!(Balance::from(-2_000_000).to_string(), "-2");
+    assert_eq!(
+        Balance::from(i128::MIN).to_string(),
+        "-170141183460469231731687303715884.105728"
+    );
+
+    // Other precisions.
+    assert_eq!(Amount::parse_decimal("12", 0).unwrap(), Amount::from(12));
+    assert!(Amount::parse_decimal("1.2", 0).is_err());
+    assert_eq!(Amount::parse_decimal("1.25", 2).unwrap(), Amount::from(125));
+    assert_eq!(Amount::from(125).to_decimal_string(2), "1.25");
+    assert_eq!(
+        Amount::from(u64::MAX).to_decimal_string(0),
+        u64::MAX.to_string()
+    );
+}
+
+#[test]
+fn test_amount_and_balance_json_encoding() {
+    let balance = Balance::from(i128::MAX);
+    let json = serde_json::to_string(&balance).unwrap();
+    assert_eq!(json, format!("\"{}\"", i128::MAX));
+    assert_eq!(serde_json::from_str::<Balance>(&json).unwrap(), balance);
+    assert_eq!(serde_json::to_string(&Balance::from(-3)).unwrap(), "\"-3\"");
+    assert_eq!(
+        serde_json::to_string(&Amount::from(u64::MAX)).unwrap(),
+        format!("\"{}\"", u64::MAX)
+    );
+
+    // Numbers written by earlier versions are still accepted.
+    assert_eq!(
+        serde_json::from_str::<Balance>("-3").unwrap(),
+        Balance::from(-3)
+    );
+    assert_eq!(
+        serde_json::from_str::<Amount>("18446744073709551615").unwrap(),
+        Amount::from(u64::MAX)
+    );
+    assert!(serde_json::from_str::<Amount>("-1").is_err());
+    assert!(serde_json::from_str::<Amount>("\"1.5\"").is_err());
+    assert!(serde_json::from_str::<Balance>("1.5").is_err());
+
+    // Binary encodings are unchanged.
+    assert_eq!(
+        bcs::to_bytes(&Amount::from(1)).unwrap(),
+        bcs::to_bytes(&1u64).unwrap()
+    );
+    let bytes = bcs::to_bytes(&balance).unwrap();
+    assert_eq!(bytes, bcs::to_bytes(&i128::MAX).unwrap());
+    assert_eq!(bcs::from_bytes::<Balance>(&bytes).unwrap(), balance);
+}
+
+mod arithmetic_properties {
+    use super::*;
+    use proptest::{
+        prelude::*,
+        test_runner::{Config, RngAlgorithm, TestRng, TestRunner},
+    };
+
+    /// Fixed seed so that failures are reproducible.
+    const SEED: [u8; 32] = [42; 32];
+
+    fn run<S, F>(strategy: S, test: F)
+    where
+        S: Strategy,
+        S::Value: std::fmt::Debug,
+        F: Fn(S::Value) -> Result<(), TestCaseError>,
+    {
+        let config = Config {
+            cases: 1024,
+            ..Config::default()
+        };
+        let mut runner =
+            TestRunner::new_with_rng(config, TestRng::from_seed(RngAlgorithm::ChaCha, &SEED));
+        runner.run(&strategy, test).unwrap();
+    }
+
+    fn amount() -> impl Strategy<Value = Amount> {
+        prop_oneof![
+            Just(0),
+            Just(1),
+            Just(u64::MAX - 1),
+            Just(u64::MAX),
+            any::<u64>(),
+        ]
+        .prop_map(Amount::from)
+    }
+
+    fn balance() -> impl Strategy<Value = Balance> {
+        prop_oneof![
+            Just(0),
+            Just(1),
+            Just(-1),
+            Just(i128::MIN),
+            Just(i128::MAX),
+            Just(i128::from(u64::MAX)),
+            any::<i64>().prop_map(i128::from),
+            any::<i128>(),
+        ]
+        .prop_map(Balance::from)
+    }
+
+    fn sequence_number() -> impl Strategy<Value = SequenceNumber> {
+        prop_oneof![
+            Just(0),
+            Just(u64::from(SequenceNumber::max())),
+            Just(u64::MAX),
+            any::<u64>(),
+        ]
+        .prop_map(SequenceNumber::from)
+    }
+
+    #[test]
+    fn amount_add_sub_round_trip() {
+        run((amount(), amount()), |(x, y)| {
+            let expected = u64::from(x).checked_add(u64::from(y));
+            match x.try_add(y) {
+                Ok(sum) => {
+                    prop_assert_eq!(Some(u64::from(sum)), expected);
+                    prop_assert_eq!(sum.try_sub(y), Ok(x));
+                    prop_assert_eq!(sum.try_sub(x), Ok(y));
+                }
+                Err(error) => {
+                    prop_assert_eq!(expected, None);
+                    prop_assert_eq!(error, FastPayError::AmountOverflow);
+                }
+            }
+            prop_assert_eq!(x.try_add(y), y.try_add(x));
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn amount_sub_underflow() {
+        run((amount(), amount()), |(x, y)| {
+            match x.try_sub(y) {
+                Ok(difference) => prop_assert_eq!(difference.try_add(y), Ok(x)),
+                Err(error) => {
+                    prop_assert!(u64::from(x) < u64::from(y));
+                    prop_assert_eq!(error, FastPayError::AmountUnderflow);
+                }
+            }
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn amount_mul_ratio() {
+        run((amount(), any::<u64>(), 1..=u64::MAX), |(x, n, d)| {
+            let expected = u128::from(u64::from(x)) * u128::from(n) / u128::from(d);
+            match x.try_mul_ratio(n, d) {
+                Ok(result) => prop_assert_eq!(u128::from(u64::from(result)), expected),
+                Err(error) => {
+                    prop_assert!(expected > u128::from(u64::MAX));
+                    prop_assert_eq!(error, FastPayError::AmountOverflow);
+                }
+            }
+            prop_assert_eq!(x.try_mul_ratio(d, d), Ok(x));
+            prop_assert_eq!(x.try_mul_ratio(n, 1), x.try_mul(n));
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn amount_decimal_round_trip() {
+        run((amount(), 0..=20u32), |(x, decimals)| {
+            prop_assert_eq!(x.to_string().parse::<Amount>().unwrap(), x);
+            let decimal = x.to_decimal_string(decimals);
+            prop_assert_eq!(Amount::parse_decimal(&decimal, decimals).unwrap(), x);
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn balance_add_sub_round_trip() {
+        run((balance(), balance()), |(x, y)| {
+            match x.try_add(y) {
+                Ok(sum) => prop_assert_eq!(sum.try_sub(y), Ok(x)),
+                Err(error) => prop_assert_eq!(error, FastPayError::BalanceOverflow),
+            }
+            match x.try_sub(y) {
+                Ok(difference) => prop_assert_eq!(difference.try_add(y), Ok(x)),
+                Err(error) => prop_assert_eq!(error, FastPayError::BalanceUnderflow),
+            }
+            prop_assert_eq!(x.try_add(y), y.try_add(x));
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn balance_amount_conversions() {
+        run(amount(), |x| {
+            let balance = Balance::from(x);
+            prop_assert!(balance >= Balance::zero());
+            prop_assert_eq!(Amount::try_from(balance), Ok(x));
+            Ok(())
+        });
+        run(balance(), |x| {
+            match Amount::try_from(x) {
+                Ok(amount) => prop_assert_eq!(Balance::from(amount), x),
+                Err(_) => prop_assert!(x < Balance::zero() || x > Balance::from(u64::MAX as i128)),
+            }
+            Ok(())
+        });
+    }
+
+    #[test]
+    fn sequence_number_increment_decrement() {
+        run(sequence_number(), |x| {
+            match x.increment() {
+                Ok(next) => {
+                    prop_assert!(next > x);
+                    prop_assert_eq!(next.decrement(), Ok(x));
+                }
+                Err(error) => {
+                    prop_assert_eq!(u64::from(x), u64::MAX);
+                    prop_assert_eq!(error, FastPayError::SequenceOverflow);
+                }
+            }
+            match x.decrement() {
+                Ok(previous) => prop_assert_eq!(previous.increment(), Ok(x)),
+                Err(error) => {
+                    prop_assert_eq!(x, SequenceNumber::new());
+                    prop_assert_eq!(error, FastPayError::SequenceUnderflow);
+                }
+            }
+            Ok(())
+        });
+    }
+}
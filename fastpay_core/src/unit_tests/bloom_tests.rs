@@ -0,0 +1,39 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+
+#[test]
+fn test_no_false_negatives() {
+    let mut filter = BloomFilter::new(1000, 0.01);
+    for i in 0..1000u32 {
+        filter.insert(&i.to_le_bytes());
+    }
+    assert_eq!(filter.len(), 1000);
+    assert!(!filter.is_full());
+    for i in 0..1000u32 {
+        assert!(filter.may_contain(&i.to_le_bytes()));
+    }
+}
+
+#[test]
+fn test_false_positive_rate() {
+    let rate = 0.01;
+    let mut filter = BloomFilter::new(2000, rate);
+    for i in 0..2000u32 {
+        filter.insert(&i.to_le_bytes());
+    }
+    let trials = 100_000u32;
+    let false_positives = (2000..2000 + trials)
+        .filter(|i| filter.may_contain(&i.to_le_bytes()))
+        .count();
+    // Leave some room for statistical noise.
+    assert!((false_positives as f64) < 1.5 * rate * trials as f64);
+}
+
+#[test]
+fn test_empty_filter() {
+    let filter = BloomFilter::new(0, 0.01);
+    assert!(filter.is_empty());
+    assert!(!filter.may_contain(b"anything"));
+}
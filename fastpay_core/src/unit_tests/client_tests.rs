@@ -4,61 +4,20 @@
 
 use super::*;
 use crate::{
-    authority::{AccountOffchainState, Authority, AuthorityState},
+    authority::{AccountOffchainState, AuthorityState},
     base_types::Amount,
+    committee::SigningDomain,
 };
-use futures::lock::Mutex;
 use std::{
     collections::{BTreeMap, HashMap},
-    sync::Arc,
+    time::Duration,
 };
 use tokio::runtime::Runtime;
 
-#[derive(Clone)]
-struct LocalAuthorityClient(Arc<Mutex<AuthorityState>>);
-
-impl AuthorityClient for LocalAuthorityClient {
-    fn handle_transfer_order(
-        &mut self,
-        order: TransferOrder,
-    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
-        let state = self.0.clone();
-        Box::pin(async move { state.lock().await.handle_transfer_order(order) })
-    }
-
-    fn handle_confirmation_order(
-        &mut self,
-        order: ConfirmationOrder,
-    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
-        let state = self.0.clone();
-        Box::pin(async move {
-            state
-                .lock()
-                .await
-                .handle_confirmation_order(order)
-                .map(|(info, _)| info)
-        })
-    }
-
-    fn handle_account_info_request(
-        &mut self,
-        request: AccountInfoRequest,
-    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
-        let state = self.0.clone();
-        Box::pin(async move { state.lock().await.handle_account_info_request(request) })
-    }
-}
-
-impl LocalAuthorityClient {
-    fn new(state: AuthorityState) -> Self {
-        Self(Arc::new(Mutex::new(state)))
-    }
-}
-
 #[cfg(test)]
 fn init_local_authorities(
     count: usize,
-) -> (HashMap<AuthorityName, LocalAuthorityClient>, Committee) {
+) -> (HashMap<AuthorityName, SimulatedAuthorityClient>, Committee) {
     let mut key_pairs = Vec::new();
     let mut voting_rights = BTreeMap::new();
     for _ in 0..count {
@@ -71,7 +30,7 @@ fn init_local_authorities(
     let mut clients = HashMap::new();
     for (address, secret) in key_pairs {
         let state = AuthorityState::new(committee.clone(), address, secret);
-        clients.insert(address, LocalAuthorityClient::new(state));
+        clients.insert(address, SimulatedAuthorityClient::new(state));
     }
     (clients, committee)
 }
@@ -79,7 +38,7 @@ fn init_local_authorities(
 #[cfg(test)]
 fn init_local_authorities_bad_1(
     count: usize,
-) -> (HashMap<AuthorityName, LocalAuthorityClient>, Committee) {
+) -> (HashMap<AuthorityName, SimulatedAuthorityClient>, Committee) {
     let mut key_pairs = Vec::new();
     let mut voting_rights = BTreeMap::new();
     for i in 0..count {
@@ -97,16 +56,16 @@ fn init_local_authorities_bad_1(
     let mut clients = HashMap::new();
     for (address, secret) in key_pairs {
         let state = AuthorityState::new(committee.clone(), address, secret);
-        clients.insert(address, LocalAuthorityClient::new(state));
+        clients.insert(address, SimulatedAuthorityClient::new(state));
     }
     (clients, committee)
 }
 
 #[cfg(test)]
 fn make_client(
-    authority_clients: HashMap<AuthorityName, LocalAuthorityClient>,
+    authority_clients: HashMap<AuthorityName, SimulatedAuthorityClient>,
     committee: Committee,
-) -> ClientState<LocalAuthorityClient> {
+) -> ClientState<SimulatedAuthorityClient> {
     let (address, secret) = get_key_pair();
     ClientState::new(
         address,
@@ -122,7 +81,7 @@ fn make_client(
 
 #[cfg(test)]
 fn fund_account<I: IntoIterator<Item = i128>>(
-    clients: &mut HashMap<AuthorityName, LocalAuthorityClient>,
+    clients: &mut HashMap<AuthorityName, SimulatedAuthorityClient>,
     address: FastPayAddress,
     balances: I,
 ) {
@@ -139,7 +98,7 @@ fn fund_account<I: IntoIterator<Item = i128>>(
 }
 
 #[cfg(test)]
-fn init_local_client_state(balances: Vec<i128>) -> ClientState<LocalAuthorityClient> {
+fn init_local_client_state(balances: Vec<i128>) -> ClientState<SimulatedAuthorityClient> {
     let (mut authority_clients, committee) = init_local_authorities(balances.len());
     let client = make_client(authority_clients.clone(), committee);
     fund_account(&mut authority_clients, client.address, balances);
@@ -149,7 +108,7 @@ fn init_local_client_state(balances: Vec<i128>) -> ClientState<LocalAuthorityCli
 #[cfg(test)]
 fn init_local_client_state_with_bad_authority(
     balances: Vec<i128>,
-) -> ClientState<LocalAuthorityClient> {
+) -> ClientState<SimulatedAuthorityClient> {
     let (mut authority_clients, committee) = init_local_authorities_bad_1(balances.len());
     let client = make_client(authority_clients.clone(), committee);
     fund_account(&mut authority_clients, client.address, balances);
@@ -171,6 +130,56 @@ fn test_get_strong_majority_balance() {
     });
 }
 
+#[test]
+fn test_unsigned_account_info_requires_opt_in() {
+    let mut rt = Runtime::new().unwrap();
+    let mut client = init_local_client_state(vec![4, 4, 4, 4]);
+    for authority in client.authority_clients.values() {
+        authority.0.try_lock().unwrap().sign_read_responses = false;
+    }
+    assert_eq!(
+        rt.block_on(client.get_strong_majority_balance()),
+        Balance::from(0)
+    );
+    assert_eq!(
+        rt.block_on(client.get_strong_majority_sequence_number(client.address)),
+        SequenceNumber::new()
+    );
+
+    let mut client = client.with_unsigned_responses();
+    assert_eq!(
+        rt.block_on(client.get_strong_majority_balance()),
+        Balance::from(4)
+    );
+}
+
+#[test]
+fn test_forged_account_info_is_rejected() {
+    let mut rt = Runtime::new().unwrap();
+    let mut client = init_local_client_state(vec![0, 4, 4, 4]);
+    // Make one of the authorities that back a balance of 4 sign with the wrong key, so
+    // that only the remaining ones count.
+    let forger = client
+        .authority_clients
+        .values()
+        .find(|authority| {
+            authority.0.try_lock().unwrap().accounts[&client.address].balance == Balance::from(4)
+        })
+        .unwrap()
+        .clone();
+    forger.0.try_lock().unwrap().secret = get_key_pair().1;
+    assert_eq!(
+        rt.block_on(client.get_strong_majority_balance()),
+        Balance::from(0)
+    );
+
+    let mut client = client.with_unsigned_responses();
+    assert_eq!(
+        rt.block_on(client.get_strong_majority_balance()),
+        Balance::from(0)
+    );
+}
+
 #[test]
 fn test_initiating_valid_transfer() {
     let mut rt = Runtime::new().unwrap();
@@ -198,6 +207,103 @@ fn test_initiating_valid_transfer() {
     );
 }
 
+#[test]
+fn test_simulated_transfer_matches_real_certificate() {
+    let mut rt = Runtime::new().unwrap();
+    let mut key_pairs = Vec::new();
+    let mut voting_rights = BTreeMap::new();
+    for _ in 0..4 {
+        let key_pair = get_key_pair();
+        voting_rights.insert(key_pair.0, 1);
+        key_pairs.push(key_pair);
+    }
+    let committee = Committee::new(voting_rights);
+    let states = key_pairs
+        .iter()
+        .map(|(name, secret)| AuthorityState::new(committee.clone(), *name, secret.copy()));
+    let (address, secret) = get_key_pair();
+    let (recipient, _) = get_key_pair();
+    let mut sender = ClientState::new_simulated(
+        address,
+        secret.copy(),
+        committee.clone(),
+        states,
+        Balance::from(4),
+    );
+    let certificate = rt
+        .block_on(sender.transfer_to_fastpay(Amount::from(3), recipient, UserData::default()))
+        .unwrap();
+    assert_eq!(sender.balance(), Balance::from(1));
+
+    // Assemble the same certificate by hand.
+    let order = TransferOrder::new(
+        Transfer {
+            sender: address,
+            recipient: Address::FastPay(recipient),
+            amount: Amount::from(3),
+            sequence_number: SequenceNumber::new(),
+            user_data: UserData::default(),
+            account_state: None,
+            domain: SigningDomain::default(),
+        },
+        &secret,
+    );
+    let mut aggregator = SignatureAggregator::try_new(order.clone(), &committee).unwrap();
+    let mut expected = None;
+    for (name, secret) in &key_pairs {
+        let vote = SignedTransferOrder::new(order.clone(), *name, secret);
+        if let Some(value) = aggregator.append(vote.authority, vote.signature).unwrap() {
+            expected = Some(value);
+            break;
+        }
+    }
+    let expected = expected.unwrap();
+    assert_eq!(certificate.value, expected.value);
+    assert_eq!(certificate.signatures.len(), expected.signatures.len());
+    assert!(certificate.check(&committee).is_ok());
+    // At least a quorum of authorities has executed the transfer.
+    let updated = sender
+        .simulated_authorities()
+        .values()
+        .filter(|authority| {
+            let state = authority.state();
+            let state = state.try_lock().unwrap();
+            state.accounts[&address].balance == Balance::from(1)
+        })
+        .count();
+    assert!(updated >= 3);
+}
+
+#[test]
+fn test_transfers_reference_account_state() {
+    let mut rt = Runtime::new().unwrap();
+    let mut key_pairs = Vec::new();
+    let mut voting_rights = BTreeMap::new();
+    for _ in 0..4 {
+        let key_pair = get_key_pair();
+        voting_rights.insert(key_pair.0, 1);
+        key_pairs.push(key_pair);
+    }
+    let mut committee = Committee::new(voting_rights);
+    committee.order_acceptance_window = Some(0);
+    let states = key_pairs
+        .iter()
+        .map(|(name, secret)| AuthorityState::new(committee.clone(), *name, secret.copy()))
+        .collect::<Vec<_>>();
+    let (address, secret) = get_key_pair();
+    let (recipient, _) = get_key_pair();
+    let mut sender =
+        ClientState::new_simulated(address, secret, committee, states, Balance::from(4));
+    for i in 0..2 {
+        let certificate = rt
+            .block_on(sender.transfer_to_fastpay(Amount::from(1), recipient, UserData::default()))
+            .unwrap();
+        let reference = certificate.value.transfer.account_state.unwrap();
+        assert_eq!(reference.sequence_number, SequenceNumber::from(i));
+    }
+    assert_eq!(sender.balance(), Balance::from(2));
+}
+
 #[test]
 fn test_initiating_valid_transfer_despite_bad_authority() {
     let mut rt = Runtime::new().unwrap();
@@ -244,6 +350,452 @@ fn test_initiating_transfer_low_funds() {
     );
 }
 
+/// A second client for the same account, as if the key was used from another device.
+fn make_twin_client(
+    client: &ClientState<SimulatedAuthorityClient>,
+) -> ClientState<SimulatedAuthorityClient> {
+    ClientState::new(
+        client.address,
+        client.secret.copy(),
+        client.committee.clone(),
+        client.authority_clients.clone(),
+        SequenceNumber::new(),
+        Vec::new(),
+        Vec::new(),
+        client.balance,
+    )
+}
+
+#[test]
+fn test_transfer_with_drifted_sequence_number() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+
+    let mut sender = init_local_client_state(vec![10, 10, 10, 10]);
+    sender.balance = Balance::from(10);
+    let mut twin = make_twin_client(&sender);
+    rt.block_on(twin.transfer_to_fastpay(Amount::from(2), recipient, UserData::default()))
+        .unwrap();
+
+    // Without auto-resync, the transfer fails and the client is left as it was.
+    assert!(rt
+        .block_on(sender.transfer_to_fastpay(Amount::from(3), recipient, UserData::default()))
+        .is_err());
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(0));
+
+    let mut sender = make_twin_client(&sender).with_auto_resync(1);
+    let certificate = rt
+        .block_on(sender.transfer_to_fastpay(Amount::from(3), recipient, UserData::default()))
+        .unwrap();
+    assert_eq!(
+        certificate.value.transfer.sequence_number,
+        SequenceNumber::from(1)
+    );
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(2));
+    assert_eq!(sender.sent_certificates.len(), 2);
+    assert_eq!(sender.pending_transfer, None);
+    assert_eq!(sender.balance, Balance::from(5));
+    assert_eq!(
+        rt.block_on(sender.get_strong_majority_balance()),
+        Balance::from(5)
+    );
+}
+
+#[test]
+fn test_auto_resync_attempts_are_capped() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+
+    let mut sender = init_local_client_state(vec![10, 10, 10, 10]);
+    sender.balance = Balance::from(10);
+    let mut twin = make_twin_client(&sender);
+    rt.block_on(twin.transfer_to_fastpay(Amount::from(2), recipient, UserData::default()))
+        .unwrap();
+    // Spending the rest from the other device makes the resubmitted transfer fail.
+    rt.block_on(twin.transfer_to_fastpay(Amount::from(7), recipient, UserData::default()))
+        .unwrap();
+
+    let mut sender = make_twin_client(&sender).with_auto_resync(3);
+    assert!(rt
+        .block_on(sender.transfer_to_fastpay(Amount::from(3), recipient, UserData::default()))
+        .is_err());
+    // The client caught up once, then found the funds insufficient.
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(2));
+    assert_eq!(sender.balance, Balance::from(1));
+    assert_eq!(sender.pending_transfer, None);
+}
+
+/// An authority whose answers to transfer orders are lost. Meanwhile, every order is
+/// certified and confirmed by a `relay` with access to all the authorities.
+#[derive(Clone)]
+struct LostVoteAuthorityClient {
+    inner: SimulatedAuthorityClient,
+    relay: Arc<Mutex<Vec<SimulatedAuthorityClient>>>,
+}
+
+impl AuthorityClient for LostVoteAuthorityClient {
+    fn handle_transfer_order(
+        &mut self,
+        order: TransferOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        let relay = self.relay.clone();
+        Box::pin(async move {
+            let mut authorities = relay.lock().await;
+            let mut signatures = Vec::new();
+            for authority in authorities.iter_mut() {
+                if let Ok(AccountInfoResponse {
+                    pending_confirmation: Some(vote),
+                    ..
+                }) = authority.handle_transfer_order(order.clone()).await
+                {
+                    signatures.push((vote.authority, vote.signature));
+                }
+            }
+            let certificate = CertifiedTransferOrder {
+                value: order,
+                signatures,
+            };
+            for authority in authorities.iter_mut() {
+                let _ = authority
+                    .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+                    .await;
+            }
+            Err(FastPayError::RequestTimeout)
+        })
+    }
+
+    fn handle_confirmation_order(
+        &mut self,
+        order: ConfirmationOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        self.inner.handle_confirmation_order(order)
+    }
+
+    fn handle_account_info_request(
+        &mut self,
+        request: AccountInfoRequest,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        self.inner.handle_account_info_request(request)
+    }
+}
+
+#[test]
+fn test_auto_resync_does_not_resubmit_certified_order() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+    let (mut authority_clients, committee) = init_local_authorities(4);
+    let (address, secret) = get_key_pair();
+    fund_account(&mut authority_clients, address, vec![10, 10, 10, 10]);
+    let relay = Arc::new(Mutex::new(authority_clients.values().cloned().collect()));
+    let authority_clients = authority_clients
+        .into_iter()
+        .map(|(name, inner)| {
+            let relay = relay.clone();
+            (name, LostVoteAuthorityClient { inner, relay })
+        })
+        .collect();
+    let mut sender = ClientState::new(
+        address,
+        secret,
+        committee,
+        authority_clients,
+        SequenceNumber::new(),
+        Vec::new(),
+        Vec::new(),
+        Balance::from(10),
+    )
+    .with_auto_resync(1);
+
+    // The order was certified although the client only saw errors.
+    let certificate = rt
+        .block_on(sender.transfer_to_fastpay(Amount::from(3), recipient, UserData::default()))
+        .unwrap();
+    assert_eq!(
+        certificate.value.transfer.sequence_number,
+        SequenceNumber::from(0)
+    );
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(1));
+    assert_eq!(sender.pending_transfer, None);
+    assert_eq!(sender.balance, Balance::from(7));
+    for authority in relay.try_lock().unwrap().iter() {
+        let state = authority.0.try_lock().unwrap();
+        let account = state.accounts.get(&address).unwrap();
+        assert_eq!(account.balance, Balance::from(7));
+        assert_eq!(account.next_sequence_number, SequenceNumber::from(1));
+    }
+}
+
+/// An authority that waits before handling each request.
+#[derive(Clone)]
+struct SlowAuthorityClient {
+    inner: SimulatedAuthorityClient,
+    delay: Duration,
+}
+
+impl SlowAuthorityClient {
+    fn delayed<'a>(
+        delay: Duration,
+        request: AsyncResult<'a, AccountInfoResponse, FastPayError>,
+    ) -> AsyncResult<'a, AccountInfoResponse, FastPayError> {
+        Box::pin(async move {
+            tokio::time::delay_for(delay).await;
+            request.await
+        })
+    }
+}
+
+impl AuthorityClient for SlowAuthorityClient {
+    fn handle_transfer_order(
+        &mut self,
+        order: TransferOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        Self::delayed(self.delay, self.inner.handle_transfer_order(order))
+    }
+
+    fn handle_confirmation_order(
+        &mut self,
+        order: ConfirmationOrder,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        Self::delayed(self.delay, self.inner.handle_confirmation_order(order))
+    }
+
+    fn handle_account_info_request(
+        &mut self,
+        request: AccountInfoRequest,
+    ) -> AsyncResult<AccountInfoResponse, FastPayError> {
+        Self::delayed(self.delay, self.inner.handle_account_info_request(request))
+    }
+}
+
+/// Requests to each authority take 150ms, except for the `slow` first ones which take
+/// 10s, and time out after 250ms. Handling a transfer takes several requests in a row.
+fn init_client_with_slow_authorities(
+    authority_clients: HashMap<AuthorityName, SimulatedAuthorityClient>,
+    committee: Committee,
+    slow: usize,
+) -> ClientState<TimeoutAuthorityClient<SlowAuthorityClient>> {
+    let mut names: Vec<_> = authority_clients.keys().cloned().collect();
+    names.sort();
+    let authority_clients = authority_clients
+        .into_iter()
+        .map(|(name, inner)| {
+            let delay = if names.iter().position(|n| *n == name).unwrap() < slow {
+                Duration::from_secs(10)
+            } else {
+                Duration::from_millis(150)
+            };
+            let client = SlowAuthorityClient { inner, delay };
+            (
+                name,
+                TimeoutAuthorityClient::new(client, Duration::from_millis(250)),
+            )
+        })
+        .collect();
+    let (address, secret) = get_key_pair();
+    ClientState::new(
+        address,
+        secret,
+        committee,
+        authority_clients,
+        SequenceNumber::new(),
+        Vec::new(),
+        Vec::new(),
+        Balance::from(4),
+    )
+}
+
+#[test]
+fn test_quorum_despite_slow_authority() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+    let (mut authority_clients, committee) = init_local_authorities(4);
+    let mut sender = init_client_with_slow_authorities(authority_clients.clone(), committee, 1);
+    fund_account(&mut authority_clients, sender.address, vec![4, 4, 4, 4]);
+    let mut slow: Vec<_> = authority_clients.keys().cloned().collect();
+    slow.sort();
+    slow.truncate(1);
+
+    let start = std::time::Instant::now();
+    rt.block_on(sender.transfer_to_fastpay(Amount::from(3), recipient, UserData::default()))
+        .unwrap();
+    assert!(start.elapsed() < Duration::from_secs(5));
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(1));
+    let report = sender.last_quorum_report();
+    assert_eq!(report.timed_out, slow);
+    assert_eq!(report.responded.len(), 3);
+    assert!(report.errors.is_empty());
+}
+
+#[test]
+fn test_quorum_report_distinguishes_errors_and_timeouts() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+    // One authority signs with a key outside of the committee, another one is slow.
+    let (mut authority_clients, committee) = init_local_authorities_bad_1(4);
+    let bad = *authority_clients
+        .keys()
+        .find(|name| committee.weight(name) == 0)
+        .unwrap();
+    let slow = *authority_clients
+        .keys()
+        .find(|name| committee.weight(name) > 0)
+        .unwrap();
+    let mut sender = init_client_with_slow_authorities(authority_clients.clone(), committee, 0);
+    sender.authority_clients.get_mut(&slow).unwrap().inner.delay = Duration::from_secs(10);
+    fund_account(&mut authority_clients, sender.address, vec![4, 4, 4, 4]);
+
+    let start = std::time::Instant::now();
+    assert!(rt
+        .block_on(sender.transfer_to_fastpay(Amount::from(3), recipient, UserData::default()))
+        .is_err());
+    assert!(start.elapsed() < Duration::from_secs(5));
+    let report = sender.last_quorum_report();
+    assert_eq!(report.timed_out, vec![slow]);
+    assert_eq!(report.errors.len(), 1);
+    assert_eq!(report.errors[0].0, bad);
+    assert_eq!(report.responded.len(), 2);
+}
+
+/// Keeps the saved votes serialized, as a file would.
+#[derive(Clone, Default)]
+struct MemoryVoteStore(Arc<std::sync::Mutex<Option<Vec<u8>>>>);
+
+impl MemoryVoteStore {
+    fn load(&self) -> Option<PartialCertificate> {
+        let bytes = self.0.lock().unwrap().clone()?;
+        Some(bincode::deserialize(&bytes).unwrap())
+    }
+}
+
+impl VoteStore for MemoryVoteStore {
+    fn save(&mut self, partial: &PartialCertificate) -> Result<(), failure::Error> {
+        *self.0.lock().unwrap() = Some(bincode::serialize(partial)?);
+        Ok(())
+    }
+
+    fn clear(&mut self) -> Result<(), failure::Error> {
+        *self.0.lock().unwrap() = None;
+        Ok(())
+    }
+}
+
+/// Sign a transfer of 3 from `sender` and collect the votes of the given authorities.
+fn collect_votes<A>(
+    rt: &mut Runtime,
+    authority_clients: &mut HashMap<AuthorityName, SimulatedAuthorityClient>,
+    voters: &[AuthorityName],
+    sender: &ClientState<A>,
+    recipient: FastPayAddress,
+) -> PartialCertificate {
+    let transfer = Transfer {
+        sender: sender.address,
+        recipient: Address::FastPay(recipient),
+        amount: Amount::from(3),
+        sequence_number: sender.next_sequence_number,
+        user_data: UserData::default(),
+        account_state: None,
+        domain: sender.committee.domain,
+    };
+    let mut partial = PartialCertificate::new(TransferOrder::new(transfer, &sender.secret));
+    for name in voters {
+        let client = authority_clients.get_mut(name).unwrap();
+        let response = rt
+            .block_on(client.handle_transfer_order(partial.order.clone()))
+            .unwrap();
+        let vote = response.pending_confirmation.unwrap();
+        partial.votes.push((vote.authority, vote.signature));
+    }
+    partial
+}
+
+#[test]
+fn test_resume_transfer_from_saved_votes() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+    let (mut authority_clients, committee) = init_local_authorities(4);
+    // The first two authorities vote, then stop answering in time. A quorum can only be
+    // reached by reusing their votes.
+    let store = MemoryVoteStore::default();
+    let mut sender =
+        init_client_with_slow_authorities(authority_clients.clone(), committee.clone(), 2)
+            .with_vote_store(Box::new(store.clone()));
+    fund_account(&mut authority_clients, sender.address, vec![4, 4, 4, 4]);
+    let mut names: Vec<_> = authority_clients.keys().cloned().collect();
+    names.sort();
+    let partial = collect_votes(
+        &mut rt,
+        &mut authority_clients,
+        &names[..2],
+        &sender,
+        recipient,
+    );
+    store.clone().save(&partial).unwrap();
+
+    let saved = store.load().unwrap();
+    assert_eq!(saved, partial);
+    let certificate = rt
+        .block_on(sender.resume_transfer(saved, /* with_confirmation */ false))
+        .unwrap();
+    certificate.check(&committee).unwrap();
+    assert_eq!(certificate.value, partial.order);
+    let signers: Vec<_> = certificate
+        .signatures
+        .iter()
+        .map(|(name, _)| *name)
+        .collect();
+    assert!(names[..2].iter().all(|name| signers.contains(name)));
+    let report = sender.last_quorum_report();
+    assert!(report.timed_out.is_empty());
+    assert_eq!(report.responded.len(), 3);
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(1));
+    assert_eq!(sender.pending_transfer, None);
+    // The votes are forgotten once the transfer is certified.
+    assert_eq!(store.load(), None);
+}
+
+#[test]
+fn test_resume_transfer_rejects_invalid_votes() {
+    let mut rt = Runtime::new().unwrap();
+    let (recipient, _) = get_key_pair();
+    let (mut authority_clients, committee) = init_local_authorities(4);
+    let mut sender = make_client(authority_clients.clone(), committee);
+    fund_account(&mut authority_clients, sender.address, vec![4, 4, 4, 4]);
+    let mut names: Vec<_> = authority_clients.keys().cloned().collect();
+    names.sort();
+    let partial = collect_votes(
+        &mut rt,
+        &mut authority_clients,
+        &names[..2],
+        &sender,
+        recipient,
+    );
+
+    // Votes attributed to the wrong authorities.
+    let mut swapped = partial.clone();
+    swapped.votes[0].0 = names[1];
+    swapped.votes[1].0 = names[0];
+    assert!(rt.block_on(sender.resume_transfer(swapped, false)).is_err());
+    // The same vote twice.
+    let mut duplicated = partial.clone();
+    duplicated.votes[1] = duplicated.votes[0];
+    assert!(rt
+        .block_on(sender.resume_transfer(duplicated, false))
+        .is_err());
+    // Votes for another order.
+    let mut transfer = partial.order.transfer.clone();
+    transfer.amount = Amount::from(1);
+    let stale = PartialCertificate {
+        order: TransferOrder::new(transfer, &sender.secret),
+        votes: partial.votes.clone(),
+    };
+    assert!(rt.block_on(sender.resume_transfer(stale, false)).is_err());
+    assert_eq!(sender.pending_transfer, None);
+    assert_eq!(sender.next_sequence_number, SequenceNumber::new());
+
+    rt.block_on(sender.resume_transfer(partial, true)).unwrap();
+    assert_eq!(sender.next_sequence_number, SequenceNumber::from(1));
+}
+
 #[test]
 fn test_bidirectional_transfer() {
     let mut rt = Runtime::new().unwrap();
@@ -2,6 +2,7 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::*;
+use crate::committee::SigningDomain;
 
 // handle_funding_transaction
 #[test]
@@ -166,6 +167,8 @@ fn init_redeem_transaction(
         amount: Amount::from(3),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
     };
     let order = TransferOrder::new(primary_transfer, &sender_key);
     let vote = SignedTransferOrder::new(order.clone(), name, &secret);
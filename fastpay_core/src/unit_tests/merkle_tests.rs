@@ -0,0 +1,58 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+
+fn make_leaf(i: u8) -> HashValue {
+    [i; 32]
+}
+
+#[test]
+fn test_root_is_deterministic() {
+    let mut accumulator1 = MerkleAccumulator::new();
+    let mut accumulator2 = MerkleAccumulator::new();
+    let mut roots = vec![accumulator1.root()];
+    for i in 0..10 {
+        accumulator1.append(make_leaf(i));
+        accumulator2.append(make_leaf(i));
+        assert_eq!(accumulator1.root(), accumulator2.root());
+        assert!(!roots.contains(&accumulator1.root()));
+        roots.push(accumulator1.root());
+    }
+    assert_eq!(accumulator1.root(), subtree_root(&accumulator1.leaves));
+
+    let mut accumulator3 = MerkleAccumulator::new();
+    for i in (0..10).rev() {
+        accumulator3.append(make_leaf(i));
+    }
+    assert_ne!(accumulator1.root(), accumulator3.root());
+}
+
+#[test]
+fn test_inclusion_proofs() {
+    let mut accumulator = MerkleAccumulator::new();
+    assert!(accumulator.prove(0).is_none());
+    for n in 0..20 {
+        accumulator.append(make_leaf(n));
+        let root = accumulator.root();
+        for i in 0..=n {
+            let proof = accumulator.prove(i as usize).unwrap();
+            assert!(proof.verify(&make_leaf(i), &root));
+            assert!(!proof.verify(&make_leaf(i + 1), &root));
+        }
+    }
+}
+
+#[test]
+fn test_old_proofs_do_not_verify_against_new_root() {
+    let mut accumulator = MerkleAccumulator::new();
+    accumulator.append(make_leaf(0));
+    accumulator.append(make_leaf(1));
+    let proof = accumulator.prove(0).unwrap();
+    accumulator.append(make_leaf(2));
+    assert!(!proof.verify(&make_leaf(0), &accumulator.root()));
+    assert!(accumulator
+        .prove(0)
+        .unwrap()
+        .verify(&make_leaf(0), &accumulator.root()));
+}
@@ -21,6 +21,8 @@ fn test_signed_values() {
         amount: Amount::from(1),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
     };
     let order = TransferOrder::new(transfer.clone(), &sec1);
     let bad_order = TransferOrder::new(transfer, &sec2);
@@ -55,6 +57,8 @@ fn test_certificates() {
         amount: Amount::from(1),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
     };
     let order = TransferOrder::new(transfer.clone(), &sec1);
     let bad_order = TransferOrder::new(transfer, &sec2);
@@ -82,3 +86,152 @@ fn test_certificates() {
 
     assert!(SignatureAggregator::try_new(bad_order, &committee).is_err());
 }
+
+#[test]
+fn test_sequence_number_proof() {
+    let keys: Vec<_> = (0..4).map(|_| get_key_pair()).collect();
+    let committee = Committee::new(keys.iter().map(|(name, _)| (*name, 1)).collect());
+    let sender = dbg_addr(1);
+    let statement = |(name, secret): &(AuthorityName, KeyPair), status| {
+        SequenceNumberStatement::new(sender, SequenceNumber::new(), status, *name, secret)
+    };
+    let statements: Vec<_> = keys
+        .iter()
+        .map(|key| statement(key, SequenceNumberStatus::Unused))
+        .collect();
+
+    let proof = SequenceNumberProof {
+        statements: statements[..3].to_vec(),
+    };
+    assert_eq!(
+        proof.check(&committee).unwrap(),
+        &SequenceNumberStatus::Unused
+    );
+
+    // Not a quorum.
+    let proof = SequenceNumberProof {
+        statements: statements[..2].to_vec(),
+    };
+    assert!(proof.check(&committee).is_err());
+    let proof = SequenceNumberProof {
+        statements: vec![statements[0].clone(); 3],
+    };
+    assert!(proof.check(&committee).is_err());
+
+    // Disagreeing statements.
+    let mut proof = SequenceNumberProof {
+        statements: statements[..3].to_vec(),
+    };
+    proof.statements[2] = statement(&keys[2], SequenceNumberStatus::Voted([0; 32]));
+    assert!(proof.check(&committee).is_err());
+
+    // Tampered statements.
+    let mut proof = SequenceNumberProof {
+        statements: statements[..3].to_vec(),
+    };
+    proof.statements[2].status = SequenceNumberStatus::Confirmed([0; 32]);
+    proof.statements[1].status = SequenceNumberStatus::Confirmed([0; 32]);
+    proof.statements[0].status = SequenceNumberStatus::Confirmed([0; 32]);
+    assert!(proof.check(&committee).is_err());
+}
+
+fn make_template_orders(recipient: Address, amount: Amount, count: u64) -> Vec<TransferOrder> {
+    (0..count)
+        .map(|i| {
+            let (sender, secret) = get_key_pair();
+            let transfer = Transfer {
+                sender,
+                recipient,
+                amount,
+                sequence_number: SequenceNumber::from(i),
+                user_data: UserData(if i % 2 == 0 {
+                    Some([i as u8; 32])
+                } else {
+                    None
+                }),
+                account_state: if i % 3 == 0 {
+                    Some(AccountStateReference {
+                        sequence_number: SequenceNumber::from(i),
+                        history_root: [7; 32],
+                    })
+                } else {
+                    None
+                },
+                domain: SigningDomain::default(),
+            };
+            TransferOrder::new(transfer, &secret)
+        })
+        .collect()
+}
+
+#[test]
+fn test_transfer_template_encoding() {
+    let recipient = Address::FastPay(get_key_pair().0);
+    let template = TransferTemplate::new(recipient, Amount::from(5));
+    for order in make_template_orders(recipient, Amount::from(5), 6) {
+        let mut expected = Vec::new();
+        order.transfer.write(&mut expected);
+        let mut message = Vec::new();
+        template.write(&order.transfer, &mut message);
+        assert_eq!(message, expected);
+    }
+}
+
+#[test]
+fn test_transfer_template_agrees_with_naive_checks() {
+    let recipient = Address::FastPay(get_key_pair().0);
+    let template = TransferTemplate::new(recipient, Amount::from(5));
+    let mut orders = make_template_orders(recipient, Amount::from(5), 8);
+    // Orders that do not match the template are checked as well.
+    orders.extend(make_template_orders(
+        Address::Primary(get_key_pair().0),
+        Amount::from(5),
+        2,
+    ));
+    orders.extend(make_template_orders(recipient, Amount::from(6), 2));
+    assert!(orders.iter().all(|order| order.check_signature().is_ok()));
+    assert!(template.check_orders(&orders).is_ok());
+    assert!(template.check_orders(&[]).is_ok());
+
+    // Altering any part of a transfer invalidates the batch.
+    let alterations: Vec<fn(&mut Transfer)> = vec![
+        |transfer| transfer.sender = get_key_pair().0,
+        |transfer| transfer.recipient = Address::FastPay(get_key_pair().0),
+        |transfer| transfer.amount = Amount::from(4),
+        |transfer| transfer.sequence_number = SequenceNumber::from(100),
+        |transfer| transfer.user_data = UserData(Some([9; 32])),
+        |transfer| transfer.account_state = None,
+    ];
+    for alter in alterations {
+        let mut altered = orders.clone();
+        alter(&mut altered[3].transfer);
+        assert!(altered[3].check_signature().is_err());
+        assert!(template.check_orders(&altered).is_err());
+    }
+    let mut swapped = orders;
+    swapped[1].signature = swapped[2].signature;
+    assert!(swapped[1].check_signature().is_err());
+    assert!(template.check_orders(&swapped).is_err());
+}
+
+#[test]
+#[ignore]
+fn bench_transfer_template_checks() {
+    let recipient = Address::FastPay(get_key_pair().0);
+    let orders = make_template_orders(recipient, Amount::from(5), 10_000);
+    let template = TransferTemplate::new(recipient, Amount::from(5));
+
+    let start = std::time::Instant::now();
+    for order in &orders {
+        order.check_signature().unwrap();
+    }
+    let naive_time = start.elapsed();
+    let start = std::time::Instant::now();
+    template.check_orders(&orders).unwrap();
+    let template_time = start.elapsed();
+    println!(
+        "Checked 10000 orders in {:?} one by one and {:?} with a template",
+        naive_time, template_time
+    );
+    assert!(template_time < naive_time);
+}
@@ -0,0 +1,354 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use crate::{authority::Authority, committee::SigningDomain};
+
+fn make_certificate(sender_key: &KeyPair, transfer: Transfer) -> CertifiedTransferOrder {
+    let (authority, authority_key) = get_key_pair();
+    let order = TransferOrder::new(transfer, sender_key);
+    let signature = Signature::new(&order.transfer, &authority_key);
+    CertifiedTransferOrder {
+        value: order,
+        signatures: vec![(authority, signature)],
+    }
+}
+
+fn make_transfer(sender: FastPayAddress, recipient: FastPayAddress, sequence: u64) -> Transfer {
+    Transfer {
+        sender,
+        recipient: Address::FastPay(recipient),
+        amount: Amount::from(1),
+        sequence_number: SequenceNumber::from(sequence),
+        user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
+    }
+}
+
+#[test]
+fn test_read_v1_account_record() {
+    let (address, key) = get_key_pair();
+    let (other, other_key) = get_key_pair();
+    let confirmed_log = vec![
+        make_certificate(&key, make_transfer(address, other, 0)),
+        make_certificate(&key, make_transfer(address, other, 1)),
+    ];
+    let received_log = vec![make_certificate(
+        &other_key,
+        make_transfer(other, address, 0),
+    )];
+    // Layout of the records written by version 1.
+    let bytes = bincode::serialize(&(
+        1u32,
+        Balance::from(5),
+        SequenceNumber::from(2),
+        Option::<SignedTransferOrder>::None,
+        &confirmed_log,
+        Vec::<PrimarySynchronizationOrder>::new(),
+        &received_log,
+    ))
+    .unwrap();
+
+    let account = deserialize_account(&bytes).unwrap();
+    assert_eq!(account.balance, Balance::from(5));
+    assert_eq!(account.next_sequence_number, SequenceNumber::from(2));
+    assert_eq!(account.confirmed_log, confirmed_log);
+    assert_eq!(account.received_log, received_log);
+    // Indexes that did not exist in version 1 are rebuilt.
+    let mut history = MerkleAccumulator::new();
+    for certificate in &confirmed_log {
+        history.append(leaf_hash(&certificate.value.transfer));
+    }
+    assert_eq!(account.confirmed_history, history);
+    assert!(account
+        .received_transfers
+        .contains(&(other, SequenceNumber::from(0))));
+    assert_eq!(account.last_activity, None);
+}
+
+#[test]
+fn test_account_record_roundtrip() {
+    let (address, key) = get_key_pair();
+    let mut account = AccountOffchainState::new();
+    account.balance = Balance::from(3);
+    account.next_sequence_number = SequenceNumber::from(1);
+    let certificate = make_certificate(&key, make_transfer(address, dbg_addr(1), 0));
+    account
+        .confirmed_history
+        .append(leaf_hash(&certificate.value.transfer));
+    account.confirmed_log.push(certificate);
+    account.last_activity = Some(1234);
+    account.last_transfer = Some(1200);
+    account.ownership = Some(Ownership::Multisig {
+        threshold: 1,
+        signers: vec![dbg_addr(2), dbg_addr(3)],
+    });
+    account.ownership_version = SequenceNumber::from(1);
+
+    let bytes = serialize_account(&account);
+    assert_eq!(deserialize_account(&bytes).unwrap(), account);
+}
+
+#[test]
+fn test_reject_unknown_account_record_version() {
+    let mut bytes = serialize_account(&AccountOffchainState::new());
+    bytes[..4].copy_from_slice(&(ACCOUNT_RECORD_VERSION + 1).to_le_bytes());
+    assert_eq!(
+        deserialize_account(&bytes),
+        Err(FastPayError::UnsupportedRecordVersion {
+            version: ACCOUNT_RECORD_VERSION + 1
+        })
+    );
+    assert_eq!(
+        deserialize_account(&[1, 0]),
+        Err(FastPayError::InvalidDecoding)
+    );
+}
+
+#[test]
+fn test_snapshot_roundtrip() {
+    let (authority, authority_key) = get_key_pair();
+    let committee = crate::committee::Committee::new(std::iter::once((authority, 1)).collect());
+    let mut state =
+        AuthorityState::new_shard(committee.clone(), authority, authority_key.copy(), 1, 2);
+    let (address, key) = get_key_pair();
+    let mut account = AccountOffchainState::new_with_balance(Balance::from(3), Vec::new());
+    let certificate = make_certificate(&key, make_transfer(address, dbg_addr(1), 0));
+    account
+        .confirmed_history
+        .append(leaf_hash(&certificate.value.transfer));
+    account.confirmed_log.push(certificate);
+    account.next_sequence_number = SequenceNumber::from(1);
+    state.accounts.insert(address, account);
+    state
+        .accounts
+        .insert(dbg_addr(2), AccountOffchainState::new());
+    state.last_transaction_index = SequenceNumber::from(7);
+    let bytes = serialize_snapshot(&state);
+
+    let mut restored =
+        AuthorityState::new_shard(committee.clone(), authority, authority_key.copy(), 1, 2);
+    restored
+        .accounts
+        .insert(dbg_addr(3), AccountOffchainState::new());
+    restore_snapshot(&mut restored, &bytes).unwrap();
+    assert_eq!(restored.accounts, state.accounts);
+    assert_eq!(restored.last_transaction_index, SequenceNumber::from(7));
+
+    // Snapshots only restore the shard they were taken from.
+    let mut other_shard = AuthorityState::new_shard(committee, authority, authority_key, 0, 2);
+    assert_eq!(
+        restore_snapshot(&mut other_shard, &bytes),
+        Err(FastPayError::WrongShard)
+    );
+}
+
+#[test]
+fn test_persist_and_load() {
+    let (authority, authority_key) = get_key_pair();
+    let committee = crate::committee::Committee::new(std::iter::once((authority, 1)).collect());
+    let make_state =
+        || AuthorityState::new_shard(committee.clone(), authority, authority_key.copy(), 1, 2);
+    let (sender, sender_key) = loop {
+        let (address, key) = get_key_pair();
+        if AuthorityState::get_shard(2, &address) == 1 {
+            break (address, key);
+        }
+    };
+    let mut state = make_state();
+    state.accounts.insert(
+        sender,
+        AccountOffchainState::new_with_balance(Balance::from(5), Vec::new()),
+    );
+    let order = TransferOrder::new(make_transfer(sender, dbg_addr(1), 0), &sender_key);
+    let vote = SignedTransferOrder::new(order.clone(), authority, &authority_key);
+    state
+        .handle_confirmation_order(ConfirmationOrder::new(CertifiedTransferOrder {
+            value: order,
+            signatures: vec![(vote.authority, vote.signature)],
+        }))
+        .unwrap();
+
+    let dir = tempfile::tempdir().unwrap();
+    let path = dir.path().join("shard1.snapshot");
+    state.persist(&path).unwrap();
+    assert_eq!(std::fs::read_dir(dir.path()).unwrap().count(), 1);
+    let mut loaded = make_state();
+    loaded.load(&path).unwrap();
+    assert_eq!(loaded.accounts, state.accounts);
+    assert_eq!(loaded.accounts[&sender].balance, Balance::from(4));
+    assert_eq!(loaded.last_transaction_index, state.last_transaction_index);
+
+    // Persisting again replaces the file.
+    loaded.accounts.remove(&sender);
+    loaded.persist(&path).unwrap();
+    let mut reloaded = make_state();
+    reloaded.load(&path).unwrap();
+    assert!(!reloaded.accounts.contains_key(&sender));
+
+    assert!(make_state().load(&dir.path().join("missing")).is_err());
+}
+
+#[test]
+fn test_diff_snapshots() {
+    let (authority, authority_key) = get_key_pair();
+    let committee = crate::committee::Committee::new(std::iter::once((authority, 1)).collect());
+    let mut state = AuthorityState::new(committee, authority, authority_key);
+    for i in 1..=3 {
+        state.accounts.insert(
+            dbg_addr(i),
+            AccountOffchainState::new_with_balance(Balance::from(10), Vec::new()),
+        );
+    }
+    let old = serialize_snapshot(&state);
+    assert_eq!(diff_snapshots(&old, &old), Ok(Vec::new()));
+
+    state.accounts.get_mut(&dbg_addr(2)).unwrap().balance = Balance::from(4);
+    let new = serialize_snapshot(&state);
+    assert_eq!(
+        diff_snapshots(&old, &new),
+        Ok(vec![(
+            dbg_addr(2),
+            AccountDiff::Changed {
+                old_balance: Balance::from(10),
+                new_balance: Balance::from(4),
+                old_sequence_number: SequenceNumber::new(),
+                new_sequence_number: SequenceNumber::new(),
+            }
+        )])
+    );
+
+    state.accounts.remove(&dbg_addr(3));
+    state
+        .accounts
+        .insert(dbg_addr(0), AccountOffchainState::new());
+    let new = serialize_snapshot(&state);
+    let diffs = diff_snapshots(&old, &new).unwrap();
+    let addresses: Vec<_> = diffs.iter().map(|(address, _)| *address).collect();
+    assert_eq!(addresses, vec![dbg_addr(0), dbg_addr(2), dbg_addr(3)]);
+    assert!(matches!(diffs[0].1, AccountDiff::Added { .. }));
+    assert!(matches!(diffs[2].1, AccountDiff::Removed { .. }));
+
+    assert_eq!(
+        diff_snapshots(&old, &[0, 1]),
+        Err(FastPayError::InvalidDecoding)
+    );
+}
+
+/// Canonical encoding as computed by a version that only knows the fields of version 1
+/// of the account records, declared in a different order.
+fn canonical_v1_bytes(
+    received_log: &[CertifiedTransferOrder],
+    confirmed_log: &[CertifiedTransferOrder],
+    next_sequence_number: SequenceNumber,
+    balance: Balance,
+) -> Vec<u8> {
+    let mut fields = Vec::new();
+    if !received_log.is_empty() {
+        fields.push((6u32, bcs::to_bytes(received_log).unwrap()));
+    }
+    if !confirmed_log.is_empty() {
+        fields.push((4u32, bcs::to_bytes(confirmed_log).unwrap()));
+    }
+    if next_sequence_number != SequenceNumber::new() {
+        fields.push((2u32, bcs::to_bytes(&next_sequence_number).unwrap()));
+    }
+    if balance != Balance::zero() {
+        fields.push((1u32, bcs::to_bytes(&balance).unwrap()));
+    }
+    fields.sort_by_key(|(id, _)| *id);
+    bcs::to_bytes(&fields).unwrap()
+}
+
+#[test]
+fn test_canonical_encoding_across_versions() {
+    let (address, key) = get_key_pair();
+    let (other, other_key) = get_key_pair();
+    let confirmed_log = vec![make_certificate(&key, make_transfer(address, other, 0))];
+    let received_log = vec![make_certificate(
+        &other_key,
+        make_transfer(other, address, 0),
+    )];
+    let v1_bytes = bincode::serialize(&(
+        1u32,
+        Balance::from(5),
+        SequenceNumber::from(1),
+        Option::<SignedTransferOrder>::None,
+        &confirmed_log,
+        Vec::<PrimarySynchronizationOrder>::new(),
+        &received_log,
+    ))
+    .unwrap();
+    let account = deserialize_account(&v1_bytes).unwrap();
+    let v2_bytes = serialize_account(&account);
+    let migrated = deserialize_account(&v2_bytes).unwrap();
+
+    let expected = canonical_v1_bytes(
+        &received_log,
+        &confirmed_log,
+        SequenceNumber::from(1),
+        Balance::from(5),
+    );
+    assert_eq!(canonical_account_bytes(&account), expected);
+    assert_eq!(canonical_account_bytes(&migrated), expected);
+    assert_eq!(account_digest(&account), account_digest(&migrated));
+
+    // Fields unknown to version 1 only change the encoding when they are set.
+    let mut owned = migrated;
+    owned.ownership_version = VersionNumber::from(1);
+    assert_ne!(account_digest(&owned), account_digest(&account));
+}
+
+#[test]
+fn test_canonical_encoding_ignores_local_times() {
+    let mut account = AccountOffchainState::new_with_balance(Balance::from(5), Vec::new());
+    let mut other = AccountOffchainState::new_with_balance(Balance::from(5), Vec::new());
+    account.last_activity = Some(1000);
+    account.last_transfer = Some(1000);
+    other.last_activity = Some(2000);
+    assert_eq!(account_digest(&account), account_digest(&other));
+
+    let mut accounts = BTreeMap::new();
+    accounts.insert(dbg_addr(1), account);
+    let mut other_accounts = BTreeMap::new();
+    other_accounts.insert(dbg_addr(1), other);
+    assert_eq!(accounts_digest(&accounts), accounts_digest(&other_accounts));
+}
+
+#[test]
+fn test_canonical_encoding_layout() {
+    let mut account = AccountOffchainState::new();
+    assert_eq!(
+        canonical_account_bytes(&account),
+        bcs::to_bytes(&Vec::<(u32, Vec<u8>)>::new()).unwrap()
+    );
+    account.ownership_version = VersionNumber::from(3);
+    account.balance = Balance::from(5);
+    let expected = vec![
+        (
+            canonical_field::BALANCE,
+            bcs::to_bytes(&Balance::from(5)).unwrap(),
+        ),
+        (
+            canonical_field::OWNERSHIP_VERSION,
+            bcs::to_bytes(&VersionNumber::from(3)).unwrap(),
+        ),
+    ];
+    assert_eq!(
+        canonical_account_bytes(&account),
+        bcs::to_bytes(&expected).unwrap()
+    );
+
+    // The digest of a shard follows the order of addresses.
+    let mut hasher = Sha256::new();
+    hasher.update(dbg_addr(1).0);
+    hasher.update(account_digest(&AccountOffchainState::new()));
+    hasher.update(dbg_addr(2).0);
+    hasher.update(account_digest(&account));
+    let mut accounts = BTreeMap::new();
+    accounts.insert(dbg_addr(2), account);
+    accounts.insert(dbg_addr(1), AccountOffchainState::new());
+    let expected: HashValue = hasher.finalize().into();
+    assert_eq!(accounts_digest(&accounts), expected);
+}
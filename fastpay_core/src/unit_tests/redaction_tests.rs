@@ -0,0 +1,64 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use crate::{
+    committee::SigningDomain,
+    messages::{Transfer, TransferOrder},
+};
+
+#[test]
+fn test_redacted_addresses() {
+    let (sender, _) = get_key_pair();
+    let encoded = encode_address(&sender);
+    assert_eq!(LogDetail::default(), LogDetail::Redacted);
+    assert_eq!(
+        LogDetail::Redacted.address(&sender),
+        format!("{}..", &encoded[..REDACTED_ADDRESS_LENGTH])
+    );
+    assert_eq!(LogDetail::Full.address(&sender), encoded);
+    assert_eq!(
+        LogDetail::Redacted.recipient(&Address::Primary(sender)),
+        format!("Primary({}..)", &encoded[..REDACTED_ADDRESS_LENGTH])
+    );
+    assert_eq!(
+        LogDetail::Full.recipient(&Address::FastPay(sender)),
+        format!("{:?}", Address::FastPay(sender))
+    );
+}
+
+#[test]
+fn test_redacted_errors() {
+    let (sender, key) = get_key_pair();
+    let recipient = dbg_addr(2);
+    let order = TransferOrder::new(
+        Transfer {
+            sender,
+            recipient: Address::FastPay(recipient),
+            amount: Amount::from(3),
+            sequence_number: SequenceNumber::from(1),
+            user_data: UserData(Some([7; 32])),
+            account_state: None,
+            domain: SigningDomain::default(),
+        },
+        &key,
+    );
+    let error = FastPayError::PreviousTransferMustBeConfirmedFirst {
+        pending_confirmation: Box::new(order),
+    };
+
+    let redacted = LogDetail::Redacted.error(&error);
+    assert!(!redacted.contains(&encode_address(&sender)));
+    assert!(!redacted.contains(&encode_address(&recipient)));
+    assert!(!redacted.contains(&hex::encode([7; 32])));
+    assert!(redacted.contains(&LogDetail::Redacted.address(&sender)));
+    assert!(redacted.contains("user data <redacted>"));
+
+    let full = LogDetail::Full.error(&error);
+    assert_eq!(full, error.to_string());
+    assert!(full.contains(&encode_address(&sender)));
+
+    // Errors without orders are unchanged.
+    let error = FastPayError::UnknownSenderAccount;
+    assert_eq!(LogDetail::Redacted.error(&error), error.to_string());
+}
@@ -0,0 +1,207 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use crate::committee::SigningDomain;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+/// A list of steps executed against an in-memory committee whose authorities are all
+/// honest and run several shards.
+#[derive(Debug, Deserialize)]
+struct Scenario {
+    authorities: usize,
+    shards: u32,
+    steps: Vec<Step>,
+}
+
+#[derive(Debug, Deserialize)]
+enum Step {
+    /// Create an account with the given balance, optionally on a given shard.
+    OpenAccount {
+        name: String,
+        balance: u64,
+        shard: Option<u32>,
+    },
+    /// Collect the votes of all authorities on a new transfer and keep the certificate.
+    Transfer {
+        from: String,
+        to: String,
+        amount: u64,
+        #[serde(default)]
+        should_fail: bool,
+    },
+    /// Confirm the last certificate of an account with all authorities, then deliver
+    /// the resulting cross-shard updates.
+    Confirm { from: String },
+    /// Check the balance of an account for all authorities.
+    AssertBalance { account: String, balance: u64 },
+}
+
+struct Account {
+    address: FastPayAddress,
+    secret: KeyPair,
+    next_sequence_number: SequenceNumber,
+    certificate: Option<CertifiedTransferOrder>,
+}
+
+struct Harness {
+    committee: Committee,
+    num_shards: u32,
+    /// Shards of each authority.
+    authorities: Vec<Vec<AuthorityState>>,
+    accounts: HashMap<String, Account>,
+}
+
+impl Harness {
+    fn new(num_authorities: usize, num_shards: u32) -> Self {
+        let keys: Vec<_> = (0..num_authorities).map(|_| get_key_pair()).collect();
+        let committee = Committee::new(keys.iter().map(|(name, _)| (*name, 1)).collect());
+        let authorities = keys
+            .iter()
+            .map(|(name, secret)| {
+                (0..num_shards)
+                    .map(|shard| {
+                        AuthorityState::new_shard(
+                            committee.clone(),
+                            *name,
+                            secret.copy(),
+                            shard,
+                            num_shards,
+                        )
+                    })
+                    .collect()
+            })
+            .collect();
+        Self {
+            committee,
+            num_shards,
+            authorities,
+            accounts: HashMap::new(),
+        }
+    }
+
+    fn account(&self, name: &str) -> &Account {
+        self.accounts
+            .get(name)
+            .unwrap_or_else(|| panic!("unknown account {}", name))
+    }
+
+    fn shard(&self, address: &FastPayAddress) -> usize {
+        AuthorityState::get_shard(self.num_shards, address) as usize
+    }
+
+    fn run(&mut self, step: Step) {
+        match step {
+            Step::OpenAccount {
+                name,
+                balance,
+                shard,
+            } => {
+                let (address, secret) = loop {
+                    let (address, secret) = get_key_pair();
+                    if shard.is_none() || shard == Some(self.shard(&address) as u32) {
+                        break (address, secret);
+                    }
+                };
+                let shard = self.shard(&address);
+                for authority in &mut self.authorities {
+                    let mut account = AccountOffchainState::new();
+                    account.balance = Balance::from(balance as i128);
+                    authority[shard].accounts.insert(address, account);
+                }
+                let account = Account {
+                    address,
+                    secret,
+                    next_sequence_number: SequenceNumber::new(),
+                    certificate: None,
+                };
+                self.accounts.insert(name, account);
+            }
+            Step::Transfer {
+                from,
+                to,
+                amount,
+                should_fail,
+            } => {
+                let sender = self.account(&from);
+                let transfer = Transfer {
+                    sender: sender.address,
+                    recipient: Address::FastPay(self.account(&to).address),
+                    amount: Amount::from(amount),
+                    sequence_number: sender.next_sequence_number,
+                    user_data: UserData::default(),
+                    account_state: None,
+                    domain: SigningDomain::default(),
+                };
+                let order = TransferOrder::new(transfer, &sender.secret);
+                let shard = self.shard(&sender.address);
+                let mut aggregator =
+                    SignatureAggregator::try_new(order.clone(), &self.committee).unwrap();
+                let mut certificate = None;
+                for authority in &mut self.authorities {
+                    match authority[shard].handle_transfer_order(order.clone()) {
+                        Ok(info) => {
+                            let vote = info.pending_confirmation.unwrap();
+                            if let Some(value) =
+                                aggregator.append(vote.authority, vote.signature).unwrap()
+                            {
+                                certificate = Some(value);
+                            }
+                        }
+                        Err(error) => assert!(should_fail, "unexpected error: {}", error),
+                    }
+                }
+                assert_eq!(certificate.is_none(), should_fail);
+                if let Some(certificate) = certificate {
+                    let sender = self.accounts.get_mut(&from).unwrap();
+                    sender.next_sequence_number = sender.next_sequence_number.increment().unwrap();
+                    sender.certificate = Some(certificate);
+                }
+            }
+            Step::Confirm { from } => {
+                let certificate = self
+                    .account(&from)
+                    .certificate
+                    .clone()
+                    .expect("no certificate to confirm");
+                let shard = self.shard(&certificate.value.transfer.sender);
+                for authority in &mut self.authorities {
+                    let (_, update) = authority[shard]
+                        .handle_confirmation_order(ConfirmationOrder::new(certificate.clone()))
+                        .unwrap();
+                    if let Some(update) = update {
+                        authority[update.shard_id as usize]
+                            .handle_cross_shard_recipient_commit(update)
+                            .unwrap();
+                    }
+                }
+            }
+            Step::AssertBalance { account, balance } => {
+                let address = self.account(&account).address;
+                let shard = self.shard(&address);
+                for authority in &self.authorities {
+                    assert_eq!(
+                        authority[shard].accounts[&address].balance,
+                        Balance::from(balance as i128),
+                        "balance of {}",
+                        account
+                    );
+                }
+            }
+        }
+    }
+}
+
+fn run_scenario(data: &str) {
+    let scenario: Scenario = serde_yaml::from_str(data).unwrap();
+    let mut harness = Harness::new(scenario.authorities, scenario.shards);
+    for step in scenario.steps {
+        harness.run(step);
+    }
+}
+
+#[test]
+fn test_cross_shard_transfer_scenario() {
+    run_scenario(include_str!("scenarios/cross_shard_transfer.yaml"));
+}
@@ -0,0 +1,87 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+use crate::{base_types::*, committee::SigningDomain, messages::*, serialize::*};
+
+fn transfer_order() -> TransferOrder {
+    let (sender, sender_key) = get_key_pair();
+    let transfer = Transfer {
+        sender,
+        recipient: Address::Primary(dbg_addr(0x20)),
+        amount: Amount::from(5),
+        sequence_number: SequenceNumber::new(),
+        user_data: UserData(Some([7; 32])),
+        account_state: None,
+        domain: SigningDomain::default(),
+    };
+    TransferOrder::new(transfer, &sender_key)
+}
+
+#[test]
+fn test_schema_validates_transfer_order() {
+    let schema = ProtocolSchema::current().unwrap();
+    assert_eq!(schema.protocol_version, PROTOCOL_VERSION);
+    assert!(schema.types.contains_key(&schema.entry_point));
+
+    let buf = serialize_transfer_order(&transfer_order());
+    schema.validate("SerializedMessage", &buf).unwrap();
+
+    assert!(schema
+        .validate("SerializedMessage", &buf[..buf.len() - 1])
+        .is_err());
+    let mut longer = buf.clone();
+    longer.push(0);
+    assert!(schema.validate("SerializedMessage", &longer).is_err());
+    assert!(schema.validate("NoSuchType", &buf).is_err());
+}
+
+#[test]
+fn test_schema_is_json() {
+    let schema = ProtocolSchema::current().unwrap();
+    let json = serde_json::to_string(&schema).unwrap();
+    let value: serde_json::Value = serde_json::from_str(&json).unwrap();
+    assert_eq!(value["protocol_version"], PROTOCOL_VERSION);
+    assert_eq!(
+        serde_json::from_str::<ProtocolSchema>(&json).unwrap(),
+        schema
+    );
+}
+
+mod v1 {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename = "Message")]
+    pub struct Message {
+        pub amount: u64,
+    }
+}
+
+mod v2 {
+    #[derive(serde::Serialize, serde::Deserialize)]
+    #[serde(rename = "Message")]
+    pub struct Message {
+        pub amount: u64,
+        pub memo: Option<String>,
+    }
+}
+
+fn schema_of<T: serde::de::DeserializeOwned>() -> ProtocolSchema {
+    let mut tracer = Tracer::new(TracerConfig::default());
+    tracer.trace_type::<T>(&Samples::new()).unwrap();
+    ProtocolSchema::new(tracer.registry().unwrap())
+}
+
+#[test]
+fn test_schema_changes_with_fields() {
+    let old = schema_of::<v1::Message>();
+    let new = schema_of::<v2::Message>();
+    assert_ne!(old, new);
+
+    let buf = bincode::serialize(&v2::Message {
+        amount: 3,
+        memo: Some("rent".to_string()),
+    })
+    .unwrap();
+    new.validate("Message", &buf).unwrap();
+    assert!(old.validate("Message", &buf).is_err());
+}
@@ -3,7 +3,7 @@
 #![allow(clippy::same_item_push)] // get_key_pair returns random elements
 
 use super::*;
-use crate::base_types::*;
+use crate::{base_types::*, committee::SigningDomain};
 use std::time::Instant;
 
 #[test]
@@ -25,11 +25,13 @@ fn test_info_request() {
         sender: dbg_addr(0x20),
         request_sequence_number: None,
         request_received_transfers_excluding_first_nth: None,
+        observer: false,
     };
     let req2 = AccountInfoRequest {
         sender: dbg_addr(0x20),
         request_sequence_number: Some(SequenceNumber::from(129)),
         request_received_transfers_excluding_first_nth: None,
+        observer: false,
     };
 
     let buf1 = serialize_info_request(&req1);
@@ -62,6 +64,8 @@ fn test_order() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
     };
     let transfer_order = TransferOrder::new(transfer, &sender_key);
 
@@ -81,6 +85,8 @@ fn test_order() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
     };
     let transfer_order2 = TransferOrder::new(transfer2, &sender_key);
 
@@ -103,6 +109,8 @@ fn test_vote() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
     };
     let order = TransferOrder::new(transfer, &sender_key);
 
@@ -128,6 +136,8 @@ fn test_cert() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
     };
     let order = TransferOrder::new(transfer, &sender_key);
     let mut cert = CertifiedTransferOrder {
@@ -161,6 +171,8 @@ fn test_info_response() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
     };
     let order = TransferOrder::new(transfer, &sender_key);
 
@@ -186,6 +198,11 @@ fn test_info_response() {
         pending_confirmation: None,
         requested_certificate: None,
         requested_received_transfers: Vec::new(),
+        history_root: [0; 32],
+        requested_certificate_proof: None,
+        last_activity: None,
+        observer_info: None,
+        signature: None,
     };
     let resp2 = AccountInfoResponse {
         sender: dbg_addr(0x20),
@@ -194,6 +211,11 @@ fn test_info_response() {
         pending_confirmation: Some(vote.clone()),
         requested_certificate: None,
         requested_received_transfers: Vec::new(),
+        history_root: [0; 32],
+        requested_certificate_proof: None,
+        last_activity: None,
+        observer_info: None,
+        signature: None,
     };
     let resp3 = AccountInfoResponse {
         sender: dbg_addr(0x20),
@@ -202,6 +224,11 @@ fn test_info_response() {
         pending_confirmation: None,
         requested_certificate: Some(cert.clone()),
         requested_received_transfers: Vec::new(),
+        history_root: [0; 32],
+        requested_certificate_proof: None,
+        last_activity: None,
+        observer_info: None,
+        signature: None,
     };
     let resp4 = AccountInfoResponse {
         sender: dbg_addr(0x20),
@@ -210,6 +237,11 @@ fn test_info_response() {
         pending_confirmation: Some(vote),
         requested_certificate: Some(cert),
         requested_received_transfers: Vec::new(),
+        history_root: [0; 32],
+        requested_certificate_proof: None,
+        last_activity: None,
+        observer_info: None,
+        signature: None,
     };
 
     for resp in [resp1, resp2, resp3, resp4].iter() {
@@ -224,6 +256,83 @@ fn test_info_response() {
     }
 }
 
+#[test]
+fn test_ping_pong() {
+    let ping = Ping { sent_at: 1000 };
+    let buf = serialize_ping(&ping);
+    if let SerializedMessage::Ping(o) = deserialize_message(buf.as_slice()).unwrap() {
+        assert_eq!(*o, ping);
+    } else {
+        panic!()
+    }
+
+    let pong = Pong {
+        sent_at: 1000,
+        received_at: 1500,
+        read_only: true,
+    };
+    let buf = serialize_pong(&pong);
+    if let SerializedMessage::Pong(o) = deserialize_message(buf.as_slice()).unwrap() {
+        assert_eq!(*o, pong);
+    } else {
+        panic!()
+    }
+}
+
+#[test]
+fn test_list_accounts() {
+    let request = ListAccountsRequest {
+        after: Some(dbg_addr(1)),
+        limit: 10,
+    };
+    let buf = serialize_list_accounts_request(&request);
+    if let SerializedMessage::ListAccountsReq(o) = deserialize_message(buf.as_slice()).unwrap() {
+        assert_eq!(*o, request);
+    } else {
+        panic!()
+    }
+
+    let response = ListAccountsResponse {
+        accounts: vec![dbg_addr(2), dbg_addr(3)],
+        next_cursor: Some(dbg_addr(3)),
+    };
+    let buf = serialize_list_accounts_response(&response);
+    if let SerializedMessage::ListAccountsResp(o) = deserialize_message(buf.as_slice()).unwrap() {
+        assert_eq!(*o, response);
+    } else {
+        panic!()
+    }
+}
+
+#[test]
+fn test_deserialization_mode() {
+    let request = AccountInfoRequest {
+        sender: dbg_addr(0x20),
+        request_sequence_number: None,
+        request_received_transfers_excluding_first_nth: None,
+        observer: false,
+    };
+    let mut buf = serialize_info_request(&request);
+    for mode in &[DeserializationMode::Strict, DeserializationMode::Lenient] {
+        if let SerializedMessage::InfoReq(o) = deserialize_message_with_mode(&buf, *mode).unwrap() {
+            assert_eq!(*o, request);
+        } else {
+            panic!()
+        }
+    }
+
+    // Simulate a newer version of the message with an additional field.
+    buf.extend(bincode::serialize(&Some(42u64)).unwrap());
+    assert!(deserialize_message_with_mode(&buf, DeserializationMode::Strict).is_err());
+    if let SerializedMessage::InfoReq(o) =
+        deserialize_message_with_mode(&buf, DeserializationMode::Lenient).unwrap()
+    {
+        assert_eq!(*o, request);
+    } else {
+        panic!()
+    }
+}
+
 #[test]
 fn test_time_order() {
     let (sender_name, sender_key) = get_key_pair();
@@ -233,6 +342,8 @@ fn test_time_order() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
     };
 
     let mut buf = Vec::new();
@@ -266,6 +377,8 @@ fn test_time_vote() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
     };
     let order = TransferOrder::new(transfer, &sender_key);
 
@@ -305,6 +418,8 @@ fn test_time_cert() {
         amount: Amount::from(5),
         sequence_number: SequenceNumber::new(),
         user_data: UserData::default(),
+        account_state: None,
+        domain: SigningDomain::default(),
     };
     let order = TransferOrder::new(transfer, &sender_key);
     let mut cert = CertifiedTransferOrder {
@@ -339,3 +454,70 @@ fn test_time_cert() {
         now.elapsed().as_micros() / count
     );
 }
+
+#[test]
+fn test_message_tags() {
+    use serde_reflection::{ContainerFormat, Samples, Tracer, TracerConfig};
+
+    let mut tracer = Tracer::new(TracerConfig::default());
+    let samples = Samples::new();
+    tracer.trace_type::<Address>(&samples).unwrap();
+    tracer.trace_type::<SequenceNumberStatus>(&samples).unwrap();
+    tracer.trace_type::<Ownership>(&samples).unwrap();
+    tracer
+        .trace_type::<crate::committee::Feature>(&samples)
+        .unwrap();
+    tracer.trace_type::<FastPayError>(&samples).unwrap();
+    tracer.trace_type::<SerializedMessage>(&samples).unwrap();
+    let registry = tracer.registry().unwrap();
+    match registry.get("SerializedMessage") {
+        Some(ContainerFormat::Enum(variants)) => {
+            assert_eq!(variants.len() as u32, NUM_MESSAGE_TYPES)
+        }
+        _ => panic!("SerializedMessage should be an enum"),
+    }
+
+    let buf = serialize_ping(&Ping { sent_at: 0 });
+    assert_eq!(message_tag(&buf), Some(7));
+    let (_, key) = get_key_pair();
+    let update = SignedCrossShardUpdate::new(
+        CrossShardUpdate {
+            shard_id: 1,
+            source_shard_id: 0,
+            transfer_certificate: CertifiedTransferOrder {
+                value: TransferOrder::new(
+                    Transfer {
+                        sender: dbg_addr(1),
+                        recipient: Address::FastPay(dbg_addr(2)),
+                        amount: Amount::from(1),
+                        sequence_number: SequenceNumber::new(),
+                        user_data: UserData::default(),
+                        account_state: None,
+                        domain: SigningDomain::default(),
+                    },
+                    &key,
+                ),
+                signatures: Vec::new(),
+            },
+        },
+        &key,
+    );
+    let buf = serialize_signed_cross_shard(&update);
+    assert_eq!(message_tag(&buf), Some(16));
+    let buf = serialize_reload_accounts_response(&ReloadAccountsResponse {
+        shard_id: 0,
+        num_accounts: 0,
+    });
+    assert_eq!(message_tag(&buf), Some(18));
+    let buf = serialize_ownership_order(&OwnershipOrder::new(
+        OwnershipChange {
+            account: dbg_addr(1),
+            ownership: Ownership::Single(dbg_addr(2)),
+            version: VersionNumber::new(),
+            domain: SigningDomain::default(),
+        },
+        &[&key],
+    ));
+    assert_eq!(message_tag(&buf), Some(NUM_MESSAGE_TYPES - 1));
+    assert_eq!(message_tag(&[1, 0, 0]), None);
+}
@@ -0,0 +1,55 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use super::*;
+
+fn digest(i: u8) -> HashValue {
+    [i; 32]
+}
+
+#[test]
+fn test_entries_expire() {
+    let mut cache = VerificationCache::new(10, Some(1000));
+    cache.insert(digest(1), 5000);
+    assert!(cache.contains(&digest(1), 5000));
+    // Using an entry does not extend its life.
+    assert!(cache.contains(&digest(1), 6000));
+    assert!(!cache.contains(&digest(1), 6001));
+    assert!(cache.is_empty());
+    // Once verified again, the entry is served for another period.
+    cache.insert(digest(1), 6001);
+    assert!(cache.contains(&digest(1), 7001));
+}
+
+#[test]
+fn test_entries_without_max_age() {
+    let mut cache = VerificationCache::new(10, None);
+    cache.insert(digest(1), 0);
+    assert!(cache.contains(&digest(1), u64::MAX));
+}
+
+#[test]
+fn test_capacity_eviction() {
+    let mut cache = VerificationCache::new(2, Some(1000));
+    cache.insert(digest(1), 0);
+    cache.insert(digest(2), 0);
+    // Use the first entry so that the second one is evicted next.
+    assert!(cache.contains(&digest(1), 10));
+    cache.insert(digest(3), 10);
+    assert_eq!(cache.len(), 2);
+    assert!(!cache.contains(&digest(2), 10));
+    assert!(cache.contains(&digest(1), 10));
+    assert!(cache.contains(&digest(3), 10));
+    // Re-inserting an entry does not evict anything.
+    cache.insert(digest(3), 20);
+    assert_eq!(cache.len(), 2);
+    assert!(cache.contains(&digest(1), 20));
+}
+
+#[test]
+fn test_zero_capacity() {
+    let mut cache = VerificationCache::new(0, None);
+    cache.insert(digest(1), 0);
+    assert!(cache.is_empty());
+    assert!(!cache.contains(&digest(1), 0));
+}
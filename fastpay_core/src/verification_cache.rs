@@ -0,0 +1,106 @@
+// Copyright (c) Facebook, Inc. and its affiliates.
+// SPDX-License-Identifier: Apache-2.0
+
+use crate::{base_types::Signable, merkle::HashValue, messages::CertifiedTransferOrder};
+
+use sha2::{Digest, Sha256};
+use std::collections::{BTreeMap, HashMap};
+
+#[cfg(test)]
+#[path = "unit_tests/verification_cache_tests.rs"]
+mod verification_cache_tests;
+
+/// Digest of a certificate, including all its signatures.
+pub fn certificate_digest(certificate: &CertifiedTransferOrder) -> HashValue {
+    let mut hasher = Sha256::new();
+    certificate.write(&mut hasher);
+    hasher.finalize().into()
+}
+
+/// Bounded set of certificates known to be valid for a given committee, so that the
+/// signatures of a certificate submitted several times are only verified once.
+/// Entries are evicted when the cache is full, least recently used first, and when they
+/// are older than the maximal age, if any.
+#[derive(Debug)]
+pub struct VerificationCache {
+    capacity: usize,
+    /// Maximal age of an entry, in milliseconds.
+    max_age: Option<u64>,
+    /// Time of insertion and last use of each entry.
+    entries: HashMap<HashValue, (u64, u64)>,
+    /// Entries by last use, where uses are numbered in increasing order.
+    recency: BTreeMap<u64, HashValue>,
+    next_use: u64,
+}
+
+impl VerificationCache {
+    pub fn new(capacity: usize, max_age: Option<u64>) -> Self {
+        Self {
+            capacity,
+            max_age,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_use: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Whether the given digest was verified recently enough. Expired entries are removed.
+    pub fn contains(&mut self, digest: &HashValue, now: u64) -> bool {
+        let (inserted, last_use) = match self.entries.get(digest) {
+            Some(entry) => *entry,
+            None => return false,
+        };
+        self.recency.remove(&last_use);
+        if self.is_expired(inserted, now) {
+            self.entries.remove(digest);
+            return false;
+        }
+        let last_use = self.next_use();
+        self.entries.insert(*digest, (inserted, last_use));
+        self.recency.insert(last_use, *digest);
+        true
+    }
+
+    /// Record that the given digest was just verified.
+    pub fn insert(&mut self, digest: HashValue, now: u64) {
+        if self.capacity == 0 {
+            return;
+        }
+        if let Some((_, last_use)) = self.entries.remove(&digest) {
+            self.recency.remove(&last_use);
+        }
+        while self.entries.len() >= self.capacity {
+            let (&oldest_use, oldest) = self
+                .recency
+                .iter()
+                .next()
+                .expect("entries are tracked in recency");
+            self.entries.remove(oldest);
+            self.recency.remove(&oldest_use);
+        }
+        let last_use = self.next_use();
+        self.entries.insert(digest, (now, last_use));
+        self.recency.insert(last_use, digest);
+    }
+
+    fn is_expired(&self, inserted: u64, now: u64) -> bool {
+        match self.max_age {
+            Some(max_age) => now.saturating_sub(inserted) > max_age,
+            None => false,
+        }
+    }
+
+    fn next_use(&mut self) -> u64 {
+        let value = self.next_use;
+        self.next_use += 1;
+        value
+    }
+}